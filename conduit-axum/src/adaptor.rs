@@ -9,23 +9,30 @@
 //! be used.  To work around this, the essential request information from hyper is captured in a
 //! `RequestInfo` which is `Send` and is moved into `ConduitRequest::new`.
 
-use std::io::{Cursor, Read};
+use std::io::Read;
 use std::net::SocketAddr;
 
 use conduit::{Host, RequestExt, Scheme, StartInstant};
 use http::request::Parts as HttpParts;
-use http::{Extensions, HeaderMap, Method, Request, Version};
-use hyper::body::Bytes;
+use http::{Extensions, HeaderMap, Method, Request, Uri, Version};
+
+use crate::baggage::Baggage;
+use crate::body::RequestBody;
+use crate::trace_context::TraceContext;
 
 pub(crate) struct ConduitRequest {
     parts: HttpParts,
     path: String,
     remote_addr: SocketAddr,
-    body: Cursor<Bytes>,
+    body: RequestBody,
 }
 
 impl ConduitRequest {
-    pub(crate) fn new(request: Request<Bytes>, remote_addr: SocketAddr, now: StartInstant) -> Self {
+    pub(crate) fn new(
+        request: Request<RequestBody>,
+        remote_addr: SocketAddr,
+        now: StartInstant,
+    ) -> Self {
         let (mut parts, body) = request.into_parts();
         let path = parts.uri.path().as_bytes();
         let path = percent_encoding::percent_decode(path)
@@ -34,13 +41,29 @@ impl ConduitRequest {
 
         parts.extensions.insert(now);
 
+        if let Some(baggage) = Baggage::from_headers(&parts.headers) {
+            parts.extensions.insert(baggage);
+        }
+
+        // Outer middleware may have already continued the trace with a span of its own
+        if parts.extensions.get::<TraceContext>().is_none() {
+            if let Some(trace_context) = TraceContext::from_headers(&parts.headers) {
+                parts.extensions.insert(trace_context);
+            }
+        }
+
         Self {
             parts,
             path,
             remote_addr,
-            body: Cursor::new(body),
+            body,
         }
     }
+
+    /// The original URI of the request, without percent-decoding
+    pub(crate) fn uri(&self) -> &Uri {
+        &self.parts.uri
+    }
 }
 
 impl RequestExt for ConduitRequest {
@@ -61,12 +84,12 @@ impl RequestExt for ConduitRequest {
         &self.parts.headers
     }
 
-    /// Returns the length of the buffered body
+    /// Returns the length of the buffered body, or the `Content-Length` of a streaming body
     fn content_length(&self) -> Option<u64> {
-        Some(self.body.get_ref().len() as u64)
+        self.body.len(&self.parts.headers)
     }
 
-    /// Always returns an address of 0.0.0.0:0
+    /// Returns the address from the `ConnectInfo`, or `0.0.0.0:0` if it was missing
     fn remote_addr(&self) -> SocketAddr {
         self.remote_addr
     }
@@ -0,0 +1,57 @@
+use std::collections::BTreeMap;
+
+use http::HeaderMap;
+use percent_encoding::percent_decode_str;
+
+const BAGGAGE: &str = "baggage";
+
+/// The entries of the W3C `baggage` request headers
+///
+/// This is available to handlers as a request extension, if the request contains at least one
+/// valid entry. Malformed list members are skipped, and the properties of an entry (after the
+/// first `;`) are ignored.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Baggage(BTreeMap<String, String>);
+
+impl Baggage {
+    /// Parse the `baggage` headers, returning `None` if they contain no valid entries
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let entries: BTreeMap<_, _> = headers
+            .get_all(BAGGAGE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(parse_member)
+            .collect();
+
+        if entries.is_empty() {
+            None
+        } else {
+            Some(Self(entries))
+        }
+    }
+
+    /// The percent-decoded value of the entry with the `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+}
+
+/// Parse a `key=value;properties` list member
+fn parse_member(member: &str) -> Option<(String, String)> {
+    let key_value = member.split(';').next()?;
+    let (key, value) = key_value.split_once('=')?;
+
+    let key = key.trim();
+    if key.is_empty() || !key.bytes().all(is_token_char) {
+        return None;
+    }
+
+    let value = percent_decode_str(value.trim()).decode_utf8().ok()?;
+    Some((key.to_string(), value.into_owned()))
+}
+
+/// Whether `byte` is a `tchar`, as defined in RFC 7230
+fn is_token_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
@@ -0,0 +1,122 @@
+use std::io::{self, Cursor, Read};
+
+use axum::body::HttpBody;
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::HeaderMap;
+use hyper::body::{Body, Bytes};
+use tokio::runtime::Handle;
+
+use crate::config::FallbackConfig;
+use crate::transferred_bytes::TransferredBytes;
+
+/// A request extension describing how the request body is passed to the handler
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BodyMode {
+    /// The body has been read into memory before the handler was called
+    Buffered,
+    /// The body is read from the client while the handler reads from `RequestExt::body()`
+    Streaming,
+}
+
+impl BodyMode {
+    /// Choose the mode for a request, based on the streaming settings of the `config`
+    pub(crate) fn for_request(config: &FallbackConfig, path: &str, headers: &HeaderMap) -> Self {
+        let media_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(str::trim);
+
+        let is_streaming_type = media_type.map_or(false, |media_type| {
+            let types = &config.streaming_content_types;
+            types.iter().any(|ty| ty.eq_ignore_ascii_case(media_type))
+        });
+
+        let prefixes = &config.streaming_path_prefixes;
+        let is_streaming_path = prefixes
+            .iter()
+            .any(|prefix| path.starts_with(prefix.as_str()));
+
+        if is_streaming_type || is_streaming_path {
+            Self::Streaming
+        } else {
+            Self::Buffered
+        }
+    }
+}
+
+/// The body of a `ConduitRequest`
+pub(crate) enum RequestBody {
+    Buffered(Cursor<Bytes>),
+    Streaming(BodyReader),
+}
+
+impl RequestBody {
+    /// The length of a buffered body, or the `Content-Length` of a streaming body
+    pub(crate) fn len(&self, headers: &HeaderMap) -> Option<u64> {
+        match self {
+            Self::Buffered(cursor) => Some(cursor.get_ref().len() as u64),
+            Self::Streaming(_) => headers
+                .get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok()),
+        }
+    }
+}
+
+impl Read for RequestBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Buffered(cursor) => cursor.read(buf),
+            Self::Streaming(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// A blocking reader for a `hyper::Body`
+///
+/// This must only be used on the blocking thread pool, since every read that needs a new chunk
+/// blocks the thread until the chunk has been received from the client.
+pub(crate) struct BodyReader {
+    body: Body,
+    handle: Handle,
+    chunk: Bytes,
+    transferred_bytes: Option<TransferredBytes>,
+}
+
+impl BodyReader {
+    pub(crate) fn new(
+        body: Body,
+        handle: Handle,
+        transferred_bytes: Option<TransferredBytes>,
+    ) -> Self {
+        Self {
+            body,
+            handle,
+            chunk: Bytes::new(),
+            transferred_bytes,
+        }
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.handle.block_on(self.body.data()) {
+                Some(Ok(chunk)) => {
+                    if let Some(transferred_bytes) = &self.transferred_bytes {
+                        transferred_bytes.count_request(chunk.len());
+                    }
+                    self.chunk = chunk;
+                }
+                Some(Err(error)) => return Err(io::Error::new(io::ErrorKind::Other, error)),
+                None => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk[..len]);
+        self.chunk = self.chunk.split_off(len);
+        Ok(len)
+    }
+}
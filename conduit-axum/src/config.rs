@@ -0,0 +1,196 @@
+use crate::file_backend::FileRedirect;
+use crate::file_stream::FileStreamLimit;
+use crate::shadow::ShadowHandler;
+use crate::transferred_bytes::TransferredBytes;
+
+use http::{HeaderValue, Method};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Configuration of the fallback handler installed via `ConduitFallback`
+#[derive(Clone, Debug, Default)]
+pub struct FallbackConfig {
+    /// The maximum duration for reading the request body and running the handler
+    ///
+    /// If set, the handler can observe the remaining time budget via the `Deadline` request
+    /// extension. Once the deadline has passed a `408 Request Timeout` response is sent to the
+    /// client, but note that a handler which is already running on the blocking thread pool
+    /// cannot be interrupted and will continue running in the background.
+    pub request_timeout: Option<Duration>,
+    /// The maximum duration for reading a buffered request body, e.g. to reject slow uploads
+    ///
+    /// This is independent of the `request_timeout`, and does not include the execution of the
+    /// handler. Clients that don't send the full body in time receive a `408 Request Timeout`
+    /// response. Streamed request bodies are not affected, see `streaming_content_types`.
+    pub body_read_timeout: Option<Duration>,
+    /// How strictly the `Content-Length` of incoming requests is checked
+    pub content_length_check: ContentLengthCheck,
+    /// The response sent when no route of the handler matched the request
+    ///
+    /// This is used if the handler fails with a `conduit_router::RouterError`. Responses that are
+    /// produced by the handler itself, including its own `404 Not Found` responses, are passed
+    /// through unchanged. If unset, such errors result in a generic `500` response.
+    pub not_found_response: Option<NotFoundResponse>,
+    /// Whether `500 Internal Server Error` responses include the error message of the handler
+    ///
+    /// This is meant to simplify debugging failing tests and is ignored in release builds
+    /// (without `debug_assertions`), which always respond with a generic message. The error is
+    /// logged and reported to Sentry either way.
+    pub verbose_errors: bool,
+    /// Media types of requests whose body is streamed to the handler, e.g. `application/x-tar`
+    ///
+    /// By default the request body is read into memory before the handler is called, which lets
+    /// the `request_timeout` cover slow clients. Streamed bodies are instead read on demand while
+    /// the handler reads from `RequestExt::body()`, and `RequestExt::content_length()` returns
+    /// the `Content-Length` header (if any). Parameters like `charset` are ignored when matching.
+    pub streaming_content_types: Vec<String>,
+    /// Path prefixes of routes whose request body is streamed to the handler
+    ///
+    /// See `streaming_content_types` for the differences to buffered request bodies.
+    pub streaming_path_prefixes: Vec<String>,
+    /// Whether buffered request bodies with `Content-Encoding: gzip` are decompressed
+    ///
+    /// The handler receives the decompressed body, without the `Content-Encoding` header and
+    /// with an adjusted `Content-Length`. Invalid gzip data results in a `400 Bad Request`
+    /// response, and bodies that exceed the built-in maximum size when decompressed in a
+    /// `413 Payload Too Large` response. Streamed request bodies are never decompressed.
+    pub decompress_gzip_bodies: bool,
+    /// Path prefixes of routes that receive the raw request body, even if it is compressed
+    ///
+    /// This is meant for routes that accept precompressed artifacts as-is, see
+    /// `decompress_gzip_bodies`.
+    pub raw_body_path_prefixes: Vec<String>,
+    /// Methods of requests that are expected to have no body, e.g. `GET`
+    ///
+    /// The body of these requests is dropped without being read, and the handler receives an
+    /// empty body. The `Content-Length` checks are skipped, since nothing is read from the
+    /// client anyway.
+    pub bodyless_methods: Vec<Method>,
+    /// Deprecation messages of routes, by their route pattern (e.g. `/api/v1/crates/:crate_id`)
+    ///
+    /// Responses of these routes get a `Warning: 299 - "<message>"` header and a `Deprecated`
+    /// response extension.
+    pub deprecated_routes: HashMap<String, String>,
+    /// Default `Cache-Control` values of routes, by their route pattern
+    ///
+    /// The default only applies to `GET` and `HEAD` requests, and only if neither the handler
+    /// set a `Cache-Control` header, nor marked the request with `NoStore`.
+    pub cache_control_defaults: HashMap<String, HeaderValue>,
+    /// The maximum number of `File` response bodies that are streamed concurrently
+    ///
+    /// Once the limit is exhausted, responses with a `File` body are replaced with a
+    /// `503 Service Unavailable` response with a `Retry-After` header. Other responses are not
+    /// affected. If unset, the number of file streams is not limited.
+    pub file_stream_limit: Option<FileStreamLimit>,
+    /// Whether `File` response bodies are replaced with a precompressed `.gz` sibling
+    ///
+    /// This only applies to responses with a `FilePath` extension, for clients that accept
+    /// gzip. The plain file is served if the sibling does not exist.
+    pub precompressed_gzip: bool,
+    /// The maximum size of `File` response bodies, and how larger files are handled
+    ///
+    /// The size is taken from the file metadata before the response is sent. In either mode, the
+    /// stream ends after the maximum size, in case the file grows while it is being streamed. If
+    /// unset, files of any size are streamed.
+    pub max_file_size: Option<FileSizeLimit>,
+    /// Redirects a share of the `File` response bodies to an alternate backend
+    ///
+    /// The other responses are streamed locally. Either way, the chosen path is recorded in a
+    /// `FileBackend` response extension. If unset, all files are streamed locally.
+    pub file_redirect: Option<FileRedirect>,
+    /// The maximum total size of the response headers, and how larger headers are handled
+    ///
+    /// The size of each header is counted as it is sent in HTTP/1.1, i.e. the name and value plus
+    /// four bytes for the separator and line break. Oversized responses are logged with their
+    /// route pattern. If unset, headers of any size are passed through.
+    pub max_response_header_size: Option<ResponseHeaderLimit>,
+    /// A second handler that receives a copy of a share of the requests
+    ///
+    /// Its responses never reach the client, but status codes that differ from the primary
+    /// handler are logged. Only safe methods like `GET` are shadowed, and requests with a
+    /// streamed body are not shadowed either. If unset, requests are
+    /// only handled by the primary handler.
+    pub shadow_handler: Option<ShadowHandler>,
+    /// Whether the time spent in the layers of the request handling is recorded
+    ///
+    /// This is meant for performance debugging. Responses of the handler carry a `LayerTimings`
+    /// extension with the `content_length_check`, `decompression` (if the body was decompressed),
+    /// `handler` and `compression` (if a precompressed file was looked up) layers. Early
+    /// rejections, e.g. due to an invalid `Content-Length`, don't.
+    pub record_layer_timings: bool,
+    /// Counts the bytes of the request bodies and of the response bodies of the handler
+    ///
+    /// Early rejections, e.g. due to an invalid `Content-Length`, are not counted. If unset,
+    /// nothing is counted.
+    pub transferred_bytes: Option<TransferredBytes>,
+}
+
+/// A canonical `404 Not Found` response for requests to unknown routes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NotFoundResponse {
+    pub(crate) content_type: &'static str,
+    pub(crate) body: String,
+}
+
+impl NotFoundResponse {
+    /// A response with an `application/json` body
+    pub fn json(body: impl Into<String>) -> Self {
+        Self {
+            content_type: "application/json; charset=utf-8",
+            body: body.into(),
+        }
+    }
+}
+
+/// The maximum size of `File` response bodies, see `FallbackConfig::max_file_size`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileSizeLimit {
+    /// Log a warning and respond with `500 Internal Server Error` instead of streaming the file
+    Reject(u64),
+    /// Only stream the first bytes of the file, with an adjusted `Content-Length` header
+    Truncate(u64),
+}
+
+impl FileSizeLimit {
+    pub fn max_bytes(&self) -> u64 {
+        match *self {
+            Self::Reject(max_bytes) | Self::Truncate(max_bytes) => max_bytes,
+        }
+    }
+}
+
+/// The maximum size of the response headers, see `FallbackConfig::max_response_header_size`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseHeaderLimit {
+    /// Respond with `500 Internal Server Error` instead of sending the headers
+    Reject(usize),
+    /// Remove the largest headers until the remaining headers are within the limit
+    Strip(usize),
+}
+
+impl ResponseHeaderLimit {
+    pub fn max_bytes(&self) -> usize {
+        match *self {
+            Self::Reject(max_bytes) | Self::Strip(max_bytes) => max_bytes,
+        }
+    }
+}
+
+/// The `Content-Length` check of the fallback handler
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentLengthCheck {
+    /// Reject requests with an invalid `Content-Length`, or one that exceeds the built-in maximum
+    Enforce,
+    /// Additionally log requests with a `Content-Length` above `limit` as `would_reject`, but let
+    /// them proceed
+    ///
+    /// This can be used to measure the impact of a lower limit before enforcing it. Requests
+    /// above the built-in maximum are still rejected.
+    Monitor { limit: u64 },
+}
+
+impl Default for ContentLengthCheck {
+    fn default() -> Self {
+        Self::Enforce
+    }
+}
@@ -0,0 +1,70 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use axum::extract::connect_info::{ConnectInfo, Connected};
+use axum::middleware::Next;
+use hyper::server::conn::AddrStream;
+use hyper::Request;
+
+use crate::AxumResponse;
+
+/// The connect info of a connection, which counts the requests that were received on it
+///
+/// Use this with `into_make_service_with_connect_info::<ConnectionInfo>()` and the
+/// `track_connection_requests()` middleware, which provides the `ConnectInfo<SocketAddr>` that
+/// the fallback handler expects. Without it, the remote address is reported as `0.0.0.0:0`.
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+    remote_addr: SocketAddr,
+    requests: Arc<AtomicU64>,
+}
+
+impl ConnectionInfo {
+    pub fn new(remote_addr: SocketAddr) -> Self {
+        Self {
+            remote_addr,
+            requests: Arc::default(),
+        }
+    }
+
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    /// Count another request on the connection
+    fn next_request(&self) -> ConnectionRequests {
+        ConnectionRequests(self.requests.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+}
+
+impl Connected<&AddrStream> for ConnectionInfo {
+    fn connect_info(target: &AddrStream) -> Self {
+        Self::new(target.remote_addr())
+    }
+}
+
+/// A request extension with the number of the request on its connection, starting at `1`
+///
+/// Values above `1` mean that the request arrived on a reused keep-alive connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionRequests(pub u64);
+
+/// Count the requests of each connection, based on the `ConnectInfo<ConnectionInfo>`
+///
+/// This adds a `ConnectionRequests` and a `ConnectInfo<SocketAddr>` request extension. Requests
+/// without a `ConnectionInfo` are passed through unchanged.
+pub async fn track_connection_requests<B>(mut req: Request<B>, next: Next<B>) -> AxumResponse {
+    let connection = req
+        .extensions()
+        .get::<ConnectInfo<ConnectionInfo>>()
+        .map(|connect_info| connect_info.0.clone());
+
+    if let Some(connection) = connection {
+        let extensions = req.extensions_mut();
+        extensions.insert(connection.next_request());
+        extensions.insert(ConnectInfo(connection.remote_addr()));
+    }
+
+    next.run(req).await
+}
@@ -0,0 +1,27 @@
+use std::time::{Duration, Instant};
+
+/// The point in time by which a request must be completed
+///
+/// This value is available in the request extensions if a `request_timeout` is configured, so
+/// that handlers doing long running work can check their remaining time budget and abort early.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self {
+        Self(Instant::now() + timeout)
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.0
+    }
+
+    /// Returns the remaining time budget, or zero if the deadline has already passed
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+}
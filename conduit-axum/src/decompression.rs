@@ -0,0 +1,55 @@
+use std::io::Read;
+
+use axum::body::Bytes;
+use flate2::read::GzDecoder;
+use http::header::{CONTENT_ENCODING, CONTENT_LENGTH};
+use http::{HeaderMap, HeaderValue};
+
+use crate::config::FallbackConfig;
+use crate::error::ServiceError;
+
+/// Whether the buffered body of a request to `path` is decompressed before the handler is called
+///
+/// This applies to bodies with `Content-Encoding: gzip` if `decompress_gzip_bodies` is enabled,
+/// unless the path matches one of the `raw_body_path_prefixes`.
+pub(crate) fn should_decompress(config: &FallbackConfig, path: &str, headers: &HeaderMap) -> bool {
+    if !config.decompress_gzip_bodies {
+        return false;
+    }
+
+    let is_gzip = headers.get(CONTENT_ENCODING).map_or(false, |value| {
+        value.as_bytes().eq_ignore_ascii_case(b"gzip")
+    });
+
+    let prefixes = &config.raw_body_path_prefixes;
+    let is_raw_path = prefixes
+        .iter()
+        .any(|prefix| path.starts_with(prefix.as_str()));
+
+    is_gzip && !is_raw_path
+}
+
+/// Decompress a gzip `body`, failing with `PayloadTooLarge` if it exceeds `max_size` bytes
+///
+/// The `Content-Encoding` header is removed and the `Content-Length` header is updated, so that
+/// the handler sees the request as if it was sent uncompressed.
+pub(crate) fn decompress_gzip(
+    body: &[u8],
+    headers: &mut HeaderMap,
+    max_size: u64,
+) -> Result<Bytes, ServiceError> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(body)
+        .take(max_size + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(ServiceError::BodyDecompressionFailed)?;
+
+    if decompressed.len() as u64 > max_size {
+        return Err(ServiceError::PayloadTooLarge);
+    }
+
+    headers.remove(CONTENT_ENCODING);
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(decompressed.len()));
+
+    Ok(Bytes::from(decompressed))
+}
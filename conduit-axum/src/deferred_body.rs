@@ -0,0 +1,94 @@
+use std::future::Future;
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::body::{Bytes, StreamBody};
+use tokio::sync::oneshot;
+use tokio::time::{interval_at, Instant, Interval};
+use tokio_stream::Stream;
+
+/// A response body that is sent after the handler has returned, with keepalive chunks in between
+///
+/// Handlers of slow endpoints can insert this into the response extensions and produce the
+/// payload in the background, e.g. on a separate thread. The response headers are sent right
+/// away, and a chunk of whitespace is sent every `keepalive_interval` until the payload is
+/// ready, which prevents proxies from timing out the request. Any body of the conduit response
+/// itself is discarded.
+///
+/// If the `DeferredBodySender` is dropped without sending a payload, the response is aborted. A
+/// `keepalive_interval` of zero disables the keepalive chunks.
+#[derive(Debug)]
+pub struct DeferredBody {
+    receiver: Option<oneshot::Receiver<Bytes>>,
+    keepalive_interval: Duration,
+    interval: Option<Interval>,
+}
+
+/// Sends the payload of a `DeferredBody`
+#[derive(Debug)]
+pub struct DeferredBodySender(oneshot::Sender<Bytes>);
+
+impl DeferredBody {
+    pub fn new(keepalive_interval: Duration) -> (Self, DeferredBodySender) {
+        let (sender, receiver) = oneshot::channel();
+        let body = Self {
+            receiver: Some(receiver),
+            keepalive_interval,
+            interval: None,
+        };
+        (body, DeferredBodySender(sender))
+    }
+
+    pub fn into_streamed_body(self) -> StreamBody<Self> {
+        StreamBody::new(self)
+    }
+}
+
+impl DeferredBodySender {
+    /// Send the payload, ending the response body
+    pub fn send(self, payload: impl Into<Bytes>) {
+        // If the client has disconnected in the meantime, there is nobody to send the payload to
+        let _ = self.0.send(payload.into());
+    }
+}
+
+impl Stream for DeferredBody {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let Self {
+            ref mut receiver,
+            keepalive_interval,
+            ref mut interval,
+        } = *self;
+
+        let result = match receiver {
+            Some(receiver) => Pin::new(receiver).poll(cx),
+            None => return Poll::Ready(None),
+        };
+
+        if let Poll::Ready(result) = result {
+            *receiver = None;
+            return Poll::Ready(Some(result.map_err(|_| {
+                Error::new(ErrorKind::BrokenPipe, "deferred body was dropped")
+            })));
+        }
+
+        // `interval_at()` panics on a zero period, and the receiver will wake the task anyway
+        if keepalive_interval.is_zero() {
+            return Poll::Pending;
+        }
+
+        // The timer is only created when the body is polled, i.e. on the async runtime
+        let interval = interval.get_or_insert_with(|| {
+            interval_at(Instant::now() + keepalive_interval, keepalive_interval)
+        });
+
+        match interval.poll_tick(cx) {
+            Poll::Ready(_) => Poll::Ready(Some(Ok(Bytes::from_static(b" ")))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use http::header::WARNING;
+use http::{HeaderMap, HeaderValue};
+
+/// A response extension for responses of deprecated routes, with the deprecation message
+///
+/// See `FallbackConfig::deprecated_routes`. Outer middleware (e.g. access logging) can use this to
+/// track how often deprecated routes are still used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Deprecated(pub String);
+
+/// Add a `Warning: 299 - "<message>"` header, as defined in RFC 7234, section 5.5
+pub(crate) fn add_warning_header(headers: &mut HeaderMap, message: &str) {
+    let mut value = String::from("299 - \"");
+    for c in message.chars() {
+        if c == '"' || c == '\\' {
+            value.push('\\');
+        }
+        value.push(c);
+    }
+    value.push('"');
+
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.append(WARNING, value);
+    }
+}
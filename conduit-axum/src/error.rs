@@ -1,3 +1,4 @@
+use http::StatusCode;
 use tokio::task::JoinError;
 
 #[derive(Debug, thiserror::Error)]
@@ -6,4 +7,56 @@ pub enum ServiceError {
     JoinError(#[from] JoinError),
     #[error(transparent)]
     Hyper(#[from] hyper::Error),
+    #[error("Failed to read the request body: {0}")]
+    BodyReadAborted(#[source] hyper::Error),
+    #[error("Request timed out")]
+    RequestTimeout,
+    #[error("Timed out reading the request body")]
+    BodyReadTimeout,
+    #[error("Payload too large")]
+    PayloadTooLarge,
+    #[error("Failed to decompress the request body: {0}")]
+    BodyDecompressionFailed(#[source] std::io::Error),
 }
+
+impl ServiceError {
+    /// The status code of the response that is sent to the client for this error
+    ///
+    /// Errors that are not caused by the client, or that are otherwise unexpected, result in a
+    /// generic `500 Internal Server Error` response.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ServiceError::BodyReadAborted(_) | ServiceError::BodyDecompressionFailed(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            ServiceError::RequestTimeout | ServiceError::BodyReadTimeout => {
+                StatusCode::REQUEST_TIMEOUT
+            }
+            ServiceError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ServiceError::JoinError(_) | ServiceError::Hyper(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+/// The reason why a request was rejected before it reached the conduit handler
+///
+/// This is attached to the extensions of the rejection response, so that outer middleware (e.g.
+/// access logging) can report the reason alongside the status code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RejectionReason(pub String);
+
+/// The ID of the Sentry event that was captured for a `500 Internal Server Error` response
+///
+/// This is attached to the extensions of the response, so that outer middleware (e.g. access
+/// logging) can link the response to the Sentry issue. Responses for errors that were not sent to
+/// Sentry (e.g. because no client is configured) don't have this extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SentryEventId(pub sentry_core::types::Uuid);
+
+/// The reason why a request would have been rejected by a check that is only monitored
+///
+/// This is attached to the extensions of the response, similar to `RejectionReason`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WouldReject(pub String);
@@ -1,23 +1,43 @@
 use crate::adaptor::ConduitRequest;
-use crate::error::ServiceError;
+use crate::body::{BodyMode, BodyReader, RequestBody};
+use crate::config::{
+    ContentLengthCheck, FallbackConfig, FileSizeLimit, NotFoundResponse, ResponseHeaderLimit,
+};
+use crate::deadline::Deadline;
+use crate::decompression::{decompress_gzip, should_decompress};
+use crate::deferred_body::DeferredBody;
+use crate::deprecation::{add_warning_header, Deprecated};
+use crate::error::{RejectionReason, SentryEventId, ServiceError, WouldReject};
+use crate::file_backend::{into_redirect, random_sample, FileBackend};
 use crate::file_stream::FileStream;
+use crate::layer_timings::LayerTimings;
+use crate::negotiated_body::{NegotiatedBody, ResponseFormat};
+use crate::no_store::{apply_no_store, NoStore};
+use crate::precompressed::{open_precompressed, FilePath};
+use crate::shadow::ShadowRequest;
+use crate::transfer_mode::TransferMode;
 use crate::{AxumResponse, ConduitResponse};
 
 use std::error::Error;
+use std::future::Future;
+use std::io::Cursor;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Once};
+use std::time::{Duration, Instant};
 
-use axum::body::{Body, HttpBody};
+use axum::body::{Body, Bytes, HttpBody};
 use axum::extract::{ConnectInfo, Extension};
 use axum::handler::Handler as AxumHandler;
 use axum::response::IntoResponse;
 use conduit::{Handler, RequestExt, StartInstant};
-use conduit_router::RoutePattern;
-use http::header::CONTENT_LENGTH;
-use http::StatusCode;
+use conduit_router::{RoutePattern, RouterError};
+use http::header::{HeaderName, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER, VARY};
+use http::{HeaderMap, HeaderValue, Method, StatusCode};
 use hyper::{Request, Response};
 use sentry_core::Hub;
-use tracing::{error, warn};
+use tokio::runtime::Handle;
+use tracing::{error, warn, Dispatch, Span};
 
 /// The maximum size allowed in the `Content-Length` header
 ///
@@ -25,83 +45,614 @@ use tracing::{error, warn};
 /// See the usage section of the README if you plan to use this server in production.
 const MAX_CONTENT_LENGTH: u64 = 128 * 1024 * 1024; // 128 MB
 
+/// The `Retry-After` value (in seconds) of responses rejected by the `FileStreamLimit`
+const FILE_STREAM_RETRY_AFTER: &str = "1";
+
+/// The remote address of requests without a `ConnectInfo<SocketAddr>` extension
+///
+/// This happens if the router isn't served via `into_make_service_with_connect_info()`, or
+/// without the `track_connection_requests()` middleware when using `ConnectionInfo`.
+const UNKNOWN_REMOTE_ADDR: ([u8; 4], u16) = ([0, 0, 0, 0], 0);
+
+static MISSING_CONNECT_INFO: Once = Once::new();
+
+/// A response extension with the thread of the blocking thread pool that ran the handler
+#[derive(Clone, Debug)]
+pub struct HandlerThread(pub std::thread::Thread);
+
+/// A response extension with the time the handler waited for a thread of the blocking thread pool
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockingWait(pub Duration);
+
+static BLOCKING_TASKS_IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+static BLOCKING_TASKS_QUEUED: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of handlers that are currently running on the blocking thread pool
+///
+/// Handlers that are still waiting for a thread are not included, see `BlockingWait`.
+pub fn blocking_tasks_in_flight() -> usize {
+    BLOCKING_TASKS_IN_FLIGHT.load(Ordering::Relaxed)
+}
+
+/// The number of handlers that are still waiting for a thread of the blocking thread pool
+pub fn blocking_tasks_queued() -> usize {
+    BLOCKING_TASKS_QUEUED.load(Ordering::Relaxed)
+}
+
+/// Counts a handler as in flight until it is dropped, even if the handler panics
+struct InFlightGuard;
+
+impl InFlightGuard {
+    fn new() -> Self {
+        BLOCKING_TASKS_IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+        Self
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        BLOCKING_TASKS_IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts a handler as queued until it starts running, or until it is dropped without ever
+/// running, e.g. if the runtime shuts down first
+struct QueuedGuard;
+
+impl QueuedGuard {
+    fn new() -> Self {
+        BLOCKING_TASKS_QUEUED.fetch_add(1, Ordering::Relaxed);
+        Self
+    }
+}
+
+impl Drop for QueuedGuard {
+    fn drop(&mut self) {
+        BLOCKING_TASKS_QUEUED.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 pub trait ConduitFallback {
     fn conduit_fallback(self, handler: impl Handler) -> Self;
+
+    fn conduit_fallback_with_config(self, handler: impl Handler, config: FallbackConfig) -> Self;
 }
 
 impl ConduitFallback for axum::Router {
     fn conduit_fallback(self, handler: impl Handler) -> Self {
+        self.conduit_fallback_with_config(handler, FallbackConfig::default())
+    }
+
+    fn conduit_fallback_with_config(self, handler: impl Handler, config: FallbackConfig) -> Self {
         let handler: Arc<dyn Handler> = Arc::new(handler);
-        self.fallback(fallback_to_conduit.layer(Extension(handler)))
+        let config = Arc::new(config);
+        self.fallback(
+            fallback_to_conduit
+                .layer(Extension(handler))
+                .layer(Extension(config)),
+        )
     }
 }
 
 async fn fallback_to_conduit(
-    handler: Extension<Arc<dyn Handler>>,
-    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Extension(handler): Extension<Arc<dyn Handler>>,
+    Extension(config): Extension<Arc<FallbackConfig>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    request: Request<Body>,
+) -> Result<AxumResponse, ServiceError> {
+    let remote_addr = remote_addr_or_unknown(connect_info.map(|connect_info| connect_info.0));
+    call_conduit(handler, config, remote_addr, request).await
+}
+
+/// The `remote_addr` of the `ConnectInfo`, or `0.0.0.0:0` with a one-time warning without it
+///
+/// This is shared by `ConduitFallback` and `ConduitService`.
+pub(crate) fn remote_addr_or_unknown(remote_addr: Option<SocketAddr>) -> SocketAddr {
+    remote_addr.unwrap_or_else(|| {
+        MISSING_CONNECT_INFO.call_once(|| {
+            warn!(
+                "Missing `ConnectInfo<SocketAddr>`, the remote address of all requests is \
+                reported as `0.0.0.0:0`. Use `into_make_service_with_connect_info()` to fix this."
+            );
+        });
+        UNKNOWN_REMOTE_ADDR.into()
+    })
+}
+
+/// Runs the conduit `handler` for the `request` on the blocking thread pool
+///
+/// This is shared by `ConduitFallback` and `ConduitService`.
+pub(crate) async fn call_conduit(
+    handler: Arc<dyn Handler>,
+    config: Arc<FallbackConfig>,
+    remote_addr: SocketAddr,
     request: Request<Body>,
 ) -> Result<AxumResponse, ServiceError> {
-    if let Err(response) = check_content_length(&request) {
-        return Ok(response);
+    let mut layer_timings = config.record_layer_timings.then(LayerTimings::default);
+    let started = Instant::now();
+
+    let is_bodyless = config.bodyless_methods.contains(request.method());
+
+    if !is_bodyless {
+        if let Err(response) = check_content_length(&request) {
+            return Ok(response);
+        }
+    }
+
+    let would_reject = match config.content_length_check {
+        _ if is_bodyless => None,
+        ContentLengthCheck::Enforce => None,
+        ContentLengthCheck::Monitor { limit } => monitor_content_length(&request, limit),
+    };
+
+    if let Some(timings) = &mut layer_timings {
+        timings.add("content_length_check", started.elapsed());
     }
 
-    let (parts, body) = request.into_parts();
+    let (mut parts, body) = request.into_parts();
     let now = StartInstant::now();
 
+    let deadline = config.request_timeout.map(Deadline::after);
+    if let Some(deadline) = deadline {
+        parts.extensions.insert(deadline);
+    }
+
     let hub = Hub::current();
+    let dispatch = tracing::dispatcher::get_default(Dispatch::clone);
+    let span = Span::current();
+
+    let body_mode = if is_bodyless {
+        BodyMode::Buffered
+    } else {
+        BodyMode::for_request(&config, parts.uri.path(), &parts.headers)
+    };
+    parts.extensions.insert(body_mode);
+
+    let body = match body_mode {
+        BodyMode::Buffered if is_bodyless => RequestBody::Buffered(Cursor::new(Bytes::new())),
+        BodyMode::Buffered => {
+            let read_body = read_body(body, config.body_read_timeout);
+            let mut full_body = with_deadline(deadline, read_body).await??;
+            if let Some(transferred_bytes) = &config.transferred_bytes {
+                transferred_bytes.count_request(full_body.len());
+            }
+            if should_decompress(&config, parts.uri.path(), &parts.headers) {
+                let started = Instant::now();
+                full_body = decompress_gzip(&full_body, &mut parts.headers, MAX_CONTENT_LENGTH)?;
+                if let Some(timings) = &mut layer_timings {
+                    timings.add("decompression", started.elapsed());
+                }
+            }
+            RequestBody::Buffered(Cursor::new(full_body))
+        }
+        BodyMode::Streaming => {
+            let transferred_bytes = config.transferred_bytes.clone();
+            RequestBody::Streaming(BodyReader::new(body, Handle::current(), transferred_bytes))
+        }
+    };
+
+    let shadow_request = match (&config.shadow_handler, &body) {
+        (Some(shadow), RequestBody::Buffered(body))
+            if shadow.should_shadow(&parts.method, random_sample()) =>
+        {
+            ShadowRequest::new(
+                shadow,
+                &parts,
+                body.get_ref(),
+                remote_addr,
+                dispatch.clone(),
+            )
+        }
+        _ => None,
+    };
+
+    let request = Request::from_parts(parts, body);
+
+    let not_found = config.not_found_response.clone();
+    let file_config = config.clone();
+    let verbose_errors = cfg!(debug_assertions) && config.verbose_errors;
+    let spawned_at = Instant::now();
+    let queued = QueuedGuard::new();
+    let task = tokio::task::spawn_blocking(move || {
+        drop(queued);
+        let blocking_wait = BlockingWait(spawned_at.elapsed());
+        let _in_flight = InFlightGuard::new();
+
+        // Events of the handler are recorded by the same subscriber and within the span of
+        // the request, like on the async task that spawned the handler
+        tracing::dispatcher::with_default(&dispatch, || {
+            let _entered = span.entered();
+            let started = Instant::now();
+            let mut response = Hub::run(hub, || {
+                let mut request = ConduitRequest::new(request, remote_addr, now);
+                handler
+                    .call(&mut request)
+                    .map(|response| conduit_into_axum(response, request, &file_config))
+                    .unwrap_or_else(|e| match not_found {
+                        Some(not_found) if e.downcast_ref::<RouterError>().is_some() => {
+                            not_found_response(not_found)
+                        }
+                        _ if verbose_errors => verbose_server_error_response(&*e),
+                        _ => server_error_response(&*e),
+                    })
+            });
+
+            if let Some(mut timings) = layer_timings {
+                // The `compression` layer of the response conversion is recorded separately
+                let extensions = response.extensions_mut();
+                let converted = extensions.remove::<LayerTimings>().unwrap_or_default();
+                let compression = converted.get("compression").unwrap_or_default();
+                timings.add("handler", started.elapsed().saturating_sub(compression));
+                for (layer, duration) in converted.iter() {
+                    timings.add(layer, duration);
+                }
+                extensions.insert(timings);
+            }
 
-    let full_body = hyper::body::to_bytes(body).await?;
-    let request = Request::from_parts(parts, full_body);
-
-    let handler = handler.clone();
-    tokio::task::spawn_blocking(move || {
-        Hub::run(hub, || {
-            let mut request = ConduitRequest::new(request, remote_addr, now);
-            handler
-                .call(&mut request)
-                .map(|response| conduit_into_axum(response, request))
-                .unwrap_or_else(|e| server_error_response(&*e))
+            let thread = HandlerThread(std::thread::current());
+            response.extensions_mut().insert(thread);
+            response.extensions_mut().insert(blocking_wait);
+            response
         })
-    })
-    .await
-    .map_err(Into::into)
+    });
+
+    let mut response = with_deadline(deadline, task).await??;
+    if let Some(transferred_bytes) = &config.transferred_bytes {
+        response = response.map(|body| transferred_bytes.count_response(body));
+    }
+    if let Some(shadow_request) = shadow_request {
+        shadow_request.spawn(response.status());
+    }
+
+    if let Some(would_reject) = would_reject {
+        response.extensions_mut().insert(would_reject);
+    }
+
+    Ok(response)
+}
+
+/// Read the full request `body`, failing with a `ServiceError::BodyReadTimeout` if that takes
+/// longer than the `timeout`
+async fn read_body(body: Body, timeout: Option<Duration>) -> Result<Bytes, ServiceError> {
+    let read = hyper::body::to_bytes(body);
+    let result = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, read)
+            .await
+            .map_err(|_| ServiceError::BodyReadTimeout)?,
+        None => read.await,
+    };
+
+    result.map_err(ServiceError::BodyReadAborted)
+}
+
+/// Await the `future`, failing with a `ServiceError::RequestTimeout` if the deadline passes first
+async fn with_deadline<F: Future>(
+    deadline: Option<Deadline>,
+    future: F,
+) -> Result<F::Output, ServiceError> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.instant().into(), future)
+            .await
+            .map_err(|_| ServiceError::RequestTimeout),
+        None => Ok(future.await),
+    }
 }
 
 /// Turns a `ConduitResponse` into a `AxumResponse`
-fn conduit_into_axum(mut response: ConduitResponse, mut request: ConduitRequest) -> AxumResponse {
+///
+/// The response `Parts` are reused as-is, so repeated headers like `Set-Cookie` are preserved.
+/// `File` bodies may be replaced with a redirect to an alternate backend, see `FileRedirect`, or
+/// with their precompressed sibling, see `FilePath`. If a `File` body would exceed the
+/// `file_stream_limit`, the file is closed and a `503 Service Unavailable` response is returned
+/// instead. Responses with headers above the `max_response_header_size` are rejected or stripped,
+/// and the resulting response carries a `TransferMode` extension.
+fn conduit_into_axum(
+    response: ConduitResponse,
+    request: ConduitRequest,
+    config: &FallbackConfig,
+) -> AxumResponse {
+    let mut response = convert_response(response, request, config);
+    if let Some(limit) = config.max_response_header_size {
+        response = limit_header_size(response, limit);
+    }
+    let transfer_mode = TransferMode::for_response(&response);
+    response.extensions_mut().insert(transfer_mode);
+    response
+}
+
+fn convert_response(
+    mut response: ConduitResponse,
+    mut request: ConduitRequest,
+    config: &FallbackConfig,
+) -> AxumResponse {
     use conduit::Body::*;
 
     if let Some(pattern) = request.mut_extensions().remove::<RoutePattern>() {
+        if let Some(message) = config.deprecated_routes.get(pattern.pattern()) {
+            add_warning_header(response.headers_mut(), message);
+            response
+                .extensions_mut()
+                .insert(Deprecated(message.clone()));
+        }
         response.extensions_mut().insert(pattern);
     }
 
-    let (parts, body) = response.into_parts();
+    if request.extensions().get::<NoStore>().is_some() {
+        apply_no_store(response.headers_mut());
+    }
+
+    if matches!(*request.method(), Method::GET | Method::HEAD) {
+        let pattern = response.extensions().get::<RoutePattern>();
+        let defaults = &config.cache_control_defaults;
+        let default = pattern.and_then(|pattern| defaults.get(pattern.pattern()));
+        if let Some(value) = default {
+            let headers = response.headers_mut();
+            if !headers.contains_key(CACHE_CONTROL) {
+                headers.insert(CACHE_CONTROL, value.clone());
+            }
+        }
+    }
+
+    if let Some(deferred_body) = response.extensions_mut().remove::<DeferredBody>() {
+        let (mut parts, _) = response.into_parts();
+        parts.headers.remove(CONTENT_LENGTH);
+        return Response::from_parts(parts, deferred_body.into_streamed_body()).into_response();
+    }
+
+    if let Some(negotiated_body) = response.extensions_mut().remove::<NegotiatedBody>() {
+        let format = ResponseFormat::from_accept(request.headers());
+        let body = match negotiated_body.encode(format) {
+            Ok(body) => body,
+            Err(error) => return server_error_response(&*error),
+        };
+
+        let (mut parts, _) = response.into_parts();
+        let content_type = HeaderValue::from_static(format.content_type());
+        parts.headers.insert(CONTENT_TYPE, content_type);
+        parts.headers.insert(CONTENT_LENGTH, body.len().into());
+        parts
+            .headers
+            .append(VARY, HeaderValue::from_static("Accept"));
+        parts.extensions.insert(format);
+        return Response::from_parts(parts, axum::body::Body::from(body)).into_response();
+    }
+
+    let (mut parts, body) = response.into_parts();
     match body {
         Static(slice) => Response::from_parts(parts, axum::body::Body::from(slice)).into_response(),
         Owned(vec) => Response::from_parts(parts, axum::body::Body::from(vec)).into_response(),
-        File(file) => Response::from_parts(parts, FileStream::from_std(file).into_streamed_body())
-            .into_response(),
+        File(mut file) => {
+            if let Some(redirect) = &config.file_redirect {
+                let location = if redirect.should_redirect(random_sample()) {
+                    redirect.location(request.uri().path())
+                } else {
+                    None
+                };
+
+                if let Some(location) = location {
+                    into_redirect(&mut parts, location);
+                    parts.extensions.insert(FileBackend::Redirect);
+                    return Response::from_parts(parts, axum::body::Body::empty()).into_response();
+                }
+            }
+            parts.extensions.insert(FileBackend::Local);
+
+            if let Some(path) = parts.extensions.remove::<FilePath>() {
+                if config.precompressed_gzip {
+                    let started = Instant::now();
+                    let headers = &mut parts.headers;
+                    if let Some(precompressed) =
+                        open_precompressed(&path.0, request.headers(), headers)
+                    {
+                        file = precompressed;
+                    }
+                    if config.record_layer_timings {
+                        let duration = started.elapsed();
+                        LayerTimings::record(&mut parts.extensions, "compression", duration);
+                    }
+                }
+            }
+
+            let mut max_bytes = None;
+            if let Some(limit) = config.max_file_size {
+                let file_size = file.metadata().map(|metadata| metadata.len());
+                match (limit, file_size) {
+                    (FileSizeLimit::Reject(max), Ok(size)) if size > max => {
+                        return file_size_limit_response(size, max, parts.extensions);
+                    }
+                    (FileSizeLimit::Truncate(max), Ok(size)) if size > max => {
+                        warn!(size, max, "Truncating oversized file response");
+                        parts.headers.insert(CONTENT_LENGTH, max.into());
+                    }
+                    _ => {}
+                }
+                max_bytes = Some(limit.max_bytes());
+            }
+
+            let mut stream = FileStream::from_std(file);
+            if let Some(max_bytes) = max_bytes {
+                stream = stream.with_max_bytes(max_bytes);
+            }
+            let content_length = parts.headers.get(CONTENT_LENGTH);
+            if let Some(len) = content_length.and_then(|value| value.to_str().ok()?.parse().ok()) {
+                stream = stream.with_expected_len(len);
+            }
+            if let Some(limit) = &config.file_stream_limit {
+                match limit.try_acquire() {
+                    Some(permit) => stream = stream.with_permit(permit),
+                    None => return file_stream_limit_response(parts.extensions),
+                }
+            }
+
+            Response::from_parts(parts, stream.into_streamed_body()).into_response()
+        }
     }
 }
 
+/// The size of a header as it is sent in HTTP/1.1, including `: ` and the `\r\n` line break
+fn header_size(name: &HeaderName, value: &HeaderValue) -> usize {
+    name.as_str().len() + value.len() + 4
+}
+
+fn total_header_size(headers: &HeaderMap) -> usize {
+    headers
+        .iter()
+        .map(|(name, value)| header_size(name, value))
+        .sum()
+}
+
+/// Enforce the `max_response_header_size` on the `response`, see `ResponseHeaderLimit`
+fn limit_header_size(mut response: AxumResponse, limit: ResponseHeaderLimit) -> AxumResponse {
+    let max = limit.max_bytes();
+    let size = total_header_size(response.headers());
+    if size <= max {
+        return response;
+    }
+
+    let pattern = response.extensions_mut().remove::<RoutePattern>();
+    let route = pattern
+        .as_ref()
+        .map_or("<unknown>", |pattern| pattern.pattern());
+
+    match limit {
+        ResponseHeaderLimit::Reject(_) => {
+            warn!(
+                size,
+                max, route, "Rejecting response with oversized headers"
+            );
+
+            let reason =
+                format!("Response headers of {size} bytes exceed the limit of {max} bytes");
+            response = rejection_response(StatusCode::INTERNAL_SERVER_ERROR, reason);
+        }
+        ResponseHeaderLimit::Strip(_) => {
+            let headers = response.headers_mut();
+            while total_header_size(headers) > max {
+                let largest = headers
+                    .iter()
+                    .max_by_key(|(name, value)| header_size(name, value))
+                    .map(|(name, _)| name.clone());
+
+                let name = match largest {
+                    Some(name) => name,
+                    None => break,
+                };
+
+                warn!(size, max, route, header = %name, "Stripping oversized response header");
+                headers.remove(&name);
+            }
+        }
+    }
+
+    if let Some(pattern) = pattern {
+        response.extensions_mut().insert(pattern);
+    }
+
+    response
+}
+
+/// Returns a `500 Internal Server Error` response for a `File` body that exceeds the
+/// `max_file_size`
+///
+/// Like for `file_stream_limit_response()`, the `extensions` of the handler's response are kept.
+fn file_size_limit_response(size: u64, max: u64, extensions: http::Extensions) -> AxumResponse {
+    warn!(size, max, "Rejecting oversized file response");
+
+    let reason = format!("File body of {size} bytes exceeds the limit of {max} bytes");
+    rejection_response_with_extensions(StatusCode::INTERNAL_SERVER_ERROR, reason, extensions)
+}
+
+/// Returns a `503 Service Unavailable` response for a `File` body that exceeds the limit
+///
+/// The `extensions` of the handler's response (e.g. the `RoutePattern`) are kept, so that outer
+/// middleware can still attribute the rejection to the route.
+fn file_stream_limit_response(extensions: http::Extensions) -> AxumResponse {
+    warn!("Rejecting request: too many concurrent file streams");
+
+    let reason = "Too many concurrent file streams".to_string();
+    let mut response =
+        rejection_response_with_extensions(StatusCode::SERVICE_UNAVAILABLE, reason, extensions);
+    let retry_after = http::HeaderValue::from_static(FILE_STREAM_RETRY_AFTER);
+    response.headers_mut().insert(RETRY_AFTER, retry_after);
+    response
+}
+
 impl IntoResponse for ServiceError {
     fn into_response(self) -> AxumResponse {
-        server_error_response(&self)
+        let status = self.status();
+        if status.is_server_error() {
+            return server_error_response(&self);
+        }
+
+        warn!(error = %self, "Rejecting request");
+
+        rejection_response(status, self.to_string())
     }
 }
 
+/// Returns an empty response with the `status`, carrying the `reason` as a `RejectionReason`
+fn rejection_response(status: StatusCode, reason: String) -> AxumResponse {
+    rejection_response_with_extensions(status, reason, http::Extensions::new())
+}
+
+/// Returns a `rejection_response` that also carries the `extensions` of the original response
+fn rejection_response_with_extensions(
+    status: StatusCode,
+    reason: String,
+    extensions: http::Extensions,
+) -> AxumResponse {
+    let mut response = Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .expect("Unexpected invalid header")
+        .into_response();
+
+    *response.extensions_mut() = extensions;
+    response.extensions_mut().insert(RejectionReason(reason));
+    response
+}
+
+/// Returns the configured canonical `404 Not Found` response
+fn not_found_response(not_found: NotFoundResponse) -> AxumResponse {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header(CONTENT_TYPE, not_found.content_type)
+        .body(Body::from(not_found.body))
+        .expect("Unexpected invalid header")
+        .into_response()
+}
+
 /// Logs an error message and returns a generic status 500 response
 fn server_error_response<E: Error + ?Sized>(error: &E) -> AxumResponse {
+    internal_server_error(error, hyper::Body::from("Internal Server Error"))
+}
+
+/// Like `server_error_response()`, but with the error message in the response body
+fn verbose_server_error_response<E: Error + ?Sized>(error: &E) -> AxumResponse {
+    let body = format!("Internal Server Error: {error}");
+    internal_server_error(error, hyper::Body::from(body))
+}
+
+/// Logs an error message and reports it to Sentry, returning a status 500 response with `body`
+///
+/// The ID of the Sentry event is attached to the response as a `SentryEventId`.
+fn internal_server_error<E: Error + ?Sized>(error: &E, body: hyper::Body) -> AxumResponse {
     error!(%error, "Internal Server Error");
 
-    sentry_core::capture_error(error);
+    let event_id = sentry_core::capture_error(error);
 
-    let body = hyper::Body::from("Internal Server Error");
-    Response::builder()
+    let mut response = Response::builder()
         .status(StatusCode::INTERNAL_SERVER_ERROR)
         .body(body)
         .expect("Unexpected invalid header")
-        .into_response()
+        .into_response();
+
+    // The event ID is nil if the event was not sent, e.g. because no client is bound
+    if !event_id.is_nil() {
+        response.extensions_mut().insert(SentryEventId(event_id));
+    }
+
+    response
 }
 
 /// Check for `Content-Length` values that are invalid or too large
@@ -116,11 +667,7 @@ fn check_content_length(request: &Request<Body>) -> Result<(), AxumResponse> {
     fn bad_request(message: &str) -> AxumResponse {
         warn!("Bad request: Content-Length {}", message);
 
-        Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .body(Body::empty())
-            .expect("Unexpected invalid header")
-            .into_response()
+        rejection_response(StatusCode::BAD_REQUEST, format!("Content-Length {message}"))
     }
 
     if let Some(content_length) = request.headers().get(CONTENT_LENGTH) {
@@ -147,3 +694,25 @@ fn check_content_length(request: &Request<Body>) -> Result<(), AxumResponse> {
 
     Ok(())
 }
+
+/// Check if the `Content-Length` exceeds the monitored `limit`, without rejecting the request
+fn monitor_content_length(request: &Request<Body>, limit: u64) -> Option<WouldReject> {
+    let content_length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())?;
+
+    if content_length <= limit {
+        return None;
+    }
+
+    warn!(
+        would_reject = true,
+        content_length, limit, "Content-Length exceeds the monitored limit"
+    );
+
+    Some(WouldReject(format!(
+        "Content-Length {content_length} exceeds {limit}"
+    )))
+}
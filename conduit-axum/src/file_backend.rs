@@ -0,0 +1,82 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use http::header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, LOCATION};
+use http::response::Parts;
+use http::{HeaderValue, StatusCode};
+
+/// Redirects a share of the `File` response bodies to an alternate backend, see
+/// `FallbackConfig::file_redirect`
+///
+/// Each `File` response is redirected with a probability of `weight`, to the request path
+/// appended to the `base_url` (e.g. `https://static.example.com` for an object store). The other
+/// responses are streamed from the local file as usual.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileRedirect {
+    weight: f64,
+    base_url: String,
+}
+
+impl FileRedirect {
+    /// The `weight` is clamped to the `0.0-1.0` range
+    pub fn new(weight: f64, base_url: impl Into<String>) -> Self {
+        let base_url = base_url.into();
+        let base_url = base_url.trim_end_matches('/').to_string();
+        let weight = if weight.is_nan() {
+            0.0
+        } else {
+            weight.clamp(0.0, 1.0)
+        };
+
+        Self { weight, base_url }
+    }
+
+    /// Decide whether to redirect a response, given a `sample` in the `0.0..1.0` range
+    pub(crate) fn should_redirect(&self, sample: f64) -> bool {
+        sample < self.weight
+    }
+
+    /// The `Location` of the redirect for a request to `path`, if it is a valid header value
+    pub(crate) fn location(&self, path: &str) -> Option<HeaderValue> {
+        HeaderValue::from_str(&format!("{}{}", self.base_url, path)).ok()
+    }
+}
+
+/// A response extension recording which backend served a `File` response body
+///
+/// Outer middleware (e.g. access logging) can use this to track the progress of a migration
+/// between the backends, see `FallbackConfig::file_redirect`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileBackend {
+    /// The file was streamed from the local file system
+    Local,
+    /// The client was redirected to the alternate backend
+    Redirect,
+}
+
+impl FileBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Local => "local",
+            Self::Redirect => "redirect",
+        }
+    }
+}
+
+/// A random sample in the `0.0..1.0` range
+///
+/// The hashers of `RandomState` are randomly seeded, which is good enough for splitting traffic
+/// and avoids a dependency on a random number generator.
+pub(crate) fn random_sample() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Turn the response `parts` into a `302 Found` redirect to the `location`
+pub(crate) fn into_redirect(parts: &mut Parts, location: HeaderValue) {
+    parts.status = StatusCode::FOUND;
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.remove(CONTENT_TYPE);
+    parts.headers.remove(CONTENT_ENCODING);
+    parts.headers.insert(LOCATION, location);
+}
@@ -1,22 +1,88 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::{io::Error, pin::Pin};
 
 use axum::body::{Bytes, StreamBody};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio::{fs::File, io::AsyncRead};
 use tokio_stream::Stream;
+use tracing::debug;
 
-const BUFFER_SIZE: usize = 8 * 1024;
+pub(crate) const BUFFER_SIZE: usize = 8 * 1024;
 
+static FILE_STREAMS_OPEN: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of `FileStream`s that currently hold an open file
+pub fn file_streams_open() -> usize {
+    FILE_STREAMS_OPEN.load(Ordering::Relaxed)
+}
+
+/// A `Stream` of the contents of a file, read in chunks of up to 8 KB
+///
+/// The file is only read when the next chunk is polled by the consumer, so there is no read-ahead
+/// and at most one chunk is buffered in memory, even if the client reads slowly.
+///
+/// If the client disconnects before it received the whole file, the stream is dropped early. This
+/// is logged at the `debug` level with a `client_disconnect` marker instead of being treated as
+/// an error. Since hyper stops polling the body once it has sent `Content-Length` bytes, the
+/// stream also counts as finished once it has passed on the `with_expected_len()` bytes.
 pub struct FileStream {
     file: File,
     buffer: Box<[u8; BUFFER_SIZE]>,
+    /// Released once the stream is dropped, see `FileStreamLimit`
+    permit: Option<OwnedSemaphorePermit>,
+    /// The number of bytes that may still be read, if the stream is capped
+    remaining: Option<u64>,
+    /// The number of bytes that were passed to the consumer so far
+    streamed: u64,
+    /// The number of bytes the consumer is expected to read, e.g. from the `Content-Length`
+    expected_len: Option<u64>,
+    /// Whether the consumer started polling the stream
+    started: bool,
+    /// Whether the end of the stream was reached
+    finished: bool,
 }
 
 impl FileStream {
     pub fn from_std(file: std::fs::File) -> Self {
+        Self::from_tokio(File::from_std(file))
+    }
+
+    /// Stream from an already opened async file handle
+    pub fn from_tokio(file: File) -> Self {
         let buffer = Box::new([0; BUFFER_SIZE]);
-        let file = File::from_std(file);
-        Self { file, buffer }
+        FILE_STREAMS_OPEN.fetch_add(1, Ordering::Relaxed);
+        Self {
+            file,
+            buffer,
+            permit: None,
+            remaining: None,
+            streamed: 0,
+            expected_len: None,
+            started: false,
+            finished: false,
+        }
+    }
+
+    /// End the stream after `max_bytes`, even if the file is larger or keeps growing
+    pub(crate) fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.remaining = Some(max_bytes);
+        self
+    }
+
+    /// Consider the stream finished once `len` bytes were streamed, even if the end of the file is
+    /// never polled
+    pub(crate) fn with_expected_len(mut self, len: u64) -> Self {
+        self.expected_len = Some(len);
+        self
+    }
+
+    /// Hold the `permit` of a `FileStreamLimit` until the stream is dropped
+    pub(crate) fn with_permit(mut self, permit: OwnedSemaphorePermit) -> Self {
+        self.permit = Some(permit);
+        self
     }
 
     pub fn into_streamed_body(self) -> StreamBody<Self> {
@@ -24,6 +90,47 @@ impl FileStream {
     }
 }
 
+impl Drop for FileStream {
+    fn drop(&mut self) {
+        FILE_STREAMS_OPEN.fetch_sub(1, Ordering::Relaxed);
+
+        if self.started && !self.finished {
+            debug!(
+                client_disconnect = true,
+                bytes = self.streamed,
+                "Client disconnected before the file stream ended"
+            );
+        }
+    }
+}
+
+impl fmt::Debug for FileStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileStream")
+            .field("file", &self.file)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A limit on the number of `File` response bodies that are streamed concurrently
+///
+/// Each stream holds an open file descriptor until the client has received the whole file, so a
+/// surge of slow downloads could otherwise exhaust the available file descriptors. Clones share
+/// the same limit.
+#[derive(Clone, Debug)]
+pub struct FileStreamLimit(Arc<Semaphore>);
+
+impl FileStreamLimit {
+    pub fn new(max_streams: usize) -> Self {
+        Self(Arc::new(Semaphore::new(max_streams)))
+    }
+
+    /// Reserve a stream, or return `None` if the limit is exhausted
+    pub(crate) fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.0.clone().try_acquire_owned().ok()
+    }
+}
+
 impl Stream for FileStream {
     type Item = Result<Bytes, Error>;
 
@@ -31,11 +138,43 @@ impl Stream for FileStream {
         let Self {
             ref mut file,
             ref mut buffer,
+            ref mut remaining,
+            ref mut streamed,
+            expected_len,
+            ref mut started,
+            ref mut finished,
+            ..
         } = *self;
-        let mut buf = tokio::io::ReadBuf::new(&mut buffer[..]);
+
+        *started = true;
+
+        let len = match *remaining {
+            Some(0) => {
+                *finished = true;
+                return Poll::Ready(None);
+            }
+            Some(remaining) => BUFFER_SIZE.min(remaining as usize),
+            None => BUFFER_SIZE,
+        };
+
+        let mut buf = tokio::io::ReadBuf::new(&mut buffer[..len]);
         match Pin::new(file).poll_read(cx, &mut buf) {
-            Poll::Ready(Ok(())) if buf.filled().is_empty() => Poll::Ready(None),
-            Poll::Ready(Ok(())) => Poll::Ready(Some(Ok(Bytes::copy_from_slice(buf.filled())))),
+            Poll::Ready(Ok(())) if buf.filled().is_empty() => {
+                *finished = true;
+                Poll::Ready(None)
+            }
+            Poll::Ready(Ok(())) => {
+                let len = buf.filled().len() as u64;
+                if let Some(remaining) = remaining {
+                    *remaining -= len;
+                }
+                *streamed += len;
+                let all_sent = expected_len.map_or(false, |expected| *streamed >= expected);
+                if all_sent || *remaining == Some(0) {
+                    *finished = true;
+                }
+                Poll::Ready(Some(Ok(Bytes::copy_from_slice(buf.filled()))))
+            }
             Poll::Ready(Err(e)) => Poll::Ready(Some(Err(e))),
             Poll::Pending => Poll::Pending,
         }
@@ -0,0 +1,56 @@
+use std::time::{Duration, Instant};
+
+use http::Extensions;
+
+/// A response extension with the time spent in the layers of the request handling, e.g. the
+/// `Content-Length` check, the request body decompression and the handler itself
+///
+/// This is only added if `FallbackConfig::record_layer_timings` is enabled. Outer middleware can
+/// record its own layers with `LayerTimings::record()`, and access logging can then report the
+/// full breakdown of a request.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LayerTimings(Vec<(&'static str, Duration)>);
+
+impl LayerTimings {
+    /// Add the `duration` to the time spent in the `layer`
+    pub fn add(&mut self, layer: &'static str, duration: Duration) {
+        match self.0.iter_mut().find(|(name, _)| *name == layer) {
+            Some((_, total)) => *total += duration,
+            None => self.0.push((layer, duration)),
+        }
+    }
+
+    /// Run `f` and add its duration to the time spent in the `layer`
+    pub fn time<T>(&mut self, layer: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.add(layer, start.elapsed());
+        result
+    }
+
+    /// Add the `duration` of the `layer` to the `LayerTimings` of a request or response, which
+    /// are created if necessary
+    pub fn record(extensions: &mut Extensions, layer: &'static str, duration: Duration) {
+        match extensions.get_mut::<LayerTimings>() {
+            Some(timings) => timings.add(layer, duration),
+            None => {
+                let mut timings = LayerTimings::default();
+                timings.add(layer, duration);
+                extensions.insert(timings);
+            }
+        }
+    }
+
+    /// The time spent in the `layer`, if it was recorded
+    pub fn get(&self, layer: &str) -> Option<Duration> {
+        self.0
+            .iter()
+            .find(|(name, _)| *name == layer)
+            .map(|(_, duration)| *duration)
+    }
+
+    /// The recorded layers and their durations, in the order in which they were first recorded
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, Duration)> + '_ {
+        self.0.iter().copied()
+    }
+}
@@ -43,15 +43,60 @@
 //! ```
 
 mod adaptor;
+mod baggage;
+mod body;
+mod config;
+mod connection;
+mod deadline;
+mod decompression;
+mod deferred_body;
+mod deprecation;
 mod error;
 mod fallback;
+mod file_backend;
 mod file_stream;
+mod layer_timings;
+mod negotiated_body;
+mod no_store;
+mod precompressed;
+#[cfg(any(test, feature = "replay"))]
+mod replay;
 mod server;
+mod service;
+mod shadow;
 #[cfg(test)]
 mod tests;
+mod trace_context;
+mod transfer_mode;
+mod transferred_bytes;
 
-pub use fallback::ConduitFallback;
+pub use baggage::Baggage;
+pub use body::BodyMode;
+pub use config::{
+    ContentLengthCheck, FallbackConfig, FileSizeLimit, NotFoundResponse, ResponseHeaderLimit,
+};
+pub use connection::{track_connection_requests, ConnectionInfo, ConnectionRequests};
+pub use deadline::Deadline;
+pub use deferred_body::{DeferredBody, DeferredBodySender};
+pub use deprecation::Deprecated;
+pub use error::{RejectionReason, SentryEventId, WouldReject};
+pub use fallback::{
+    blocking_tasks_in_flight, blocking_tasks_queued, BlockingWait, ConduitFallback, HandlerThread,
+};
+pub use file_backend::{FileBackend, FileRedirect};
+pub use file_stream::{file_streams_open, FileStream, FileStreamLimit};
+pub use layer_timings::LayerTimings;
+pub use negotiated_body::{NegotiatedBody, ResponseFormat};
+pub use no_store::NoStore;
+pub use precompressed::FilePath;
+#[cfg(any(test, feature = "replay"))]
+pub use replay::replay_request;
 pub use server::Server;
+pub use service::ConduitService;
+pub use shadow::{shadow_status_mismatches, ShadowHandler};
+pub use trace_context::TraceContext;
+pub use transfer_mode::TransferMode;
+pub use transferred_bytes::TransferredBytes;
 
 type AxumResponse = axum::response::Response;
 type ConduitResponse = http::Response<conduit::Body>;
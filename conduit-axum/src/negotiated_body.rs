@@ -0,0 +1,102 @@
+use std::fmt;
+
+use conduit::{box_error, BoxError};
+use http::header::ACCEPT;
+use http::HeaderMap;
+use serde::Serialize;
+
+type Encoder = dyn Fn(ResponseFormat) -> Result<Vec<u8>, BoxError> + Send + Sync;
+
+/// A response body that is serialized as JSON or MessagePack, depending on the `Accept` header
+///
+/// Handlers can insert this into the response extensions instead of serializing the value
+/// themselves. The value is then encoded in the `ResponseFormat` that the client prefers, with a
+/// matching `Content-Type` and a `Vary: Accept` header. Any body of the conduit response itself is
+/// discarded. The chosen `ResponseFormat` is added to the response extensions, so that outer
+/// middleware (e.g. access logging) can record it.
+pub struct NegotiatedBody(Box<Encoder>);
+
+impl NegotiatedBody {
+    pub fn new<T: Serialize + Send + Sync + 'static>(value: T) -> Self {
+        Self(Box::new(move |format| format.encode(&value)))
+    }
+
+    pub(crate) fn encode(&self, format: ResponseFormat) -> Result<Vec<u8>, BoxError> {
+        (self.0)(format)
+    }
+}
+
+impl fmt::Debug for NegotiatedBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NegotiatedBody").finish_non_exhaustive()
+    }
+}
+
+/// The encoding of a `NegotiatedBody`, as negotiated via the `Accept` request header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseFormat {
+    Json,
+    MessagePack,
+}
+
+impl ResponseFormat {
+    /// Choose the format with the highest quality value in the `Accept` headers
+    ///
+    /// Formats with the same quality are preferred in the order that the client listed them.
+    /// JSON is the default, so MessagePack is only chosen if the client explicitly asks for it,
+    /// e.g. via `Accept: application/msgpack`.
+    pub fn from_accept(headers: &HeaderMap) -> Self {
+        let entries = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+
+        let mut best: Option<(Self, f32)> = None;
+        for entry in entries {
+            let mut params = entry.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default().to_ascii_lowercase();
+            let quality = params
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            let format = match media_type.as_str() {
+                "application/msgpack" | "application/x-msgpack" => Self::MessagePack,
+                "application/json" | "application/*" | "*/*" => Self::Json,
+                _ => continue,
+            };
+
+            // Only a strictly higher quality replaces an earlier entry
+            let is_better = best.map_or(true, |(_, best_quality)| quality > best_quality);
+            if quality > 0.0 && is_better {
+                best = Some((format, quality));
+            }
+        }
+
+        best.map_or(Self::Json, |(format, _)| format)
+    }
+
+    /// The name of the format, as logged in the `repr` field
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::MessagePack => "msgpack",
+        }
+    }
+
+    pub(crate) fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json; charset=utf-8",
+            Self::MessagePack => "application/msgpack",
+        }
+    }
+
+    fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, BoxError> {
+        match self {
+            Self::Json => serde_json::to_vec(value).map_err(box_error),
+            // Structs are encoded as maps with their field names, like in JSON
+            Self::MessagePack => rmp_serde::to_vec_named(value).map_err(box_error),
+        }
+    }
+}
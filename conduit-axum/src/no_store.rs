@@ -0,0 +1,15 @@
+use http::header::CACHE_CONTROL;
+use http::{HeaderMap, HeaderValue};
+
+/// A request extension that marks the response as uncacheable
+///
+/// If a handler inserts this into the request extensions, a `Cache-Control: no-store` header is
+/// added to the response, unless the handler has set a `Cache-Control` header itself.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NoStore;
+
+pub(crate) fn apply_no_store(headers: &mut HeaderMap) {
+    if !headers.contains_key(CACHE_CONTROL) {
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    }
+}
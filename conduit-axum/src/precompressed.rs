@@ -0,0 +1,59 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, VARY};
+use http::{HeaderMap, HeaderValue};
+
+/// A response extension with the path of a `File` response body
+///
+/// If `FallbackConfig::precompressed_gzip` is enabled and the client accepts gzip, a sibling of
+/// the file with an additional `.gz` extension (e.g. `foo.crate.gz` for `foo.crate`) is served
+/// instead, if it exists.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilePath(pub PathBuf);
+
+/// Open the precompressed sibling of the file at `path`, if the client accepts gzip
+///
+/// On success, the response `headers` are updated for the compressed file.
+pub(crate) fn open_precompressed(
+    path: &Path,
+    request_headers: &HeaderMap,
+    headers: &mut HeaderMap,
+) -> Option<File> {
+    if headers.contains_key(CONTENT_ENCODING) || !accepts_gzip(request_headers) {
+        return None;
+    }
+
+    let mut sibling = OsString::from(path);
+    sibling.push(".gz");
+
+    let file = File::open(sibling).ok()?;
+    let metadata = file.metadata().ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    headers.insert(CONTENT_LENGTH, metadata.len().into());
+    headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    Some(file)
+}
+
+/// Check if the `Accept-Encoding` header allows a gzip encoded response
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| {
+            let mut params = encoding.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            let is_rejected = params
+                .filter_map(|param| param.strip_prefix("q="))
+                .any(|quality| quality.parse::<f32>().ok() == Some(0.0));
+
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !is_rejected
+        })
+}
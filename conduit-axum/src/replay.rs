@@ -0,0 +1,28 @@
+use std::io::Cursor;
+use std::net::SocketAddr;
+
+use axum::body::Bytes;
+use conduit::{Handler, HandlerResult, StartInstant};
+use http::Request;
+
+use crate::adaptor::ConduitRequest;
+use crate::body::{BodyMode, RequestBody};
+
+/// Run the `handler` for a captured `request`, without a server or async runtime
+///
+/// This is meant for reproducing issues with requests that were captured in production, e.g.
+/// from the method, URI, headers and body in a log. The handler runs on the current thread with
+/// a buffered body and a remote address of `0.0.0.0:0`, and its `ConduitResponse` is returned
+/// as-is. Note that the conversion to an axum response (e.g. the `file_stream_limit` and the
+/// default `Cache-Control` headers of the `FallbackConfig`) is skipped.
+pub fn replay_request(handler: &dyn Handler, request: Request<Vec<u8>>) -> HandlerResult {
+    let (mut parts, body) = request.into_parts();
+    parts.extensions.insert(BodyMode::Buffered);
+
+    let body = RequestBody::Buffered(Cursor::new(Bytes::from(body)));
+    let request = Request::from_parts(parts, body);
+
+    let remote_addr: SocketAddr = ([0, 0, 0, 0], 0).into();
+    let mut request = ConduitRequest::new(request, remote_addr, StartInstant::now());
+    handler.call(&mut request)
+}
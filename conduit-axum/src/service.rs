@@ -0,0 +1,81 @@
+use crate::config::FallbackConfig;
+use crate::fallback::{call_conduit, remote_addr_or_unknown};
+use crate::AxumResponse;
+
+use std::convert::Infallible;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::response::IntoResponse;
+use conduit::Handler;
+use hyper::service::Service;
+use hyper::Request;
+
+/// A `tower::Service` that runs a conduit handler
+///
+/// Unlike `ConduitFallback`, which installs the handler as the fallback of an axum `Router`,
+/// this can be wrapped in arbitrary Tower layers (e.g. rate limiting or authentication) that
+/// only apply to the conduit portion of the application. The requests are handled in the same
+/// way, including the `Content-Length` checks and the execution on the blocking thread pool.
+///
+/// The remote address is taken from the `ConnectInfo<SocketAddr>` request extension, so the
+/// server should be started with `into_make_service_with_connect_info()`. Without the extension,
+/// the remote address is reported as `0.0.0.0:0` and a warning is logged once.
+#[derive(Clone)]
+pub struct ConduitService {
+    handler: Arc<dyn Handler>,
+    config: Arc<FallbackConfig>,
+}
+
+impl ConduitService {
+    pub fn new(handler: impl Handler) -> Self {
+        Self::with_config(handler, FallbackConfig::default())
+    }
+
+    pub fn with_config(handler: impl Handler, config: FallbackConfig) -> Self {
+        Self {
+            handler: Arc::new(handler),
+            config: Arc::new(config),
+        }
+    }
+}
+
+impl fmt::Debug for ConduitService {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConduitService")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Service<Request<Body>> for ConduitService {
+    type Response = AxumResponse;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<AxumResponse, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let handler = self.handler.clone();
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let remote_addr = request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|connect_info| connect_info.0);
+            let remote_addr = remote_addr_or_unknown(remote_addr);
+
+            let response = call_conduit(handler, config, remote_addr, request).await;
+            Ok(response.into_response())
+        })
+    }
+}
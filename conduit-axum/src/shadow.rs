@@ -0,0 +1,173 @@
+use std::fmt;
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use conduit::{Handler, StartInstant};
+use http::request::Parts;
+use http::{Method, Request, StatusCode};
+use tracing::{warn, Dispatch};
+
+use crate::adaptor::ConduitRequest;
+use crate::body::{BodyMode, RequestBody};
+
+static SHADOW_STATUS_MISMATCHES: AtomicUsize = AtomicUsize::new(0);
+
+/// The methods that can be shadowed, since running them a second time has no side effects
+const SAFE_METHODS: [Method; 3] = [Method::GET, Method::HEAD, Method::OPTIONS];
+
+/// The number of shadowed requests whose shadow response had a different status code
+pub fn shadow_status_mismatches() -> usize {
+    SHADOW_STATUS_MISMATCHES.load(Ordering::Relaxed)
+}
+
+/// A second handler that receives a copy of a share of the requests, see
+/// `FallbackConfig::shadow_handler`
+///
+/// Each `GET` or `HEAD` request with a buffered body is copied to the shadow handler with a
+/// probability of `weight`, see `with_methods()` for other safe methods. Requests with other
+/// methods are never shadowed, since their side effects (e.g. on the database) would be
+/// doubled. The shadow handler runs on the blocking thread pool after the primary handler has
+/// returned its response, and its own response is discarded. If the status codes of the two
+/// responses differ, a warning is logged and counted, see `shadow_status_mismatches()`.
+#[derive(Clone)]
+pub struct ShadowHandler {
+    weight: f64,
+    methods: Vec<Method>,
+    handler: Arc<dyn Handler>,
+}
+
+impl ShadowHandler {
+    /// The `weight` is clamped to the `0.0-1.0` range
+    pub fn new(weight: f64, handler: impl Handler) -> Self {
+        let weight = if weight.is_nan() {
+            0.0
+        } else {
+            weight.clamp(0.0, 1.0)
+        };
+
+        let handler = Arc::new(handler);
+        let methods = vec![Method::GET, Method::HEAD];
+        Self {
+            weight,
+            methods,
+            handler,
+        }
+    }
+
+    /// Replace the shadowed methods, which default to `GET` and `HEAD`
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the `methods` is not safe, i.e. not `GET`, `HEAD` or `OPTIONS`.
+    pub fn with_methods(mut self, methods: Vec<Method>) -> Self {
+        if let Some(method) = methods.iter().find(|m| !SAFE_METHODS.contains(m)) {
+            panic!("cannot shadow {method} requests, only safe methods can be shadowed");
+        }
+
+        self.methods = methods;
+        self
+    }
+
+    /// Decide whether to shadow a request with the `method`, given a `sample` in the `0.0..1.0`
+    /// range
+    pub(crate) fn should_shadow(&self, method: &Method, sample: f64) -> bool {
+        self.methods.contains(method) && sample < self.weight
+    }
+}
+
+impl fmt::Debug for ShadowHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShadowHandler")
+            .field("weight", &self.weight)
+            .field("methods", &self.methods)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A copy of a request for the shadow handler
+///
+/// The request extensions are not copied, since they can't be cloned.
+pub(crate) struct ShadowRequest {
+    handler: Arc<dyn Handler>,
+    parts: Parts,
+    body: Bytes,
+    remote_addr: SocketAddr,
+    dispatch: Dispatch,
+}
+
+impl ShadowRequest {
+    pub(crate) fn new(
+        shadow: &ShadowHandler,
+        parts: &Parts,
+        body: &Bytes,
+        remote_addr: SocketAddr,
+        dispatch: Dispatch,
+    ) -> Option<Self> {
+        let mut request = Request::builder()
+            .method(parts.method.clone())
+            .uri(parts.uri.clone())
+            .version(parts.version)
+            .body(())
+            .ok()?;
+        *request.headers_mut() = parts.headers.clone();
+        let (parts, _) = request.into_parts();
+
+        Some(Self {
+            handler: shadow.handler.clone(),
+            parts,
+            body: body.clone(),
+            remote_addr,
+            dispatch,
+        })
+    }
+
+    /// Run the shadow handler on the blocking thread pool, and compare its status with the
+    /// `primary` status
+    ///
+    /// This returns immediately, so that the client response is not delayed. Events are recorded
+    /// by the same subscriber as the events of the primary handler.
+    pub(crate) fn spawn(self, primary: StatusCode) {
+        tokio::task::spawn_blocking(move || {
+            let dispatch = self.dispatch.clone();
+            tracing::dispatcher::with_default(&dispatch, || self.run(primary))
+        });
+    }
+
+    fn run(self, primary: StatusCode) {
+        let Self {
+            handler,
+            mut parts,
+            body,
+            remote_addr,
+            ..
+        } = self;
+
+        let method = parts.method.clone();
+        let uri = parts.uri.clone();
+
+        parts.extensions.insert(BodyMode::Buffered);
+        let body = RequestBody::Buffered(Cursor::new(body));
+        let request = Request::from_parts(parts, body);
+
+        let mut request = ConduitRequest::new(request, remote_addr, StartInstant::now());
+        let shadow = match handler.call(&mut request) {
+            Ok(response) => response.status(),
+            Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        if shadow != primary {
+            SHADOW_STATUS_MISMATCHES.fetch_add(1, Ordering::Relaxed);
+
+            warn!(
+                %method,
+                path = %uri,
+                primary = primary.as_u16(),
+                shadow = shadow.as_u16(),
+                "Shadow handler responded with a different status"
+            );
+        }
+    }
+}
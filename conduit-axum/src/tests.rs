@@ -1,13 +1,25 @@
+use std::io::{Read, Seek, Write};
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use axum::extract::ConnectInfo;
+use axum::response::IntoResponse;
 use axum::{Extension, Router};
 use conduit::{box_error, Body, Handler, HandlerResult, RequestExt};
 use http::{HeaderValue, Request, Response, StatusCode};
 use hyper::{body::to_bytes, service::Service};
 use tokio::{sync::oneshot, task::JoinHandle};
 
-use crate::{AxumResponse, ConduitFallback};
+use crate::error::ServiceError;
+use crate::{
+    blocking_tasks_in_flight, replay_request, shadow_status_mismatches, track_connection_requests,
+    AxumResponse, Baggage, BlockingWait, BodyMode, ConduitFallback, ConduitService, ConnectionInfo,
+    ConnectionRequests, ContentLengthCheck, Deadline, DeferredBody, Deprecated, FallbackConfig,
+    FileBackend, FilePath, FileRedirect, FileSizeLimit, FileStream, FileStreamLimit, HandlerThread,
+    LayerTimings, NegotiatedBody, NoStore, NotFoundResponse, RejectionReason, ResponseFormat,
+    ResponseHeaderLimit, SentryEventId, ShadowHandler, TraceContext, TransferMode,
+    TransferredBytes, WouldReject,
+};
 
 struct OkResult;
 impl Handler for OkResult {
@@ -19,6 +31,225 @@ impl Handler for OkResult {
     }
 }
 
+struct GiantCookie;
+impl Handler for GiantCookie {
+    fn call(&self, _req: &mut dyn RequestExt) -> HandlerResult {
+        let cookie = format!("giant={}", "x".repeat(1000));
+        Response::builder()
+            .header("set-cookie", cookie)
+            .header("ok", "value")
+            .body(Body::from_static(b"Hello, world!"))
+            .map_err(box_error)
+    }
+}
+
+/// Reports the body of each call to the sender, and responds with a body that must not reach the
+/// client
+struct ShadowTeapot(std::sync::Mutex<std::sync::mpsc::Sender<String>>);
+impl Handler for ShadowTeapot {
+    fn call(&self, req: &mut dyn RequestExt) -> HandlerResult {
+        let mut body = String::new();
+        req.body().read_to_string(&mut body).map_err(box_error)?;
+        self.0.lock().unwrap().send(body).map_err(box_error)?;
+
+        Response::builder()
+            .status(StatusCode::IM_A_TEAPOT)
+            .body(Body::from_static(b"shadow"))
+            .map_err(box_error)
+    }
+}
+
+struct MultipleCookies;
+impl Handler for MultipleCookies {
+    fn call(&self, _req: &mut dyn RequestExt) -> HandlerResult {
+        Response::builder()
+            .header("set-cookie", "first=1")
+            .header("set-cookie", "second=2")
+            .body(Body::empty())
+            .map_err(box_error)
+    }
+}
+
+struct MarkNoStore(Option<&'static str>);
+impl Handler for MarkNoStore {
+    fn call(&self, req: &mut dyn RequestExt) -> HandlerResult {
+        // Marking the request twice has no additional effect
+        req.mut_extensions().insert(NoStore);
+        req.mut_extensions().insert(NoStore);
+
+        let mut builder = Response::builder();
+        if let Some(cache_control) = self.0 {
+            builder = builder.header("cache-control", cache_control);
+        }
+        builder.body(Body::empty()).map_err(box_error)
+    }
+}
+
+struct CacheForAnHour;
+impl Handler for CacheForAnHour {
+    fn call(&self, _req: &mut dyn RequestExt) -> HandlerResult {
+        Response::builder()
+            .header("cache-control", "public, max-age=3600")
+            .body(Body::empty())
+            .map_err(box_error)
+    }
+}
+
+struct HandlerNotFound;
+impl Handler for HandlerNotFound {
+    fn call(&self, _req: &mut dyn RequestExt) -> HandlerResult {
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from_static(b"No such crate"))
+            .map_err(box_error)
+    }
+}
+
+struct EchoBaggage;
+impl Handler for EchoBaggage {
+    fn call(&self, req: &mut dyn RequestExt) -> HandlerResult {
+        let baggage = req.extensions().get::<Baggage>();
+        let tenant = baggage.and_then(|baggage| baggage.get("tenant"));
+        let body = tenant.unwrap_or("<none>").to_string();
+        Response::builder()
+            .body(Body::from_vec(body.into_bytes()))
+            .map_err(box_error)
+    }
+}
+
+struct EchoTraceContext;
+impl Handler for EchoTraceContext {
+    fn call(&self, req: &mut dyn RequestExt) -> HandlerResult {
+        let body = match req.extensions().get::<TraceContext>() {
+            Some(context) => format!(
+                "{} {:?}",
+                context.child([0, 1, 2, 3, 4, 5, 6, 7]).traceparent(),
+                context.tracestate()
+            ),
+            None => "<none>".to_string(),
+        };
+        Response::builder()
+            .body(Body::from_vec(body.into_bytes()))
+            .map_err(box_error)
+    }
+}
+
+struct EchoLayerHeader;
+impl Handler for EchoLayerHeader {
+    fn call(&self, req: &mut dyn RequestExt) -> HandlerResult {
+        let header = req.headers().get("x-layer");
+        let header = header.and_then(|value| value.to_str().ok());
+        let body = header.unwrap_or("<none>").to_string();
+        Response::builder()
+            .body(Body::from_vec(body.into_bytes()))
+            .map_err(box_error)
+    }
+}
+
+/// Serves the file for requests to `/file`, and a static response otherwise
+struct ServeFile(std::path::PathBuf);
+impl Handler for ServeFile {
+    fn call(&self, req: &mut dyn RequestExt) -> HandlerResult {
+        if req.path() != "/file" {
+            return OkResult.call(req);
+        }
+
+        let file = std::fs::File::open(&self.0).map_err(box_error)?;
+        Response::builder()
+            .body(Body::File(file))
+            .map_err(box_error)
+    }
+}
+
+/// Serves the file with a `FilePath` extension, so that a precompressed sibling can be used
+struct ServeCrateFile(std::path::PathBuf);
+impl Handler for ServeCrateFile {
+    fn call(&self, _req: &mut dyn RequestExt) -> HandlerResult {
+        let file = std::fs::File::open(&self.0).map_err(box_error)?;
+        Response::builder()
+            .header("content-length", 5)
+            .extension(FilePath(self.0.clone()))
+            .body(Body::File(file))
+            .map_err(box_error)
+    }
+}
+
+struct ReportBodyMode;
+impl Handler for ReportBodyMode {
+    fn call(&self, req: &mut dyn RequestExt) -> HandlerResult {
+        let mode = *req
+            .extensions()
+            .get::<BodyMode>()
+            .expect("missing body mode");
+        let content_length = req.content_length();
+
+        let mut body = String::new();
+        req.body().read_to_string(&mut body).map_err(box_error)?;
+
+        let body = format!("{mode:?} {content_length:?} {body}");
+        Response::builder()
+            .body(Body::from_vec(body.into_bytes()))
+            .map_err(box_error)
+    }
+}
+
+struct EchoBody;
+impl Handler for EchoBody {
+    fn call(&self, req: &mut dyn RequestExt) -> HandlerResult {
+        let mut body = Vec::new();
+        req.body().read_to_end(&mut body).map_err(box_error)?;
+
+        Response::builder()
+            .body(Body::from_vec(body))
+            .map_err(box_error)
+    }
+}
+
+struct EchoRemoteAddr;
+impl Handler for EchoRemoteAddr {
+    fn call(&self, req: &mut dyn RequestExt) -> HandlerResult {
+        let body = req.remote_addr().to_string();
+        Response::builder()
+            .body(Body::from_vec(body.into_bytes()))
+            .map_err(box_error)
+    }
+}
+
+/// Leaves the encoding of the response body to the `Accept` header of the request
+struct Summary;
+impl Handler for Summary {
+    fn call(&self, _req: &mut dyn RequestExt) -> HandlerResult {
+        let body = NegotiatedBody::new(serde_json::json!({ "num_crates": 42 }));
+        Response::builder()
+            .extension(body)
+            .body(Body::empty())
+            .map_err(box_error)
+    }
+}
+
+/// Produces the response body on a background thread, or drops it if `payload` is `None`
+struct SlowReport(Option<&'static str>);
+impl Handler for SlowReport {
+    fn call(&self, _req: &mut dyn RequestExt) -> HandlerResult {
+        let (body, sender) = DeferredBody::new(Duration::from_millis(10));
+
+        let payload = self.0;
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            if let Some(payload) = payload {
+                sender.send(payload);
+            }
+        });
+
+        let mut response = Response::builder()
+            .header("content-type", "application/json")
+            .body(Body::empty())
+            .map_err(box_error)?;
+        response.extensions_mut().insert(body);
+        Ok(response)
+    }
+}
+
 struct ErrorResult;
 impl Handler for ErrorResult {
     fn call(&self, _req: &mut dyn RequestExt) -> HandlerResult {
@@ -27,6 +258,14 @@ impl Handler for ErrorResult {
     }
 }
 
+struct DescriptiveError;
+impl Handler for DescriptiveError {
+    fn call(&self, _req: &mut dyn RequestExt) -> HandlerResult {
+        let error = ::std::io::Error::new(::std::io::ErrorKind::Other, "the database is on fire");
+        Err(Box::new(error))
+    }
+}
+
 struct Panic;
 impl Handler for Panic {
     fn call(&self, _req: &mut dyn RequestExt) -> HandlerResult {
@@ -73,11 +312,47 @@ impl Handler for AssertPercentDecodedPath {
     }
 }
 
+struct AssertInFlight;
+impl Handler for AssertInFlight {
+    fn call(&self, req: &mut dyn RequestExt) -> HandlerResult {
+        // Other tests may run handlers concurrently, so only a lower bound can be checked
+        if blocking_tasks_in_flight() >= 1 {
+            OkResult.call(req)
+        } else {
+            ErrorResult.call(req)
+        }
+    }
+}
+
+struct AssertDeadline;
+impl Handler for AssertDeadline {
+    fn call(&self, req: &mut dyn RequestExt) -> HandlerResult {
+        let deadline = *req
+            .extensions()
+            .get::<Deadline>()
+            .expect("missing deadline");
+
+        let first = deadline.remaining();
+        std::thread::sleep(Duration::from_millis(10));
+        let second = deadline.remaining();
+
+        if second < first && !deadline.is_expired() {
+            OkResult.call(req)
+        } else {
+            ErrorResult.call(req)
+        }
+    }
+}
+
 fn make_service<H: Handler>(handler: H) -> Router {
+    make_service_with_config(handler, FallbackConfig::default())
+}
+
+fn make_service_with_config<H: Handler>(handler: H, config: FallbackConfig) -> Router {
     let remote_addr: SocketAddr = ([0, 0, 0, 0], 0).into();
 
     Router::new()
-        .conduit_fallback(handler)
+        .conduit_fallback_with_config(handler, config)
         .layer(Extension(ConnectInfo(remote_addr)))
 }
 
@@ -108,6 +383,87 @@ async fn valid_ok_response() {
     assert_eq!(&*full_body, b"Hello, world!");
 }
 
+#[tokio::test]
+async fn conduit_service_composes_with_tower_layers() {
+    use tower::{ServiceBuilder, ServiceExt};
+
+    let remote_addr: SocketAddr = ([0, 0, 0, 0], 0).into();
+    let service = ServiceBuilder::new()
+        .map_request(move |mut request: Request<hyper::Body>| {
+            request.extensions_mut().insert(ConnectInfo(remote_addr));
+            request
+                .headers_mut()
+                .insert("x-layer", HeaderValue::from_static("outer"));
+            request
+        })
+        .map_response(|mut response: AxumResponse| {
+            let headers = response.headers_mut();
+            headers.insert("x-layered", HeaderValue::from_static("yes"));
+            response
+        })
+        .service(ConduitService::new(EchoLayerHeader));
+
+    let resp = service.clone().oneshot(Request::default()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["x-layered"], "yes");
+    assert!(resp.extensions().get::<HandlerThread>().is_some());
+    let full_body = to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(&*full_body, b"outer");
+
+    // The `Content-Length` check still applies
+    let req = Request::builder()
+        .header(hyper::header::CONTENT_LENGTH, "not a number")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let resp = service.oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(resp.headers()["x-layered"], "yes");
+}
+
+#[tokio::test]
+async fn handler_thread_is_recorded() {
+    let resp = simulate_request(OkResult).await;
+    let thread = resp.extensions().get::<HandlerThread>().unwrap();
+    assert_ne!(thread.0.id(), std::thread::current().id());
+
+    // The thread is recorded for error responses too
+    let resp = simulate_request(ErrorResult).await;
+    assert!(resp.extensions().get::<HandlerThread>().is_some());
+}
+
+#[tokio::test]
+async fn blocking_wait_is_recorded() {
+    let resp = simulate_request(AssertInFlight).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.extensions().get::<BlockingWait>().is_some());
+
+    // The wait is recorded for error responses too
+    let resp = simulate_request(ErrorResult).await;
+    assert!(resp.extensions().get::<BlockingWait>().is_some());
+}
+
+#[tokio::test]
+async fn repeated_headers_are_preserved() {
+    let resp = simulate_request(MultipleCookies).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let cookies: Vec<_> = resp.headers().get_all("set-cookie").iter().collect();
+    assert_eq!(cookies, vec!["first=1", "second=2"]);
+}
+
+#[tokio::test]
+async fn no_store_responses() {
+    let resp = simulate_request(MarkNoStore(None)).await;
+    let cache_control: Vec<_> = resp.headers().get_all("cache-control").iter().collect();
+    assert_eq!(cache_control, vec!["no-store"]);
+
+    let resp = simulate_request(MarkNoStore(Some("public, max-age=60"))).await;
+    let cache_control: Vec<_> = resp.headers().get_all("cache-control").iter().collect();
+    assert_eq!(cache_control, vec!["public, max-age=60"]);
+
+    let resp = simulate_request(OkResult).await;
+    assert!(resp.headers().get("cache-control").is_none());
+}
+
 #[tokio::test]
 async fn invalid_ok_responses() {
     assert_generic_err(simulate_request(InvalidHeader).await).await;
@@ -119,6 +475,59 @@ async fn err_responses() {
     assert_generic_err(simulate_request(ErrorResult).await).await;
 }
 
+#[tokio::test]
+async fn sentry_event_id_is_recorded() {
+    use sentry_core::test::TestTransport;
+    use sentry_core::{ClientOptions, Hub, SentryFutureExt};
+    use std::sync::Arc;
+
+    let transport = TestTransport::new();
+    let options = ClientOptions {
+        dsn: Some("https://public@sentry.invalid/1".parse().unwrap()),
+        transport: Some(Arc::new(transport.clone())),
+        ..Default::default()
+    };
+    let hub = Arc::new(Hub::new(
+        Some(Arc::new(options.into())),
+        Arc::new(Default::default()),
+    ));
+
+    let mut service = make_service(ErrorResult);
+    let request = service.call(Request::default()).bind_hub(hub);
+    let resp = request.await.unwrap();
+    assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    let events = transport.fetch_and_clear_events();
+    assert_eq!(events.len(), 1);
+    let event_id = resp.extensions().get::<SentryEventId>().unwrap();
+    assert_eq!(event_id.0, events[0].event_id);
+
+    // Without a Sentry client the error is not captured
+    let resp = simulate_request(ErrorResult).await;
+    assert!(resp.extensions().get::<SentryEventId>().is_none());
+}
+
+#[tokio::test]
+#[cfg(debug_assertions)]
+async fn verbose_err_responses() {
+    let config = FallbackConfig {
+        verbose_errors: true,
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(DescriptiveError, config);
+    let resp = service.call(Request::default()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    let full_body = to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(
+        &*full_body,
+        b"Internal Server Error: the database is on fire"
+    );
+
+    // Errors are only included in the response body if enabled
+    assert_generic_err(simulate_request(DescriptiveError).await).await;
+}
+
 #[ignore] // catch_unwind not yet implemented
 #[tokio::test]
 async fn recover_from_panic() {
@@ -174,6 +583,44 @@ async fn spawn_http_server() -> (
     (url, tokio::spawn(server), quit_tx)
 }
 
+struct EchoConnectionRequests;
+impl Handler for EchoConnectionRequests {
+    fn call(&self, req: &mut dyn RequestExt) -> HandlerResult {
+        let requests = req.extensions().get::<ConnectionRequests>().unwrap();
+        let body = requests.0.to_string().into_bytes();
+        Response::builder()
+            .body(Body::from_vec(body))
+            .map_err(box_error)
+    }
+}
+
+#[tokio::test]
+async fn connection_requests_are_counted() {
+    let addr = ([127, 0, 0, 1], 0).into();
+    let router = Router::new()
+        .conduit_fallback(EchoConnectionRequests)
+        .layer(axum::middleware::from_fn(track_connection_requests));
+    let make_service = router.into_make_service_with_connect_info::<ConnectionInfo>();
+    let server = hyper::Server::bind(&addr).serve(make_service);
+    let url: hyper::Uri = format!("http://{}", server.local_addr()).parse().unwrap();
+    let server = tokio::spawn(server);
+
+    // The client reuses its keep-alive connection for the second request
+    let client = hyper::Client::new();
+    for expected in ["1", "2"] {
+        let resp = client.get(url.clone()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(to_bytes(resp.into_body()).await.unwrap(), expected);
+    }
+
+    // A new connection starts counting from the beginning
+    let client = hyper::Client::new();
+    let resp = client.get(url).await.unwrap();
+    assert_eq!(to_bytes(resp.into_body()).await.unwrap(), "1");
+
+    server.abort();
+}
+
 #[tokio::test]
 async fn content_length_too_large() {
     const ACTUAL_BODY_SIZE: usize = 10_000;
@@ -202,3 +649,1189 @@ async fn content_length_too_large() {
 
     assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
 }
+
+#[tokio::test]
+async fn content_length_rejection_reason() {
+    let mut service = make_service(OkResult);
+    let req = hyper::Request::put("/")
+        .header(hyper::header::CONTENT_LENGTH, "not a number")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        resp.extensions().get::<RejectionReason>(),
+        Some(&RejectionReason("Content-Length not a u64".into()))
+    );
+}
+
+#[tokio::test]
+async fn content_length_monitor_mode() {
+    let config = FallbackConfig {
+        content_length_check: ContentLengthCheck::Monitor { limit: 10 },
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(OkResult, config);
+
+    let req = hyper::Request::put("/")
+        .header(hyper::header::CONTENT_LENGTH, 100)
+        .body(hyper::Body::from(vec![0; 100]))
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.extensions().get::<WouldReject>(),
+        Some(&WouldReject("Content-Length 100 exceeds 10".into()))
+    );
+
+    let req = hyper::Request::put("/")
+        .header(hyper::header::CONTENT_LENGTH, 10)
+        .body(hyper::Body::from(vec![0; 10]))
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.extensions().get::<WouldReject>(), None);
+}
+
+#[tokio::test]
+async fn service_error_statuses() {
+    let join_error = tokio::spawn(async { panic!() }).await.unwrap_err();
+    let error = ServiceError::JoinError(join_error);
+    assert_eq!(
+        error.into_response().status(),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+
+    let (sender, body) = hyper::Body::channel();
+    sender.abort();
+    let hyper_error = to_bytes(body).await.unwrap_err();
+    let error = ServiceError::BodyReadAborted(hyper_error);
+    assert_eq!(error.into_response().status(), StatusCode::BAD_REQUEST);
+
+    let error = ServiceError::RequestTimeout;
+    let resp = error.into_response();
+    assert_eq!(resp.status(), StatusCode::REQUEST_TIMEOUT);
+    assert_eq!(
+        resp.extensions().get::<RejectionReason>(),
+        Some(&RejectionReason("Request timed out".into()))
+    );
+
+    let error = ServiceError::BodyReadTimeout;
+    assert_eq!(error.into_response().status(), StatusCode::REQUEST_TIMEOUT);
+
+    let error = ServiceError::PayloadTooLarge;
+    assert_eq!(
+        error.into_response().status(),
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+}
+
+#[tokio::test]
+async fn handler_observes_remaining_deadline() {
+    let config = FallbackConfig {
+        request_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(AssertDeadline, config);
+    let resp = service.call(Request::default()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn request_timeout_exceeded() {
+    let config = FallbackConfig {
+        request_timeout: Some(Duration::from_millis(10)),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(Sleep, config);
+    let resp = service.call(Request::default()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::REQUEST_TIMEOUT);
+}
+
+#[tokio::test]
+async fn body_read_timeout_exceeded() {
+    let config = FallbackConfig {
+        body_read_timeout: Some(Duration::from_millis(50)),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(OkResult, config);
+
+    // The client sends the first chunk of the body, but then stalls
+    let (mut sender, body) = hyper::Body::channel();
+    let trickle = tokio::spawn(async move {
+        sender.send_data(vec![0; 10].into()).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let _ = sender.send_data(vec![0; 10].into()).await;
+    });
+
+    let req = hyper::Request::put("/").body(body).unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::REQUEST_TIMEOUT);
+    assert_eq!(
+        resp.extensions().get::<RejectionReason>(),
+        Some(&RejectionReason(
+            "Timed out reading the request body".into()
+        ))
+    );
+
+    trickle.abort();
+}
+
+#[tokio::test]
+async fn file_stream_does_not_read_ahead_of_slow_consumer() {
+    use crate::file_stream::BUFFER_SIZE;
+    use futures_util::StreamExt;
+
+    let data = vec![0; 10 * BUFFER_SIZE];
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(&data).unwrap();
+
+    // The cloned handle shares the file offset, which reveals how far the stream has read
+    let std_file = std::fs::File::open(file.path()).unwrap();
+    let mut position = std_file.try_clone().unwrap();
+    let mut stream = FileStream::from_std(std_file);
+
+    let chunk = stream.next().await.unwrap().unwrap();
+    assert_eq!(chunk.len(), BUFFER_SIZE);
+
+    // Simulate a slow consumer that doesn't poll the stream for a while
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(position.stream_position().unwrap(), BUFFER_SIZE as u64);
+
+    let chunk = stream.next().await.unwrap().unwrap();
+    assert_eq!(chunk.len(), BUFFER_SIZE);
+    assert_eq!(position.stream_position().unwrap(), 2 * BUFFER_SIZE as u64);
+}
+
+/// Collects the output of a `tracing_subscriber::fmt()` subscriber
+#[derive(Clone, Default)]
+struct LogBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl LogBuffer {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn client_disconnects_during_file_stream_are_benign() {
+    use crate::file_stream::BUFFER_SIZE;
+    use hyper::body::HttpBody;
+
+    let logs = LogBuffer::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_max_level(tracing::Level::DEBUG)
+        .with_ansi(false)
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(&vec![0; 10 * BUFFER_SIZE]).unwrap();
+
+    let mut service = make_service(ServeFile(file.path().into()));
+    let req = Request::get("/file").body(hyper::Body::empty()).unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // The client receives the first chunk and then goes away
+    let mut body = resp.into_body();
+    let chunk = body.data().await.unwrap().unwrap();
+    assert_eq!(chunk.len(), BUFFER_SIZE);
+    drop(body);
+
+    let logs = logs.contents();
+    assert!(logs.contains("client_disconnect=true"), "{logs}");
+    assert!(logs.contains(&format!("bytes={BUFFER_SIZE}")), "{logs}");
+    assert!(!logs.contains("ERROR"), "{logs}");
+}
+
+#[tokio::test]
+async fn complete_file_streams_are_not_client_disconnects() {
+    use hyper::body::HttpBody;
+
+    let logs = LogBuffer::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_max_level(tracing::Level::DEBUG)
+        .with_ansi(false)
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(b"plain").unwrap();
+
+    // The handler sets a `Content-Length` of 5 bytes
+    let mut service = make_service(ServeCrateFile(file.path().into()));
+    let req = Request::get("/file").body(hyper::Body::empty()).unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Like hyper, the client stops polling once it has read `Content-Length` bytes
+    let mut body = resp.into_body();
+    let chunk = body.data().await.unwrap().unwrap();
+    assert_eq!(&*chunk, b"plain");
+    drop(body);
+
+    let logs = logs.contents();
+    assert!(!logs.contains("client_disconnect"), "{logs}");
+}
+
+#[tokio::test]
+async fn file_stream_constructors_stream_identical_bytes() {
+    // Larger than the internal buffer, so that multiple chunks are read
+    let data: Vec<u8> = (0..20_000u32).map(|i| i as u8).collect();
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(&data).unwrap();
+
+    let std_file = std::fs::File::open(file.path()).unwrap();
+    let std_body = FileStream::from_std(std_file).into_streamed_body();
+    let std_bytes = to_bytes(std_body).await.unwrap();
+
+    let tokio_file = tokio::fs::File::open(file.path()).await.unwrap();
+    let tokio_body = FileStream::from_tokio(tokio_file).into_streamed_body();
+    let tokio_bytes = to_bytes(tokio_body).await.unwrap();
+
+    assert_eq!(std_bytes, data);
+    assert_eq!(tokio_bytes, std_bytes);
+}
+
+#[tokio::test]
+async fn oversized_files_are_rejected_or_truncated() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(&[b'x'; 100]).unwrap();
+
+    let file_request = || Request::get("/file").body(hyper::Body::empty()).unwrap();
+
+    let config = FallbackConfig {
+        max_file_size: Some(FileSizeLimit::Reject(10)),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(ServeFile(file.path().into()), config);
+    let resp = service.call(file_request()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(
+        resp.extensions().get::<RejectionReason>(),
+        Some(&RejectionReason(
+            "File body of 100 bytes exceeds the limit of 10 bytes".into()
+        ))
+    );
+    assert_eq!(
+        resp.extensions().get::<FileBackend>(),
+        Some(&FileBackend::Local)
+    );
+
+    let config = FallbackConfig {
+        max_file_size: Some(FileSizeLimit::Truncate(10)),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(ServeFile(file.path().into()), config);
+    let resp = service.call(file_request()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-length"], "10");
+    assert_eq!(to_bytes(resp.into_body()).await.unwrap(), [b'x'; 10][..]);
+
+    // Files within the limit are streamed as usual
+    let config = FallbackConfig {
+        max_file_size: Some(FileSizeLimit::Reject(100)),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(ServeFile(file.path().into()), config);
+    let resp = service.call(file_request()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(to_bytes(resp.into_body()).await.unwrap().len(), 100);
+}
+
+#[tokio::test]
+async fn oversized_response_headers_are_rejected_or_stripped() {
+    let config = FallbackConfig {
+        max_response_header_size: Some(ResponseHeaderLimit::Reject(100)),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(GiantCookie, config);
+    let resp = service.call(Request::default()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(resp.headers().get("set-cookie").is_none());
+    let reason = resp.extensions().get::<RejectionReason>().unwrap();
+    assert!(
+        reason.0.ends_with("exceed the limit of 100 bytes"),
+        "{}",
+        reason.0
+    );
+
+    let config = FallbackConfig {
+        max_response_header_size: Some(ResponseHeaderLimit::Strip(100)),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(GiantCookie, config);
+    let resp = service.call(Request::default()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get("set-cookie").is_none());
+    assert_eq!(resp.headers()["ok"], "value");
+    assert_eq!(to_bytes(resp.into_body()).await.unwrap(), "Hello, world!");
+
+    // Headers within the limit are passed through
+    let config = FallbackConfig {
+        max_response_header_size: Some(ResponseHeaderLimit::Reject(2000)),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(GiantCookie, config);
+    let resp = service.call(Request::default()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get("set-cookie").is_some());
+}
+
+#[tokio::test]
+async fn sampled_requests_are_shadowed() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let shadow = ShadowHandler::new(1.0, ShadowTeapot(std::sync::Mutex::new(sender)));
+    let mismatches = shadow_status_mismatches();
+
+    let config = FallbackConfig {
+        shadow_handler: Some(shadow),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(OkResult, config);
+    let req = Request::get("/")
+        .body(hyper::Body::from("payload"))
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["ok"], "value");
+    assert_eq!(to_bytes(resp.into_body()).await.unwrap(), "Hello, world!");
+
+    let shadowed = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(shadowed, "payload");
+
+    // The mismatch is counted after the shadow handler returned
+    for _ in 0..100 {
+        if shadow_status_mismatches() > mismatches {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(shadow_status_mismatches() > mismatches);
+
+    // Requests are not shadowed with a weight of zero
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let shadow = ShadowHandler::new(0.0, ShadowTeapot(std::sync::Mutex::new(sender)));
+    let config = FallbackConfig {
+        shadow_handler: Some(shadow),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(OkResult, config);
+    let resp = service.call(Request::default()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+}
+
+#[tokio::test]
+async fn unsafe_requests_are_never_shadowed() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let shadow = ShadowHandler::new(1.0, ShadowTeapot(std::sync::Mutex::new(sender)));
+    let config = FallbackConfig {
+        shadow_handler: Some(shadow),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(OkResult, config);
+
+    let req = Request::put("/api/v1/crates/new")
+        .body(hyper::Body::from("payload"))
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+}
+
+#[test]
+#[should_panic(expected = "cannot shadow PUT requests")]
+fn unsafe_methods_cannot_be_shadowed() {
+    let (sender, _receiver) = std::sync::mpsc::channel();
+    let shadow = ShadowHandler::new(1.0, ShadowTeapot(std::sync::Mutex::new(sender)));
+    shadow.with_methods(vec![http::Method::GET, http::Method::PUT]);
+}
+
+#[test]
+fn captured_requests_are_replayed() {
+    let request = Request::put("/api/v1/crates/new?dry_run=1")
+        .header(hyper::header::CONTENT_TYPE, "text/plain")
+        .body(b"payload".to_vec())
+        .unwrap();
+
+    let response = replay_request(&ReportBodyMode, request).unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(matches!(
+        response.body(),
+        Body::Owned(body) if body == b"Buffered Some(7) payload"
+    ));
+
+    let request = Request::get("/").body(Vec::new()).unwrap();
+    let response = replay_request(&OkResult, request).unwrap();
+    assert_eq!(response.headers()["ok"], "value");
+}
+
+#[tokio::test]
+async fn transfer_mode_is_recorded() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(b"Hello").unwrap();
+
+    let transfer_mode = |resp: &AxumResponse| resp.extensions().get::<TransferMode>().copied();
+
+    let mut service = make_service(ServeFile(file.path().into()));
+    let req = Request::get("/file").body(hyper::Body::empty()).unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(transfer_mode(&resp), Some(TransferMode::Chunked));
+
+    let resp = service.call(Request::default()).await.unwrap();
+    assert_eq!(transfer_mode(&resp), Some(TransferMode::ContentLength));
+
+    // `File` bodies with a `Content-Length` header are not chunked
+    let mut service = make_service(ServeCrateFile(file.path().into()));
+    let resp = service.call(Request::default()).await.unwrap();
+    assert_eq!(transfer_mode(&resp), Some(TransferMode::ContentLength));
+}
+
+#[tokio::test]
+async fn file_redirects_are_weighted() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(b"local").unwrap();
+
+    let file_request = || Request::get("/file").body(hyper::Body::empty()).unwrap();
+
+    let config = FallbackConfig {
+        file_redirect: Some(FileRedirect::new(1.0, "https://static.example.com/")),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(ServeFile(file.path().into()), config);
+    for _ in 0..20 {
+        let resp = service.call(file_request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        assert_eq!(
+            resp.headers()["location"],
+            "https://static.example.com/file"
+        );
+        assert_eq!(
+            resp.extensions().get::<FileBackend>(),
+            Some(&FileBackend::Redirect)
+        );
+        assert!(to_bytes(resp.into_body()).await.unwrap().is_empty());
+    }
+
+    let config = FallbackConfig {
+        file_redirect: Some(FileRedirect::new(0.0, "https://static.example.com")),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(ServeFile(file.path().into()), config);
+    for _ in 0..20 {
+        let resp = service.call(file_request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.extensions().get::<FileBackend>(),
+            Some(&FileBackend::Local)
+        );
+        assert_eq!(to_bytes(resp.into_body()).await.unwrap(), "local");
+    }
+
+    // Responses without a `File` body are not affected
+    let resp = service.call(Request::default()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.extensions().get::<FileBackend>().is_none());
+}
+
+#[tokio::test]
+async fn concurrent_file_streams_are_limited() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(b"file contents").unwrap();
+
+    let config = FallbackConfig {
+        file_stream_limit: Some(FileStreamLimit::new(2)),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(ServeFile(file.path().into()), config);
+
+    let file_request = || Request::get("/file").body(hyper::Body::empty()).unwrap();
+
+    // The bodies of these responses are not consumed yet, so their streams are still open
+    let first = service.call(file_request()).await.unwrap();
+    assert_eq!(first.status(), StatusCode::OK);
+    let second = service.call(file_request()).await.unwrap();
+    assert_eq!(second.status(), StatusCode::OK);
+
+    let resp = service.call(file_request()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(resp.headers()["retry-after"], "1");
+    assert_eq!(
+        resp.extensions().get::<RejectionReason>(),
+        Some(&RejectionReason("Too many concurrent file streams".into()))
+    );
+    assert_eq!(
+        resp.extensions().get::<FileBackend>(),
+        Some(&FileBackend::Local)
+    );
+
+    // Responses without a file body are not affected
+    let req = Request::get("/static").body(hyper::Body::empty()).unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let full_body = to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(&*full_body, b"Hello, world!");
+
+    // Finishing a stream releases its slot
+    let full_body = to_bytes(first.into_body()).await.unwrap();
+    assert_eq!(&*full_body, b"file contents");
+    let resp = service.call(file_request()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    drop(second);
+}
+
+#[tokio::test]
+async fn precompressed_siblings_are_served_to_gzip_clients() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("foo-1.0.0.crate");
+    std::fs::write(&path, b"plain").unwrap();
+    std::fs::write(dir.path().join("foo-1.0.0.crate.gz"), b"compressed").unwrap();
+
+    let config = FallbackConfig {
+        precompressed_gzip: true,
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(ServeCrateFile(path), config);
+
+    let req = Request::get("/")
+        .header("accept-encoding", "deflate, gzip")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-encoding"], "gzip");
+    assert_eq!(resp.headers()["content-length"], "10");
+    assert_eq!(resp.headers()["vary"], "accept-encoding");
+    let full_body = to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(&*full_body, b"compressed");
+
+    // Clients that don't accept gzip receive the plain file
+    for accept_encoding in ["br", "gzip;q=0"] {
+        let req = Request::get("/")
+            .header("accept-encoding", accept_encoding)
+            .body(hyper::Body::empty())
+            .unwrap();
+        let resp = service.call(req).await.unwrap();
+        assert!(resp.headers().get("content-encoding").is_none());
+        assert_eq!(resp.headers()["content-length"], "5");
+        let full_body = to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(&*full_body, b"plain");
+    }
+
+    // Without a sibling, the plain file is served to gzip clients too
+    std::fs::remove_file(dir.path().join("foo-1.0.0.crate.gz")).unwrap();
+    let req = Request::get("/")
+        .header("accept-encoding", "gzip")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert!(resp.headers().get("content-encoding").is_none());
+    let full_body = to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(&*full_body, b"plain");
+}
+
+#[tokio::test]
+async fn precompressed_siblings_are_opt_in() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("foo-1.0.0.crate");
+    std::fs::write(&path, b"plain").unwrap();
+    std::fs::write(dir.path().join("foo-1.0.0.crate.gz"), b"compressed").unwrap();
+
+    let mut service = make_service(ServeCrateFile(path));
+    let req = Request::get("/")
+        .header("accept-encoding", "gzip")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert!(resp.headers().get("content-encoding").is_none());
+    let full_body = to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(&*full_body, b"plain");
+}
+
+#[tokio::test]
+async fn deprecated_routes_get_a_warning_header() {
+    let mut router = conduit_router::RouteBuilder::new();
+    router.get("/ok", OkResult);
+    router.get("/old/:name", OkResult);
+
+    let mut config = FallbackConfig::default();
+    config.deprecated_routes.insert(
+        "/old/:name".into(),
+        r#"Deprecated endpoint, use "/ok" instead"#.into(),
+    );
+    let mut service = make_service_with_config(router, config);
+
+    let req = Request::get("/old/foo").body(hyper::Body::empty()).unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.headers()["warning"],
+        r#"299 - "Deprecated endpoint, use \"/ok\" instead""#
+    );
+    assert_eq!(
+        resp.extensions().get::<Deprecated>(),
+        Some(&Deprecated(
+            r#"Deprecated endpoint, use "/ok" instead"#.into()
+        ))
+    );
+
+    let req = Request::get("/ok").body(hyper::Body::empty()).unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get("warning").is_none());
+    assert!(resp.extensions().get::<Deprecated>().is_none());
+}
+
+#[tokio::test]
+async fn cache_control_defaults_apply_to_get_requests() {
+    let mut router = conduit_router::RouteBuilder::new();
+    router.get("/summary", OkResult);
+    router.post("/summary", OkResult);
+    router.get("/cached", CacheForAnHour);
+    router.get("/uncached", MarkNoStore(None));
+
+    let mut config = FallbackConfig::default();
+    let max_age = HeaderValue::from_static("public, max-age=60");
+    for pattern in ["/summary", "/cached", "/uncached"] {
+        let defaults = &mut config.cache_control_defaults;
+        defaults.insert(pattern.into(), max_age.clone());
+    }
+    let mut service = make_service_with_config(router, config);
+
+    let req = Request::get("/summary").body(hyper::Body::empty()).unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.headers()["cache-control"], "public, max-age=60");
+
+    // Values set by the handler are preserved
+    let req = Request::get("/cached").body(hyper::Body::empty()).unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.headers()["cache-control"], "public, max-age=3600");
+
+    let req = Request::get("/uncached")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.headers()["cache-control"], "no-store");
+
+    let req = Request::post("/summary")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get("cache-control").is_none());
+}
+
+#[tokio::test]
+async fn canonical_not_found_response() {
+    let mut router = conduit_router::RouteBuilder::new();
+    router.get("/ok", OkResult);
+    router.get("/crates/:name", HandlerNotFound);
+
+    let config = FallbackConfig {
+        not_found_response: Some(NotFoundResponse::json(
+            r#"{"errors":[{"detail":"Not Found"}]}"#,
+        )),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(router, config);
+
+    let req = Request::get("/does-not-exist")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        resp.headers().get("content-type"),
+        Some(&HeaderValue::from_static("application/json; charset=utf-8"))
+    );
+    let full_body = to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(&*full_body, br#"{"errors":[{"detail":"Not Found"}]}"#);
+
+    // 404 responses of the handler itself are passed through as-is
+    let req = Request::get("/crates/foo")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let full_body = to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(&*full_body, b"No such crate");
+
+    let req = Request::get("/ok").body(hyper::Body::empty()).unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn unknown_routes_without_canonical_not_found_response() {
+    let mut router = conduit_router::RouteBuilder::new();
+    router.get("/ok", OkResult);
+
+    let mut service = make_service(router);
+    let req = Request::get("/does-not-exist")
+        .body(hyper::Body::empty())
+        .unwrap();
+    assert_generic_err(service.call(req).await.unwrap()).await;
+}
+
+#[tokio::test]
+async fn baggage_is_available_to_handlers() {
+    async fn tenant(baggage: &[&'static str]) -> String {
+        let mut service = make_service(EchoBaggage);
+        let mut req = Request::get("/");
+        for value in baggage {
+            req = req.header("baggage", *value);
+        }
+        let req = req.body(hyper::Body::empty()).unwrap();
+        let resp = service.call(req).await.unwrap();
+        let full_body = to_bytes(resp.into_body()).await.unwrap();
+        String::from_utf8(full_body.to_vec()).unwrap()
+    }
+
+    assert_eq!(tenant(&["tenant=acme%20corp"]).await, "acme corp");
+    assert_eq!(tenant(&["user=1, tenant = acme;ttl=60"]).await, "acme");
+    assert_eq!(tenant(&["user=1", "tenant=acme"]).await, "acme");
+
+    // Malformed list members are skipped
+    assert_eq!(tenant(&["garbage,tenant=acme,=nokey"]).await, "acme");
+    assert_eq!(tenant(&["tenant"]).await, "<none>");
+    assert_eq!(tenant(&["ten ant=acme"]).await, "<none>");
+    assert_eq!(tenant(&["tenant=%ff"]).await, "<none>");
+    assert_eq!(tenant(&[]).await, "<none>");
+}
+
+#[tokio::test]
+async fn trace_context_is_available_to_handlers() {
+    async fn context(headers: &[(&'static str, &'static str)]) -> String {
+        let mut service = make_service(EchoTraceContext);
+        let mut req = Request::get("/");
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let req = req.body(hyper::Body::empty()).unwrap();
+        let resp = service.call(req).await.unwrap();
+        let full_body = to_bytes(resp.into_body()).await.unwrap();
+        String::from_utf8(full_body.to_vec()).unwrap()
+    }
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    // The child keeps the trace ID and the sampling decision, with a new parent ID
+    let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    assert_eq!(
+        context(&[("traceparent", traceparent)]).await,
+        format!("00-{TRACE_ID}-0001020304050607-01 None")
+    );
+
+    let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00";
+    let tracestate = [
+        ("tracestate", "rojo=00f067aa0ba902b7"),
+        ("tracestate", "congo=t61rcWkgMzE"),
+    ];
+    assert_eq!(
+        context(&[("traceparent", traceparent), tracestate[0], tracestate[1]]).await,
+        format!(
+            r#"00-{TRACE_ID}-0001020304050607-00 Some("rojo=00f067aa0ba902b7,congo=t61rcWkgMzE")"#
+        )
+    );
+
+    // Future versions may have additional fields
+    let traceparent = "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-09-extra";
+    assert_eq!(
+        context(&[("traceparent", traceparent)]).await,
+        format!("00-{TRACE_ID}-0001020304050607-01 None")
+    );
+
+    // Invalid headers are ignored
+    for traceparent in [
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        "garbage",
+    ] {
+        assert_eq!(context(&[("traceparent", traceparent)]).await, "<none>");
+    }
+    assert_eq!(context(&[("tracestate", "rojo=1")]).await, "<none>");
+    assert_eq!(context(&[]).await, "<none>");
+}
+
+#[tokio::test]
+async fn bodyless_methods_skip_body_buffering() {
+    let config = FallbackConfig {
+        bodyless_methods: vec![http::Method::GET, http::Method::HEAD],
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(OkResult, config);
+
+    let req = Request::get("/")
+        .header(hyper::header::CONTENT_LENGTH, "not a number")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // The `Content-Length` is still checked for other methods
+    let req = Request::post("/")
+        .header(hyper::header::CONTENT_LENGTH, "not a number")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn gzip_bodies_are_decompressed_unless_raw() {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(b"payload").unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let config = FallbackConfig {
+        decompress_gzip_bodies: true,
+        raw_body_path_prefixes: vec!["/artifacts/".into()],
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(EchoBody, config);
+
+    let mut call = |path: &str, body: Vec<u8>| {
+        let req = hyper::Request::put(path)
+            .header(hyper::header::CONTENT_ENCODING, "gzip")
+            .body(hyper::Body::from(body))
+            .unwrap();
+        let resp = service.call(req);
+        async move {
+            let resp = resp.await.unwrap();
+            let status = resp.status();
+            (status, to_bytes(resp.into_body()).await.unwrap())
+        }
+    };
+
+    let (status, body) = call("/api/v1/crates/new", compressed.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "payload");
+
+    let (status, body) = call("/artifacts/foo.gz", compressed.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, compressed);
+
+    let (status, _) = call("/api/v1/crates/new", b"not gzip".to_vec()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn request_bodies_are_buffered_or_streamed() {
+    let config = FallbackConfig {
+        streaming_content_types: vec!["application/x-tar".into()],
+        streaming_path_prefixes: vec!["/uploads/".into()],
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(ReportBodyMode, config);
+
+    let mut call = |path: &str, content_type: &str| {
+        let req = hyper::Request::put(path)
+            .header(hyper::header::CONTENT_TYPE, content_type)
+            .body(hyper::Body::from("payload"))
+            .unwrap();
+        let resp = service.call(req);
+        async move {
+            let resp = resp.await.unwrap();
+            let full_body = to_bytes(resp.into_body()).await.unwrap();
+            String::from_utf8(full_body.to_vec()).unwrap()
+        }
+    };
+
+    let body = call("/api/v1/crates/new", "application/x-tar").await;
+    assert_eq!(body, "Streaming None payload");
+
+    let body = call("/uploads/foo", "application/octet-stream").await;
+    assert_eq!(body, "Streaming None payload");
+
+    let body = call("/api/v1/crates/new", "application/json; charset=utf-8").await;
+    assert_eq!(body, "Buffered Some(7) payload");
+
+    // Request bodies are buffered by default
+    let mut service = make_service(ReportBodyMode);
+    let req = hyper::Request::put("/api/v1/crates/new")
+        .header(hyper::header::CONTENT_TYPE, "application/x-tar")
+        .body(hyper::Body::from("payload"))
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    let full_body = to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(&*full_body, b"Buffered Some(7) payload");
+}
+
+#[tokio::test]
+async fn deferred_bodies_send_keepalive_chunks() {
+    let resp = simulate_request(SlowReport(Some(r#"{"ok":true}"#))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-type"], "application/json");
+    assert!(resp.headers().get("content-length").is_none());
+
+    let full_body = to_bytes(resp.into_body()).await.unwrap();
+    let full_body = std::str::from_utf8(&full_body).unwrap();
+    assert!(full_body.starts_with(' '), "{full_body:?}");
+    assert_eq!(full_body.trim_start(), r#"{"ok":true}"#);
+
+    // The response is aborted if the payload is never sent
+    let resp = simulate_request(SlowReport(None)).await;
+    assert!(to_bytes(resp.into_body()).await.is_err());
+}
+
+#[tokio::test]
+async fn deferred_bodies_without_keepalive_interval() {
+    let (body, sender) = DeferredBody::new(Duration::ZERO);
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        sender.send("payload");
+    });
+
+    let full_body = to_bytes(body.into_streamed_body()).await.unwrap();
+    assert_eq!(&*full_body, b"payload");
+}
+
+#[tokio::test]
+async fn negotiated_bodies_are_encoded_as_json_or_msgpack() {
+    let mut service = make_service(Summary);
+    let request = |accept| {
+        Request::get("/api/v1/summary")
+            .header(hyper::header::ACCEPT, accept)
+            .body(hyper::Body::empty())
+            .unwrap()
+    };
+
+    let resp = service.call(request("application/json")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.headers()["content-type"],
+        "application/json; charset=utf-8"
+    );
+    assert_eq!(resp.headers()["vary"], "Accept");
+    assert_eq!(
+        resp.extensions().get::<ResponseFormat>(),
+        Some(&ResponseFormat::Json)
+    );
+    let full_body = to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(&*full_body, br#"{"num_crates":42}"#);
+
+    let resp = service.call(request("application/msgpack")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-type"], "application/msgpack");
+    assert_eq!(resp.headers()["vary"], "Accept");
+    assert_eq!(
+        resp.extensions().get::<ResponseFormat>(),
+        Some(&ResponseFormat::MessagePack)
+    );
+    let full_body = to_bytes(resp.into_body()).await.unwrap();
+    let value: serde_json::Value = rmp_serde::from_slice(&full_body).unwrap();
+    assert_eq!(value, serde_json::json!({ "num_crates": 42 }));
+}
+
+#[test]
+fn response_formats_are_negotiated_by_quality_and_order() {
+    let from_accept = |value| {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(hyper::header::ACCEPT, HeaderValue::from_static(value));
+        ResponseFormat::from_accept(&headers)
+    };
+
+    assert_eq!(
+        ResponseFormat::from_accept(&http::HeaderMap::new()),
+        ResponseFormat::Json
+    );
+    assert_eq!(from_accept("*/*"), ResponseFormat::Json);
+    assert_eq!(
+        from_accept("application/x-msgpack"),
+        ResponseFormat::MessagePack
+    );
+    assert_eq!(
+        from_accept("application/json;q=0.5, application/msgpack"),
+        ResponseFormat::MessagePack
+    );
+    assert_eq!(
+        from_accept("application/msgpack;q=0.5, application/json"),
+        ResponseFormat::Json
+    );
+    assert_eq!(from_accept("application/msgpack;q=0"), ResponseFormat::Json);
+
+    // Ties are broken by the order of the header
+    assert_eq!(
+        from_accept("application/msgpack, application/json"),
+        ResponseFormat::MessagePack
+    );
+    assert_eq!(
+        from_accept("application/json, application/msgpack"),
+        ResponseFormat::Json
+    );
+}
+
+#[tokio::test]
+async fn layer_timings_are_recorded() {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(b"payload").unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let config = FallbackConfig {
+        decompress_gzip_bodies: true,
+        record_layer_timings: true,
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(EchoBody, config);
+
+    let req = hyper::Request::put("/api/v1/crates/new")
+        .header(hyper::header::CONTENT_ENCODING, "gzip")
+        .body(hyper::Body::from(compressed))
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    let timings = resp.extensions().get::<LayerTimings>().unwrap();
+    let layers = timings.iter().map(|(layer, _)| layer).collect::<Vec<_>>();
+    assert_eq!(layers, ["content_length_check", "decompression", "handler"]);
+
+    // Bodies that are not decompressed have no `decompression` layer
+    let req = hyper::Request::put("/api/v1/crates/new")
+        .body(hyper::Body::from("payload"))
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    let timings = resp.extensions().get::<LayerTimings>().unwrap();
+    assert!(timings.get("handler").is_some());
+    assert!(timings.get("decompression").is_none());
+
+    // Looking up a precompressed file is recorded as the `compression` layer
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("foo-1.0.0.crate");
+    std::fs::write(&path, b"plain").unwrap();
+    std::fs::write(dir.path().join("foo-1.0.0.crate.gz"), b"compressed").unwrap();
+
+    let config = FallbackConfig {
+        precompressed_gzip: true,
+        record_layer_timings: true,
+        ..Default::default()
+    };
+    let mut file_service = make_service_with_config(ServeCrateFile(path), config);
+
+    let req = Request::get("/")
+        .header("accept-encoding", "gzip")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let resp = file_service.call(req).await.unwrap();
+    assert_eq!(resp.headers()["content-encoding"], "gzip");
+    let timings = resp.extensions().get::<LayerTimings>().unwrap();
+    let layers = timings.iter().map(|(layer, _)| layer).collect::<Vec<_>>();
+    assert_eq!(layers, ["content_length_check", "handler", "compression"]);
+
+    // Layer timings are not recorded by default
+    let mut service = make_service(EchoBody);
+    let req = hyper::Request::put("/api/v1/crates/new")
+        .body(hyper::Body::from("payload"))
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    assert!(resp.extensions().get::<LayerTimings>().is_none());
+}
+
+#[tokio::test]
+async fn transferred_bytes_are_counted() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    let request_bytes = Arc::new(AtomicU64::new(0));
+    let response_bytes = Arc::new(AtomicU64::new(0));
+    let transferred_bytes = {
+        let request_bytes = request_bytes.clone();
+        let response_bytes = response_bytes.clone();
+        TransferredBytes::new(
+            move |bytes| {
+                request_bytes.fetch_add(bytes, Ordering::SeqCst);
+            },
+            move |bytes| {
+                response_bytes.fetch_add(bytes, Ordering::SeqCst);
+            },
+        )
+    };
+    let config = FallbackConfig {
+        streaming_content_types: vec!["application/x-tar".into()],
+        transferred_bytes: Some(transferred_bytes),
+        ..Default::default()
+    };
+    let mut service = make_service_with_config(EchoBody, config);
+    let counted = || {
+        let request_bytes = request_bytes.load(Ordering::SeqCst);
+        (request_bytes, response_bytes.load(Ordering::SeqCst))
+    };
+
+    let req = hyper::Request::put("/")
+        .body(hyper::Body::from("payload"))
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(counted(), (7, 7));
+
+    // Streamed bodies without a `Content-Length` are counted as they are read
+    let (mut sender, body) = hyper::Body::channel();
+    sender.send_data("hello world".into()).await.unwrap();
+    drop(sender);
+    let req = hyper::Request::put("/")
+        .header(hyper::header::CONTENT_TYPE, "application/x-tar")
+        .body(body)
+        .unwrap();
+    let resp = service.call(req).await.unwrap();
+    let full_body = to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(&*full_body, b"hello world");
+    assert_eq!(counted(), (18, 18));
+
+    // Response bodies that are never sent are not counted
+    let req = hyper::Request::put("/")
+        .body(hyper::Body::from("payload"))
+        .unwrap();
+    drop(service.call(req).await.unwrap());
+    assert_eq!(counted(), (25, 18));
+}
+
+#[tokio::test]
+async fn missing_connect_info_uses_a_sentinel_address() {
+    use tower::ServiceExt;
+
+    // Without the `ConnectInfo` extension of `make_service()`
+    let mut service = Router::new().conduit_fallback(EchoRemoteAddr);
+
+    for _ in 0..2 {
+        let resp = service.call(Request::default()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let full_body = to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(&*full_body, b"0.0.0.0:0");
+    }
+
+    // `ConduitService` uses the same sentinel address
+    let resp = ConduitService::new(EchoRemoteAddr)
+        .oneshot(Request::default())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let full_body = to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(&*full_body, b"0.0.0.0:0");
+
+    // The actual remote address is used if it is available
+    let remote_addr: SocketAddr = ([10, 0, 0, 1], 1234).into();
+    let mut service = Router::new()
+        .conduit_fallback(EchoRemoteAddr)
+        .layer(Extension(ConnectInfo(remote_addr)));
+    let resp = service.call(Request::default()).await.unwrap();
+    let full_body = to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(&*full_body, b"10.0.0.1:1234");
+}
@@ -0,0 +1,138 @@
+use std::fmt::Write;
+
+use http::HeaderMap;
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: &str = "tracestate";
+
+/// The W3C trace context of the `traceparent` and `tracestate` request headers
+///
+/// This is available to handlers as a request extension, if the request contains a valid
+/// `traceparent` header, so that the trace can be continued by the spans of the application
+/// instead of starting a new trace. See <https://www.w3.org/TR/trace-context/> for the format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    trace_id: String,
+    parent_id: String,
+    sampled: bool,
+    tracestate: Option<String>,
+}
+
+impl TraceContext {
+    /// Parse the trace context headers, returning `None` if there is no valid `traceparent`
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let mut traceparents = headers.get_all(TRACEPARENT).iter();
+        let traceparent = traceparents.next()?.to_str().ok()?;
+        if traceparents.next().is_some() {
+            return None;
+        }
+
+        let mut context = parse_traceparent(traceparent)?;
+
+        let tracestate = headers
+            .get_all(TRACESTATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .collect::<Vec<_>>();
+        if !tracestate.is_empty() {
+            context.tracestate = Some(tracestate.join(","));
+        }
+
+        Some(context)
+    }
+
+    /// The 32 hex digits of the trace ID
+    pub fn trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    /// The 16 hex digits of the ID of the parent span
+    pub fn parent_id(&self) -> &str {
+        &self.parent_id
+    }
+
+    /// Whether the caller may have recorded the trace, i.e. the `sampled` flag
+    pub fn is_sampled(&self) -> bool {
+        self.sampled
+    }
+
+    /// The vendor-specific `tracestate` entries, which are propagated unchanged
+    pub fn tracestate(&self) -> Option<&str> {
+        self.tracestate.as_deref()
+    }
+
+    /// The context of a child span with the `span_id`, continuing the same trace
+    ///
+    /// Outgoing requests of the child span should send this context as their `traceparent`.
+    pub fn child(&self, span_id: [u8; 8]) -> Self {
+        let mut parent_id = String::with_capacity(16);
+        for byte in span_id {
+            let _ = write!(parent_id, "{:02x}", byte);
+        }
+
+        Self {
+            trace_id: self.trace_id.clone(),
+            parent_id,
+            sampled: self.sampled,
+            tracestate: self.tracestate.clone(),
+        }
+    }
+
+    /// The value of the `traceparent` header for this context
+    pub fn traceparent(&self) -> String {
+        let flags = if self.sampled { "01" } else { "00" };
+        format!("00-{}-{}-{}", self.trace_id, self.parent_id, flags)
+    }
+}
+
+/// Parse a `version-trace_id-parent_id-flags` header value
+///
+/// Versions other than `00` may have additional fields, which are ignored.
+fn parse_traceparent(value: &str) -> Option<TraceContext> {
+    let mut fields = value.trim().split('-');
+
+    let version = fields.next()?;
+    if !is_hex(version, 2) || version == "ff" {
+        return None;
+    }
+
+    let trace_id = fields.next()?;
+    let parent_id = fields.next()?;
+    let flags = fields.next()?;
+    if version == "00" && fields.next().is_some() {
+        return None;
+    }
+
+    if !is_hex(trace_id, 32) || is_zero(trace_id) {
+        return None;
+    }
+
+    if !is_hex(parent_id, 16) || is_zero(parent_id) {
+        return None;
+    }
+
+    if !is_hex(flags, 2) {
+        return None;
+    }
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+
+    Some(TraceContext {
+        trace_id: trace_id.to_string(),
+        parent_id: parent_id.to_string(),
+        sampled: flags & 0x01 != 0,
+        tracestate: None,
+    })
+}
+
+/// Whether `s` consists of `len` lowercase hex digits
+fn is_hex(s: &str, len: usize) -> bool {
+    s.len() == len
+        && s.bytes()
+            .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn is_zero(s: &str) -> bool {
+    s.bytes().all(|byte| byte == b'0')
+}
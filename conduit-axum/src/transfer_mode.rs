@@ -0,0 +1,39 @@
+use axum::body::HttpBody;
+use http::header::CONTENT_LENGTH;
+
+use crate::AxumResponse;
+
+/// A response extension recording how the length of the response body is conveyed to the client
+///
+/// `Static` and `Owned` bodies always have a known length, while `File` and deferred bodies are
+/// streamed with `Transfer-Encoding: chunked`, unless the handler set a `Content-Length` header.
+/// Outer middleware (e.g. access logging) can use this to correlate client-side issues with the
+/// transfer mode. Note that HTTP/2 connections use their own framing instead of chunks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferMode {
+    /// The body is streamed in chunks of unknown total length
+    Chunked,
+    /// The body length is sent in the `Content-Length` header
+    ContentLength,
+}
+
+impl TransferMode {
+    /// Determine the mode of a converted `response`, based on its headers and body
+    pub(crate) fn for_response(response: &AxumResponse) -> Self {
+        let has_length = response.headers().contains_key(CONTENT_LENGTH)
+            || response.body().size_hint().exact().is_some();
+
+        if has_length {
+            Self::ContentLength
+        } else {
+            Self::Chunked
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Chunked => "chunked",
+            Self::ContentLength => "content-length",
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::body::{BoxBody, Bytes, HttpBody};
+use http::HeaderMap;
+use hyper::body::SizeHint;
+
+type Counter = dyn Fn(u64) + Send + Sync;
+
+/// Counts the body bytes that the fallback handler transfers, see
+/// `FallbackConfig::transferred_bytes`
+///
+/// The bytes are counted as they are read from the client and polled from the response body, so
+/// streamed bodies without a `Content-Length` are included. Response bodies that are never sent,
+/// e.g. for `HEAD` requests, are not counted.
+#[derive(Clone)]
+pub struct TransferredBytes {
+    request: Arc<Counter>,
+    response: Arc<Counter>,
+}
+
+impl TransferredBytes {
+    /// Call `request` with the size of each chunk of a request body, and `response` with the size
+    /// of each chunk of a response body
+    pub fn new(
+        request: impl Fn(u64) + Send + Sync + 'static,
+        response: impl Fn(u64) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            request: Arc::new(request),
+            response: Arc::new(response),
+        }
+    }
+
+    pub(crate) fn count_request(&self, bytes: usize) {
+        (self.request)(bytes as u64)
+    }
+
+    pub(crate) fn count_response(&self, body: BoxBody) -> BoxBody {
+        axum::body::boxed(CountedBody {
+            inner: body,
+            counter: self.response.clone(),
+        })
+    }
+}
+
+impl fmt::Debug for TransferredBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransferredBytes").finish_non_exhaustive()
+    }
+}
+
+/// A response body that reports the size of each chunk as it is polled
+struct CountedBody {
+    inner: BoxBody,
+    counter: Arc<Counter>,
+}
+
+impl HttpBody for CountedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            (self.counter)(chunk.len() as u64);
+        }
+        poll
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -3,13 +3,16 @@
 use crate::db::{ConnectionConfig, DieselPool};
 use crate::{config, Env};
 use std::ops::Deref;
-use std::sync::atomic::AtomicUsize;
-use std::{sync::Arc, time::Duration};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::downloads_counter::DownloadsCounter;
 use crate::email::Emails;
 use crate::github::{GitHubClient, RealGitHubClient};
 use crate::metrics::{InstanceMetrics, ServiceMetrics};
+use crate::middleware::limit_client_rate::ClientRateLimits;
+use axum::body::Bytes;
 use axum::extract::FromRef;
 use diesel::r2d2;
 use moka::sync::{Cache, CacheBuilder};
@@ -17,6 +20,8 @@ use oauth2::basic::BasicClient;
 use reqwest::blocking::Client;
 use scheduled_thread_pool::ScheduledThreadPool;
 
+const STATIC_GZIP_CACHE_SIZE: u64 = 64 * 1024 * 1024; // 64 MB
+
 /// The `App` struct holds the main components of the application like
 /// the database connection pool and configurations
 pub struct App {
@@ -65,6 +70,21 @@ pub struct App {
 
     /// In-flight request counters for the `balance_capacity` middleware.
     pub balance_capacity: BalanceCapacityState,
+
+    /// Whether the application is ready to serve requests, see the `readiness` middleware.
+    pub readiness: Readiness,
+
+    /// Whether the application is in maintenance mode, see the `maintenance_mode` middleware.
+    pub maintenance_mode: MaintenanceMode,
+
+    /// The token buckets of the `limit_client_rate` middleware, if the rate limit is enabled
+    pub client_rate_limits: Option<ClientRateLimits>,
+
+    /// Static files that were gzip compressed on the fly, keyed by path and `Last-Modified`
+    pub(crate) static_gzip_cache: Cache<(String, String), (Bytes, u64)>,
+
+    /// When the application was started, for the uptime reported by `/api/private/diagnostics`
+    pub started_at: Instant,
 }
 
 impl App {
@@ -173,6 +193,11 @@ impl App {
             .time_to_live(config.version_id_cache_ttl)
             .build();
 
+        // The gzip cache is weighed by the size of the compressed files in bytes
+        let static_gzip_cache = CacheBuilder::new(STATIC_GZIP_CACHE_SIZE)
+            .weigher(|_key, (value, _): &(Bytes, u64)| value.len().try_into().unwrap_or(u32::MAX))
+            .build();
+
         let fastboot_client = match dotenv::var("USE_FASTBOOT") {
             Ok(val) if val == "staging-experimental" => Some(reqwest::Client::new()),
             _ => None,
@@ -191,6 +216,11 @@ impl App {
             http_client,
             fastboot_client,
             balance_capacity: Default::default(),
+            readiness: Default::default(),
+            maintenance_mode: MaintenanceMode::new(config.maintenance.enabled),
+            client_rate_limits: ClientRateLimits::new(&config.client_rate_limit),
+            static_gzip_cache,
+            started_at: Instant::now(),
             config,
         }
     }
@@ -222,6 +252,38 @@ pub struct BalanceCapacityState {
     pub in_flight_non_dl_requests: AtomicUsize,
 }
 
+/// Whether the dependencies of the application are available, which is initially not the case
+#[derive(Debug, Default)]
+pub struct Readiness(AtomicBool);
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    pub fn set_ready(&self, ready: bool) {
+        self.0.store(ready, Ordering::Release);
+    }
+}
+
+/// Whether all requests are rejected because of a planned maintenance
+#[derive(Debug, Default)]
+pub struct MaintenanceMode(AtomicBool);
+
+impl MaintenanceMode {
+    pub fn new(enabled: bool) -> Self {
+        Self(AtomicBool::new(enabled))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::Release);
+    }
+}
+
 #[derive(Clone)]
 pub struct AppState(pub Arc<App>);
 
@@ -1,6 +1,7 @@
 use crate::controllers;
 use crate::db::RequestTransaction;
-use crate::middleware::log_request::CustomMetadataRequestExt;
+use crate::middleware::app::RequestApp;
+use crate::middleware::log_request::{AuthOutcome, CustomMetadataRequestExt};
 use crate::middleware::session::RequestSession;
 use crate::models::token::{CrateScope, EndpointScope};
 use crate::models::{ApiToken, User};
@@ -11,6 +12,17 @@ use chrono::Utc;
 use conduit::RequestExt;
 use http::header;
 
+/// The tier of the authenticated user, which can grant higher limits than the default
+///
+/// `AuthCheck::check_with_tier()` inserts this into the request extensions. Requests without a
+/// tier (e.g. anonymous requests) are treated as `Free`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserTier {
+    Free,
+    /// Users on the `premium_users` list of the config
+    Premium,
+}
+
 #[derive(Debug, Clone)]
 pub struct AuthCheck {
     allow_token: bool,
@@ -97,6 +109,22 @@ impl AuthCheck {
         Ok(auth)
     }
 
+    /// Like `check()`, but also records the `UserTier` of the user in the request extensions,
+    /// e.g. for a `TierBodySizeLimit`
+    pub fn check_with_tier(&self, request: &mut dyn RequestExt) -> AppResult<AuthenticatedUser> {
+        let auth = self.check(request)?;
+
+        let premium_users = &request.app().config.premium_users;
+        let tier = if premium_users.contains(&auth.user.gh_login) {
+            UserTier::Premium
+        } else {
+            UserTier::Free
+        };
+        request.mut_extensions().insert(tier);
+
+        Ok(auth)
+    }
+
     fn endpoint_scope_matches(&self, token_scopes: Option<&Vec<EndpointScope>>) -> bool {
         match (&token_scopes, &self.endpoint_scope) {
             // The token is a legacy token.
@@ -161,9 +189,12 @@ fn authenticate_user(req: &dyn RequestExt) -> AppResult<AuthenticatedUser> {
         .and_then(|s| s.parse::<i32>().ok());
 
     if let Some(id) = user_id_from_session {
-        let user = User::find(&conn, id)
-            .map_err(|err| err.chain(internal("user_id from cookie not found in database")))?;
+        let user = User::find(&conn, id).map_err(|err| {
+            req.set_auth_outcome(AuthOutcome::Failed);
+            err.chain(internal("user_id from cookie not found in database"))
+        })?;
 
+        req.set_auth_outcome(AuthOutcome::Cookie);
         return Ok(AuthenticatedUser { user, token: None });
     }
 
@@ -175,6 +206,7 @@ fn authenticate_user(req: &dyn RequestExt) -> AppResult<AuthenticatedUser> {
 
     if let Some(header_value) = maybe_authorization {
         let token = ApiToken::find_by_api_token(&conn, header_value).map_err(|e| {
+            req.set_auth_outcome(AuthOutcome::Failed);
             if e.is::<InsecurelyGeneratedTokenRevoked>() {
                 e
             } else {
@@ -182,9 +214,12 @@ fn authenticate_user(req: &dyn RequestExt) -> AppResult<AuthenticatedUser> {
             }
         })?;
 
-        let user = User::find(&conn, token.user_id)
-            .map_err(|err| err.chain(internal("user_id from token not found in database")))?;
+        let user = User::find(&conn, token.user_id).map_err(|err| {
+            req.set_auth_outcome(AuthOutcome::Failed);
+            err.chain(internal("user_id from token not found in database"))
+        })?;
 
+        req.set_auth_outcome(AuthOutcome::Token);
         return Ok(AuthenticatedUser {
             user,
             token: Some(token),
@@ -192,6 +227,7 @@ fn authenticate_user(req: &dyn RequestExt) -> AppResult<AuthenticatedUser> {
     }
 
     // Unable to authenticate the user
+    req.set_auth_outcome(AuthOutcome::Anonymous);
     return Err(internal("no cookie session or auth header found").chain(forbidden()));
 }
 
@@ -27,11 +27,11 @@ use std::time::Duration;
 use cargo_registry::swirl;
 
 fn main() {
-    let _sentry = cargo_registry::sentry::init();
-
-    // Initialize logging
+    // Initialize logging, before Sentry so that its warnings are logged
     cargo_registry::util::tracing::init();
 
+    let _sentry = cargo_registry::sentry::init();
+
     info!("Booting runner");
 
     let config = config::Server::default();
@@ -28,11 +28,11 @@ enum SubCommand {
 }
 
 fn main() -> anyhow::Result<()> {
-    let _sentry = cargo_registry::sentry::init();
-
-    // Initialize logging
+    // Initialize logging, before Sentry so that its warnings are logged
     cargo_registry::util::tracing::init();
 
+    let _sentry = cargo_registry::sentry::init();
+
     use clap::Parser;
 
     let opts: Opts = Opts::parse();
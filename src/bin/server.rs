@@ -5,6 +5,7 @@ extern crate tracing;
 
 use cargo_registry::middleware::normalize_path::normalize_path;
 use cargo_registry::{env_optional, metrics::LogEncoder, util::errors::AppResult, App, Env};
+use conduit_axum::{track_connection_requests, ConnectionInfo};
 use std::{fs::File, process::Command, sync::Arc, time::Duration};
 
 use axum::ServiceExt;
@@ -12,18 +13,17 @@ use futures_util::future::FutureExt;
 use prometheus::Encoder;
 use reqwest::blocking::Client;
 use std::io::{self, Write};
-use std::net::SocketAddr;
 use tokio::signal::unix::{signal, SignalKind};
 use tower::Layer;
 
 const CORE_THREADS: usize = 4;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let _sentry = cargo_registry::sentry::init();
-
-    // Initialize logging
+    // Initialize logging, before Sentry so that its warnings are logged
     cargo_registry::util::tracing::init();
 
+    let _sentry = cargo_registry::sentry::init();
+
     let config = cargo_registry::config::Server::default();
     let env = config.env();
     let client = Client::new();
@@ -35,12 +35,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Start the background thread periodically logging instance metrics.
     log_instance_metrics_thread(app.clone());
 
+    // Start the background thread that marks the app as ready once the database is available.
+    readiness_thread(app.clone());
+
     let axum_router = cargo_registry::build_handler(app.clone());
 
     // Apply the `normalize_path` middleware around the axum router
-    let normalize_path = axum::middleware::from_fn(normalize_path);
+    let normalize_path_mode = app.config.normalize_path_mode;
+    let normalize_path = axum::middleware::from_fn_with_state(normalize_path_mode, normalize_path);
     let axum_router = normalize_path.layer(axum_router);
 
+    // Count the requests of each connection, for logging the reuse of keep-alive connections
+    let track_connection_requests = axum::middleware::from_fn(track_connection_requests);
+    let axum_router = track_connection_requests.layer(axum_router);
+
     let heroku = dotenv::var("HEROKU").is_ok();
     let fastboot = dotenv::var("USE_FASTBOOT").is_ok();
     let dev_docker = dotenv::var("DEV_DOCKER").is_ok();
@@ -71,7 +79,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .build()
         .unwrap();
 
-    let make_service = axum_router.into_make_service_with_connect_info::<SocketAddr>();
+    let make_service = axum_router.into_make_service_with_connect_info::<ConnectionInfo>();
 
     let (addr, server) = rt.block_on(async {
         let server = hyper::Server::bind(&(ip, port).into()).serve(make_service);
@@ -128,6 +136,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     info!("Server has gracefully shutdown!");
+    cargo_registry::util::tracing::shutdown_logging();
     Ok(())
 }
 
@@ -146,6 +155,22 @@ fn downloads_counter_thread(app: Arc<App>) {
     });
 }
 
+fn readiness_thread(app: Arc<App>) {
+    std::thread::spawn(move || loop {
+        match app.primary_database.get() {
+            Ok(_) => {
+                info!("The primary database is available, accepting requests");
+                app.readiness.set_ready(true);
+                return;
+            }
+            Err(err) => {
+                warn!(?err, "The primary database is not available yet");
+                std::thread::sleep(Duration::from_secs(1));
+            }
+        }
+    });
+}
+
 fn log_instance_metrics_thread(app: Arc<App>) {
     // Only run the thread if the configuration is provided
     let interval = if let Some(secs) = app.config.instance_metrics_log_every_seconds {
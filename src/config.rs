@@ -1,21 +1,38 @@
 use anyhow::{anyhow, Context};
+use conduit_axum::{FileRedirect, ResponseHeaderLimit};
 use ipnetwork::IpNetwork;
 
+use crate::middleware::normalize_path::NormalizePathMode;
+use crate::middleware::require_tls_version::TlsVersion;
+use crate::middleware::rewrite_legacy_paths::LegacyPath;
 use crate::publish_rate_limit::PublishRateLimit;
 use crate::{env, env_optional, uploaders::Uploader, Env};
 
 mod balance_capacity;
 mod base;
+mod client_rate_limit;
 mod database_pools;
+mod log_requests;
+mod maintenance;
+mod security_headers;
+mod static_files;
 
 pub use self::base::Base;
 pub use self::database_pools::{DatabasePools, DbPoolConfig};
 pub use crate::config::balance_capacity::BalanceCapacityConfig;
+pub use crate::config::client_rate_limit::ClientRateLimitConfig;
+pub use crate::config::log_requests::{
+    IpLogging, LogFormat, LogQueryParam, LogRequestsConfig, LogSinks, LogStatuses,
+};
+pub use crate::config::maintenance::MaintenanceConfig;
+pub use crate::config::security_headers::SecurityHeadersConfig;
+pub use crate::config::static_files::StaticFilesConfig;
 use std::collections::HashSet;
 use std::time::Duration;
 
 const DEFAULT_VERSION_ID_CACHE_SIZE: u64 = 10_000;
 const DEFAULT_VERSION_ID_CACHE_TTL: u64 = 5 * 60; // 5 minutes
+const DEFAULT_MAX_URI_LENGTH: usize = 8 * 1024; // 8 KB
 
 pub struct Server {
     pub base: Base,
@@ -26,6 +43,9 @@ pub struct Server {
     pub gh_base_url: String,
     pub max_upload_size: u64,
     pub max_unpack_size: u64,
+    pub premium_max_upload_size: u64,
+    pub premium_users: Vec<String>,
+    pub max_publish_body_size: u64,
     pub publish_rate_limit: PublishRateLimit,
     pub new_version_rate_limit: Option<u32>,
     pub blocked_traffic: Vec<(String, Vec<String>)>,
@@ -38,6 +58,8 @@ pub struct Server {
     pub downloads_persist_interval_ms: usize,
     pub ownership_invitations_expiration_days: u64,
     pub metrics_authorization_token: Option<String>,
+    pub expose_log_config: bool,
+    pub expose_diagnostics: bool,
     pub use_test_database_pool: bool,
     pub instance_metrics_log_every_seconds: Option<u64>,
     pub force_unconditional_redirects: bool,
@@ -46,6 +68,30 @@ pub struct Server {
     pub version_id_cache_ttl: Duration,
     pub cdn_user_agent: String,
     pub balance_capacity: BalanceCapacityConfig,
+    pub static_files: StaticFilesConfig,
+    pub max_uri_length: usize,
+    pub behind_proxy: bool,
+    pub prefer_forwarded_header: bool,
+    pub log_requests: LogRequestsConfig,
+    pub content_length_monitor_limit: Option<u64>,
+    pub max_file_streams: Option<usize>,
+    pub file_redirect: Option<FileRedirect>,
+    pub body_read_timeout: Option<Duration>,
+    pub decompress_request_bodies: bool,
+    pub raw_body_path_prefixes: Vec<String>,
+    pub max_response_header_size: Option<ResponseHeaderLimit>,
+    pub coalesce_requests: bool,
+    pub log_layer_timings: bool,
+    pub client_rate_limit: ClientRateLimitConfig,
+    pub maintenance: MaintenanceConfig,
+    pub allowed_hosts: Vec<String>,
+    pub min_tls_version: Option<TlsVersion>,
+    pub allow_missing_tls_version: bool,
+    pub normalize_accept_encoding: bool,
+    pub strip_accept_encoding_user_agents: Vec<String>,
+    pub security_headers: SecurityHeadersConfig,
+    pub legacy_paths: Vec<LegacyPath>,
+    pub normalize_path_mode: NormalizePathMode,
 }
 
 impl Default for Server {
@@ -66,6 +112,11 @@ impl Default for Server {
     /// - `DOWNLOADS_PERSIST_INTERVAL_MS`: how frequent to persist download counts (in ms).
     /// - `METRICS_AUTHORIZATION_TOKEN`: authorization token needed to query metrics. If missing,
     ///   querying metrics will be completely disabled.
+    /// - `WEB_EXPOSE_LOG_CONFIG`: Whether the effective logging configuration can be queried at
+    ///   `/api/private/log_config`, with the `METRICS_AUTHORIZATION_TOKEN`. Defaults to `false`.
+    /// - `WEB_EXPOSE_DIAGNOSTICS`: Whether the request and blocking thread pool statistics can be
+    ///   queried at `/api/private/diagnostics`, with the `METRICS_AUTHORIZATION_TOKEN`. Defaults
+    ///   to `false`.
     /// - `WEB_MAX_ALLOWED_PAGE_OFFSET`: Page offsets larger than this value are rejected. Defaults
     ///   to 200.
     /// - `WEB_PAGE_OFFSET_UA_BLOCKLIST`: A comma seperated list of user-agent substrings that will
@@ -75,12 +126,159 @@ impl Default for Server {
     /// - `WEB_PAGE_OFFSET_CIDR_BLOCKLIST`: A comma separated list of CIDR blocks that will be used
     ///   to block IP addresses given in the `X-Real-Ip` HTTP header, e.g. `192.168.1.0/24`.
     ///   If not set or empty, no blocking will occur.
+    /// - `WEB_PREMIUM_USERS`: A comma separated list of GitHub logins of users in the premium
+    ///   tier, which can publish crates up to `WEB_PREMIUM_MAX_UPLOAD_SIZE`.
+    /// - `WEB_PREMIUM_MAX_UPLOAD_SIZE`: The upload size limit of the premium tier in bytes.
+    ///   Defaults to 50MiB.
+    /// - `WEB_MAX_PUBLISH_BODY_SIZE`: The size limit of publish request bodies in bytes, which is
+    ///   enforced before the user is authenticated. This needs to be at least as large as the
+    ///   largest crate-specific upload size limit. Defaults to 100MiB.
     /// - `INSTANCE_METRICS_LOG_EVERY_SECONDS`: How frequently should instance metrics be logged.
     ///   If the environment variable is not present instance metrics are not logged.
     /// - `FORCE_UNCONDITIONAL_REDIRECTS`: Whether to force unconditional redirects in the download
     ///   endpoint even with a healthy database pool.
     /// - `BLOCKED_ROUTES`: A comma separated list of HTTP route patterns that are manually blocked
     ///   by an operator (e.g. `/crates/:crate_id/:version/download`).
+    /// - `WEB_PRELOAD_RESOURCES`: A comma separated list of asset paths that are advertised as
+    ///   `Link: <...>; rel=preload` headers when serving `index.html` (e.g. `/assets/vendor.js`).
+    /// - `WEB_STATIC_GZIP_LEVEL`: If set, static files without a precompressed `.gz` variant are
+    ///   gzip compressed on the fly using this compression level (0-9).
+    /// - `WEB_STATIC_GZIP_MAX_SIZE`: Static files larger than this number of bytes are not
+    ///   compressed on the fly (default: 10MiB).
+    /// - `WEB_STATIC_MIME_TYPES`: A comma separated list of `extension=mime/type` pairs, which
+    ///   override the `Content-Type` of static files. `.wasm` files are served as
+    ///   `application/wasm` by default.
+    /// - `WEB_STATIC_STRIP_PREFIX`: A path prefix (e.g. `/assets`) that is removed from the request
+    ///   path before looking up static files in the `dist` directory.
+    /// - `WEB_MAX_URI_LENGTH`: Requests with a longer URI are rejected with a `414 URI Too Long`
+    ///   response. Defaults to 8 KB.
+    /// - `WEB_BEHIND_PROXY`: Whether the `X-Real-Ip` and `X-Forwarded-*` headers set by a reverse
+    ///   proxy are trusted (`true`), or only the connection information is used (`false`).
+    ///   Defaults to `true`.
+    /// - `WEB_PREFER_FORWARDED_HEADER`: Whether the standard `Forwarded` header takes precedence
+    ///   over the `X-Real-Ip` and `X-Forwarded-*` headers if both are present. Defaults to `true`.
+    /// - `WEB_LARGE_RESPONSE_THRESHOLD`: Responses with a larger body (in bytes) are marked with
+    ///   `LARGE RESPONSE` in the request log. Defaults to 5 MB.
+    /// - `WEB_LOG_PATH_PARAMS`: A comma separated list of router path parameters (e.g. `crate_id`)
+    ///   that are included in the request log.
+    /// - `WEB_LOG_QUERY_PARAMS`: A comma separated list of query parameters that are included in
+    ///   the request log as `q_<name>` fields, either for all routes (e.g. `page`) or for a
+    ///   single route pattern (e.g. `sort@/api/v1/crates`). Credentials like the OAuth `code`
+    ///   are redacted.
+    /// - `WEB_LOG_FORMAT`: The format of the request log, either `logfmt` (default), `gelf` for
+    ///   Graylog, `syslog` for RFC 5424 syslog messages, or `ecs` for JSON with Elastic Common
+    ///   Schema field names. GELF, syslog and ECS messages report the `DYNO` or `HOSTNAME`
+    ///   environment variable as the host.
+    /// - `WEB_LOG_THREAD_INFO`: Whether the request log includes the `thread_id` and
+    ///   `thread_name` of the thread that ran the request handler. Defaults to `false`.
+    /// - `WEB_LOG_IP`: What the request log contains as the client IP address: `raw` (default),
+    ///   `hashed` for a hash with a salt that is randomly generated on startup, or `none`.
+    /// - `WEB_LOG_BAGGAGE_KEYS`: A comma separated list of keys of the W3C `baggage` request
+    ///   header that are included in the request log.
+    /// - `WEB_LOG_REQUEST_HEADERS` and `WEB_LOG_RESPONSE_HEADERS`: Comma separated lists of
+    ///   request and response header names that are included in the request log as
+    ///   `hdr_<name>` and `res_hdr_<name>` fields. Empty by default, since headers may contain
+    ///   private data.
+    /// - `WEB_LOG_VERBOSE_SAMPLE_RATE`: The probability (0.0-1.0) that a request is logged a
+    ///   second time to the `http.verbose` target, with all headers except for credentials and
+    ///   the service time in microseconds. Defaults to `0.0`.
+    /// - `WEB_LOG_STATUSES`: Which responses are included in the request log: `all` (default),
+    ///   `errors` for `4xx` and `5xx` responses, or a comma separated list of status codes and
+    ///   ranges, e.g. `302,400-599`.
+    /// - `WEB_REPORT_SLOW_REQUESTS`: Whether requests that took longer than a second are reported
+    ///   to Sentry as a warning, tagged with the route and the duration. Defaults to `false`.
+    /// - `WEB_LOG_DECODE_PATH`: Whether the `path` field of the request log is percent-decoded
+    ///   (e.g. `✓` instead of `%E2%9C%93`). The original path is logged as `raw_path` if it
+    ///   differs. Defaults to `false`.
+    /// - `WEB_LOG_BLOCK_WAIT`: Whether the request log includes the time (in milliseconds) that
+    ///   the conduit handler waited for a thread of the blocking thread pool as `block_wait`.
+    ///   Defaults to `false`.
+    /// - `WEB_LOG_COMPACT_ROUTES`: A comma separated list of route patterns without user-supplied
+    ///   segments (e.g. `/api/v1/summary`). Requests for these routes are logged with an
+    ///   unquoted `route` field instead of the quoted `path` field, unless they have a query
+    ///   string.
+    /// - `WEB_LOG_MAX_LINE_LENGTH`: The maximum length of a logfmt request log line. Longer lines
+    ///   are logged without their optional fields, starting with the custom metadata, and are
+    ///   marked with `truncated=true`. If unset, the length is not limited.
+    /// - `WEB_LOG_DOWNLOAD_REDIRECT_LOCATION`: Whether the `location` field, which is logged for
+    ///   all redirect responses, is also logged for the redirects of the download endpoint.
+    ///   Defaults to `false`, to keep the log lines of the download endpoint short.
+    /// - `WEB_LOG_START_LINE`: Whether an additional line with the `method`, `path` and
+    ///   `request_id` is logged (with the `http.start` target) when a request starts, so that
+    ///   long-running requests are visible before they complete. Defaults to `false`.
+    /// - `WEB_RESPONSE_TIME_HEADER`: Whether responses get an `X-Response-Time` header with the
+    ///   service time in milliseconds, as logged in the `service` field. Defaults to `false`.
+    /// - `HEROKU_SLUG_COMMIT`: The deployed commit, which is logged as the `version` field of
+    ///   every request log line.
+    /// - `WEB_CONTENT_LENGTH_MONITOR_LIMIT`: Requests with a larger `Content-Length` are logged
+    ///   with a `would_reject` field, without rejecting them.
+    /// - `WEB_MAX_FILE_STREAMS`: The maximum number of file responses (e.g. local crate
+    ///   downloads) that are streamed concurrently. Further file responses are rejected with a
+    ///   `503 Service Unavailable` response. If unset, the number is not limited.
+    /// - `WEB_FILE_REDIRECT_URL`: The base URL of an alternate backend for file responses (e.g. an
+    ///   object store). A share of the file responses is redirected to this URL with the request
+    ///   path appended, instead of being streamed locally.
+    /// - `WEB_FILE_REDIRECT_WEIGHT`: The share (0.0-1.0) of file responses that are redirected to
+    ///   `WEB_FILE_REDIRECT_URL`. Defaults to `0.0`.
+    /// - `WEB_BODY_READ_TIMEOUT`: The maximum number of seconds for receiving the request body,
+    ///   excluding the processing of the request. Clients that send the body too slowly receive
+    ///   a `408 Request Timeout` response. If unset, the duration is not limited.
+    /// - `WEB_DECOMPRESS_REQUEST_BODIES`: Whether request bodies with `Content-Encoding: gzip`
+    ///   are decompressed before they are passed to the endpoints. Defaults to `false`.
+    /// - `WEB_RAW_BODY_PATH_PREFIXES`: A comma separated list of path prefixes of endpoints that
+    ///   receive the raw request body, even if `WEB_DECOMPRESS_REQUEST_BODIES` is enabled.
+    /// - `WEB_MAX_RESPONSE_HEADER_SIZE`: The maximum total size of the response headers in bytes.
+    ///   Larger responses are replaced with a `500 Internal Server Error` response, or, if
+    ///   `WEB_STRIP_OVERSIZED_RESPONSE_HEADERS` is set, the largest headers are removed. If unset,
+    ///   the size is not limited.
+    /// - `WEB_COALESCE_REQUESTS`: Whether concurrent identical `GET` requests without credentials
+    ///   share a single handler execution and its response. Defaults to `false`.
+    /// - `WEB_LOG_LAYER_TIMINGS`: Whether the time spent in the layers of the request handling
+    ///   (e.g. the static file check, the body decompression and the handler) is logged as
+    ///   `t_layer_<name>` fields in microseconds, for performance debugging. Defaults to `false`.
+    /// - `WEB_CLIENT_RATE_LIMIT`: The number of requests per second that a client IP address can
+    ///   sustain. Further requests are rejected with a `429 Too Many Requests` response. If unset,
+    ///   the request rate is not limited.
+    /// - `WEB_CLIENT_RATE_LIMIT_BURST`: The number of requests that a client can send at once
+    ///   before `WEB_CLIENT_RATE_LIMIT` applies. Defaults to `20`.
+    /// - `WEB_CLIENT_RATE_LIMIT_TRUSTED_IPS`: A comma separated list of IP addresses and CIDR
+    ///   blocks of clients that are exempt from the rate limit.
+    /// - `WEB_CLIENT_RATE_LIMIT_TRUSTED_USER_AGENTS`: A comma separated list of `User-Agent`
+    ///   substrings of clients that are exempt from the rate limit, e.g. mirrors.
+    /// - `WEB_CLIENT_RATE_LIMIT_TRUSTED_TOKEN_HASHES`: A comma separated list of hex encoded
+    ///   SHA-256 hashes of API tokens whose requests are exempt from the rate limit.
+    /// - `WEB_MAINTENANCE_MODE`: Whether the application starts in maintenance mode, in which all
+    ///   requests except for the `/healthz` route are rejected with a `503 Service Unavailable`
+    ///   response. Defaults to `false`.
+    /// - `WEB_MAINTENANCE_MESSAGE`: The message of the maintenance page.
+    /// - `WEB_MAINTENANCE_RETRY_AFTER`: The `Retry-After` value (in seconds) of the maintenance
+    ///   page. Defaults to `300`.
+    /// - `WEB_MAINTENANCE_ASSET_PATH`: The path of a static asset (e.g. a logo) that is still
+    ///   served in maintenance mode, and is shown on the maintenance page.
+    /// - `WEB_ALLOWED_HOSTS`: A comma separated list of the allowed `Host` header values. Requests
+    ///   for other hosts are rejected. If empty, all hosts are allowed.
+    /// - `WEB_MIN_TLS_VERSION`: Requests that the proxy reports (via the `X-SSL-Protocol` header)
+    ///   to have arrived over an older TLS version, e.g. `1.2`, are rejected with a
+    ///   `426 Upgrade Required` response. If unset, all TLS versions are allowed.
+    /// - `WEB_ALLOW_MISSING_TLS_VERSION`: Whether requests without the `X-SSL-Protocol` header
+    ///   are allowed if `WEB_MIN_TLS_VERSION` is set. Defaults to `true`.
+    /// - `WEB_NORMALIZE_ACCEPT_ENCODING`: Whether invalid entries are dropped from the
+    ///   `Accept-Encoding` request header, and quality values are clamped. Defaults to `false`.
+    /// - `WEB_STRIP_ACCEPT_ENCODING_USER_AGENTS`: A comma separated list of `User-Agent` prefixes
+    ///   of clients whose `Accept-Encoding` header is removed, so that they receive uncompressed
+    ///   responses.
+    /// - `WEB_X_CONTENT_TYPE_OPTIONS`, `WEB_X_FRAME_OPTIONS`, `WEB_REFERRER_POLICY` and
+    ///   `WEB_STRICT_TRANSPORT_SECURITY`: The values of the security headers that are added to
+    ///   all responses that don't set them already. Defaults to `nosniff`, `DENY` and
+    ///   `strict-origin-when-cross-origin`, and no `Strict-Transport-Security` header. An empty
+    ///   value disables the header.
+    /// - `WEB_LEGACY_PATHS`: A comma separated list of legacy path mappings like
+    ///   `redirect:/crates/:id/downloads=/api/v1/crates/:id/downloads`. Requests for the legacy
+    ///   paths are either rewritten internally (`rewrite:`) or redirected (`redirect:`) to the new
+    ///   paths.
+    /// - `WEB_NORMALIZE_PATH_MODE`: How requests for paths that are not normalized (e.g. with a
+    ///   trailing slash) are handled. Either `rewrite` (the default), to handle them like the
+    ///   normalized path, or `redirect`, to respond with a `308 Permanent Redirect` to it.
     ///
     /// # Panics
     ///
@@ -90,6 +288,11 @@ impl Default for Server {
             .split(',')
             .map(ToString::to_string)
             .collect();
+        let premium_users = match env_optional::<String>("WEB_PREMIUM_USERS") {
+            None => vec![],
+            Some(s) if s.is_empty() => vec![],
+            Some(s) => s.split(',').map(str::trim).map(String::from).collect(),
+        };
         let page_offset_ua_blocklist = match env_optional::<String>("WEB_PAGE_OFFSET_UA_BLOCKLIST")
         {
             None => vec![],
@@ -107,6 +310,24 @@ impl Default for Server {
                     .unwrap(),
             };
 
+        let allowed_hosts = env_list("WEB_ALLOWED_HOSTS");
+
+        let strip_accept_encoding_user_agents = env_list("WEB_STRIP_ACCEPT_ENCODING_USER_AGENTS");
+
+        let raw_body_path_prefixes = env_list("WEB_RAW_BODY_PATH_PREFIXES");
+
+        let legacy_paths = match env_optional::<String>("WEB_LEGACY_PATHS") {
+            None => vec![],
+            Some(s) if s.is_empty() => vec![],
+            Some(s) => s
+                .split(',')
+                .map(|path| {
+                    path.parse()
+                        .unwrap_or_else(|error| panic!("invalid WEB_LEGACY_PATHS: {error}"))
+                })
+                .collect(),
+        };
+
         let base = Base::from_environment();
         let excluded_crate_names = match env_optional::<String>("EXCLUDED_CRATE_NAMES") {
             None => vec![],
@@ -122,6 +343,11 @@ impl Default for Server {
             gh_base_url: "https://api.github.com".to_string(),
             max_upload_size: 10 * 1024 * 1024, // 10 MB default file upload size limit
             max_unpack_size: 512 * 1024 * 1024, // 512 MB max when decompressed
+            premium_max_upload_size: env_optional("WEB_PREMIUM_MAX_UPLOAD_SIZE")
+                .unwrap_or(50 * 1024 * 1024),
+            premium_users,
+            max_publish_body_size: env_optional("WEB_MAX_PUBLISH_BODY_SIZE")
+                .unwrap_or(100 * 1024 * 1024),
             publish_rate_limit: Default::default(),
             new_version_rate_limit: env_optional("MAX_NEW_VERSIONS_DAILY"),
             blocked_traffic: blocked_traffic(),
@@ -140,6 +366,8 @@ impl Default for Server {
                 .unwrap_or(60_000), // 1 minute
             ownership_invitations_expiration_days: 30,
             metrics_authorization_token: dotenv::var("METRICS_AUTHORIZATION_TOKEN").ok(),
+            expose_log_config: env_optional("WEB_EXPOSE_LOG_CONFIG").unwrap_or(false),
+            expose_diagnostics: env_optional("WEB_EXPOSE_DIAGNOSTICS").unwrap_or(false),
             use_test_database_pool: false,
             instance_metrics_log_every_seconds: env_optional("INSTANCE_METRICS_LOG_EVERY_SECONDS"),
             force_unconditional_redirects: dotenv::var("FORCE_UNCONDITIONAL_REDIRECTS").is_ok(),
@@ -154,6 +382,42 @@ impl Default for Server {
             cdn_user_agent: dotenv::var("WEB_CDN_USER_AGENT")
                 .unwrap_or_else(|_| "Amazon CloudFront".into()),
             balance_capacity: BalanceCapacityConfig::from_environment(),
+            static_files: StaticFilesConfig::from_environment(),
+            max_uri_length: env_optional("WEB_MAX_URI_LENGTH").unwrap_or(DEFAULT_MAX_URI_LENGTH),
+            behind_proxy: env_optional("WEB_BEHIND_PROXY").unwrap_or(true),
+            prefer_forwarded_header: env_optional("WEB_PREFER_FORWARDED_HEADER").unwrap_or(true),
+            log_requests: LogRequestsConfig::from_environment(),
+            content_length_monitor_limit: env_optional("WEB_CONTENT_LENGTH_MONITOR_LIMIT"),
+            max_file_streams: env_optional("WEB_MAX_FILE_STREAMS"),
+            file_redirect: dotenv::var("WEB_FILE_REDIRECT_URL").ok().map(|url| {
+                let weight = env_optional("WEB_FILE_REDIRECT_WEIGHT").unwrap_or(0.0);
+                FileRedirect::new(weight, url)
+            }),
+            body_read_timeout: env_optional("WEB_BODY_READ_TIMEOUT").map(Duration::from_secs),
+            decompress_request_bodies: env_optional("WEB_DECOMPRESS_REQUEST_BODIES")
+                .unwrap_or(false),
+            raw_body_path_prefixes,
+            max_response_header_size: env_optional("WEB_MAX_RESPONSE_HEADER_SIZE").map(|max| {
+                match dotenv::var("WEB_STRIP_OVERSIZED_RESPONSE_HEADERS") {
+                    Ok(_) => ResponseHeaderLimit::Strip(max),
+                    Err(_) => ResponseHeaderLimit::Reject(max),
+                }
+            }),
+            coalesce_requests: env_optional("WEB_COALESCE_REQUESTS").unwrap_or(false),
+            log_layer_timings: env_optional("WEB_LOG_LAYER_TIMINGS").unwrap_or(false),
+            client_rate_limit: ClientRateLimitConfig::from_environment(),
+            maintenance: MaintenanceConfig::from_environment(),
+            allowed_hosts,
+            min_tls_version: env_optional("WEB_MIN_TLS_VERSION"),
+            allow_missing_tls_version: env_optional("WEB_ALLOW_MISSING_TLS_VERSION")
+                .unwrap_or(true),
+            normalize_accept_encoding: env_optional("WEB_NORMALIZE_ACCEPT_ENCODING")
+                .unwrap_or(false),
+            strip_accept_encoding_user_agents,
+            security_headers: SecurityHeadersConfig::from_environment(),
+            legacy_paths,
+            normalize_path_mode: env_optional("WEB_NORMALIZE_PATH_MODE")
+                .unwrap_or(NormalizePathMode::Rewrite),
         }
     }
 }
@@ -222,6 +486,32 @@ fn parse_traffic_patterns(patterns: &str) -> impl Iterator<Item = (&str, &str)>
     })
 }
 
+/// Read a comma separated list from an optional environment variable
+///
+/// Surrounding whitespace is trimmed from each entry and empty entries are skipped.
+fn env_list(name: &str) -> Vec<String> {
+    env_optional::<String>(name)
+        .map(|s| parse_list(&s))
+        .unwrap_or_default()
+}
+
+fn parse_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn parse_list_trims_entries_and_skips_empty_ones() {
+    assert_eq!(parse_list(""), Vec::<String>::new());
+    assert_eq!(
+        parse_list(" a.example ,, b.example,"),
+        ["a.example", "b.example"]
+    );
+}
+
 #[test]
 fn parse_traffic_patterns_splits_on_comma_and_looks_for_equal_sign() {
     let pattern_string_1 = "Foo=BAR,Bar=BAZ";
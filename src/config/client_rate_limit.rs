@@ -0,0 +1,113 @@
+use crate::env_optional;
+use crate::util::token::SecureToken;
+use http::{header, HeaderMap};
+use ipnetwork::IpNetwork;
+
+const DEFAULT_BURST: u32 = 20;
+
+/// The request rate limit of the `limit_client_rate` middleware
+pub struct ClientRateLimitConfig {
+    /// The number of requests per second that a single client IP can sustain, or `None` to
+    /// disable the limit
+    pub rate: Option<f64>,
+    /// The number of requests that a client can send at once, before the `rate` applies
+    pub burst: u32,
+    /// IP addresses and CIDR blocks of clients that are not limited, e.g. internal services
+    pub trusted_ips: Vec<IpNetwork>,
+    /// Substrings of the `User-Agent` header of clients that are not limited, e.g. mirrors
+    pub trusted_user_agents: Vec<String>,
+    /// SHA-256 hashes of the API tokens of clients that are not limited, e.g. our own CI
+    pub trusted_token_hashes: Vec<Vec<u8>>,
+}
+
+impl ClientRateLimitConfig {
+    pub fn from_environment() -> Self {
+        let rate = env_optional::<f64>("WEB_CLIENT_RATE_LIMIT");
+        if let Some(rate) = rate {
+            assert!(rate > 0.0, "WEB_CLIENT_RATE_LIMIT must be positive");
+        }
+
+        let trusted_ips = env_optional::<String>("WEB_CLIENT_RATE_LIMIT_TRUSTED_IPS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty())
+            .map(|ip| {
+                ip.parse()
+                    .unwrap_or_else(|_| panic!("invalid trusted IP address: {ip}"))
+            })
+            .collect();
+
+        let trusted_user_agents =
+            env_optional::<String>("WEB_CLIENT_RATE_LIMIT_TRUSTED_USER_AGENTS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|user_agent| !user_agent.is_empty())
+                .map(String::from)
+                .collect();
+
+        let trusted_token_hashes =
+            env_optional::<String>("WEB_CLIENT_RATE_LIMIT_TRUSTED_TOKEN_HASHES")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|hash| !hash.is_empty())
+                .map(|hash| {
+                    hex::decode(hash)
+                        .unwrap_or_else(|_| panic!("invalid trusted token hash: {hash}"))
+                })
+                .collect();
+
+        Self {
+            rate,
+            burst: env_optional("WEB_CLIENT_RATE_LIMIT_BURST").unwrap_or(DEFAULT_BURST),
+            trusted_ips,
+            trusted_user_agents,
+            trusted_token_hashes,
+        }
+    }
+
+    pub fn for_testing() -> Self {
+        Self {
+            rate: None,
+            burst: DEFAULT_BURST,
+            trusted_ips: vec![],
+            trusted_user_agents: vec![],
+            trusted_token_hashes: vec![],
+        }
+    }
+
+    pub fn is_trusted(&self, ip: std::net::IpAddr) -> bool {
+        self.trusted_ips.iter().any(|network| network.contains(ip))
+    }
+
+    /// Whether the request comes from a client with a trusted `User-Agent` or API token
+    ///
+    /// The token is only hashed if any trusted token hashes are configured.
+    pub fn is_trusted_request(&self, headers: &HeaderMap) -> bool {
+        let get =
+            |name: header::HeaderName| headers.get(name).and_then(|value| value.to_str().ok());
+
+        if !self.trusted_user_agents.is_empty() {
+            if let Some(user_agent) = get(header::USER_AGENT) {
+                let trusted = &self.trusted_user_agents;
+                if trusted
+                    .iter()
+                    .any(|trusted| user_agent.contains(trusted.as_str()))
+                {
+                    return true;
+                }
+            }
+        }
+
+        if !self.trusted_token_hashes.is_empty() {
+            if let Some(token) = get(header::AUTHORIZATION) {
+                let hash = SecureToken::hash(token);
+                return self.trusted_token_hashes.contains(&hash);
+            }
+        }
+
+        false
+    }
+}
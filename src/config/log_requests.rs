@@ -0,0 +1,317 @@
+use crate::config::env_list;
+use crate::env_optional;
+use crate::middleware::log_request::LogSink;
+use rand::distributions::{Alphanumeric, DistString};
+use rand::rngs::OsRng;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::Arc;
+
+const DEFAULT_LARGE_RESPONSE_THRESHOLD: u64 = 5 * 1024 * 1024; // 5 MB
+
+const DEFAULT_HOST: &str = "crates-io";
+
+/// The format of the request log lines
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `key=value` pairs, similar to the Heroku router logs
+    Logfmt,
+    /// JSON objects in the Graylog Extended Log Format
+    Gelf,
+    /// RFC 5424 syslog messages, with the key fields as structured data
+    Syslog,
+    /// JSON objects with Elastic Common Schema field names
+    Ecs,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "logfmt" => Ok(Self::Logfmt),
+            "gelf" => Ok(Self::Gelf),
+            "syslog" => Ok(Self::Syslog),
+            "ecs" => Ok(Self::Ecs),
+            _ => Err(format!("unknown log format: {s}")),
+        }
+    }
+}
+
+/// Which responses are included in the request log, based on their status code
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogStatuses {
+    /// All responses are logged
+    All,
+    /// Only `4xx` and `5xx` responses are logged
+    ErrorsOnly,
+    /// Only responses with a status code in one of the ranges are logged
+    Custom(Vec<RangeInclusive<u16>>),
+}
+
+impl LogStatuses {
+    pub fn includes(&self, status: u16) -> bool {
+        match self {
+            Self::All => true,
+            Self::ErrorsOnly => status >= 400,
+            Self::Custom(ranges) => ranges.iter().any(|range| range.contains(&status)),
+        }
+    }
+}
+
+impl FromStr for LogStatuses {
+    type Err = String;
+
+    /// Parses `all`, `errors`, or a comma separated list of status codes and ranges like
+    /// `302,400-599`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => return Ok(Self::All),
+            "errors" => return Ok(Self::ErrorsOnly),
+            _ => {}
+        }
+
+        let parse_status = |status: &str| {
+            status
+                .trim()
+                .parse::<u16>()
+                .map_err(|_| format!("invalid status code in log statuses: {status}"))
+        };
+
+        let ranges = s
+            .split(',')
+            .map(|range| match range.split_once('-') {
+                Some((start, end)) => Ok(parse_status(start)?..=parse_status(end)?),
+                None => parse_status(range).map(|status| status..=status),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self::Custom(ranges))
+    }
+}
+
+/// A query parameter that is logged as a `q_<name>` field, see `LogRequestsConfig::query_params`
+///
+/// Parsed from strings like `page` for all routes, or `sort@/api/v1/crates` for a single route
+/// pattern.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LogQueryParam {
+    pub name: String,
+    pub route: Option<String>,
+}
+
+impl LogQueryParam {
+    /// Whether the parameter is logged for requests to the `route` pattern
+    pub fn applies_to(&self, route: Option<&str>) -> bool {
+        match &self.route {
+            Some(expected) => route == Some(expected.as_str()),
+            None => true,
+        }
+    }
+}
+
+impl FromStr for LogQueryParam {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, route) = match s.split_once('@') {
+            Some((name, route)) if route.starts_with('/') => (name, Some(route.to_string())),
+            Some(_) => return Err(format!("invalid route of logged query parameter: {s}")),
+            None => (s, None),
+        };
+
+        if name.is_empty() {
+            return Err(format!("missing name of logged query parameter: {s}"));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            route,
+        })
+    }
+}
+
+/// What the `fwd` field of the request log contains
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IpLogging {
+    /// The client IP address
+    Raw,
+    /// A salted hash of the client IP address
+    ///
+    /// The hash is the same for all requests from an IP address as long as the salt does not
+    /// change, which allows counting unique clients without logging their addresses.
+    Hashed(String),
+    /// The `fwd` field is omitted
+    None,
+}
+
+/// Serialized without the salt of `Hashed`, which must not be exposed
+impl Serialize for IpLogging {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = match self {
+            Self::Raw => "raw",
+            Self::Hashed(_) => "hashed",
+            Self::None => "none",
+        };
+        serializer.serialize_str(name)
+    }
+}
+
+impl IpLogging {
+    fn from_environment() -> Self {
+        match env_optional::<String>("WEB_LOG_IP").as_deref() {
+            None | Some("raw") => Self::Raw,
+            // A new salt is generated for every process, so the hashes can't be correlated
+            // across restarts
+            Some("hashed") => Self::Hashed(Alphanumeric.sample_string(&mut OsRng, 32)),
+            Some("none") => Self::None,
+            Some(value) => panic!("unknown WEB_LOG_IP value: {value}"),
+        }
+    }
+}
+
+/// The additional destinations of the request log, see `LogSink`
+#[derive(Clone, Default)]
+pub struct LogSinks(Arc<[Arc<dyn LogSink>]>);
+
+impl LogSinks {
+    pub fn new(sinks: Vec<Arc<dyn LogSink>>) -> Self {
+        Self(sinks.into())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn LogSink> {
+        self.0.iter().map(|sink| &**sink)
+    }
+}
+
+impl fmt::Debug for LogSinks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LogSinks({})", self.0.len())
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LogRequestsConfig {
+    /// Responses with a larger body (in bytes) are marked with `LARGE RESPONSE` in the log
+    pub large_response_threshold: u64,
+    /// Names of the router path parameters that are logged as `param_<name>` fields
+    pub path_params: Vec<String>,
+    /// Query parameters that are logged as `q_<name>` fields
+    pub query_params: Vec<LogQueryParam>,
+    /// The format of the request log lines
+    pub format: LogFormat,
+    /// The name of this host, reported as `host` in GELF and `HOSTNAME` in syslog messages
+    pub host: String,
+    /// Whether the `thread_id` and `thread_name` of the thread that ran the handler are logged
+    pub thread_info: bool,
+    /// What is logged as the client IP address
+    pub ip_logging: IpLogging,
+    /// Keys of the W3C `baggage` request header that are logged as `baggage_<key>` fields
+    pub baggage_keys: Vec<String>,
+    /// Names of the request headers that are logged as `hdr_<name>` fields
+    pub request_headers: Vec<String>,
+    /// Names of the response headers that are logged as `res_hdr_<name>` fields
+    pub response_headers: Vec<String>,
+    /// The probability (0.0-1.0) that a request is additionally logged with all headers to the
+    /// `http.verbose` target
+    pub verbose_sample_rate: f32,
+    /// Which responses are logged, based on their status code
+    pub statuses: LogStatuses,
+    /// Whether requests that are marked with `SLOW REQUEST` are also reported to Sentry
+    pub report_slow_requests: bool,
+    /// Whether the `path` field is percent-decoded, with the original in the `raw_path` field
+    pub decode_path: bool,
+    /// Whether the time the handler waited for a blocking thread is logged as `block_wait`
+    pub block_wait: bool,
+    /// Route patterns that are logged as an unquoted `route` field instead of the `path` field
+    pub compact_routes: Vec<String>,
+    /// The maximum length of a logfmt line, before optional fields are dropped from it
+    pub max_line_length: Option<usize>,
+    /// Whether the `location` of download redirects is logged, like for all other redirects
+    pub download_redirect_location: bool,
+    /// Whether an additional `http.start` line is logged before the request is handled
+    pub start_line: bool,
+    /// Whether responses get an `X-Response-Time` header with the logged service time
+    pub response_time_header: bool,
+    /// The deployment version (e.g. the git sha) that is logged as `version` on every line
+    pub version: Option<String>,
+    /// Destinations that receive the metadata of each logged request, besides `tracing`
+    #[serde(skip)]
+    pub sinks: LogSinks,
+}
+
+impl LogRequestsConfig {
+    pub fn from_environment() -> Self {
+        Self {
+            large_response_threshold: env_optional("WEB_LARGE_RESPONSE_THRESHOLD")
+                .unwrap_or(DEFAULT_LARGE_RESPONSE_THRESHOLD),
+            path_params: env_list("WEB_LOG_PATH_PARAMS"),
+            query_params: env_list("WEB_LOG_QUERY_PARAMS")
+                .iter()
+                .map(|param| param.parse().expect("invalid WEB_LOG_QUERY_PARAMS"))
+                .collect(),
+            format: env_optional("WEB_LOG_FORMAT").unwrap_or(LogFormat::Logfmt),
+            host: env_optional("DYNO")
+                .or_else(|| env_optional("HOSTNAME"))
+                .unwrap_or_else(|| DEFAULT_HOST.into()),
+            thread_info: env_optional("WEB_LOG_THREAD_INFO").unwrap_or(false),
+            ip_logging: IpLogging::from_environment(),
+            baggage_keys: env_list("WEB_LOG_BAGGAGE_KEYS"),
+            request_headers: header_names("WEB_LOG_REQUEST_HEADERS"),
+            response_headers: header_names("WEB_LOG_RESPONSE_HEADERS"),
+            verbose_sample_rate: env_optional("WEB_LOG_VERBOSE_SAMPLE_RATE").unwrap_or(0.0),
+            statuses: env_optional("WEB_LOG_STATUSES").unwrap_or(LogStatuses::All),
+            report_slow_requests: env_optional("WEB_REPORT_SLOW_REQUESTS").unwrap_or(false),
+            decode_path: env_optional("WEB_LOG_DECODE_PATH").unwrap_or(false),
+            block_wait: env_optional("WEB_LOG_BLOCK_WAIT").unwrap_or(false),
+            compact_routes: env_list("WEB_LOG_COMPACT_ROUTES"),
+            max_line_length: env_optional("WEB_LOG_MAX_LINE_LENGTH"),
+            download_redirect_location: env_optional("WEB_LOG_DOWNLOAD_REDIRECT_LOCATION")
+                .unwrap_or(false),
+            start_line: env_optional("WEB_LOG_START_LINE").unwrap_or(false),
+            response_time_header: env_optional("WEB_RESPONSE_TIME_HEADER").unwrap_or(false),
+            version: env_optional("HEROKU_SLUG_COMMIT"),
+            sinks: LogSinks::default(),
+        }
+    }
+
+    pub fn for_testing() -> Self {
+        Self {
+            large_response_threshold: DEFAULT_LARGE_RESPONSE_THRESHOLD,
+            path_params: vec![],
+            query_params: vec![],
+            format: LogFormat::Logfmt,
+            host: DEFAULT_HOST.into(),
+            thread_info: false,
+            ip_logging: IpLogging::Raw,
+            baggage_keys: vec![],
+            request_headers: vec![],
+            response_headers: vec![],
+            verbose_sample_rate: 0.0,
+            statuses: LogStatuses::All,
+            report_slow_requests: false,
+            decode_path: false,
+            block_wait: false,
+            compact_routes: vec![],
+            max_line_length: None,
+            download_redirect_location: false,
+            start_line: false,
+            response_time_header: false,
+            version: None,
+            sinks: LogSinks::default(),
+        }
+    }
+}
+
+/// Header names are case-insensitive, but they are logged in lowercase
+fn header_names(name: &str) -> Vec<String> {
+    let mut names = env_list(name);
+    names
+        .iter_mut()
+        .for_each(|name| name.make_ascii_lowercase());
+    names
+}
@@ -0,0 +1,40 @@
+use crate::env_optional;
+
+const DEFAULT_MESSAGE: &str = "crates.io is down for maintenance, please try again later";
+const DEFAULT_RETRY_AFTER_SECONDS: u64 = 5 * 60;
+
+/// The response of the `maintenance_mode` middleware
+pub struct MaintenanceConfig {
+    /// Whether the application starts in maintenance mode, see `App::maintenance_mode`
+    pub enabled: bool,
+    /// The message that is shown to clients while the maintenance is in progress
+    pub message: String,
+    /// The number of seconds after which clients should retry their requests
+    pub retry_after: u64,
+    /// The path of a static asset (e.g. a logo) that is still served during the maintenance,
+    /// and is shown on the maintenance page
+    pub asset_path: Option<String>,
+}
+
+impl MaintenanceConfig {
+    pub fn from_environment() -> Self {
+        Self {
+            enabled: env_optional("WEB_MAINTENANCE_MODE").unwrap_or(false),
+            message: env_optional("WEB_MAINTENANCE_MESSAGE")
+                .unwrap_or_else(|| DEFAULT_MESSAGE.to_string()),
+            retry_after: env_optional("WEB_MAINTENANCE_RETRY_AFTER")
+                .unwrap_or(DEFAULT_RETRY_AFTER_SECONDS),
+            asset_path: env_optional::<String>("WEB_MAINTENANCE_ASSET_PATH")
+                .filter(|path| !path.is_empty()),
+        }
+    }
+
+    pub fn for_testing() -> Self {
+        Self {
+            enabled: false,
+            message: DEFAULT_MESSAGE.to_string(),
+            retry_after: DEFAULT_RETRY_AFTER_SECONDS,
+            asset_path: None,
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::env_optional;
+use http::header::{self, HeaderName, HeaderValue};
+
+const DEFAULT_CONTENT_TYPE_OPTIONS: &str = "nosniff";
+const DEFAULT_FRAME_OPTIONS: &str = "DENY";
+const DEFAULT_REFERRER_POLICY: &str = "strict-origin-when-cross-origin";
+
+/// Security headers that are added to all responses, unless the handler already set them
+pub struct SecurityHeadersConfig {
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl SecurityHeadersConfig {
+    pub fn from_environment() -> Self {
+        let headers = [
+            (
+                header::X_CONTENT_TYPE_OPTIONS,
+                "WEB_X_CONTENT_TYPE_OPTIONS",
+                Some(DEFAULT_CONTENT_TYPE_OPTIONS),
+            ),
+            (
+                header::X_FRAME_OPTIONS,
+                "WEB_X_FRAME_OPTIONS",
+                Some(DEFAULT_FRAME_OPTIONS),
+            ),
+            (
+                header::REFERRER_POLICY,
+                "WEB_REFERRER_POLICY",
+                Some(DEFAULT_REFERRER_POLICY),
+            ),
+            (
+                header::STRICT_TRANSPORT_SECURITY,
+                "WEB_STRICT_TRANSPORT_SECURITY",
+                None,
+            ),
+        ];
+
+        let headers = headers
+            .into_iter()
+            .filter_map(|(name, variable, default)| {
+                // An empty value disables the header
+                let value = env_optional::<String>(variable).or_else(|| default.map(Into::into))?;
+                if value.is_empty() {
+                    return None;
+                }
+
+                let value = HeaderValue::from_str(&value)
+                    .unwrap_or_else(|_| panic!("{variable} is not a valid header value"));
+                Some((name, value))
+            })
+            .collect();
+
+        Self { headers }
+    }
+
+    pub fn for_testing() -> Self {
+        Self {
+            headers: vec![
+                (
+                    header::X_CONTENT_TYPE_OPTIONS,
+                    HeaderValue::from_static(DEFAULT_CONTENT_TYPE_OPTIONS),
+                ),
+                (
+                    header::X_FRAME_OPTIONS,
+                    HeaderValue::from_static(DEFAULT_FRAME_OPTIONS),
+                ),
+                (
+                    header::REFERRER_POLICY,
+                    HeaderValue::from_static(DEFAULT_REFERRER_POLICY),
+                ),
+            ],
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::env_optional;
+use std::collections::HashMap;
+
+/// MIME types that are used instead of the guesses of `ServeDir`, unless configured otherwise
+const DEFAULT_MIME_TYPES: &[(&str, &str)] = &[("wasm", "application/wasm")];
+
+const DEFAULT_GZIP_MAX_SIZE: u64 = 10 * 1024 * 1024; // 10 MiB
+
+pub struct StaticFilesConfig {
+    /// Resources advertised via `Link: <...>; rel=preload` headers on the `index.html` response
+    pub preload_resources: Vec<String>,
+    /// The gzip compression level (0-9) used for files without a precompressed variant
+    ///
+    /// If `None`, static files are not compressed on the fly.
+    pub gzip_level: Option<u32>,
+    /// Files larger than this number of bytes are not compressed on the fly, since the whole file
+    /// is buffered in memory for the compression
+    pub gzip_max_size: u64,
+    /// `Content-Type` overrides for static files, keyed by the lowercase file extension
+    pub mime_types: HashMap<String, String>,
+    /// A path prefix that is removed before looking up files in the `dist` directory
+    ///
+    /// With a prefix of `/assets`, `/assets/app.js` is served from `dist/app.js`. Paths without
+    /// the prefix are looked up unchanged.
+    pub strip_prefix: Option<String>,
+}
+
+impl StaticFilesConfig {
+    pub fn from_environment() -> Self {
+        let preload_resources = match env_optional::<String>("WEB_PRELOAD_RESOURCES") {
+            None => vec![],
+            Some(s) if s.is_empty() => vec![],
+            Some(s) => s.split(',').map(String::from).collect(),
+        };
+
+        let gzip_level: Option<u32> = env_optional("WEB_STATIC_GZIP_LEVEL");
+        if let Some(level) = gzip_level {
+            assert!(level <= 9, "WEB_STATIC_GZIP_LEVEL must be between 0 and 9");
+        }
+        let gzip_max_size =
+            env_optional("WEB_STATIC_GZIP_MAX_SIZE").unwrap_or(DEFAULT_GZIP_MAX_SIZE);
+
+        let mut mime_types = default_mime_types();
+        if let Some(s) = env_optional::<String>("WEB_STATIC_MIME_TYPES") {
+            for entry in s.split(',').filter(|entry| !entry.is_empty()) {
+                let (extension, mime_type) = entry
+                    .split_once('=')
+                    .expect("WEB_STATIC_MIME_TYPES entries must look like `extension=mime/type`");
+                mime_types.insert(extension.to_ascii_lowercase(), mime_type.to_string());
+            }
+        }
+
+        let strip_prefix = env_optional::<String>("WEB_STATIC_STRIP_PREFIX")
+            .map(|prefix| prefix.trim_end_matches('/').to_string())
+            .filter(|prefix| !prefix.is_empty());
+        if let Some(prefix) = &strip_prefix {
+            assert!(
+                prefix.starts_with('/'),
+                "WEB_STATIC_STRIP_PREFIX must start with a `/`"
+            );
+        }
+
+        Self {
+            preload_resources,
+            gzip_level,
+            gzip_max_size,
+            mime_types,
+            strip_prefix,
+        }
+    }
+
+    pub fn for_testing() -> Self {
+        Self {
+            preload_resources: vec![],
+            gzip_level: None,
+            gzip_max_size: DEFAULT_GZIP_MAX_SIZE,
+            mime_types: default_mime_types(),
+            strip_prefix: None,
+        }
+    }
+}
+
+fn default_mime_types() -> HashMap<String, String> {
+    DEFAULT_MIME_TYPES
+        .iter()
+        .map(|(extension, mime_type)| (extension.to_string(), mime_type.to_string()))
+        .collect()
+}
@@ -21,6 +21,7 @@ mod prelude {
     pub use crate::util::errors::{cargo_err, AppError, AppResult}; // TODO: Remove cargo_err from here
     pub use crate::util::{AppResponse, EndpointResult};
 
+    use conduit_axum::NegotiatedBody;
     use indexmap::IndexMap;
     use serde::Serialize;
 
@@ -28,6 +29,9 @@ mod prelude {
         fn redirect(&self, url: String) -> AppResponse;
 
         fn json<T: Serialize>(&self, t: &T) -> AppResponse;
+        /// Like `json()`, but encoded as MessagePack if the client prefers it, see
+        /// `conduit_axum::NegotiatedBody`
+        fn negotiated<T: Serialize + Send + Sync + 'static>(&self, t: T) -> AppResponse;
         fn query(&self) -> IndexMap<String, String>;
         fn wants_json(&self) -> bool;
         fn query_with_params(&self, params: IndexMap<String, String>) -> String;
@@ -38,6 +42,13 @@ mod prelude {
             crate::util::json_response(t)
         }
 
+        fn negotiated<T: Serialize + Send + Sync + 'static>(&self, t: T) -> AppResponse {
+            http::Response::builder()
+                .extension(NegotiatedBody::new(t))
+                .body(conduit::Body::empty())
+                .unwrap() // Should not panic, since no headers are set
+        }
+
         fn query(&self) -> IndexMap<String, String> {
             url::form_urlencoded::parse(self.query_string().unwrap_or("").as_bytes())
                 .into_owned()
@@ -75,9 +86,11 @@ pub mod util;
 
 pub mod category;
 pub mod crate_owner_invitation;
+pub mod diagnostics;
 pub mod github;
 pub mod keyword;
 pub mod krate;
+pub mod log_config;
 pub mod metrics;
 pub mod site_metadata;
 pub mod team;
@@ -0,0 +1,37 @@
+use super::frontend_prelude::*;
+use crate::controllers::metrics;
+use crate::util::errors::not_found;
+
+/// Handles the `GET /api/private/diagnostics` endpoint.
+///
+/// Returns the in-flight requests, the state of the blocking thread pool, the open file streams
+/// and the uptime of this instance, if enabled via `WEB_EXPOSE_DIAGNOSTICS`. The endpoint
+/// requires the `METRICS_AUTHORIZATION_TOKEN`.
+pub fn show(req: &mut dyn RequestExt) -> EndpointResult {
+    let app = req.app();
+    if !app.config.expose_diagnostics {
+        return Err(not_found());
+    }
+
+    metrics::authorize(req)?;
+
+    let metrics = &app.instance_metrics;
+    let waits = metrics.blocking_wait_times.get_sample_count();
+    let mean_wait_ms = if waits == 0 {
+        0.0
+    } else {
+        metrics.blocking_wait_times.get_sample_sum() * 1000.0 / waits as f64
+    };
+
+    Ok(req.json(&json!({
+        "requests_in_flight": metrics.requests_in_flight.get(),
+        "blocking_pool": {
+            "in_flight": conduit_axum::blocking_tasks_in_flight(),
+            "queued": conduit_axum::blocking_tasks_queued(),
+            "waits": waits,
+            "mean_wait_ms": mean_wait_ms,
+        },
+        "file_streams_open": conduit_axum::file_streams_open(),
+        "uptime_seconds": app.started_at.elapsed().as_secs(),
+    })))
+}
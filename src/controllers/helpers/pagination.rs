@@ -1,5 +1,6 @@
 use crate::config::Server;
 use crate::controllers::prelude::*;
+use crate::middleware::client_info::ClientInfo;
 use crate::middleware::log_request::CustomMetadataRequestExt;
 use crate::models::helpers::with_count::*;
 use crate::util::errors::{bad_request, AppResult};
@@ -12,8 +13,6 @@ use diesel::query_dsl::LoadQuery;
 use diesel::sql_types::BigInt;
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use std::net::IpAddr;
-use std::str::FromStr;
 use std::sync::Arc;
 
 const MAX_PAGE_BEFORE_SUSPECTED_BOT: u32 = 10;
@@ -260,7 +259,10 @@ impl RawSeekPayload {
 /// IP is on the CIDR block list.
 fn is_useragent_or_ip_blocked(config: &Server, req: &dyn RequestExt) -> bool {
     let user_agent = request_header(req, header::USER_AGENT);
-    let client_ip = request_header(req, "x-real-ip");
+    let client_ip = req
+        .extensions()
+        .get::<ClientInfo>()
+        .and_then(|client_info| client_info.ip);
 
     // check if user agent is blocked
     if config
@@ -271,8 +273,8 @@ fn is_useragent_or_ip_blocked(config: &Server, req: &dyn RequestExt) -> bool {
         return true;
     }
 
-    // check if client ip is blocked, needs to be an IPv4 address
-    if let Ok(client_ip) = IpAddr::from_str(client_ip) {
+    // check if client ip is blocked
+    if let Some(client_ip) = client_ip {
         if config
             .page_offset_cidr_blocklist
             .iter()
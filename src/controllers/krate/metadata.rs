@@ -111,7 +111,7 @@ pub fn summary(req: &mut dyn RequestExt) -> EndpointResult {
         .map(Category::into)
         .collect::<Vec<EncodableCategory>>();
 
-    Ok(req.json(&json!({
+    Ok(req.negotiated(json!({
         "num_downloads": num_downloads,
         "num_crates": num_crates,
         "new_crates": encode_crates(new_crates)?,
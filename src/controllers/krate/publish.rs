@@ -5,7 +5,8 @@ use flate2::read::GzDecoder;
 use hex::ToHex;
 use sha2::{Digest, Sha256};
 use std::collections::BTreeMap;
-use std::io::Read;
+use std::io::{self, Read};
+use std::net::IpAddr;
 use std::path::Path;
 use std::sync::Arc;
 
@@ -16,11 +17,13 @@ use crate::models::{
 };
 use crate::worker;
 
+use crate::middleware::client_info::ClientInfo;
 use crate::middleware::log_request::CustomMetadataRequestExt;
 use crate::models::token::EndpointScope;
+use crate::router::TierBodySizeLimit;
 use crate::schema::*;
-use crate::util::errors::{cargo_err, AppResult};
-use crate::util::{read_fill, read_le_u32, CargoVcsInfo, LimitErrorReader, Maximums};
+use crate::util::errors::{cargo_err, AppError, AppResult};
+use crate::util::{read_fill, read_le_u32, CargoVcsInfo, LimitErrorReader, LimitReached, Maximums};
 use crate::views::{
     EncodableCrate, EncodableCrateDependency, EncodableCrateUpload, GoodCrate, PublishWarnings,
 };
@@ -36,6 +39,9 @@ pub const WILDCARD_ERROR_MESSAGE: &str = "wildcard (`*`) dependency constraints
      libraries-use--as-a-version-for-their-dependencies for more \
      information";
 
+const MALFORMED_TARBALL_ERROR_MESSAGE: &str =
+    "uploaded tarball is malformed or too large when decompressed";
+
 /// Handles the `PUT /crates/new` route.
 /// Used by `cargo publish` to publish a new crate or to publish a new version of an
 /// existing crate.
@@ -81,7 +87,14 @@ pub fn publish(req: &mut dyn RequestExt) -> EndpointResult {
     let auth = AuthCheck::default()
         .with_endpoint_scope(endpoint_scope)
         .for_crate(&new_crate.name)
-        .check(req)?;
+        .check_with_tier(req)?;
+
+    // Crates with their own upload size limit are checked against it below
+    let tier_limit = TierBodySizeLimit::for_publish(&app.config);
+    let crate_limit = existing_crate.and_then(|krate| krate.max_upload_size);
+    if crate_limit.is_none() {
+        tier_limit.check(req)?;
+    }
 
     let api_token_id = auth.api_token_id();
     let user = auth.user();
@@ -167,7 +180,7 @@ pub fn publish(req: &mut dyn RequestExt) -> EndpointResult {
 
         let maximums = Maximums::new(
             krate.max_upload_size,
-            app.config.max_upload_size,
+            tier_limit.for_request(req),
             app.config.max_unpack_size,
         );
 
@@ -223,7 +236,15 @@ pub fn publish(req: &mut dyn RequestExt) -> EndpointResult {
         let top_versions = krate.top_versions(&conn)?;
 
         let pkg_name = format!("{}-{}", krate.name, vers);
-        let cargo_vcs_info = verify_tarball(&pkg_name, &tarball, maximums.max_unpack_size)?;
+        let max_unpack = maximums.max_unpack_size;
+        let cargo_vcs_info = match verify_tarball(&pkg_name, &tarball, max_unpack) {
+            Ok(cargo_vcs_info) => cargo_vcs_info,
+            Err(TarballError::TooLarge) => {
+                report_suspected_zip_bomb(req, tarball.len() as u64, max_unpack);
+                return Err(cargo_err(MALFORMED_TARBALL_ERROR_MESSAGE));
+            }
+            Err(TarballError::Invalid(error)) => return Err(error),
+        };
         let pkg_path_in_vcs = cargo_vcs_info.map(|info| info.path_in_vcs);
 
         if let Some(readme) = new_crate.readme {
@@ -423,11 +444,53 @@ pub fn add_dependencies(
     Ok(git_deps)
 }
 
+/// Why `verify_tarball()` rejected a tarball
+#[derive(Debug)]
+enum TarballError {
+    /// The tarball exceeds `max_unpack` when decompressed, which may be a zip bomb
+    TooLarge,
+    Invalid(Box<dyn AppError>),
+}
+
+impl From<io::Error> for TarballError {
+    fn from(error: io::Error) -> Self {
+        if LimitReached::is_cause_of(&error) {
+            return Self::TooLarge;
+        }
+
+        Self::Invalid(error.into())
+    }
+}
+
+/// Log and count an upload whose decompression was aborted by the `max_unpack` limit
+fn report_suspected_zip_bomb(req: &dyn RequestExt, compressed: u64, max_unpack: u64) {
+    let client_ip = req
+        .extensions()
+        .get::<ClientInfo>()
+        .and_then(|client_info| client_info.ip);
+
+    log_suspected_zip_bomb(compressed, max_unpack, client_ip);
+    req.add_custom_metadata("zip_bomb_suspected", true);
+    req.app().instance_metrics.zip_bombs_suspected_total.inc();
+}
+
+/// Only `max_unpack` bytes were decompressed before the limit was hit, so that is all we know
+/// about the decompressed size.
+fn log_suspected_zip_bomb(compressed: u64, max_unpack: u64, client_ip: Option<IpAddr>) {
+    warn!(
+        zip_bomb_suspected = true,
+        compressed_bytes = compressed,
+        decompressed_bytes = max_unpack,
+        client_ip = ?client_ip,
+        "Aborted the decompression of an uploaded tarball"
+    );
+}
+
 fn verify_tarball(
     pkg_name: &str,
     tarball: &[u8],
     max_unpack: u64,
-) -> AppResult<Option<CargoVcsInfo>> {
+) -> Result<Option<CargoVcsInfo>, TarballError> {
     // All our data is currently encoded with gzip
     let decoder = GzDecoder::new(tarball);
 
@@ -443,9 +506,11 @@ fn verify_tarball(
 
     for entry in archive.entries()? {
         let mut entry = entry.map_err(|err| {
-            err.chain(cargo_err(
-                "uploaded tarball is malformed or too large when decompressed",
-            ))
+            if LimitReached::is_cause_of(&err) {
+                return TarballError::TooLarge;
+            }
+
+            TarballError::Invalid(err.chain(cargo_err(MALFORMED_TARBALL_ERROR_MESSAGE)))
         })?;
 
         // Verify that all entries actually start with `$name-$vers/`.
@@ -455,7 +520,7 @@ fn verify_tarball(
         // the registry!
         let entry_path = entry.path()?;
         if !entry_path.starts_with(pkg_name) {
-            return Err(cargo_err("invalid tarball uploaded"));
+            return Err(TarballError::Invalid(cargo_err("invalid tarball uploaded")));
         }
         if entry_path == vcs_info_path {
             let mut contents = String::new();
@@ -470,7 +535,7 @@ fn verify_tarball(
         // generate a tarball with these file types so this should work for now.
         let entry_type = entry.header().entry_type();
         if entry_type.is_hard_link() || entry_type.is_symlink() {
-            return Err(cargo_err("invalid tarball uploaded"));
+            return Err(TarballError::Invalid(cargo_err("invalid tarball uploaded")));
         }
     }
     Ok(vcs_info)
@@ -478,8 +543,11 @@ fn verify_tarball(
 
 #[cfg(test)]
 mod tests {
-    use super::{missing_metadata_error_message, verify_tarball};
+    use super::{
+        log_suspected_zip_bomb, missing_metadata_error_message, verify_tarball, TarballError,
+    };
     use crate::admin::render_readmes::tests::add_file;
+    use crate::util::tracing::capture_logs;
     use flate2::read::GzEncoder;
     use std::io::Read;
 
@@ -546,4 +614,33 @@ mod tests {
             .unwrap();
         assert_eq!(vcs_info.path_in_vcs, "path/in/vcs");
     }
+
+    #[test]
+    fn verify_tarball_test_zip_bomb() {
+        let len = 1024 * 1024;
+        let mut pkg = tar::Builder::new(vec![]);
+        add_file(&mut pkg, "foo-0.0.1/a", &vec![0; len]);
+        let mut serialized_archive = vec![];
+        GzEncoder::new(pkg.into_inner().unwrap().as_slice(), Default::default())
+            .read_to_end(&mut serialized_archive)
+            .unwrap();
+
+        let limit = 128 * 1024;
+        assert!(matches!(
+            verify_tarball("foo-0.0.1", &serialized_archive, limit),
+            Err(TarballError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn suspected_zip_bombs_are_logged() {
+        let (logs, _guard) = capture_logs();
+        log_suspected_zip_bomb(1024, 128 * 1024, Some([10, 0, 0, 1].into()));
+
+        let output = logs.contents();
+        assert!(output.contains("zip_bomb_suspected=true"), "{output}");
+        assert!(output.contains("compressed_bytes=1024"), "{output}");
+        assert!(output.contains("decompressed_bytes=131072"), "{output}");
+        assert!(output.contains("client_ip=Some(10.0.0.1)"), "{output}");
+    }
 }
@@ -0,0 +1,22 @@
+use super::frontend_prelude::*;
+use crate::controllers::metrics;
+use crate::util::errors::not_found;
+use crate::util::tracing::log_directives;
+
+/// Handles the `GET /api/private/log_config` endpoint.
+///
+/// Returns the effective request log configuration and the `RUST_LOG` directives, if enabled
+/// via `WEB_EXPOSE_LOG_CONFIG`. The endpoint requires the `METRICS_AUTHORIZATION_TOKEN`.
+pub fn show(req: &mut dyn RequestExt) -> EndpointResult {
+    let config = &req.app().config;
+    if !config.expose_log_config {
+        return Err(not_found());
+    }
+
+    metrics::authorize(req)?;
+
+    Ok(req.json(&json!({
+        "log_requests": &config.log_requests,
+        "rust_log": log_directives(),
+    })))
+}
@@ -8,6 +8,30 @@ use prometheus::{Encoder, TextEncoder};
 pub fn prometheus(req: &mut dyn RequestExt) -> EndpointResult {
     let app = req.app();
 
+    authorize(req)?;
+
+    let metrics = match req.params()["kind"].as_str() {
+        "service" => app.service_metrics.gather(&*req.db_read()?)?,
+        "instance" => app.instance_metrics.gather(app)?,
+        _ => return Err(not_found()),
+    };
+
+    let mut output = Vec::new();
+    TextEncoder::new().encode(&metrics, &mut output)?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .header(header::CONTENT_LENGTH, output.len())
+        .body(Body::from_vec(output))?)
+}
+
+/// Check that the request carries the `METRICS_AUTHORIZATION_TOKEN`
+///
+/// This also guards the other private endpoints that are meant for operators, like
+/// `GET /api/private/log_config`.
+pub(crate) fn authorize(req: &dyn RequestExt) -> AppResult<()> {
+    let app = req.app();
+
     if let Some(expected_token) = &app.config.metrics_authorization_token {
         let provided_token = req
             .headers()
@@ -24,17 +48,5 @@ pub fn prometheus(req: &mut dyn RequestExt) -> EndpointResult {
         return Err(Box::new(MetricsDisabled));
     }
 
-    let metrics = match req.params()["kind"].as_str() {
-        "service" => app.service_metrics.gather(&*req.db_read()?)?,
-        "instance" => app.instance_metrics.gather(app)?,
-        _ => return Err(not_found()),
-    };
-
-    let mut output = Vec::new();
-    TextEncoder::new().encode(&metrics, &mut output)?;
-
-    Ok(Response::builder()
-        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
-        .header(header::CONTENT_LENGTH, output.len())
-        .body(Body::from_vec(output))?)
+    Ok(())
 }
@@ -30,7 +30,10 @@ use std::str::FromStr;
 use std::sync::Arc;
 
 use crate::app::AppState;
-use conduit_axum::ConduitFallback;
+use axum::Extension;
+use conduit_axum::{
+    ConduitFallback, ContentLengthCheck, FallbackConfig, FileStreamLimit, TransferredBytes,
+};
 use tikv_jemallocator::Jemalloc;
 
 #[global_allocator]
@@ -82,11 +85,44 @@ pub enum Env {
 /// Called from *src/bin/server.rs*.
 pub fn build_handler(app: Arc<App>) -> axum::Router {
     let endpoints = router::build_router(&app);
+    let body_size_limits = Arc::new(router::build_body_size_limits(&app.config));
+    let body_required_routes = Arc::new(router::build_body_required_routes());
     let conduit_handler = middleware::build_middleware(app.clone(), endpoints);
 
+    let content_length_check = match app.config.content_length_monitor_limit {
+        Some(limit) => ContentLengthCheck::Monitor { limit },
+        None => ContentLengthCheck::Enforce,
+    };
+    let request_bytes = app.instance_metrics.request_body_bytes_total.clone();
+    let response_bytes = app.instance_metrics.response_body_bytes_total.clone();
+    let transferred_bytes = TransferredBytes::new(
+        move |bytes| request_bytes.inc_by(bytes),
+        move |bytes| response_bytes.inc_by(bytes),
+    );
+
+    let fallback_config = FallbackConfig {
+        content_length_check,
+        verbose_errors: app.config.env() != Env::Production,
+        file_stream_limit: app.config.max_file_streams.map(FileStreamLimit::new),
+        file_redirect: app.config.file_redirect.clone(),
+        body_read_timeout: app.config.body_read_timeout,
+        decompress_gzip_bodies: app.config.decompress_request_bodies,
+        raw_body_path_prefixes: app.config.raw_body_path_prefixes.clone(),
+        max_response_header_size: app.config.max_response_header_size,
+        record_layer_timings: app.config.log_layer_timings,
+        transferred_bytes: Some(transferred_bytes),
+        deprecated_routes: router::build_deprecated_routes(),
+        cache_control_defaults: router::build_cache_control_defaults(),
+        ..Default::default()
+    };
+
     let state = AppState(app);
-    let axum_router = axum::Router::new().with_state(state.clone());
-    middleware::apply_axum_middleware(state, axum_router.conduit_fallback(conduit_handler))
+    let axum_router = axum::Router::new()
+        .with_state(state.clone())
+        .conduit_fallback_with_config(conduit_handler, fallback_config)
+        .layer(Extension(body_size_limits))
+        .layer(Extension(body_required_routes));
+    middleware::apply_axum_middleware(state, axum_router)
 }
 
 /// Convenience function requiring that an environment variable is set.
@@ -43,6 +43,15 @@ metrics! {
         pub response_times: HistogramVec["endpoint"],
         /// Nmber of responses per status code
         pub responses_by_status_code_total: IntCounterVec["status"],
+        /// Total size of the request bodies, in bytes
+        pub request_body_bytes_total: IntCounter,
+        /// Total size of the response bodies, in bytes
+        pub response_body_bytes_total: IntCounter,
+
+        /// Time the conduit handlers waited for a thread of the blocking thread pool
+        pub blocking_wait_times: Histogram,
+        /// Number of conduit handlers currently running on the blocking thread pool
+        blocking_tasks_in_flight: IntGauge,
 
         /// Number of download requests that were served with an unconditional redirect.
         pub downloads_unconditional_redirects_total: IntCounter,
@@ -57,6 +66,9 @@ metrics! {
         pub version_id_cache_hits: IntCounter,
         /// Number of version ID cache misses on the download endpoint.
         pub version_id_cache_misses: IntCounter,
+
+        /// Number of uploaded tarballs that exceeded the unpack limit when decompressed.
+        pub zip_bombs_suspected_total: IntCounter,
     }
 
     // All instance metrics will be prefixed with this namespace.
@@ -74,6 +86,9 @@ impl InstanceMetrics {
         self.downloads_not_counted_total
             .set(app.downloads_counter.pending_count());
 
+        self.blocking_tasks_in_flight
+            .set(conduit_axum::blocking_tasks_in_flight() as i64);
+
         Ok(self.registry.gather())
     }
 
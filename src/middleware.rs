@@ -7,20 +7,35 @@ mod prelude {
 
 use self::app::AppMiddleware;
 use self::known_error_to_json::KnownErrorToJson;
+use self::single_flight::SingleFlight;
 
 pub mod app;
+pub mod app_router;
 mod balance_capacity;
 mod block_traffic;
+pub mod catch_panic;
+pub mod check_host;
+pub mod client_info;
 mod debug;
 mod ember_html;
 mod head;
 mod known_error_to_json;
+pub mod limit_client_rate;
+mod limit_uri_length;
 pub mod log_request;
+mod maintenance_mode;
+mod normalize_accept_encoding;
 pub mod normalize_path;
+mod readiness;
+pub mod require_tls_version;
 mod require_user_agent;
+pub mod rewrite_legacy_paths;
+mod security_headers;
 pub mod session;
+mod single_flight;
 mod static_or_continue;
 mod update_metrics;
+mod verify_origin;
 
 use conduit_conditional_get::ConditionalGet;
 use conduit_middleware::MiddlewareBuilder;
@@ -30,6 +45,7 @@ use ::sentry::integrations::tower as sentry_tower;
 use axum::error_handling::HandleErrorLayer;
 use axum::middleware::{from_fn, from_fn_with_state};
 use axum::Router;
+use std::path::Path;
 use std::sync::Arc;
 
 use crate::app::AppState;
@@ -50,7 +66,52 @@ pub fn apply_axum_middleware(state: AppState, router: Router) -> Router {
     let middleware = tower::ServiceBuilder::new()
         .layer(sentry_tower::NewSentryLayer::<Request>::new_from_top())
         .layer(sentry_tower::SentryHttpLayer::with_transaction())
-        .layer(from_fn(log_request::log_requests))
+        .layer(from_fn_with_state(
+            state.clone(),
+            client_info::resolve_client_info,
+        ))
+        .layer(from_fn_with_state(
+            Arc::new(state.config.log_requests.clone()),
+            log_request::log_requests,
+        ))
+        // Panics are caught inside of the access log, so that they are logged with their `500`
+        .layer(from_fn_with_state(
+            Arc::new(catch_panic::report_panic) as catch_panic::PanicHook,
+            catch_panic::catch_panic,
+        ))
+        .layer(from_fn_with_state(
+            state.clone(),
+            security_headers::add_security_headers,
+        ))
+        .layer(from_fn_with_state(
+            state.clone(),
+            readiness::check_readiness,
+        ))
+        .layer(from_fn_with_state(
+            state.clone(),
+            maintenance_mode::check_maintenance_mode,
+        ))
+        .layer(from_fn_with_state(
+            state.clone(),
+            limit_uri_length::limit_uri_length,
+        ))
+        .layer(from_fn_with_state(state.clone(), check_host::check_host))
+        .layer(from_fn_with_state(
+            state.clone(),
+            limit_client_rate::limit_client_rate,
+        ))
+        .layer(from_fn_with_state(
+            state.clone(),
+            require_tls_version::require_tls_version,
+        ))
+        .layer(from_fn_with_state(
+            state.clone(),
+            normalize_accept_encoding::normalize_accept_encoding,
+        ))
+        .layer(from_fn_with_state(
+            state.clone(),
+            rewrite_legacy_paths::rewrite_legacy_paths,
+        ))
         .layer(from_fn_with_state(
             state.clone(),
             update_metrics::update_metrics,
@@ -69,15 +130,23 @@ pub fn apply_axum_middleware(state: AppState, router: Router) -> Router {
             state.clone(),
             block_traffic::block_traffic,
         ))
+        .layer(from_fn_with_state(
+            state.clone(),
+            verify_origin::verify_origin,
+        ))
         .layer(from_fn(head::support_head_requests))
         .layer(HandleErrorLayer::new(dummy_error_handler))
-        .option_layer(
-            (env == Env::Development).then(|| from_fn(static_or_continue::serve_local_uploads)),
-        )
+        .option_layer((env == Env::Development).then(|| {
+            let dir: static_or_continue::UploadsDir = Arc::from(Path::new("local_uploads"));
+            from_fn_with_state(dir, static_or_continue::serve_local_uploads)
+        }))
         // Serve the static files in the *dist* directory, which are the frontend assets.
         // Not needed for the backend tests.
         .layer(HandleErrorLayer::new(dummy_error_handler))
-        .option_layer((env != Env::Test).then(|| from_fn(static_or_continue::serve_dist)))
+        .option_layer((env != Env::Test).then(|| {
+            let dir: static_or_continue::DistDir = (state.clone(), Arc::from(Path::new("dist")));
+            from_fn_with_state(dir, static_or_continue::serve_dist)
+        }))
         .layer(HandleErrorLayer::new(dummy_error_handler))
         .option_layer(
             (env != Env::Test).then(|| from_fn_with_state(state.clone(), ember_html::serve_html)),
@@ -105,6 +174,7 @@ async fn dummy_error_handler(_err: axum::BoxError) -> http::StatusCode {
 }
 
 pub fn build_middleware(app: Arc<App>, endpoints: RouteBuilder) -> MiddlewareBuilder {
+    let coalesce_requests = app.config.coalesce_requests;
     let mut m = MiddlewareBuilder::new(endpoints);
 
     m.add(log_request::LogRequests::default());
@@ -114,5 +184,9 @@ pub fn build_middleware(app: Arc<App>, endpoints: RouteBuilder) -> MiddlewareBui
     m.add(AppMiddleware::new(app));
     m.add(KnownErrorToJson);
 
+    if coalesce_requests {
+        m.around(SingleFlight::new(single_flight::request_signature));
+    }
+
     m
 }
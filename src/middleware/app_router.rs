@@ -0,0 +1,80 @@
+//! A builder that combines the static file middlewares and a conduit handler into a single
+//! axum `Router`.
+
+use crate::app::AppState;
+use crate::middleware::static_or_continue::{self, DistDir};
+use crate::App;
+use axum::middleware::from_fn_with_state;
+use conduit::Handler;
+use conduit_axum::ConduitFallback;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Builds an axum `Router` that serves uploaded files and frontend assets before falling back
+/// to a conduit handler
+///
+/// The static files are only served for `GET` and `HEAD` requests. Uploads take precedence over
+/// the frontend assets, and all requests that do not match a file are passed to the conduit
+/// handler, regardless of the order in which the builder methods are called.
+///
+/// ```ignore
+/// let router = AppRouter::new(app)
+///     .with_dist("dist")
+///     .with_uploads("local_uploads")
+///     .with_conduit(handler)
+///     .build();
+/// ```
+pub struct AppRouter {
+    state: AppState,
+    router: axum::Router,
+    dist: Option<Arc<Path>>,
+    uploads: Option<Arc<Path>>,
+}
+
+impl AppRouter {
+    pub fn new(app: Arc<App>) -> Self {
+        Self {
+            state: AppState(app),
+            router: axum::Router::new(),
+            dist: None,
+            uploads: None,
+        }
+    }
+
+    /// Serve the frontend assets in `dir`, see `StaticFilesConfig` for the related settings
+    pub fn with_dist(mut self, dir: impl AsRef<Path>) -> Self {
+        self.dist = Some(Arc::from(dir.as_ref()));
+        self
+    }
+
+    /// Serve the files uploaded to `dir` by the local uploader
+    pub fn with_uploads(mut self, dir: impl AsRef<Path>) -> Self {
+        self.uploads = Some(Arc::from(dir.as_ref()));
+        self
+    }
+
+    /// Handle all remaining requests with the conduit `handler`
+    pub fn with_conduit(mut self, handler: impl Handler) -> Self {
+        self.router = self.router.conduit_fallback(handler);
+        self
+    }
+
+    pub fn build(self) -> axum::Router {
+        let mut router = self.router;
+
+        // Layers that are added last run first
+        if let Some(dir) = self.dist {
+            let dist_dir: DistDir = (self.state, dir);
+            router = router.layer(from_fn_with_state(dist_dir, static_or_continue::serve_dist));
+        }
+
+        if let Some(dir) = self.uploads {
+            router = router.layer(from_fn_with_state(
+                dir,
+                static_or_continue::serve_local_uploads,
+            ));
+        }
+
+        router
+    }
+}
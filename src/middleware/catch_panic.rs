@@ -0,0 +1,172 @@
+//! Keep the server alive if a request panics outside of the conduit handler
+//!
+//! Panics of the conduit handler are already caught by `conduit_axum`, since the handler runs on
+//! the blocking thread pool, and result in a regular `500 Internal Server Error` response that
+//! is reported by the fallback handler. Panics in the async middleware stack would instead abort
+//! the connection. This middleware catches them, passes them to a `PanicHook` together with the
+//! request context, and responds with a generic `500 Internal Server Error`.
+
+use super::prelude::*;
+use axum::extract::State;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use futures_util::FutureExt;
+use http::{Method, Uri};
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+
+/// Called for every panic that is caught by the `catch_panic` middleware
+pub type PanicHook = Arc<dyn Fn(&PanicContext<'_>) + Send + Sync>;
+
+/// The request during which a panic occurred
+#[derive(Debug)]
+pub struct PanicContext<'a> {
+    pub method: &'a Method,
+    pub uri: &'a Uri,
+    pub request_id: Option<&'a str>,
+    /// The message of the panic, if its payload is a string
+    pub message: Option<&'a str>,
+}
+
+/// The default `PanicHook`, which logs the panic and reports it to Sentry
+pub fn report_panic(context: &PanicContext<'_>) {
+    let message = context.message.unwrap_or("<non-string panic payload>");
+
+    error!(
+        method = %context.method,
+        path = %context.uri.path(),
+        request_id = context.request_id,
+        panic = message,
+        "Caught a panic while handling a request"
+    );
+
+    sentry::with_scope(
+        |scope| {
+            scope.set_tag("method", context.method);
+            scope.set_extra("path", context.uri.path().into());
+        },
+        || sentry::capture_message(&format!("Panic: {message}"), sentry::Level::Error),
+    );
+}
+
+pub async fn catch_panic<B>(
+    State(hook): State<PanicHook>,
+    req: http::Request<B>,
+    next: Next<B>,
+) -> axum::response::Response {
+    // The request is moved into the inner layers, so the context is copied upfront
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    let request_id = req.headers().get("x-request-id").cloned();
+
+    let payload = match AssertUnwindSafe(next.run(req)).catch_unwind().await {
+        Ok(response) => return response,
+        Err(payload) => payload,
+    };
+
+    hook(&PanicContext {
+        method: &method,
+        uri: &uri,
+        request_id: request_id.as_ref().and_then(|value| value.to_str().ok()),
+        message: panic_message(&*payload),
+    });
+
+    (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    payload
+        .downcast_ref::<&'static str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LogRequestsConfig;
+    use crate::middleware::log_request::log_requests;
+    use crate::util::tracing::capture_logs;
+    use axum::extract::ConnectInfo;
+    use axum::middleware::{from_fn, from_fn_with_state};
+    use axum::routing::get;
+    use axum::{Extension, Router};
+    use conduit_axum::ConduitFallback;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    fn hook(hook: impl Fn(&PanicContext<'_>) + Send + Sync + 'static) -> PanicHook {
+        Arc::new(hook)
+    }
+
+    async fn panicking_layer<B>(req: http::Request<B>, next: Next<B>) -> axum::response::Response {
+        if req.uri().path() == "/panic" {
+            panic!("broken layer");
+        }
+
+        next.run(req).await
+    }
+
+    struct PanickingHandler;
+
+    impl Handler for PanickingHandler {
+        fn call(&self, _req: &mut dyn RequestExt) -> conduit::HandlerResult {
+            panic!("broken handler");
+        }
+    }
+
+    #[tokio::test]
+    async fn panics_are_caught() {
+        let (logs, _guard) = capture_logs();
+
+        let remote_addr: SocketAddr = ([127, 0, 0, 1], 80).into();
+        let caught_panics = Arc::new(AtomicUsize::new(0));
+        let hook = hook({
+            let caught_panics = caught_panics.clone();
+            move |context| {
+                assert_eq!(context.uri.path(), "/panic");
+                assert_eq!(context.request_id, Some("abcd"));
+                assert_eq!(context.message, Some("broken layer"));
+                caught_panics.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        let router = Router::new()
+            .route("/ok", get(|| async { "OK" }))
+            .conduit_fallback(PanickingHandler)
+            .layer(from_fn(panicking_layer))
+            .layer(from_fn_with_state(hook, catch_panic))
+            .layer(from_fn_with_state(
+                Arc::new(LogRequestsConfig::for_testing()),
+                log_requests,
+            ))
+            .layer(Extension(ConnectInfo(remote_addr)));
+
+        let request = |path| {
+            http::Request::get(path)
+                .header("x-request-id", "abcd")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let response = router.clone().oneshot(request("/panic")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(caught_panics.load(Ordering::SeqCst), 1);
+
+        // The panicking request still shows up in the access log
+        let line = logs.contents();
+        assert!(line.contains(r#"path="/panic""#), "{line}");
+        assert!(line.contains("status=500"), "{line}");
+
+        // The service is still usable after the panic
+        let response = router.clone().oneshot(request("/ok")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Panics of the conduit handler are already handled by `conduit_axum`
+        let response = router.oneshot(request("/handler")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(caught_panics.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,88 @@
+//! Reject requests for hosts that are not on the `allowed_hosts` list
+//!
+//! Generated URLs and cached responses may depend on the `Host` header (or the forwarded host, if
+//! the application is running behind a proxy), so unexpected values are rejected with a
+//! `400 Bad Request` response instead of being passed on to the endpoints. If the list is empty,
+//! all hosts are allowed.
+//!
+//! The matching entry of the list is stored as a `CanonicalHost` in the request extensions.
+
+use super::prelude::*;
+use crate::app::AppState;
+use crate::middleware::client_info::ClientInfo;
+use axum::extract::State;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use http::uri::Authority;
+
+/// The host of the request, as configured in the `allowed_hosts` list
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CanonicalHost(pub String);
+
+pub async fn check_host<B>(
+    State(state): State<AppState>,
+    mut req: http::Request<B>,
+    next: Next<B>,
+) -> axum::response::Response {
+    let allowed_hosts = &state.config.allowed_hosts;
+    if allowed_hosts.is_empty() {
+        return next.run(req).await;
+    }
+
+    let client_info = req.extensions().get::<ClientInfo>();
+    let host = client_info.and_then(|client_info| client_info.host.as_deref());
+
+    match canonical_host(host, allowed_hosts) {
+        Some(canonical_host) => {
+            req.extensions_mut().insert(canonical_host);
+            next.run(req).await
+        }
+        None => {
+            req.add_custom_metadata("cause", "host not allowed");
+            (StatusCode::BAD_REQUEST, "Invalid Host header").into_response()
+        }
+    }
+}
+
+/// Find the entry of the `allowed_hosts` list matching the `host`, ignoring the port
+fn canonical_host(host: Option<&str>, allowed_hosts: &[String]) -> Option<CanonicalHost> {
+    let authority = host?.trim().parse::<Authority>().ok()?;
+    let host = authority.host();
+
+    allowed_hosts
+        .iter()
+        .find(|allowed_host| allowed_host.eq_ignore_ascii_case(host))
+        .map(|allowed_host| CanonicalHost(allowed_host.to_ascii_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed_hosts() -> Vec<String> {
+        vec!["crates.io".into(), "static.crates.io".into()]
+    }
+
+    #[test]
+    fn allowed_hosts_are_canonicalized() {
+        let allowed_hosts = allowed_hosts();
+        let expected = Some(CanonicalHost("crates.io".into()));
+        assert_eq!(canonical_host(Some("crates.io"), &allowed_hosts), expected);
+        assert_eq!(canonical_host(Some("Crates.IO"), &allowed_hosts), expected);
+        assert_eq!(
+            canonical_host(Some("crates.io:443"), &allowed_hosts),
+            expected
+        );
+    }
+
+    #[test]
+    fn unexpected_hosts_are_rejected() {
+        let allowed_hosts = allowed_hosts();
+        assert_eq!(canonical_host(Some("evil.example"), &allowed_hosts), None);
+        assert_eq!(canonical_host(Some("crates.io.evil"), &allowed_hosts), None);
+        assert_eq!(canonical_host(Some("127.0.0.1"), &allowed_hosts), None);
+        assert_eq!(canonical_host(Some("[::1]:8888"), &allowed_hosts), None);
+        assert_eq!(canonical_host(Some("crates.io/path"), &allowed_hosts), None);
+        assert_eq!(canonical_host(None, &allowed_hosts), None);
+    }
+}
@@ -0,0 +1,287 @@
+//! Resolve the IP address, scheme and host that the client used to connect
+//!
+//! If the application is configured to run `behind_proxy`, the standard `Forwarded` header and the
+//! `X-Real-Ip`, `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host` headers set by the
+//! proxy are trusted. Otherwise
+//! these headers are ignored entirely and only information from the connection itself is used.
+//!
+//! The result is stored as a `ClientInfo` in the request extensions, so that all other
+//! middleware and the endpoints agree on the client information.
+
+use crate::app::AppState;
+use axum::extract::{ConnectInfo, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use http::header::{self, AsHeaderName};
+use http::uri::Scheme;
+use http::{HeaderMap, Request};
+use std::net::{IpAddr, SocketAddr};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// The IP address of the client, if known
+    pub ip: Option<IpAddr>,
+    /// The scheme the client used to connect
+    pub scheme: Scheme,
+    /// The host the client connected to, if known
+    pub host: Option<String>,
+}
+
+impl ClientInfo {
+    /// Resolve the client information from the request `headers` and the `remote_ip` of the
+    /// connection
+    ///
+    /// If `prefer_forwarded` is set, values from the standard `Forwarded` header (RFC 7239) take
+    /// precedence over the `X-Real-Ip` and `X-Forwarded-*` headers when both are present.
+    pub fn resolve(
+        headers: &HeaderMap,
+        remote_ip: Option<IpAddr>,
+        behind_proxy: bool,
+        prefer_forwarded: bool,
+    ) -> Self {
+        let host = header_str(headers, header::HOST).map(String::from);
+
+        if !behind_proxy {
+            return Self {
+                ip: remote_ip,
+                scheme: Scheme::HTTP,
+                host,
+            };
+        }
+
+        let forwarded = Forwarded::last(headers);
+
+        let x_ip = header_str(headers, "x-real-ip")
+            .and_then(|value| value.trim().parse().ok())
+            .or_else(|| last_forwarded_for(headers));
+        let x_proto = header_str(headers, "x-forwarded-proto").map(|proto| proto.trim());
+        let x_host = header_str(headers, "x-forwarded-host");
+
+        let (ip, proto, forwarded_host) = if prefer_forwarded {
+            (
+                forwarded.ip.or(x_ip),
+                forwarded.proto.or(x_proto),
+                forwarded.host.or(x_host),
+            )
+        } else {
+            (
+                x_ip.or(forwarded.ip),
+                x_proto.or(forwarded.proto),
+                x_host.or(forwarded.host),
+            )
+        };
+
+        let scheme = match proto {
+            Some(proto) if proto.eq_ignore_ascii_case("https") => Scheme::HTTPS,
+            _ => Scheme::HTTP,
+        };
+
+        Self {
+            ip: ip.or(remote_ip),
+            scheme,
+            host: forwarded_host.map(String::from).or(host),
+        }
+    }
+}
+
+/// The parameters of a single element of a `Forwarded` header
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Forwarded<'a> {
+    ip: Option<IpAddr>,
+    proto: Option<&'a str>,
+    host: Option<&'a str>,
+}
+
+impl<'a> Forwarded<'a> {
+    /// Parse the last element of the `Forwarded` headers, which was added by the proxy directly
+    /// in front of the application
+    fn last(headers: &'a HeaderMap) -> Self {
+        headers
+            .get_all(header::FORWARDED)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter(|element| !element.trim().is_empty())
+            .last()
+            .map(Self::parse)
+            .unwrap_or_default()
+    }
+
+    fn parse(element: &'a str) -> Self {
+        let mut forwarded = Self::default();
+
+        for pair in element.split(';') {
+            let Some((key, value)) = pair.split_once('=') else {
+                continue;
+            };
+
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+
+            match key.trim().to_ascii_lowercase().as_str() {
+                "for" => forwarded.ip = parse_node(value),
+                "proto" => forwarded.proto = Some(value),
+                "host" => forwarded.host = Some(value),
+                _ => {}
+            }
+        }
+
+        forwarded
+    }
+}
+
+/// Parse the IP address of a `Forwarded` node, e.g. `192.0.2.1`, `192.0.2.1:4711` or
+/// `[2001:db8::1]:4711`
+///
+/// Obfuscated identifiers and `unknown` don't contain an IP address and result in `None`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        let (ip, _port) = rest.split_once(']')?;
+        return ip.parse().ok();
+    }
+
+    let ip = node.split_once(':').map(|(ip, _port)| ip).unwrap_or(node);
+    ip.parse().ok()
+}
+
+pub async fn resolve_client_info<B>(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let remote_ip = connect_info.map(|ConnectInfo(remote_addr)| remote_addr.ip());
+    let behind_proxy = state.config.behind_proxy;
+    let prefer_forwarded = state.config.prefer_forwarded_header;
+
+    let client_info = ClientInfo::resolve(req.headers(), remote_ip, behind_proxy, prefer_forwarded);
+    req.extensions_mut().insert(client_info);
+
+    next.run(req).await
+}
+
+fn header_str<K: AsHeaderName>(headers: &HeaderMap, key: K) -> Option<&str> {
+    headers.get(key).and_then(|value| value.to_str().ok())
+}
+
+/// Returns the last valid IP address of the `X-Forwarded-For` chain
+///
+/// Earlier entries in the chain are supplied by the client and can not be trusted, while the
+/// last entry has been appended by the proxy directly in front of the application.
+fn last_forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|ip| ip.trim().parse().ok())
+        .last()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    fn remote_ip() -> Option<IpAddr> {
+        Some([10, 0, 0, 1].into())
+    }
+
+    #[test]
+    fn behind_proxy_trusts_forwarded_headers() {
+        let headers = headers(&[
+            ("host", "internal:8888"),
+            ("x-real-ip", "192.0.2.1"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "crates.io"),
+        ]);
+
+        let client_info = ClientInfo::resolve(&headers, remote_ip(), true, true);
+        assert_eq!(client_info.ip, Some([192, 0, 2, 1].into()));
+        assert_eq!(client_info.scheme, Scheme::HTTPS);
+        assert_eq!(client_info.host.as_deref(), Some("crates.io"));
+    }
+
+    #[test]
+    fn behind_proxy_falls_back_to_forwarded_for_and_connection() {
+        let headers = headers(&[("x-forwarded-for", "203.0.113.7, 192.0.2.2")]);
+        let client_info = ClientInfo::resolve(&headers, remote_ip(), true, true);
+        assert_eq!(client_info.ip, Some([192, 0, 2, 2].into()));
+        assert_eq!(client_info.scheme, Scheme::HTTP);
+
+        let client_info = ClientInfo::resolve(&HeaderMap::new(), remote_ip(), true, true);
+        assert_eq!(client_info.ip, remote_ip());
+    }
+
+    #[test]
+    fn not_behind_proxy_ignores_forwarded_headers() {
+        let headers = headers(&[
+            ("host", "crates.io"),
+            ("x-real-ip", "192.0.2.1"),
+            ("x-forwarded-for", "192.0.2.2"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "evil.example"),
+            ("forwarded", "for=192.0.2.3;proto=https;host=evil.example"),
+        ]);
+
+        let client_info = ClientInfo::resolve(&headers, remote_ip(), false, true);
+        assert_eq!(client_info.ip, remote_ip());
+        assert_eq!(client_info.scheme, Scheme::HTTP);
+        assert_eq!(client_info.host.as_deref(), Some("crates.io"));
+    }
+
+    #[test]
+    fn behind_proxy_trusts_forwarded_header() {
+        let forwarded = headers(&[
+            ("host", "internal:8888"),
+            (
+                "forwarded",
+                "for=203.0.113.7;proto=http, for=192.0.2.1;proto=https;host=crates.io",
+            ),
+        ]);
+
+        let client_info = ClientInfo::resolve(&forwarded, remote_ip(), true, true);
+        assert_eq!(client_info.ip, Some([192, 0, 2, 1].into()));
+        assert_eq!(client_info.scheme, Scheme::HTTPS);
+        assert_eq!(client_info.host.as_deref(), Some("crates.io"));
+
+        let forwarded = headers(&[("forwarded", r#"For="[2001:db8:cafe::17]:4711""#)]);
+        let client_info = ClientInfo::resolve(&forwarded, remote_ip(), true, true);
+        let expected: IpAddr = "2001:db8:cafe::17".parse().unwrap();
+        assert_eq!(client_info.ip, Some(expected));
+
+        let forwarded = headers(&[("forwarded", "for=unknown")]);
+        let client_info = ClientInfo::resolve(&forwarded, remote_ip(), true, true);
+        assert_eq!(client_info.ip, remote_ip());
+    }
+
+    #[test]
+    fn forwarded_header_precedence_is_configurable() {
+        let headers = headers(&[
+            ("x-real-ip", "192.0.2.1"),
+            ("x-forwarded-host", "x.crates.io"),
+            ("forwarded", "for=192.0.2.2;proto=https"),
+        ]);
+
+        let client_info = ClientInfo::resolve(&headers, remote_ip(), true, true);
+        assert_eq!(client_info.ip, Some([192, 0, 2, 2].into()));
+        assert_eq!(client_info.scheme, Scheme::HTTPS);
+        assert_eq!(client_info.host.as_deref(), Some("x.crates.io"));
+
+        let client_info = ClientInfo::resolve(&headers, remote_ip(), true, false);
+        assert_eq!(client_info.ip, Some([192, 0, 2, 1].into()));
+        assert_eq!(client_info.scheme, Scheme::HTTPS);
+        assert_eq!(client_info.host.as_deref(), Some("x.crates.io"));
+    }
+}
@@ -0,0 +1,202 @@
+//! Limit the request rate of each client IP address
+//!
+//! Each client IP address (as resolved by the `client_info` middleware) gets a token bucket
+//! that holds up to `burst` tokens and is refilled with `rate` tokens per second. Every request
+//! takes one token, and requests that find the bucket empty are rejected with a
+//! `429 Too Many Requests` response, including a `Retry-After` header with the number of seconds
+//! until the next token is available. Clients on the `trusted_ips` list and requests without a
+//! known client IP are not limited. Requests with a `User-Agent` on the `trusted_user_agents`
+//! list or an API token on the `trusted_token_hashes` list are not limited either, and are logged
+//! with `rate_limit=exempt`.
+
+use super::prelude::*;
+use crate::app::AppState;
+use crate::config::ClientRateLimitConfig;
+use crate::middleware::client_info::ClientInfo;
+use axum::extract::State;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use moka::sync::Cache;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The maximum number of client IP addresses that are tracked at the same time
+const MAX_TRACKED_CLIENTS: u64 = 100_000;
+
+/// The token buckets of all clients that recently sent requests
+pub struct ClientRateLimits {
+    rate: f64,
+    burst: f64,
+    buckets: Cache<IpAddr, Arc<Mutex<TokenBucket>>>,
+}
+
+impl ClientRateLimits {
+    /// Returns `None` if the rate limit is disabled
+    pub fn new(config: &ClientRateLimitConfig) -> Option<Self> {
+        let rate = config.rate?;
+        let burst = f64::from(config.burst.max(1));
+
+        // Buckets that were idle long enough to be refilled completely can be dropped, since a
+        // new bucket is full as well
+        let refill_time = Duration::from_secs_f64(burst / rate);
+        let buckets = Cache::builder()
+            .max_capacity(MAX_TRACKED_CLIENTS)
+            .time_to_idle(refill_time)
+            .build();
+
+        Some(Self {
+            rate,
+            burst,
+            buckets,
+        })
+    }
+
+    /// Take a token from the bucket of the `ip`, or return how long the client needs to wait
+    fn try_acquire(&self, ip: IpAddr) -> Result<(), Duration> {
+        let bucket = self
+            .buckets
+            .get_with(ip, || Arc::new(Mutex::new(TokenBucket::new(self.burst))));
+
+        let mut bucket = bucket.lock().unwrap_or_else(|error| error.into_inner());
+        bucket.try_take(Instant::now(), self.rate, self.burst)
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(burst: f64) -> Self {
+        Self {
+            tokens: burst,
+            updated_at: Instant::now(),
+        }
+    }
+
+    fn try_take(&mut self, now: Instant, rate: f64, burst: f64) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.updated_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate).min(burst);
+        self.updated_at = now;
+
+        if self.tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - self.tokens) / rate));
+        }
+
+        self.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+pub async fn limit_client_rate<B>(
+    State(state): State<AppState>,
+    req: http::Request<B>,
+    next: Next<B>,
+) -> axum::response::Response {
+    let Some(limits) = &state.client_rate_limits else {
+        return next.run(req).await;
+    };
+
+    if state
+        .config
+        .client_rate_limit
+        .is_trusted_request(req.headers())
+    {
+        req.add_custom_metadata("rate_limit", "exempt");
+        return next.run(req).await;
+    }
+
+    let client_info = req.extensions().get::<ClientInfo>();
+    let Some(ip) = client_info.and_then(|client_info| client_info.ip) else {
+        return next.run(req).await;
+    };
+
+    if state.config.client_rate_limit.is_trusted(ip) {
+        return next.run(req).await;
+    }
+
+    if let Err(retry_after) = limits.try_acquire(ip) {
+        req.add_custom_metadata("cause", "client rate limit exceeded");
+
+        // `Retry-After` only supports whole seconds
+        let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+        let headers = [(header::RETRY_AFTER, retry_after.to_string())];
+        let body = "Too many requests, please slow down";
+        return (StatusCode::TOO_MANY_REQUESTS, headers, body).into_response();
+    }
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::token::SecureToken;
+
+    #[test]
+    fn tokens_are_refilled_over_time() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket {
+            tokens: 2.0,
+            updated_at: start,
+        };
+
+        assert_ok!(bucket.try_take(start, 0.5, 2.0));
+        assert_ok!(bucket.try_take(start, 0.5, 2.0));
+        let retry_after = assert_err!(bucket.try_take(start, 0.5, 2.0));
+        assert_eq!(retry_after, Duration::from_secs(2));
+
+        let later = start + Duration::from_secs(1);
+        let retry_after = assert_err!(bucket.try_take(later, 0.5, 2.0));
+        assert_eq!(retry_after, Duration::from_secs(1));
+
+        // The bucket never holds more than `burst` tokens
+        let much_later = start + Duration::from_secs(60);
+        assert_ok!(bucket.try_take(much_later, 0.5, 2.0));
+        assert_ok!(bucket.try_take(much_later, 0.5, 2.0));
+        assert_err!(bucket.try_take(much_later, 0.5, 2.0));
+    }
+
+    #[test]
+    fn trusted_ips_are_matched() {
+        let config = ClientRateLimitConfig {
+            rate: Some(1.0),
+            burst: 1,
+            trusted_ips: vec!["10.0.0.0/8".parse().unwrap(), "::1".parse().unwrap()],
+            ..ClientRateLimitConfig::for_testing()
+        };
+
+        assert!(config.is_trusted("10.1.2.3".parse().unwrap()));
+        assert!(config.is_trusted("::1".parse().unwrap()));
+        assert!(!config.is_trusted("11.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn trusted_requests_are_matched() {
+        let config = ClientRateLimitConfig {
+            trusted_user_agents: vec!["crates-mirror/".into()],
+            trusted_token_hashes: vec![SecureToken::hash("ci-token")],
+            ..ClientRateLimitConfig::for_testing()
+        };
+
+        let headers = |name, value| {
+            let mut headers = http::HeaderMap::new();
+            headers.insert(name, http::HeaderValue::from_static(value));
+            headers
+        };
+
+        let mirror = headers(header::USER_AGENT, "crates-mirror/1.0 (mirror.example.com)");
+        assert!(config.is_trusted_request(&mirror));
+        let cargo = headers(header::USER_AGENT, "cargo 1.66.0");
+        assert!(!config.is_trusted_request(&cargo));
+
+        let ci = headers(header::AUTHORIZATION, "ci-token");
+        assert!(config.is_trusted_request(&ci));
+        let other = headers(header::AUTHORIZATION, "other-token");
+        assert!(!config.is_trusted_request(&other));
+
+        assert!(!config.is_trusted_request(&http::HeaderMap::new()));
+    }
+}
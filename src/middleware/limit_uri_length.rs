@@ -0,0 +1,52 @@
+//! Reject requests with excessively long URIs
+//!
+//! Crafted URIs with huge query strings bloat the access logs and can stress the parsing code
+//! further down the stack. Rejected requests are still logged by `log_requests`, which truncates
+//! the logged path so that they don't produce huge log lines.
+
+use crate::app::AppState;
+use crate::middleware::log_request::CustomMetadataRequestExt;
+use axum::extract::State;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use http::{Request, StatusCode, Uri};
+
+pub async fn limit_uri_length<B>(
+    State(state): State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let max_uri_length = state.config.max_uri_length;
+
+    let uri_length = uri_length(req.uri());
+    if uri_length > max_uri_length {
+        req.add_custom_metadata("cause", "uri too long");
+        req.add_custom_metadata("uri_length", uri_length);
+
+        return StatusCode::URI_TOO_LONG.into_response();
+    }
+
+    next.run(req).await
+}
+
+fn uri_length(uri: &Uri) -> usize {
+    let scheme = uri.scheme_str().map(|scheme| scheme.len() + 3).unwrap_or(0);
+    let authority = uri.authority().map(|authority| authority.as_str().len());
+    let path_and_query = uri.path_and_query().map(|pq| pq.as_str().len());
+    scheme + authority.unwrap_or(0) + path_and_query.unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uri_lengths() {
+        assert_eq!(uri_length(&Uri::from_static("/")), 1);
+        assert_eq!(uri_length(&Uri::from_static("/api/v1/crates?q=foo")), 20);
+        assert_eq!(
+            uri_length(&Uri::from_static("https://crates.io/crates")),
+            24
+        );
+    }
+}
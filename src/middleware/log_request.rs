@@ -5,20 +5,58 @@ use super::prelude::*;
 
 use conduit::RequestExt;
 
-use crate::headers::{XRealIp, XRequestId};
+use crate::config::{IpLogging, LogFormat, LogRequestsConfig, LogStatuses};
+use crate::headers::XRequestId;
+use crate::middleware::client_info::ClientInfo;
 use crate::middleware::normalize_path::OriginalPath;
-use axum::headers::UserAgent;
+use crate::router::PathParams;
+use axum::body::HttpBody;
+use axum::extract::State;
+use axum::headers::{ContentType, UserAgent};
 use axum::middleware::Next;
 use axum::response::IntoResponse;
 use axum::{Extension, TypedHeader};
-use http::{Method, Request, StatusCode, Uri};
+use conduit_axum::{
+    Baggage, BlockingWait, ConnectionRequests, Deprecated, FileBackend, HandlerThread,
+    LayerTimings, RejectionReason, ResponseFormat, SentryEventId, TraceContext, TransferMode,
+    WouldReject,
+};
+use conduit_router::RoutePattern;
+use http::{HeaderMap, HeaderValue, Method, Request, StatusCode, Uri};
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+#[cfg(test)]
 use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
 use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
+use tracing::{field, Instrument, Span};
 
 const SLOW_REQUEST_THRESHOLD_MS: u128 = 1000;
 
+/// Paths longer than this are truncated in the log line
+const MAX_LOGGED_PATH_LENGTH: usize = 1000;
+
+/// The sequence number of the last logged request of this process
+static SEQUENCE_NUMBER: AtomicU64 = AtomicU64::new(0);
+
+/// Headers with credentials, whose values are not included in the verbose log line
+const REDACTED_HEADERS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "proxy-authorization",
+    "set-cookie",
+];
+
+/// The header with the service time in milliseconds, see `LogRequestsConfig::response_time_header`
+const X_RESPONSE_TIME: &str = "x-response-time";
+
+/// Query parameters with credentials, whose values are not included in the `q_<name>` fields
+const REDACTED_QUERY_PARAMS: &[&str] = &["code", "state", "token"];
+
 #[derive(Default)]
 pub(super) struct LogRequests();
 
@@ -38,198 +76,2905 @@ pub struct RequestMetadata {
     method: Method,
     uri: Uri,
     original_path: Option<Extension<OriginalPath>>,
-    user_agent: TypedHeader<UserAgent>,
+    user_agent: Option<TypedHeader<UserAgent>>,
     request_id: Option<TypedHeader<XRequestId>>,
-    real_ip: Option<TypedHeader<XRealIp>>,
+    client_info: Option<Extension<ClientInfo>>,
+    content_type: Option<TypedHeader<ContentType>>,
+    connection_requests: Option<Extension<ConnectionRequests>>,
+}
+
+#[cfg(test)]
+impl RequestMetadata {
+    /// Builds the metadata without a request, so that tests can format log lines directly
+    pub(crate) fn new(
+        method: Method,
+        uri: &str,
+        original_path: Option<&str>,
+        user_agent: Option<&'static str>,
+        request_id: Option<&str>,
+        real_ip: Option<IpAddr>,
+    ) -> Self {
+        Self {
+            method,
+            uri: uri.parse().unwrap(),
+            original_path: original_path.map(|path| Extension(OriginalPath(path.into()))),
+            user_agent: user_agent
+                .map(|user_agent| TypedHeader(UserAgent::from_static(user_agent))),
+            request_id: request_id.map(|request_id| TypedHeader(XRequestId::new(request_id))),
+            client_info: real_ip.map(|ip| {
+                Extension(ClientInfo {
+                    ip: Some(ip),
+                    scheme: http::uri::Scheme::HTTPS,
+                    host: None,
+                })
+            }),
+            content_type: None,
+            connection_requests: None,
+        }
+    }
 }
 
+/// A response extension with the size of the response body before it was compressed
+///
+/// This is logged as `bytes_raw`, in addition to the compressed size in `bytes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UncompressedSize(pub u64);
+
 pub struct Metadata {
     request: RequestMetadata,
     status: StatusCode,
+    response_content_type: Option<String>,
+    /// The `Location` header of the response, which is only recorded for redirects
+    location: Option<String>,
+    response_bytes: Option<u64>,
+    response_bytes_raw: Option<u64>,
+    path_params: Option<PathParams>,
+    route: Option<String>,
+    handler_thread: Option<HandlerThread>,
+    blocking_wait: Option<BlockingWait>,
+    baggage: Option<Baggage>,
+    /// The allowlisted request headers, as configured via `LogRequestsConfig::request_headers`
+    request_headers: Vec<(String, String)>,
+    /// The allowlisted response headers, as configured via `LogRequestsConfig::response_headers`
+    response_headers: Vec<(String, String)>,
     duration: Duration,
     custom_metadata: CustomMetadata,
+    phase_timings: PhaseTimings,
+    /// The time spent in the layers of the request handling, if `WEB_LOG_LAYER_TIMINGS` is set
+    layer_timings: Option<LayerTimings>,
+    /// The sequence number of the log line, which is only assigned if the request is logged
+    seq: Option<u64>,
+    config: Arc<LogRequestsConfig>,
 }
 
-impl Display for Metadata {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let mut line = LogLine::new(f);
+impl Metadata {
+    pub fn method(&self) -> &Method {
+        &self.request.method
+    }
 
-        // The download endpoint is our most requested endpoint by 1-2 orders of
-        // magnitude. Since we pay per logged GB we try to reduce the amount of
-        // bytes per log line for this endpoint.
+    pub fn uri(&self) -> &Uri {
+        &self.request.uri
+    }
 
-        let is_download_endpoint = self.request.uri.path().ends_with("/download");
-        let is_download_redirect = is_download_endpoint && self.status.is_redirection();
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
 
-        let method = &self.request.method;
-        if !is_download_redirect || method != Method::GET {
-            line.add_field("method", method)?;
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The route pattern that matched the request, e.g. `/api/v1/crates/:crate_id`
+    pub fn route(&self) -> Option<&str> {
+        self.route.as_deref()
+    }
+
+    /// The size of the response body, if it was known before the body was streamed
+    pub fn response_bytes(&self) -> Option<u64> {
+        self.response_bytes
+    }
+
+    /// The value of the `fwd` field, or `None` if it is omitted from the log
+    fn fwd(&self) -> Option<String> {
+        let client_info = self.request.client_info.as_deref();
+        let ip = client_info.and_then(|client_info| client_info.ip);
+
+        match &self.config.ip_logging {
+            IpLogging::Raw => Some(ip.map(|ip| ip.to_string()).unwrap_or_default()),
+            IpLogging::Hashed(salt) => Some(ip.map(|ip| hash_ip(salt, ip)).unwrap_or_default()),
+            IpLogging::None => None,
         }
+    }
 
-        if let Some(original_path) = &self.request.original_path {
-            line.add_quoted_field("path", &original_path.deref().0)?;
-        } else {
-            line.add_quoted_field("path", &self.request.uri)?;
+    /// The `location` field, which is omitted for download redirects unless it is enabled via
+    /// the config
+    fn location(&self) -> Option<&str> {
+        let location = self.location.as_deref()?;
+
+        let is_download_endpoint = self.request.uri.path().ends_with("/download");
+        if is_download_endpoint && !self.config.download_redirect_location {
+            return None;
         }
 
-        if !is_download_redirect {
-            match &self.request.request_id {
-                Some(header) => line.add_field("request_id", header.as_str())?,
-                None => line.add_field("request_id", "")?,
-            };
+        Some(location)
+    }
+
+    /// The configured entries of the `baggage` request header
+    fn baggage(&self) -> impl Iterator<Item = (&str, &str)> {
+        let baggage = self.baggage.as_ref();
+        self.config.baggage_keys.iter().filter_map(move |key| {
+            let value = baggage?.get(key)?;
+            Some((key.as_str(), value))
+        })
+    }
+
+    /// The id and name of the thread that ran the handler, if enabled via the config
+    fn thread_info(&self) -> Option<(String, &str)> {
+        if !self.config.thread_info {
+            return None;
+        }
+
+        let thread = &self.handler_thread.as_ref()?.0;
+
+        // `ThreadId::as_u64()` is unstable, so the number is taken from the `Debug` output
+        let thread_id = format!("{:?}", thread.id());
+        let thread_id = thread_id
+            .trim_start_matches("ThreadId(")
+            .trim_end_matches(')');
+
+        Some((thread_id.to_string(), thread.name().unwrap_or_default()))
+    }
+
+    /// The route pattern, if it is configured to be logged instead of the `path` field
+    ///
+    /// The path of these routes is fully determined by the pattern, so it doesn't need to be
+    /// quoted. Requests with a query string are still logged with their full path.
+    fn compact_route(&self) -> Option<&str> {
+        let route = self.route.as_deref()?;
+        if self.request.uri.query().is_some()
+            || !self.config.compact_routes.iter().any(|r| r == route)
+        {
+            return None;
         }
 
-        match &self.request.real_ip {
-            Some(header) => line.add_quoted_field("fwd", header.as_str())?,
-            None => line.add_quoted_field("fwd", "")?,
+        Some(route)
+    }
+
+    /// The values of the query parameters that are configured to be logged for this route
+    ///
+    /// Only the first value of repeated parameters is logged.
+    fn query_params(&self) -> Vec<(&str, String)> {
+        let query = match self.request.uri.query() {
+            Some(query) => query,
+            None => return vec![],
         };
 
-        let response_time_in_ms = self.duration.as_millis();
-        if !is_download_redirect || response_time_in_ms > 0 {
-            line.add_field("service", format!("{response_time_in_ms}ms"))?;
+        let route = self.route.as_deref();
+        let params = url::form_urlencoded::parse(query.as_bytes()).collect::<Vec<_>>();
+
+        let logged = self.config.query_params.iter();
+        logged
+            .filter(|param| param.applies_to(route))
+            .filter_map(|param| {
+                let name = param.name.as_str();
+                let (_, value) = params.iter().find(|(key, _)| key == name)?;
+                let value = if REDACTED_QUERY_PARAMS.contains(&name) {
+                    "[redacted]".to_string()
+                } else {
+                    truncate_path(value).into_owned()
+                };
+                Some((name, value))
+            })
+            .collect()
+    }
+
+    /// The number of the request on its connection, see `ConnectionRequests`
+    fn connection_requests(&self) -> Option<u64> {
+        let Extension(ConnectionRequests(requests)) = self.request.connection_requests.as_ref()?;
+        Some(*requests)
+    }
+
+    /// The values of the `path` field and, if the path was percent-decoded, the `raw_path` field
+    fn paths(&self) -> (String, Option<String>) {
+        let path = match &self.request.original_path {
+            Some(original_path) => original_path.deref().0.clone(),
+            None => self.request.uri.to_string(),
+        };
+
+        if self.config.decode_path {
+            if let Some(decoded) = decode_path(&path).filter(|decoded| *decoded != path) {
+                let raw_path = truncate_path(&path).into_owned();
+                return (truncate_path(&decoded).into_owned(), Some(raw_path));
+            }
         }
 
-        if !is_download_redirect {
-            line.add_field("status", self.status.as_str())?;
+        (truncate_path(&path).into_owned(), None)
+    }
+
+    /// The time the handler waited for a blocking thread in milliseconds, if enabled via the config
+    fn block_wait_ms(&self) -> Option<u64> {
+        if !self.config.block_wait {
+            return None;
         }
 
-        line.add_quoted_field("user_agent", self.request.user_agent.as_str())?;
+        self.blocking_wait.map(|wait| wait.0.as_millis() as u64)
+    }
+
+    /// Renders the request as a message in the Graylog Extended Log Format
+    ///
+    /// See <https://go2docs.graylog.org/5-0/getting_in_log_data/gelf.html> for the format.
+    pub fn to_gelf(&self) -> serde_json::Value {
+        let (path, raw_path) = self.paths();
+
+        // Syslog severity levels: 3 = error, 6 = informational
+        let level = if self.status.is_server_error() { 3 } else { 6 };
 
-        if self.request.original_path.is_some() {
-            line.add_quoted_field("normalized_path", &self.request.uri)?;
+        let method = self.request.method.as_str();
+        let status = self.status.as_u16();
+
+        let mut message = serde_json::Map::new();
+        message.insert("version".into(), "1.1".into());
+        message.insert("host".into(), self.config.host.clone().into());
+        let short_message = format!("{method} {path} {status}");
+        message.insert("short_message".into(), short_message.into());
+        message.insert("level".into(), level.into());
+        message.insert("_method".into(), method.into());
+        message.insert("_path".into(), path.into());
+        if let Some(raw_path) = raw_path {
+            message.insert("_raw_path".into(), raw_path.into());
+        }
+        message.insert("_status".into(), status.into());
+        if let Some(seq) = self.seq {
+            message.insert("_seq".into(), seq.into());
         }
+        if let Some(requests) = self.connection_requests() {
+            message.insert("_conn_reqs".into(), requests.into());
+        }
+        let service_ms = self.duration.as_millis() as u64;
+        message.insert("_service_ms".into(), service_ms.into());
 
-        if let Ok(metadata) = self.custom_metadata.lock() {
-            for (key, value) in &*metadata {
-                line.add_quoted_field(key, value)?;
+        if let Some(header) = &self.request.request_id {
+            message.insert("_request_id".into(), header.as_str().into());
+        }
+
+        if let Some(fwd) = self.fwd().filter(|fwd| !fwd.is_empty()) {
+            message.insert("_fwd".into(), fwd.into());
+        }
+
+        if let Some(header) = &self.request.user_agent {
+            message.insert("_user_agent".into(), header.as_str().into());
+        }
+
+        if let Some(bytes) = self.response_bytes {
+            message.insert("_bytes".into(), bytes.into());
+        }
+
+        if let Some(location) = self.location() {
+            message.insert("_location".into(), location.into());
+        }
+
+        if let Some(path_params) = &self.path_params {
+            if let Some(crate_name) = path_params.crate_name() {
+                message.insert("_crate".into(), crate_name.into());
+            }
+
+            for name in &self.config.path_params {
+                if let Some(value) = path_params.get(name) {
+                    message.insert(format!("_param_{name}"), value.into());
+                }
             }
         }
 
-        if response_time_in_ms > SLOW_REQUEST_THRESHOLD_MS {
-            line.add_marker("SLOW REQUEST")?;
+        for (name, value) in self.query_params() {
+            message.insert(format!("_q_{name}"), value.into());
         }
 
-        Ok(())
+        for (key, value) in self.baggage() {
+            message.insert(format!("_baggage_{key}"), value.into());
+        }
+
+        for (name, value) in &self.request_headers {
+            message.insert(format!("_hdr_{name}"), value.as_str().into());
+        }
+
+        for (name, value) in &self.response_headers {
+            message.insert(format!("_res_hdr_{name}"), value.as_str().into());
+        }
+
+        if let Some((thread_id, thread_name)) = self.thread_info() {
+            message.insert("_thread_id".into(), thread_id.into());
+            message.insert("_thread_name".into(), thread_name.into());
+        }
+
+        if let Some(block_wait_ms) = self.block_wait_ms() {
+            message.insert("_block_wait".into(), block_wait_ms.into());
+        }
+
+        if let Ok(timings) = self.phase_timings.lock() {
+            for (phase, duration) in &*timings {
+                let duration_ms = duration.as_millis() as u64;
+                message.insert(format!("_t_{phase}"), duration_ms.into());
+            }
+        }
+
+        for (layer, duration) in self.layer_timings.iter().flat_map(LayerTimings::iter) {
+            let duration_us = duration.as_micros() as u64;
+            message.insert(format!("_t_layer_{layer}"), duration_us.into());
+        }
+
+        if let Ok(metadata) = self.custom_metadata.lock() {
+            for (key, value) in &*metadata {
+                message.insert(format!("_{key}"), value.as_str().into());
+            }
+        }
+
+        message.into()
     }
-}
 
-pub async fn log_requests<B>(
-    request_metadata: RequestMetadata,
-    mut req: Request<B>,
-    next: Next<B>,
-) -> impl IntoResponse {
-    let start_instant = Instant::now();
+    /// Renders the request as a JSON object with Elastic Common Schema field names
+    ///
+    /// See <https://www.elastic.co/guide/en/ecs/current/ecs-field-reference.html> for the fields.
+    /// Values without a matching ECS field, like the custom metadata, are reported as `labels`.
+    fn to_ecs(&self) -> serde_json::Value {
+        let (path, raw_path) = self.paths();
 
-    let custom_metadata = CustomMetadata::default();
-    req.extensions_mut().insert(custom_metadata.clone());
+        let method = self.request.method.as_str();
+        let status = self.status.as_u16();
+        let level = if self.status.is_server_error() {
+            "error"
+        } else {
+            "info"
+        };
 
-    let response = next.run(req).await;
+        let mut request = json!({ "method": method });
+        if let Some(header) = &self.request.request_id {
+            request["id"] = header.as_str().into();
+        }
 
-    let metadata = Metadata {
-        request: request_metadata,
-        status: response.status(),
-        duration: start_instant.elapsed(),
-        custom_metadata,
-    };
+        let mut response = json!({ "status_code": status });
+        if let Some(bytes) = self.response_bytes {
+            response["body"] = json!({ "bytes": bytes });
+        }
 
-    if metadata.status.is_server_error() {
-        error!(target: "http", "{metadata}");
-    } else {
-        info!(target: "http", "{metadata}");
-    };
+        let mut url = json!({ "path": path });
+        if let Some(raw_path) = raw_path {
+            url["original"] = raw_path.into();
+        }
 
-    response
-}
+        // ECS durations are in nanoseconds
+        let mut event = json!({ "duration": self.duration.as_nanos() as u64 });
+        if let Some(seq) = self.seq {
+            event["sequence"] = seq.into();
+        }
 
-#[derive(Clone, Debug, Deref, Default)]
-pub struct CustomMetadata(Arc<Mutex<Vec<(&'static str, String)>>>);
+        let mut message = json!({
+            "@timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            "ecs": { "version": ECS_VERSION },
+            "message": format!("{method} {path} {status}"),
+            "log": { "level": level, "logger": "http" },
+            "host": { "name": self.config.host },
+            "http": { "request": request, "response": response },
+            "url": url,
+            "event": event,
+        });
 
-pub trait CustomMetadataRequestExt {
-    fn add_custom_metadata<V: Display>(&self, key: &'static str, value: V) {
-        if let Some(metadata) = self.metadata_extension() {
-            if let Ok(mut metadata) = metadata.lock() {
-                metadata.push((key, value.to_string()));
+        if let Some(header) = &self.request.user_agent {
+            message["user_agent"] = json!({ "original": header.as_str() });
+        }
+
+        let mut labels = serde_json::Map::new();
+        if let Some(crate_name) = self.path_params.as_ref().and_then(|p| p.crate_name()) {
+            labels.insert("crate".into(), crate_name.into());
+        }
+        if let Ok(metadata) = self.custom_metadata.lock() {
+            for (key, value) in &*metadata {
+                labels.insert((*key).into(), value.as_str().into());
             }
         }
+        if !labels.is_empty() {
+            message["labels"] = labels.into();
+        }
 
-        sentry::configure_scope(|scope| scope.set_extra(key, value.to_string().into()));
+        message
     }
 
-    fn metadata_extension(&self) -> Option<&CustomMetadata>;
-}
+    /// Renders the request as an RFC 5424 syslog message
+    ///
+    /// The key fields are included as structured data, and the message is the regular logfmt
+    /// line. See <https://www.rfc-editor.org/rfc/rfc5424> for the format.
+    fn to_syslog(&self) -> String {
+        // Facility `local0`, with the same severity levels as the GELF messages
+        let severity = if self.status.is_server_error() { 3 } else { 6 };
+        let priority = SYSLOG_FACILITY * 8 + severity;
 
-impl CustomMetadataRequestExt for dyn RequestExt + '_ {
-    fn metadata_extension(&self) -> Option<&CustomMetadata> {
-        self.extensions().get::<CustomMetadata>()
+        let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+        let hostname = syslog_header_field(&self.config.host);
+        let process_id = std::process::id();
+
+        let (path, _) = self.paths();
+        let mut params = vec![
+            ("method", self.request.method.to_string()),
+            ("path", path),
+            ("status", self.status.as_u16().to_string()),
+            ("service_ms", self.duration.as_millis().to_string()),
+        ];
+        if let Some(header) = &self.request.request_id {
+            params.push(("request_id", header.as_str().to_string()));
+        }
+        if let Some(seq) = self.seq {
+            params.push(("seq", seq.to_string()));
+        }
+        if let Some(bytes) = self.response_bytes {
+            params.push(("bytes", bytes.to_string()));
+        }
+        if let Some(crate_name) = self.path_params.as_ref().and_then(|p| p.crate_name()) {
+            params.push(("crate", crate_name.to_string()));
+        }
+
+        let mut structured_data = format!("[{SYSLOG_SD_ID}");
+        for (name, value) in params {
+            let value = escape_sd_param_value(&value);
+            structured_data.push_str(&format!(" {name}=\"{value}\""));
+        }
+        structured_data.push(']');
+
+        let message = self.to_logfmt();
+        format!(
+            "<{priority}>1 {timestamp} {hostname} {SYSLOG_APP_NAME} {process_id} {SYSLOG_MSG_ID} \
+             {structured_data} {message}"
+        )
     }
 }
 
-impl<B> CustomMetadataRequestExt for Request<B> {
-    fn metadata_extension(&self) -> Option<&CustomMetadata> {
-        self.extensions().get::<CustomMetadata>()
+/// The version of the Elastic Common Schema that `Metadata::to_ecs()` follows
+const ECS_VERSION: &str = "8.6.0";
+
+/// The syslog facility of the request log (`local0`)
+const SYSLOG_FACILITY: u8 = 16;
+const SYSLOG_APP_NAME: &str = "crates-io";
+const SYSLOG_MSG_ID: &str = "http";
+/// The ID of the structured data element, using the private enterprise number reserved for
+/// documentation purposes
+const SYSLOG_SD_ID: &str = "http@32473";
+
+/// Sanitize a syslog header field, which may only contain printable ASCII characters
+///
+/// Empty values are replaced with the `-` placeholder.
+fn syslog_header_field(value: &str) -> Cow<'_, str> {
+    if value.is_empty() {
+        return Cow::Borrowed("-");
+    }
+
+    if value.bytes().all(|byte| byte.is_ascii_graphic()) {
+        return Cow::Borrowed(value);
     }
+
+    let sanitized = value
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' });
+    Cow::Owned(sanitized.collect())
 }
 
-#[cfg(test)]
-pub(crate) fn get_log_message(req: &dyn RequestExt, key: &'static str) -> String {
-    // Unwrap shouldn't panic as no other code has access to the private struct to remove it
-    if let Some(metadata) = req.extensions().get::<CustomMetadata>() {
-        if let Ok(metadata) = metadata.lock() {
-            for (k, v) in &*metadata {
-                if key == *k {
-                    return v.clone();
-                }
-            }
+/// Escape `"`, `\` and `]` in a structured data parameter value, as required by RFC 5424
+fn escape_sd_param_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
         }
+        escaped.push(c);
     }
+    escaped
+}
 
-    panic!("expected log message for {key} not found");
+/// The optional fields that are dropped from log lines that exceed the `max_line_length`
+///
+/// Each variant also drops the fields of the previous ones, which have a lower priority.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum DroppedFields {
+    Nothing,
+    /// The custom metadata added by middleware and endpoints
+    CustomMetadata,
+    /// The path parameters, baggage, headers, thread information and phase timings
+    Details,
+    /// The information about the client, e.g. `user_agent`, `fwd` and the content types
+    ClientInfo,
 }
 
-struct LogLine<'f, 'g> {
-    f: &'f mut Formatter<'g>,
-    first: bool,
+impl Display for Metadata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.write_logfmt(f, DroppedFields::Nothing)
+    }
 }
 
-impl<'f, 'g> LogLine<'f, 'g> {
-    fn new(f: &'f mut Formatter<'g>) -> Self {
-        Self { f, first: true }
+/// A logfmt line of the `Metadata`, without the `dropped` fields
+struct LogfmtLine<'a> {
+    metadata: &'a Metadata,
+    dropped: DroppedFields,
+}
+
+impl Display for LogfmtLine<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.metadata.write_logfmt(f, self.dropped)
     }
+}
 
-    fn add_field<K: Display, V: Display>(&mut self, key: K, value: V) -> fmt::Result {
-        self.start_item()?;
+impl Metadata {
+    /// The logfmt line, with optional fields dropped if it exceeds the `max_line_length`
+    ///
+    /// Truncated lines end with a `truncated=true` field. Fields are only dropped as a whole, so
+    /// the line stays valid, and the core fields like `path` and `status` are always included.
+    fn to_logfmt(&self) -> String {
+        let mut line = self.to_string();
+        let Some(max_line_length) = self.config.max_line_length else {
+            return line;
+        };
 
-        key.fmt(self.f)?;
-        self.f.write_str("=")?;
-        value.fmt(self.f)?;
+        for dropped in [
+            DroppedFields::CustomMetadata,
+            DroppedFields::Details,
+            DroppedFields::ClientInfo,
+        ] {
+            if line.len() <= max_line_length {
+                break;
+            }
 
-        Ok(())
+            let metadata = self;
+            line = LogfmtLine { metadata, dropped }.to_string();
+        }
+
+        line
     }
 
-    fn add_quoted_field<K: Display, V: Display>(&mut self, key: K, value: V) -> fmt::Result {
-        self.start_item()?;
+    fn write_logfmt(&self, f: &mut Formatter<'_>, dropped: DroppedFields) -> fmt::Result {
+        let mut line = LogLine::new(f);
+        let keep_client_info = dropped < DroppedFields::ClientInfo;
+        let keep_details = dropped < DroppedFields::Details;
 
-        key.fmt(self.f)?;
-        self.f.write_str("=\"")?;
-        value.fmt(self.f)?;
-        self.f.write_str("\"")?;
+        // The download endpoint is our most requested endpoint by 1-2 orders of
+        // magnitude. Since we pay per logged GB we try to reduce the amount of
+        // bytes per log line for this endpoint.
 
-        Ok(())
-    }
+        let is_download_endpoint = self.request.uri.path().ends_with("/download");
+        let is_download_redirect = is_download_endpoint && self.status.is_redirection();
 
-    fn add_marker<M: Display>(&mut self, marker: M) -> fmt::Result {
-        self.start_item()?;
+        let method = &self.request.method;
+        if !is_download_redirect || method != Method::GET {
+            line.add_field("method", method)?;
+        }
 
-        marker.fmt(self.f)?;
+        if let Some(route) = self.compact_route() {
+            line.add_field("route", route)?;
+        } else {
+            let (path, raw_path) = self.paths();
+            line.add_quoted_field("path", path)?;
+            if let Some(raw_path) = raw_path.filter(|_| keep_client_info) {
+                line.add_quoted_field("raw_path", raw_path)?;
+            }
+        }
 
-        Ok(())
-    }
+        if !is_download_redirect {
+            match &self.request.request_id {
+                Some(header) => line.add_field("request_id", header.as_str())?,
+                None => line.add_field("request_id", "")?,
+            };
+        }
 
-    fn start_item(&mut self) -> fmt::Result {
-        if !self.first {
-            self.f.write_str(" ")?;
+        if let Some(seq) = self.seq {
+            line.add_field("seq", seq)?;
         }
-        self.first = false;
-        Ok(())
+
+        if let Some(requests) = self.connection_requests() {
+            line.add_field("conn_reqs", requests)?;
+        }
+
+        if let Some(fwd) = self.fwd().filter(|_| keep_client_info) {
+            line.add_quoted_field("fwd", fwd)?;
+        }
+
+        let client_info = self.request.client_info.as_deref();
+
+        if !is_download_redirect {
+            if let Some(client_info) = client_info {
+                line.add_field("protocol", &client_info.scheme)?;
+            }
+        }
+
+        let response_time_in_ms = self.duration.as_millis();
+        if !is_download_redirect || response_time_in_ms > 0 {
+            line.add_field("service", format!("{response_time_in_ms}ms"))?;
+        }
+
+        if !is_download_redirect {
+            line.add_field("status", self.status.as_str())?;
+        }
+
+        if !is_download_redirect {
+            if let Some(bytes) = self.response_bytes {
+                line.add_field("bytes", bytes)?;
+            }
+
+            if let Some(bytes_raw) = self.response_bytes_raw {
+                line.add_field("bytes_raw", bytes_raw)?;
+            }
+        }
+
+        if let Some(location) = self.location() {
+            line.add_quoted_field("location", truncate_path(location))?;
+        }
+
+        if keep_client_info {
+            let user_agent = self.request.user_agent.as_ref();
+            let user_agent = user_agent.map(|header| header.as_str()).unwrap_or_default();
+            line.add_quoted_field("user_agent", user_agent)?;
+
+            if let Some(content_type) = &self.request.content_type {
+                line.add_quoted_field("req_content_type", content_type.deref())?;
+            }
+
+            if let Some(content_type) = &self.response_content_type {
+                line.add_quoted_field("res_content_type", content_type)?;
+            }
+
+            if self.request.original_path.is_some() {
+                let normalized_path = self.request.uri.to_string();
+                line.add_quoted_field("normalized_path", truncate_path(&normalized_path))?;
+            }
+        }
+
+        if let Some(path_params) = &self.path_params {
+            if let Some(crate_name) = path_params.crate_name() {
+                line.add_quoted_field("crate", crate_name)?;
+            }
+
+            if keep_details {
+                for name in &self.config.path_params {
+                    if let Some(value) = path_params.get(name) {
+                        line.add_quoted_field(format_args!("param_{name}"), value)?;
+                    }
+                }
+            }
+        }
+
+        if keep_details {
+            for (name, value) in self.query_params() {
+                line.add_quoted_field(format_args!("q_{name}"), value)?;
+            }
+        }
+
+        if keep_details {
+            for (key, value) in self.baggage() {
+                line.add_quoted_field(format_args!("baggage_{key}"), value)?;
+            }
+
+            for (name, value) in &self.request_headers {
+                line.add_quoted_field(format_args!("hdr_{name}"), value)?;
+            }
+
+            for (name, value) in &self.response_headers {
+                line.add_quoted_field(format_args!("res_hdr_{name}"), value)?;
+            }
+
+            if let Some((thread_id, thread_name)) = self.thread_info() {
+                line.add_field("thread_id", thread_id)?;
+                line.add_quoted_field("thread_name", thread_name)?;
+            }
+
+            if let Some(block_wait_ms) = self.block_wait_ms() {
+                line.add_field("block_wait", block_wait_ms)?;
+            }
+
+            if let Ok(timings) = self.phase_timings.lock() {
+                for (phase, duration) in &*timings {
+                    line.add_field(format_args!("t_{phase}"), duration.as_millis())?;
+                }
+            }
+
+            for (layer, duration) in self.layer_timings.iter().flat_map(LayerTimings::iter) {
+                line.add_field(format_args!("t_layer_{layer}"), duration.as_micros())?;
+            }
+        }
+
+        if dropped < DroppedFields::CustomMetadata {
+            if let Ok(metadata) = self.custom_metadata.lock() {
+                for (key, value) in &*metadata {
+                    line.add_quoted_field(key, value)?;
+                }
+            }
+        }
+
+        if let Some(version) = &self.config.version {
+            line.add_field("version", version)?;
+        }
+
+        if response_time_in_ms > SLOW_REQUEST_THRESHOLD_MS {
+            line.add_marker("SLOW REQUEST")?;
+        }
+
+        let large_response_threshold = self.config.large_response_threshold;
+        if matches!(self.response_bytes, Some(bytes) if bytes > large_response_threshold) {
+            line.add_marker("LARGE RESPONSE")?;
+        }
+
+        if dropped != DroppedFields::Nothing {
+            line.add_field("truncated", true)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The log line that is emitted before a request is handled, see `LogRequestsConfig::start_line`
+///
+/// The matching completion line has the same `request_id`.
+struct RequestStartLine<'a> {
+    request: &'a RequestMetadata,
+}
+
+impl RequestStartLine<'_> {
+    fn path(&self) -> String {
+        let path = match &self.request.original_path {
+            Some(original_path) => original_path.deref().0.clone(),
+            None => self.request.uri.to_string(),
+        };
+        truncate_path(&path).into_owned()
+    }
+
+    fn request_id(&self) -> &str {
+        let request_id = self.request.request_id.as_ref();
+        request_id.map(|header| header.as_str()).unwrap_or_default()
+    }
+
+    fn to_gelf(&self, host: &str) -> serde_json::Value {
+        let method = self.request.method.as_str();
+        let path = self.path();
+
+        json!({
+            "version": "1.1",
+            "host": host,
+            "short_message": format!("{method} {path} started"),
+            "level": 6,
+            "_method": method,
+            "_path": path,
+            "_request_id": self.request_id(),
+        })
+    }
+
+    fn to_ecs(&self, host: &str) -> serde_json::Value {
+        let method = self.request.method.as_str();
+        let path = self.path();
+
+        json!({
+            "@timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            "ecs": { "version": ECS_VERSION },
+            "message": format!("{method} {path} started"),
+            "log": { "level": "info", "logger": "http.start" },
+            "host": { "name": host },
+            "http": { "request": { "method": method, "id": self.request_id() } },
+            "url": { "path": path },
+        })
+    }
+}
+
+impl Display for RequestStartLine<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut line = LogLine::new(f);
+        line.add_field("method", &self.request.method)?;
+        line.add_quoted_field("path", self.path())?;
+        line.add_field("request_id", self.request_id())?;
+        Ok(())
+    }
+}
+
+/// The additional log line for the requests that are sampled via `verbose_sample_rate`
+///
+/// This contains the regular log line, followed by the service time in microseconds and all
+/// request and response headers, except for the values of the `REDACTED_HEADERS`.
+struct VerboseLogLine<'a> {
+    metadata: &'a Metadata,
+    request_headers: &'a HeaderMap,
+    response_headers: &'a HeaderMap,
+}
+
+impl VerboseLogLine<'_> {
+    fn to_gelf(&self) -> serde_json::Value {
+        let mut message = self.metadata.to_gelf();
+        if let Some(message) = message.as_object_mut() {
+            let service_us = self.metadata.duration.as_micros() as u64;
+            message.insert("_service_us".into(), service_us.into());
+
+            for (name, value) in all_headers(self.request_headers) {
+                message.insert(format!("_hdr_{name}"), value.into());
+            }
+
+            for (name, value) in all_headers(self.response_headers) {
+                message.insert(format!("_res_hdr_{name}"), value.into());
+            }
+        }
+
+        message
+    }
+}
+
+impl Display for VerboseLogLine<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ", self.metadata)?;
+
+        let mut line = LogLine::new(f);
+        line.add_field("service_us", self.metadata.duration.as_micros())?;
+
+        for (name, value) in all_headers(self.request_headers) {
+            line.add_quoted_field(format_args!("hdr_{name}"), value)?;
+        }
+
+        for (name, value) in all_headers(self.response_headers) {
+            line.add_quoted_field(format_args!("res_hdr_{name}"), value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// All headers in `headers`, with the values of the `REDACTED_HEADERS` replaced
+///
+/// Multiple values of the same header are joined with `, `, and values that are not valid UTF-8
+/// are skipped.
+fn all_headers(headers: &HeaderMap) -> impl Iterator<Item = (&str, String)> {
+    headers.keys().filter_map(move |name| {
+        if REDACTED_HEADERS.contains(&name.as_str()) {
+            return Some((name.as_str(), "[redacted]".to_string()));
+        }
+
+        let values = headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>();
+
+        if values.is_empty() {
+            return None;
+        }
+
+        Some((name.as_str(), values.join(", ")))
+    })
+}
+
+/// Whether a request is sampled for the verbose log, with the probability `sample_rate`
+fn is_sampled(sample_rate: f32) -> bool {
+    sample_rate > 0.0 && rand::random::<f32>() < sample_rate
+}
+
+/// Percent-decode the `path`, leaving invalid escape sequences as they are
+///
+/// Returns `None` if the decoded path is not valid UTF-8, or if it contains characters that could
+/// break the log line, like quotes or control characters.
+fn decode_path(path: &str) -> Option<String> {
+    let decoded = percent_decode_str(path).decode_utf8().ok()?;
+
+    let is_loggable = |c: char| c != '"' && c != '\\' && !c.is_control();
+    decoded
+        .chars()
+        .all(is_loggable)
+        .then(|| decoded.into_owned())
+}
+
+fn truncate_path(path: &str) -> Cow<'_, str> {
+    if path.len() <= MAX_LOGGED_PATH_LENGTH {
+        return path.into();
+    }
+
+    // Step back to the previous char boundary, so multibyte chars are not split
+    let end = (0..=MAX_LOGGED_PATH_LENGTH)
+        .rev()
+        .find(|&i| path.is_char_boundary(i))
+        .unwrap_or(0);
+    format!("{}...", &path[..end]).into()
+}
+
+/// A salted SHA-256 hash of the `ip`, truncated to 16 hex characters
+fn hash_ip(salt: &str, ip: IpAddr) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(ip.to_string().as_bytes());
+    hex::encode(&hasher.finalize()[..8])
+}
+
+/// The values of the headers in `names` that are present in `headers`, with the values of the
+/// `REDACTED_HEADERS` replaced
+///
+/// Multiple values of the same header are joined with `, `, and values that are not valid UTF-8
+/// are skipped.
+fn selected_headers(headers: &HeaderMap, names: &[String]) -> Vec<(String, String)> {
+    names
+        .iter()
+        .filter_map(|name| {
+            let is_redacted = REDACTED_HEADERS
+                .iter()
+                .any(|redacted| redacted.eq_ignore_ascii_case(name));
+            if is_redacted {
+                return headers
+                    .contains_key(name.as_str())
+                    .then(|| (name.clone(), "[redacted]".to_string()));
+            }
+
+            let values = headers
+                .get_all(name.as_str())
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect::<Vec<_>>();
+
+            if values.is_empty() {
+                return None;
+            }
+
+            Some((name.clone(), values.join(", ")))
+        })
+        .collect()
+}
+
+/// Logs a line for every request, including requests that are rejected by the inner middleware
+/// layers or by `conduit_axum` before they reach a handler
+pub async fn log_requests<B>(
+    State(config): State<Arc<LogRequestsConfig>>,
+    request_metadata: RequestMetadata,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> impl IntoResponse {
+    let start_instant = Instant::now();
+
+    let custom_metadata = CustomMetadata::default();
+    req.extensions_mut().insert(custom_metadata.clone());
+
+    let phase_timings = PhaseTimings::default();
+    req.extensions_mut().insert(phase_timings.clone());
+
+    let baggage = if config.baggage_keys.is_empty() {
+        None
+    } else {
+        Baggage::from_headers(req.headers())
+    };
+
+    let request_headers = selected_headers(req.headers(), &config.request_headers);
+
+    // The request headers are only cloned if they are needed for the verbose log line
+    let verbose_request_headers =
+        is_sampled(config.verbose_sample_rate).then(|| req.headers().clone());
+
+    let span = info_span!(
+        "request",
+        crate = field::Empty,
+        trace_id = field::Empty,
+        span_id = field::Empty,
+        parent_span_id = field::Empty,
+        sampled = field::Empty,
+    );
+
+    // Continue the trace of the caller, with the request span as a child of the caller's span.
+    // The handler receives the context of the request span, for propagating it further.
+    if let Some(parent) = TraceContext::from_headers(req.headers()) {
+        let child = parent.child(rand::random::<u64>().max(1).to_be_bytes());
+        span.record("trace_id", child.trace_id());
+        span.record("span_id", child.parent_id());
+        span.record("parent_span_id", parent.parent_id());
+        span.record("sampled", parent.is_sampled());
+        req.extensions_mut().insert(child);
+    }
+
+    if config.start_line {
+        let start = RequestStartLine {
+            request: &request_metadata,
+        };
+        match config.format {
+            LogFormat::Gelf => info!(target: "http.start", "{}", start.to_gelf(&config.host)),
+            LogFormat::Ecs => info!(target: "http.start", "{}", start.to_ecs(&config.host)),
+            LogFormat::Logfmt | LogFormat::Syslog => info!(target: "http.start", "{start}"),
+        }
+    }
+
+    let mut response = next.run(req).instrument(span).await;
+
+    if let Some(reason) = response.extensions().get::<RejectionReason>() {
+        if let Ok(mut metadata) = custom_metadata.lock() {
+            metadata.push(("cause", reason.0.clone()));
+        }
+    }
+
+    if let Some(would_reject) = response.extensions().get::<WouldReject>() {
+        if let Ok(mut metadata) = custom_metadata.lock() {
+            metadata.push(("would_reject", would_reject.0.clone()));
+        }
+    }
+
+    if response.extensions().get::<Deprecated>().is_some() {
+        if let Ok(mut metadata) = custom_metadata.lock() {
+            metadata.push(("deprecated", "true".into()));
+        }
+    }
+
+    if let Some(backend) = response.extensions().get::<FileBackend>() {
+        if let Ok(mut metadata) = custom_metadata.lock() {
+            metadata.push(("file_backend", backend.as_str().into()));
+        }
+    }
+
+    if let Some(transfer_mode) = response.extensions().get::<TransferMode>() {
+        if let Ok(mut metadata) = custom_metadata.lock() {
+            metadata.push(("transfer", transfer_mode.as_str().into()));
+        }
+    }
+
+    if let Some(format) = response.extensions().get::<ResponseFormat>() {
+        if let Ok(mut metadata) = custom_metadata.lock() {
+            metadata.push(("repr", format.as_str().into()));
+        }
+    }
+
+    if let Some(event_id) = response.extensions().get::<SentryEventId>() {
+        if let Ok(mut metadata) = custom_metadata.lock() {
+            metadata.push(("sentry_id", event_id.0.to_string()));
+        }
+    }
+
+    let response_content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+
+    let location = response
+        .status()
+        .is_redirection()
+        .then(|| response.headers().get(header::LOCATION))
+        .flatten()
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+
+    let mut metadata = Metadata {
+        request: request_metadata,
+        status: response.status(),
+        response_content_type,
+        location,
+        response_bytes: response_bytes(&response),
+        response_bytes_raw: response
+            .extensions()
+            .get::<UncompressedSize>()
+            .map(|size| size.0),
+        path_params: response.extensions().get::<PathParams>().cloned(),
+        route: response
+            .extensions()
+            .get::<RoutePattern>()
+            .map(|route_pattern| route_pattern.pattern().to_string()),
+        handler_thread: response.extensions().get::<HandlerThread>().cloned(),
+        blocking_wait: response.extensions().get::<BlockingWait>().copied(),
+        baggage,
+        request_headers,
+        response_headers: selected_headers(response.headers(), &config.response_headers),
+        duration: start_instant.elapsed(),
+        custom_metadata,
+        phase_timings,
+        layer_timings: response.extensions().get::<LayerTimings>().cloned(),
+        seq: None,
+        config,
+    };
+
+    if metadata.config.response_time_header {
+        let response_time = HeaderValue::from(metadata.duration.as_millis() as u64);
+        response
+            .headers_mut()
+            .insert(X_RESPONSE_TIME, response_time);
+    }
+
+    if metadata.config.report_slow_requests {
+        report_slow_request(&metadata);
+    }
+
+    if !metadata.config.statuses.includes(metadata.status.as_u16()) {
+        return response;
+    }
+
+    // The verbose log line shares the sequence number of the regular log line
+    metadata.seq = Some(SEQUENCE_NUMBER.fetch_add(1, Ordering::Relaxed) + 1);
+
+    let logfmt;
+    let gelf;
+    let syslog;
+    let ecs;
+    let message: &dyn Display = match metadata.config.format {
+        LogFormat::Logfmt => {
+            logfmt = metadata.to_logfmt();
+            &logfmt
+        }
+        LogFormat::Gelf => {
+            gelf = metadata.to_gelf();
+            &gelf
+        }
+        LogFormat::Syslog => {
+            syslog = metadata.to_syslog();
+            &syslog
+        }
+        LogFormat::Ecs => {
+            ecs = metadata.to_ecs();
+            &ecs
+        }
+    };
+
+    if metadata.status.is_server_error() {
+        error!(target: "http", "{message}");
+    } else {
+        info!(target: "http", "{message}");
+    };
+
+    for sink in metadata.config.sinks.iter() {
+        sink.log(&metadata);
+    }
+
+    if let Some(request_headers) = &verbose_request_headers {
+        let verbose = VerboseLogLine {
+            metadata: &metadata,
+            request_headers,
+            response_headers: response.headers(),
+        };
+
+        let gelf;
+        let message: &dyn Display = match metadata.config.format {
+            // The verbose line is only used for debugging, so there are no syslog and ECS variants
+            LogFormat::Logfmt | LogFormat::Syslog | LogFormat::Ecs => &verbose,
+            LogFormat::Gelf => {
+                gelf = verbose.to_gelf();
+                &gelf
+            }
+        };
+
+        info!(target: "http.verbose", "{message}");
+    }
+
+    response
+}
+
+/// Report the request to Sentry if it is marked with `SLOW REQUEST` in the log
+fn report_slow_request(metadata: &Metadata) {
+    let duration_ms = metadata.duration.as_millis();
+    if duration_ms <= SLOW_REQUEST_THRESHOLD_MS {
+        return;
+    }
+
+    let route = metadata.route.as_deref().unwrap_or("<unknown>");
+    sentry::with_scope(
+        |scope| {
+            scope.set_tag("route", route);
+            scope.set_tag("duration_ms", duration_ms);
+            scope.set_extra("status", metadata.status.as_u16().into());
+        },
+        || {
+            let message = format!("Slow request: {} {route}", metadata.request.method);
+            sentry::capture_message(&message, sentry::Level::Warning)
+        },
+    );
+}
+
+/// Records the crate name of crate-specific routes as the `crate` field of the request span
+///
+/// This needs to be called from within the span that is created by `log_requests()`.
+pub fn record_crate_name(path_params: &PathParams) {
+    if let Some(crate_name) = path_params.crate_name() {
+        Span::current().record("crate", crate_name);
+    }
+}
+
+/// The size of the response body, if it is known before the body is streamed to the client
+fn response_bytes<B: HttpBody>(response: &Response<B>) -> Option<u64> {
+    response.body().size_hint().exact().or_else(|| {
+        let content_length = response.headers().get(header::CONTENT_LENGTH)?;
+        content_length.to_str().ok()?.parse().ok()
+    })
+}
+
+/// A destination of the request log, in addition to the `tracing` output
+///
+/// Sinks are registered via `LogRequestsConfig::sinks`, and receive the metadata of every
+/// request that is included in the log, e.g. to feed it to a metrics service. Since they are
+/// called before the response is sent, sinks should hand the data off (e.g. to a channel)
+/// instead of doing expensive work themselves.
+pub trait LogSink: Send + Sync {
+    fn log(&self, metadata: &Metadata);
+}
+
+impl<F: Fn(&Metadata) + Send + Sync> LogSink for F {
+    fn log(&self, metadata: &Metadata) {
+        self(metadata)
+    }
+}
+
+#[derive(Clone, Debug, Deref, Default)]
+pub struct CustomMetadata(Arc<Mutex<Vec<(&'static str, String)>>>);
+
+impl CustomMetadata {
+    /// A copy of all entries, in the order in which they were added
+    ///
+    /// If a thread panicked while adding an entry, the entries that were added until then are
+    /// still returned.
+    pub fn snapshot(&self) -> Vec<(&'static str, String)> {
+        match self.lock() {
+            Ok(metadata) => metadata.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+}
+
+/// The durations of the named phases of a request, logged as `t_<phase>` fields
+#[derive(Clone, Debug, Deref, Default)]
+pub struct PhaseTimings(Arc<Mutex<Vec<(&'static str, Duration)>>>);
+
+pub trait CustomMetadataRequestExt {
+    fn add_custom_metadata<V: Display>(&self, key: &'static str, value: V) {
+        if let Some(metadata) = self.metadata_extension() {
+            if let Ok(mut metadata) = metadata.lock() {
+                metadata.push((key, value.to_string()));
+            }
+        }
+
+        sentry::configure_scope(|scope| scope.set_extra(key, value.to_string().into()));
+    }
+
+    /// Record how the request was authenticated, logged as the `auth` field
+    fn set_auth_outcome(&self, outcome: AuthOutcome) {
+        self.add_custom_metadata("auth", outcome);
+    }
+
+    /// Record whether the response was served from a cache, logged as the `cache` field
+    fn set_cache_result(&self, result: CacheResult) {
+        self.add_custom_metadata("cache", result);
+    }
+
+    /// Record an attempt to call an upstream service, logged as the `upstream_attempts` field
+    ///
+    /// Calling this multiple times increments the count, e.g. when the call is retried. The field
+    /// is omitted for requests that do not call any upstream service.
+    fn record_upstream_attempt(&self) {
+        const KEY: &str = "upstream_attempts";
+
+        let attempts = match self.metadata_extension().map(|metadata| metadata.lock()) {
+            Some(Ok(mut metadata)) => match metadata.iter_mut().find(|(key, _)| *key == KEY) {
+                Some((_, value)) => {
+                    let attempts = value.parse::<u32>().unwrap_or_default() + 1;
+                    *value = attempts.to_string();
+                    attempts
+                }
+                None => {
+                    metadata.push((KEY, "1".into()));
+                    1
+                }
+            },
+            _ => return,
+        };
+
+        sentry::configure_scope(|scope| scope.set_extra(KEY, attempts.into()));
+    }
+
+    /// Record the duration of a phase of the request handling (e.g. `auth` or `query`), logged
+    /// as the `t_<phase>` field in milliseconds
+    ///
+    /// Durations of phases that are recorded multiple times are added up.
+    fn record_phase(&self, phase: &'static str, duration: Duration) {
+        let Some(timings) = self.phase_timings_extension() else {
+            return;
+        };
+
+        if let Ok(mut timings) = timings.lock() {
+            match timings.iter_mut().find(|(name, _)| *name == phase) {
+                Some((_, total)) => *total += duration,
+                None => timings.push((phase, duration)),
+            }
+        }
+    }
+
+    /// Run `f` and record its duration as the `phase`, see `record_phase()`
+    fn time_phase<T>(&self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record_phase(phase, start.elapsed());
+        result
+    }
+
+    fn metadata_extension(&self) -> Option<&CustomMetadata>;
+
+    fn phase_timings_extension(&self) -> Option<&PhaseTimings>;
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AuthOutcome {
+    /// Authenticated via an API token in the `Authorization` header
+    Token,
+    /// Authenticated via a cookie session
+    Cookie,
+    /// No credentials were provided
+    Anonymous,
+    /// Credentials were provided but could not be verified
+    Failed,
+}
+
+impl Display for AuthOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let outcome = match self {
+            AuthOutcome::Token => "token",
+            AuthOutcome::Cookie => "cookie",
+            AuthOutcome::Anonymous => "anonymous",
+            AuthOutcome::Failed => "failed",
+        };
+        f.write_str(outcome)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CacheResult {
+    /// The response was served from the cache
+    Hit,
+    /// The response was not cached yet, and was generated by the handler
+    Miss,
+    /// The cache was skipped, e.g. because the response must not be cached
+    Bypass,
+}
+
+impl Display for CacheResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let result = match self {
+            CacheResult::Hit => "hit",
+            CacheResult::Miss => "miss",
+            CacheResult::Bypass => "bypass",
+        };
+        f.write_str(result)
+    }
+}
+
+impl CustomMetadataRequestExt for dyn RequestExt + '_ {
+    fn metadata_extension(&self) -> Option<&CustomMetadata> {
+        self.extensions().get::<CustomMetadata>()
+    }
+
+    fn phase_timings_extension(&self) -> Option<&PhaseTimings> {
+        self.extensions().get::<PhaseTimings>()
+    }
+}
+
+impl<B> CustomMetadataRequestExt for Request<B> {
+    fn metadata_extension(&self) -> Option<&CustomMetadata> {
+        self.extensions().get::<CustomMetadata>()
+    }
+
+    fn phase_timings_extension(&self) -> Option<&PhaseTimings> {
+        self.extensions().get::<PhaseTimings>()
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn get_log_message(req: &dyn RequestExt, key: &'static str) -> String {
+    // Unwrap shouldn't panic as no other code has access to the private struct to remove it
+    if let Some(metadata) = req.extensions().get::<CustomMetadata>() {
+        for (k, v) in metadata.snapshot() {
+            if key == k {
+                return v;
+            }
+        }
+    }
+
+    panic!("expected log message for {key} not found");
+}
+
+struct LogLine<'f, 'g> {
+    f: &'f mut Formatter<'g>,
+    first: bool,
+}
+
+impl<'f, 'g> LogLine<'f, 'g> {
+    fn new(f: &'f mut Formatter<'g>) -> Self {
+        Self { f, first: true }
+    }
+
+    fn add_field<K: Display, V: Display>(&mut self, key: K, value: V) -> fmt::Result {
+        self.start_item()?;
+
+        key.fmt(self.f)?;
+        self.f.write_str("=")?;
+        value.fmt(self.f)?;
+
+        Ok(())
+    }
+
+    fn add_quoted_field<K: Display, V: Display>(&mut self, key: K, value: V) -> fmt::Result {
+        self.start_item()?;
+
+        key.fmt(self.f)?;
+        self.f.write_str("=\"")?;
+        value.fmt(self.f)?;
+        self.f.write_str("\"")?;
+
+        Ok(())
+    }
+
+    fn add_marker<M: Display>(&mut self, marker: M) -> fmt::Result {
+        self.start_item()?;
+
+        marker.fmt(self.f)?;
+
+        Ok(())
+    }
+
+    fn start_item(&mut self) -> fmt::Result {
+        if !self.first {
+            self.f.write_str(" ")?;
+        }
+        self.first = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LogQueryParam;
+    use crate::util::tracing::capture_logs;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::get;
+    use axum::Router;
+    use conduit_test::MockRequest;
+    use tower::ServiceExt;
+
+    fn request_metadata(method: Method, uri: &str) -> RequestMetadata {
+        RequestMetadata::new(method, uri, None, Some("cargo 1.66.0"), None, None)
+    }
+
+    fn metadata(request: RequestMetadata, status: StatusCode, req: &dyn RequestExt) -> Metadata {
+        Metadata {
+            request,
+            status,
+            response_content_type: None,
+            location: None,
+            response_bytes: None,
+            response_bytes_raw: None,
+            path_params: None,
+            route: None,
+            handler_thread: None,
+            blocking_wait: None,
+            baggage: None,
+            request_headers: vec![],
+            response_headers: vec![],
+            duration: Duration::from_millis(5),
+            custom_metadata: assert_some!(req.metadata_extension()).clone(),
+            phase_timings: req.phase_timings_extension().cloned().unwrap_or_default(),
+            layer_timings: None,
+            seq: None,
+            config: Arc::new(LogRequestsConfig::for_testing()),
+        }
+    }
+
+    fn mock_request(path: &str) -> MockRequest {
+        let mut req = MockRequest::new(Method::GET, path);
+        req.mut_extensions().insert(CustomMetadata::default());
+        req
+    }
+
+    #[test]
+    fn cache_result_is_logged() {
+        let results = [
+            (CacheResult::Hit, "hit"),
+            (CacheResult::Miss, "miss"),
+            (CacheResult::Bypass, "bypass"),
+        ];
+
+        for (result, expected) in results {
+            let req = mock_request("/api/v1/summary");
+            let req: &dyn RequestExt = &req;
+            req.set_cache_result(result);
+            assert_eq!(get_log_message(req, "cache"), expected);
+
+            let request = request_metadata(Method::GET, "/api/v1/summary");
+            let line = metadata(request, StatusCode::OK, req).to_string();
+            assert!(line.contains(&format!("cache=\"{expected}\"")), "{line}");
+        }
+
+        let req = mock_request("/api/v1/summary");
+        let req: &dyn RequestExt = &req;
+        let request = request_metadata(Method::GET, "/api/v1/summary");
+        let line = metadata(request, StatusCode::OK, req).to_string();
+        assert!(!line.contains("cache="), "{line}");
+    }
+
+    #[test]
+    fn upstream_attempts_are_logged() {
+        let mut req = MockRequest::new(Method::GET, "/api/v1/crates/foo");
+        req.mut_extensions().insert(CustomMetadata::default());
+
+        let req: &dyn RequestExt = &req;
+        let request = request_metadata(Method::GET, "/api/v1/crates/foo");
+        let line = metadata(request, StatusCode::OK, req).to_string();
+        assert!(!line.contains("upstream_attempts"), "{line}");
+
+        req.record_upstream_attempt();
+        req.record_upstream_attempt();
+        assert_eq!(get_log_message(req, "upstream_attempts"), "2");
+
+        let request = request_metadata(Method::GET, "/api/v1/crates/foo");
+        let line = metadata(request, StatusCode::OK, req).to_string();
+        assert!(line.contains(r#"upstream_attempts="2""#), "{line}");
+        assert_eq!(line.matches("upstream_attempts").count(), 1, "{line}");
+    }
+
+    #[test]
+    fn phase_timings_are_logged() {
+        let mut req = mock_request("/api/v1/crates/foo");
+        req.mut_extensions().insert(PhaseTimings::default());
+
+        let req: &dyn RequestExt = &req;
+        req.record_phase("auth", Duration::from_millis(12));
+        req.record_phase("query", Duration::from_millis(30));
+        req.record_phase("query", Duration::from_millis(5));
+        assert_eq!(req.time_phase("serialize", || 42), 42);
+
+        let request = request_metadata(Method::GET, "/api/v1/crates/foo");
+        let line = metadata(request, StatusCode::OK, req).to_string();
+        assert!(line.contains(" t_auth=12 "), "{line}");
+        assert!(line.contains(" t_query=35 "), "{line}");
+        assert!(line.contains(" t_serialize="), "{line}");
+        assert_eq!(line.matches("t_query").count(), 1, "{line}");
+    }
+
+    #[test]
+    fn slow_requests_are_reported() {
+        let req = mock_request("/api/v1/crates/foo");
+        let req: &dyn RequestExt = &req;
+
+        let request = request_metadata(Method::GET, "/api/v1/crates/foo");
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.route = Some("/api/v1/crates/:crate_id".into());
+
+        let events = sentry::test::with_captured_events(|| report_slow_request(&log));
+        assert!(events.is_empty());
+
+        log.duration = Duration::from_millis(1500);
+        let events = sentry::test::with_captured_events(|| report_slow_request(&log));
+        assert_eq!(events.len(), 1);
+
+        let event = &events[0];
+        assert_eq!(event.level, sentry::Level::Warning);
+        assert_eq!(
+            assert_some!(event.message.as_deref()),
+            "Slow request: GET /api/v1/crates/:crate_id"
+        );
+        assert_eq!(event.tags["route"], "/api/v1/crates/:crate_id");
+        assert_eq!(event.tags["duration_ms"], "1500");
+    }
+
+    #[test]
+    fn version_is_logged() {
+        let req = mock_request("/api/v1/summary");
+        let request = request_metadata(Method::GET, "/api/v1/summary");
+        let mut log = metadata(request, StatusCode::OK, &req);
+
+        let line = log.to_string();
+        assert!(!line.contains("version="), "{line}");
+
+        log.config = Arc::new(LogRequestsConfig {
+            version: Some("0123abc".into()),
+            ..LogRequestsConfig::for_testing()
+        });
+        let line = log.to_string();
+        assert!(line.contains(" version=0123abc"), "{line}");
+    }
+
+    #[test]
+    fn compact_routes_are_logged_without_path() {
+        let req = mock_request("/api/v1/summary");
+        let req: &dyn RequestExt = &req;
+
+        let log_line = |uri: &str, compact_routes: Vec<String>| {
+            let request = request_metadata(Method::GET, uri);
+            let mut log = metadata(request, StatusCode::OK, req);
+            log.route = Some("/api/v1/summary".into());
+            log.config = Arc::new(LogRequestsConfig {
+                compact_routes,
+                ..LogRequestsConfig::for_testing()
+            });
+            log.to_string()
+        };
+
+        let compact_routes = vec!["/api/v1/summary".to_string()];
+
+        let normal = log_line("/api/v1/summary", vec![]);
+        assert!(normal.contains(r#" path="/api/v1/summary" "#), "{normal}");
+        assert!(!normal.contains("route="), "{normal}");
+
+        let compact = log_line("/api/v1/summary", compact_routes.clone());
+        assert!(compact.contains(" route=/api/v1/summary "), "{compact}");
+        assert!(!compact.contains("path="), "{compact}");
+        assert_eq!(
+            normal.len() - compact.len(),
+            r#"path="""#.len() - "route=".len()
+        );
+
+        // The query string is not part of the route pattern, so the full path is logged
+        let line = log_line("/api/v1/summary?foo=bar", compact_routes);
+        assert!(
+            line.contains(r#" path="/api/v1/summary?foo=bar" "#),
+            "{line}"
+        );
+        assert!(!line.contains("route="), "{line}");
+    }
+
+    /// Parse a logfmt line into its fields, panicking if it is malformed
+    fn parse_logfmt(line: &str) -> Vec<(&str, &str)> {
+        let mut fields = vec![];
+        let mut rest = line;
+        while !rest.is_empty() {
+            let (key, value) = assert_some!(rest.split_once('='), "{line}");
+            assert!(!key.is_empty() && !key.contains([' ', '"']), "{line}");
+
+            let (value, remaining) = match value.strip_prefix('"') {
+                Some(value) => {
+                    let (value, remaining) = assert_some!(value.split_once('"'), "{line}");
+                    (value, remaining)
+                }
+                None => value.split_at(value.find(' ').unwrap_or(value.len())),
+            };
+
+            fields.push((key, value));
+            rest = match remaining.strip_prefix(' ') {
+                Some(remaining) => remaining,
+                None => {
+                    assert_eq!(remaining, "", "{line}");
+                    remaining
+                }
+            };
+        }
+        fields
+    }
+
+    #[test]
+    fn long_lines_are_truncated() {
+        const MAX_LINE_LENGTH: usize = 300;
+
+        let req = mock_request("/api/v1/crates");
+        let req: &dyn RequestExt = &req;
+        for i in 0..50 {
+            req.add_custom_metadata("cause", format!("an overly long explanation #{i}"));
+        }
+
+        let mut request = request_metadata(Method::GET, "/api/v1/crates?page=2");
+        let user_agent = format!("cargo 1.66.0 ({})", "x".repeat(200));
+        request.user_agent = Some(TypedHeader(user_agent.parse().unwrap()));
+
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.request_headers = vec![("accept".into(), "application/json".repeat(10))];
+
+        let line = log.to_logfmt();
+        assert!(!line.contains("truncated"), "{line}");
+        assert!(line.len() > 2000);
+
+        log.config = Arc::new(LogRequestsConfig {
+            max_line_length: Some(MAX_LINE_LENGTH),
+            ..LogRequestsConfig::for_testing()
+        });
+
+        let line = log.to_logfmt();
+        assert!(line.len() <= MAX_LINE_LENGTH, "{line}");
+
+        let fields = parse_logfmt(&line);
+        let keys = fields.iter().map(|(key, _)| *key).collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "method",
+                "path",
+                "request_id",
+                "service",
+                "status",
+                "truncated"
+            ]
+        );
+        assert!(fields.contains(&("path", "/api/v1/crates?page=2")));
+        assert!(fields.contains(&("truncated", "true")));
+
+        // Only as many fields as necessary are dropped
+        log.config = Arc::new(LogRequestsConfig {
+            max_line_length: Some(600),
+            ..LogRequestsConfig::for_testing()
+        });
+
+        let line = log.to_logfmt();
+        let fields = parse_logfmt(&line);
+        assert!(fields.iter().any(|(key, _)| *key == "user_agent"), "{line}");
+        assert!(fields.iter().any(|(key, _)| *key == "hdr_accept"), "{line}");
+        assert!(!fields.iter().any(|(key, _)| *key == "cause"), "{line}");
+        assert!(line.ends_with(" truncated=true"), "{line}");
+    }
+
+    #[test]
+    fn paths_can_be_decoded() {
+        let req = mock_request("/api/v1/crates");
+        let req: &dyn RequestExt = &req;
+
+        let log_line = |uri: &str, decode_path: bool| {
+            let request = request_metadata(Method::GET, uri);
+            let mut log = metadata(request, StatusCode::OK, req);
+            log.config = Arc::new(LogRequestsConfig {
+                decode_path,
+                ..LogRequestsConfig::for_testing()
+            });
+            log.to_string()
+        };
+
+        let uri = "/api/v1/crates?q=%E2%9C%93%20check";
+        let line = log_line(uri, false);
+        assert!(line.contains(&format!(r#"path="{uri}""#)), "{line}");
+        assert!(!line.contains("raw_path"), "{line}");
+
+        let line = log_line(uri, true);
+        assert!(
+            line.contains(r#"path="/api/v1/crates?q=✓ check""#),
+            "{line}"
+        );
+        assert!(line.contains(&format!(r#"raw_path="{uri}""#)), "{line}");
+
+        // Invalid escape sequences are left as they are
+        let line = log_line("/api/v1/crates?q=%E2%9C%93%zz", true);
+        assert!(line.contains(r#"path="/api/v1/crates?q=✓%zz""#), "{line}");
+
+        // Paths that can't be decoded safely are logged in their raw form
+        for uri in [
+            "/api/v1/crates?q=%FF",
+            "/api/v1/crates?q=%22",
+            "/api/v1/crates?q=%0A",
+        ] {
+            let line = log_line(uri, true);
+            assert!(line.contains(&format!(r#"path="{uri}""#)), "{line}");
+            assert!(!line.contains("raw_path"), "{line}");
+        }
+
+        // Paths without escape sequences don't need a `raw_path` field
+        let line = log_line("/api/v1/crates", true);
+        assert!(!line.contains("raw_path"), "{line}");
+    }
+
+    #[test]
+    fn gelf_messages() {
+        let req = mock_request("/api/v1/crates/foo");
+        let req: &dyn RequestExt = &req;
+        req.add_custom_metadata("auth", AuthOutcome::Cookie);
+
+        let request = request_metadata(Method::GET, "/api/v1/crates/foo");
+        let message = metadata(request, StatusCode::NOT_FOUND, req).to_gelf();
+        assert_eq!(message["version"], "1.1");
+        assert_eq!(message["host"], "crates-io");
+        assert_eq!(message["short_message"], "GET /api/v1/crates/foo 404");
+        assert_eq!(message["level"], 6);
+        assert_eq!(message["_method"], "GET");
+        assert_eq!(message["_path"], "/api/v1/crates/foo");
+        assert_eq!(message["_status"], 404);
+        assert_eq!(message["_user_agent"], "cargo 1.66.0");
+        assert_eq!(message["_auth"], "cookie");
+
+        let message = metadata(
+            request_metadata(Method::GET, "/api/v1/crates/foo"),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            req,
+        )
+        .to_gelf();
+        assert_eq!(message["level"], 3);
+    }
+
+    #[test]
+    fn ecs_messages() {
+        let req = mock_request("/api/v1/crates/foo");
+        let req: &dyn RequestExt = &req;
+        req.add_custom_metadata("auth", AuthOutcome::Cookie);
+
+        let request = request_metadata(Method::GET, "/api/v1/crates/foo");
+        let mut log = metadata(request, StatusCode::NOT_FOUND, req);
+        log.response_bytes = Some(1234);
+        let message = log.to_ecs();
+        assert_eq!(message["ecs"]["version"], ECS_VERSION);
+        assert_eq!(message["message"], "GET /api/v1/crates/foo 404");
+        assert_eq!(message["log"]["level"], "info");
+        assert_eq!(message["host"]["name"], "crates-io");
+        assert_eq!(message["http"]["request"]["method"], "GET");
+        assert_eq!(message["http"]["response"]["status_code"], 404);
+        assert!(message["http"]["response"]["status_code"].is_u64());
+        assert_eq!(message["http"]["response"]["body"]["bytes"], 1234);
+        assert_eq!(message["url"]["path"], "/api/v1/crates/foo");
+        assert_eq!(message["event"]["duration"], 5_000_000);
+        assert!(message["event"]["duration"].is_u64());
+        assert_eq!(message["user_agent"]["original"], "cargo 1.66.0");
+        assert_eq!(message["labels"]["auth"], "cookie");
+        assert!(message["@timestamp"].is_string());
+
+        let message = metadata(
+            request_metadata(Method::GET, "/api/v1/crates/foo"),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            req,
+        )
+        .to_ecs();
+        assert_eq!(message["log"]["level"], "error");
+    }
+
+    #[test]
+    fn custom_metadata_snapshot() {
+        let req = mock_request("/api/v1/crates");
+        let req: &dyn RequestExt = &req;
+        req.add_custom_metadata("first", 1);
+        req.add_custom_metadata("second", "two");
+        req.add_custom_metadata("first", 3);
+
+        let metadata = assert_some!(req.metadata_extension()).clone();
+        let expected = vec![
+            ("first", "1".to_string()),
+            ("second", "two".to_string()),
+            ("first", "3".to_string()),
+        ];
+        assert_eq!(metadata.snapshot(), expected);
+
+        // Poison the lock by panicking while it is held
+        let poisoned = metadata.clone();
+        let result = std::thread::spawn(move || {
+            let _guard = poisoned.lock().unwrap();
+            panic!("poisoning the lock");
+        })
+        .join();
+        assert_err!(result);
+        assert!(metadata.is_poisoned());
+
+        assert_eq!(metadata.snapshot(), expected);
+    }
+
+    #[test]
+    fn syslog_messages() {
+        let req = mock_request("/api/v1/crates/foo");
+        let req: &dyn RequestExt = &req;
+
+        let request = request_metadata(Method::GET, "/api/v1/crates/foo");
+        let mut log = metadata(request, StatusCode::NOT_FOUND, req);
+        log.seq = Some(7);
+        let message = log.to_syslog();
+
+        // local0.info
+        let rest = assert_some!(message.strip_prefix("<134>1 "));
+        let mut header = rest.splitn(6, ' ');
+        let timestamp = header.next().unwrap();
+        assert_ok!(chrono::DateTime::parse_from_rfc3339(timestamp));
+        assert_eq!(header.next(), Some("crates-io"));
+        assert_eq!(header.next(), Some("crates-io"));
+        assert_eq!(header.next(), Some(std::process::id().to_string().as_str()));
+        assert_eq!(header.next(), Some("http"));
+
+        let rest = header.next().unwrap();
+        let structured_data = r#"[http@32473 method="GET" path="/api/v1/crates/foo" status="404" service_ms="5" seq="7"]"#;
+        assert!(rest.starts_with(structured_data), "{message}");
+        assert!(rest.ends_with(&log.to_string()), "{message}");
+
+        let request = request_metadata(Method::GET, "/api/v1/crates/foo");
+        let message = metadata(request, StatusCode::INTERNAL_SERVER_ERROR, req).to_syslog();
+        assert!(message.starts_with("<131>1 "), "{message}");
+
+        assert_eq!(escape_sd_param_value(r#"a"b\c]d"#), r#"a\"b\\c\]d"#);
+        assert_eq!(syslog_header_field(""), "-");
+        assert_eq!(syslog_header_field("web 1"), "web_1");
+    }
+
+    #[test]
+    fn block_wait_is_logged_if_enabled() {
+        let req = mock_request("/api/v1/crates");
+        let req: &dyn RequestExt = &req;
+
+        let request = request_metadata(Method::GET, "/api/v1/crates");
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.blocking_wait = Some(BlockingWait(Duration::from_millis(42)));
+
+        let line = log.to_string();
+        assert!(!line.contains("block_wait"), "{line}");
+
+        log.config = Arc::new(LogRequestsConfig {
+            block_wait: true,
+            ..LogRequestsConfig::for_testing()
+        });
+
+        let line = log.to_string();
+        assert!(line.contains(" block_wait=42"), "{line}");
+    }
+
+    #[test]
+    fn thread_info_is_logged_if_enabled() {
+        let req = mock_request("/api/v1/crates");
+        let req: &dyn RequestExt = &req;
+
+        let thread = std::thread::Builder::new()
+            .name("handler-thread".into())
+            .spawn(std::thread::current)
+            .unwrap()
+            .join()
+            .unwrap();
+
+        let request = request_metadata(Method::GET, "/api/v1/crates");
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.handler_thread = Some(HandlerThread(thread));
+
+        let line = log.to_string();
+        assert!(!line.contains("thread_"), "{line}");
+
+        log.config = Arc::new(LogRequestsConfig {
+            thread_info: true,
+            ..LogRequestsConfig::for_testing()
+        });
+
+        let line = log.to_string();
+        assert!(line.contains(r#"thread_name="handler-thread""#), "{line}");
+        let thread_id = assert_some!(line
+            .split_whitespace()
+            .find(|f| f.starts_with("thread_id=")));
+        assert_ok!(thread_id.trim_start_matches("thread_id=").parse::<u64>());
+    }
+
+    #[test]
+    fn auth_outcome_is_logged() {
+        let outcomes = [
+            (AuthOutcome::Token, "token"),
+            (AuthOutcome::Cookie, "cookie"),
+            (AuthOutcome::Anonymous, "anonymous"),
+            (AuthOutcome::Failed, "failed"),
+        ];
+
+        for (outcome, expected) in outcomes {
+            let mut req = MockRequest::new(Method::GET, "/api/v1/me");
+            req.mut_extensions().insert(CustomMetadata::default());
+
+            let req: &dyn RequestExt = &req;
+            req.set_auth_outcome(outcome);
+            assert_eq!(get_log_message(req, "auth"), expected);
+
+            let request = request_metadata(Method::GET, "/api/v1/me");
+            let line = metadata(request, StatusCode::OK, req).to_string();
+            assert!(line.contains(&format!("auth=\"{expected}\"")), "{line}");
+        }
+    }
+
+    #[test]
+    fn client_info_is_logged() {
+        let req = mock_request("/api/v1/crates");
+        let req: &dyn RequestExt = &req;
+
+        let mut request = request_metadata(Method::GET, "/api/v1/crates");
+        request.client_info = Some(Extension(ClientInfo {
+            ip: Some([192, 0, 2, 1].into()),
+            scheme: http::uri::Scheme::HTTPS,
+            host: Some("crates.io".into()),
+        }));
+        let line = metadata(request, StatusCode::OK, req).to_string();
+        assert!(line.contains(r#"fwd="192.0.2.1""#), "{line}");
+        assert!(line.contains("protocol=https"), "{line}");
+
+        let request = request_metadata(Method::GET, "/api/v1/crates");
+        let line = metadata(request, StatusCode::OK, req).to_string();
+        assert!(line.contains(r#"fwd="""#), "{line}");
+        assert!(!line.contains("protocol="), "{line}");
+    }
+
+    #[test]
+    fn ip_logging_modes() {
+        let req = mock_request("/api/v1/crates");
+        let req: &dyn RequestExt = &req;
+
+        let log_line = |ip: [u8; 4], ip_logging: IpLogging| {
+            let mut request = request_metadata(Method::GET, "/api/v1/crates");
+            request.client_info = Some(Extension(ClientInfo {
+                ip: Some(ip.into()),
+                scheme: http::uri::Scheme::HTTPS,
+                host: None,
+            }));
+            let mut log = metadata(request, StatusCode::OK, req);
+            log.config = Arc::new(LogRequestsConfig {
+                ip_logging,
+                ..LogRequestsConfig::for_testing()
+            });
+            log.to_string()
+        };
+
+        let line = log_line([192, 0, 2, 1], IpLogging::Raw);
+        assert!(line.contains(r#"fwd="192.0.2.1""#), "{line}");
+
+        let hashed = || IpLogging::Hashed("salt".into());
+        let line = log_line([192, 0, 2, 1], hashed());
+        assert!(!line.contains("192.0.2.1"), "{line}");
+        let expected = format!(r#"fwd="{}""#, hash_ip("salt", [192, 0, 2, 1].into()));
+        assert!(line.contains(&expected), "{line}");
+        assert_eq!(log_line([192, 0, 2, 1], hashed()), line);
+        assert_ne!(log_line([192, 0, 2, 2], hashed()), line);
+        assert_ne!(
+            log_line([192, 0, 2, 1], IpLogging::Hashed("pepper".into())),
+            line
+        );
+
+        let line = log_line([192, 0, 2, 1], IpLogging::None);
+        assert!(!line.contains("fwd="), "{line}");
+        assert!(!line.contains("192.0.2.1"), "{line}");
+    }
+
+    #[test]
+    fn configured_baggage_is_logged() {
+        let req = mock_request("/api/v1/crates");
+        let req: &dyn RequestExt = &req;
+
+        let mut headers = http::HeaderMap::new();
+        let baggage = "tenant=acme,user_id=42,session=secret";
+        headers.insert("baggage", baggage.parse().unwrap());
+
+        let request = request_metadata(Method::GET, "/api/v1/crates");
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.baggage = Baggage::from_headers(&headers);
+        log.config = Arc::new(LogRequestsConfig {
+            baggage_keys: vec!["tenant".into(), "user_id".into(), "missing".into()],
+            ..LogRequestsConfig::for_testing()
+        });
+
+        let line = log.to_string();
+        assert!(line.contains(r#"baggage_tenant="acme""#), "{line}");
+        assert!(line.contains(r#"baggage_user_id="42""#), "{line}");
+        assert!(!line.contains("baggage_missing"), "{line}");
+        assert!(!line.contains("secret"), "{line}");
+    }
+
+    #[test]
+    fn selected_headers_are_logged() {
+        let req = mock_request("/api/v1/crates");
+        let req: &dyn RequestExt = &req;
+
+        let mut request_headers = http::HeaderMap::new();
+        request_headers.insert("accept-language", "en-US".parse().unwrap());
+        request_headers.insert(header::AUTHORIZATION, "secret-token".parse().unwrap());
+
+        let mut response_headers = http::HeaderMap::new();
+        response_headers.append(header::VARY, "accept".parse().unwrap());
+        response_headers.append(header::VARY, "accept-encoding".parse().unwrap());
+        response_headers.insert(header::SET_COOKIE, "session=secret".parse().unwrap());
+
+        let request_names = vec!["accept-language".to_string()];
+        let response_names = vec!["vary".to_string()];
+
+        let request = request_metadata(Method::GET, "/api/v1/crates");
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.request_headers = selected_headers(&request_headers, &request_names);
+        log.response_headers = selected_headers(&response_headers, &response_names);
+
+        let line = log.to_string();
+        assert!(line.contains(r#"hdr_accept-language="en-US""#), "{line}");
+        assert!(
+            line.contains(r#"res_hdr_vary="accept, accept-encoding""#),
+            "{line}"
+        );
+        assert!(!line.contains("authorization"), "{line}");
+        assert!(!line.contains("set-cookie"), "{line}");
+        assert!(!line.contains("secret"), "{line}");
+
+        let request_names = vec!["authorization".to_string()];
+        let response_names = vec!["set-cookie".to_string()];
+
+        let request = request_metadata(Method::GET, "/api/v1/crates");
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.request_headers = selected_headers(&request_headers, &request_names);
+        log.response_headers = selected_headers(&response_headers, &response_names);
+
+        let line = log.to_string();
+        assert!(line.contains(r#"hdr_authorization="[redacted]""#), "{line}");
+        assert!(
+            line.contains(r#"res_hdr_set-cookie="[redacted]""#),
+            "{line}"
+        );
+        assert!(!line.contains("secret"), "{line}");
+    }
+
+    #[test]
+    fn content_types_are_logged() {
+        let req = mock_request("/api/v1/crates/new");
+        let req: &dyn RequestExt = &req;
+
+        let mut request = request_metadata(Method::PUT, "/api/v1/crates/new");
+        request.content_type = Some(TypedHeader(ContentType::octet_stream()));
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.response_content_type = Some("application/json; charset=utf-8".into());
+
+        let line = log.to_string();
+        assert!(
+            line.contains(r#"req_content_type="application/octet-stream""#),
+            "{line}"
+        );
+        assert!(
+            line.contains(r#"res_content_type="application/json; charset=utf-8""#),
+            "{line}"
+        );
+
+        let request = request_metadata(Method::GET, "/api/v1/crates");
+        let line = metadata(request, StatusCode::OK, req).to_string();
+        assert!(!line.contains("content_type="), "{line}");
+    }
+
+    struct Unreachable;
+
+    impl conduit::Handler for Unreachable {
+        fn call(&self, _req: &mut dyn RequestExt) -> conduit::HandlerResult {
+            unreachable!("rejected requests must not reach the handler")
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_requests_are_logged() {
+        use axum::extract::ConnectInfo;
+        use conduit_axum::ConduitFallback;
+        use std::net::SocketAddr;
+
+        let (logs, _guard) = capture_logs();
+
+        let config = Arc::new(LogRequestsConfig::for_testing());
+        let remote_addr: SocketAddr = ([127, 0, 0, 1], 80).into();
+        let router = Router::new()
+            .conduit_fallback(Unreachable)
+            .layer(from_fn_with_state(config, log_requests))
+            .layer(Extension(ConnectInfo(remote_addr)));
+
+        let request = Request::put("/api/v1/crates/new")
+            .header(header::USER_AGENT, "cargo 1.66.0")
+            .header(header::CONTENT_LENGTH, 1024 * 1024 * 1024)
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let line = logs.contents();
+        assert!(line.contains(r#"path="/api/v1/crates/new""#), "{line}");
+        assert!(line.contains("status=400"), "{line}");
+        assert!(
+            line.contains(r#"cause="Content-Length too large""#),
+            "{line}"
+        );
+
+        // Requests without a `User-Agent` header are logged too
+        let request = Request::put("/api/v1/crates/new")
+            .header(header::CONTENT_LENGTH, "invalid")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let line = logs.contents();
+        assert!(
+            line.contains(r#"cause="Content-Length not a u64""#),
+            "{line}"
+        );
+    }
+
+    #[tokio::test]
+    async fn sampled_requests_are_logged_verbosely() {
+        async fn request_logs(verbose_sample_rate: f32) -> String {
+            let (logs, _guard) = capture_logs();
+
+            let config = Arc::new(LogRequestsConfig {
+                verbose_sample_rate,
+                ..LogRequestsConfig::for_testing()
+            });
+            let router = Router::new()
+                .route("/api/v1/summary", get(|| async { "{}" }))
+                .layer(from_fn_with_state(config, log_requests));
+
+            let request = Request::get("/api/v1/summary")
+                .header(header::USER_AGENT, "cargo 1.66.0")
+                .header(header::AUTHORIZATION, "secret-token")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let response = router.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            logs.contents()
+        }
+
+        let logs = request_logs(1.0).await;
+        let lines = logs.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "{logs}");
+        assert!(lines[0].contains(" http: "), "{logs}");
+        assert!(!lines[0].contains("hdr_"), "{logs}");
+        assert!(lines[1].contains(" http.verbose: "), "{logs}");
+        assert!(lines[1].contains("service_us="), "{logs}");
+        assert!(
+            lines[1].contains(r#"hdr_user-agent="cargo 1.66.0""#),
+            "{logs}"
+        );
+        assert!(
+            lines[1].contains(r#"hdr_authorization="[redacted]""#),
+            "{logs}"
+        );
+        assert!(lines[1].contains("res_hdr_content-type="), "{logs}");
+        assert!(!logs.contains("secret-token"), "{logs}");
+
+        let logs = request_logs(0.0).await;
+        assert_eq!(logs.lines().count(), 1, "{logs}");
+        assert!(logs.contains(" http: "), "{logs}");
+        assert!(!logs.contains("http.verbose"), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn log_statuses_are_configurable() {
+        let (logs, _guard) = capture_logs();
+
+        let config = Arc::new(LogRequestsConfig {
+            statuses: LogStatuses::ErrorsOnly,
+            ..LogRequestsConfig::for_testing()
+        });
+        let router = Router::new()
+            .route("/ok", get(|| async { StatusCode::OK }))
+            .route(
+                "/error",
+                get(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+            )
+            .layer(from_fn_with_state(config, log_requests));
+
+        for path in ["/ok", "/error"] {
+            let request = Request::get(path).body(axum::body::Body::empty()).unwrap();
+            router.clone().oneshot(request).await.unwrap();
+        }
+
+        let logs = logs.contents();
+        assert_eq!(logs.lines().count(), 1, "{logs}");
+        assert!(logs.contains(r#"path="/error""#), "{logs}");
+        assert!(logs.contains("status=500"), "{logs}");
+        assert!(!logs.contains(r#"path="/ok""#), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn redirect_locations_are_logged() {
+        async fn request_logs(config: LogRequestsConfig) -> String {
+            let (logs, _guard) = capture_logs();
+
+            let redirect = |location: &'static str| {
+                get(move || async move { (StatusCode::FOUND, [(header::LOCATION, location)]) })
+            };
+            let router = Router::new()
+                .route("/ok", get(|| async { StatusCode::OK }))
+                .route("/redirect", redirect("https://example.com/new"))
+                .route(
+                    "/api/v1/crates/foo/1.0.0/download",
+                    redirect("https://static.crates.io/crates/foo/foo-1.0.0.crate"),
+                )
+                .layer(from_fn_with_state(Arc::new(config), log_requests));
+
+            for path in ["/ok", "/redirect", "/api/v1/crates/foo/1.0.0/download"] {
+                let request = Request::get(path).body(axum::body::Body::empty()).unwrap();
+                router.clone().oneshot(request).await.unwrap();
+            }
+
+            logs.contents()
+        }
+
+        let logs = request_logs(LogRequestsConfig::for_testing()).await;
+        let lines = logs.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3, "{logs}");
+        assert!(!lines[0].contains("location="), "{logs}");
+        assert!(
+            lines[1].contains(r#"location="https://example.com/new""#),
+            "{logs}"
+        );
+        assert!(!lines[2].contains("location="), "{logs}");
+
+        let logs = request_logs(LogRequestsConfig {
+            download_redirect_location: true,
+            ..LogRequestsConfig::for_testing()
+        })
+        .await;
+        assert!(
+            logs.contains(r#"location="https://static.crates.io/crates/foo/foo-1.0.0.crate""#),
+            "{logs}"
+        );
+    }
+
+    #[tokio::test]
+    async fn sinks_receive_logged_requests() {
+        use crate::config::LogSinks;
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let received = received.clone();
+            move |metadata: &Metadata| {
+                let entry = (metadata.uri().path().to_string(), metadata.status());
+                received.lock().unwrap().push(entry);
+            }
+        };
+        let sink: Arc<dyn LogSink> = Arc::new(sink);
+
+        let config = Arc::new(LogRequestsConfig {
+            sinks: LogSinks::new(vec![sink]),
+            ..LogRequestsConfig::for_testing()
+        });
+        let router = Router::new()
+            .route("/ok", get(|| async { StatusCode::OK }))
+            .route("/missing", get(|| async { StatusCode::NOT_FOUND }))
+            .layer(from_fn_with_state(config, log_requests));
+
+        for path in ["/ok", "/missing"] {
+            let request = Request::get(path).body(axum::body::Body::empty()).unwrap();
+            router.clone().oneshot(request).await.unwrap();
+        }
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                ("/ok".to_string(), StatusCode::OK),
+                ("/missing".to_string(), StatusCode::NOT_FOUND),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn traces_are_continued_from_traceparent() {
+        let (logs, _guard) = capture_logs();
+
+        let handler = |Extension(context): Extension<TraceContext>| async move {
+            info!("handling request");
+            context.traceparent()
+        };
+
+        let config = Arc::new(LogRequestsConfig::for_testing());
+        let router = Router::new()
+            .route("/", get(handler))
+            .layer(from_fn_with_state(config, log_requests));
+
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        let parent_id = "00f067aa0ba902b7";
+        let request = Request::get("/")
+            .header("traceparent", format!("00-{trace_id}-{parent_id}-01"))
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let traceparent = std::str::from_utf8(&body).unwrap();
+
+        // The handler continues the same trace, as a child of the request span
+        let fields = traceparent.split('-').collect::<Vec<_>>();
+        assert_eq!(fields.len(), 4, "{traceparent}");
+        assert_eq!(fields[1], trace_id);
+        assert_ne!(fields[2], parent_id);
+        assert_eq!(fields[3], "01");
+
+        let logs = logs.contents();
+        let line = assert_some!(logs.lines().find(|line| line.contains("handling request")));
+        assert!(line.contains(&format!("trace_id={trace_id}")), "{line}");
+        assert!(line.contains(&format!("span_id={}", fields[2])), "{line}");
+        assert!(
+            line.contains(&format!("parent_span_id={parent_id}")),
+            "{line}"
+        );
+        assert!(line.contains("sampled=true"), "{line}");
+    }
+
+    #[tokio::test]
+    async fn deprecated_routes_are_logged() {
+        use axum::response::Response;
+
+        let (logs, _guard) = capture_logs();
+
+        let deprecated = || async {
+            let mut response = Response::new(axum::body::boxed(axum::body::Empty::new()));
+            let deprecated = Deprecated("Deprecated endpoint".into());
+            response.extensions_mut().insert(deprecated);
+            response
+        };
+
+        let config = Arc::new(LogRequestsConfig::for_testing());
+        let router = Router::new()
+            .route("/ok", get(|| async { StatusCode::OK }))
+            .route("/old", get(deprecated))
+            .layer(from_fn_with_state(config, log_requests));
+
+        for path in ["/ok", "/old"] {
+            let request = Request::get(path).body(axum::body::Body::empty()).unwrap();
+            router.clone().oneshot(request).await.unwrap();
+        }
+
+        let logs = logs.contents();
+        let line = assert_some!(logs.lines().find(|line| line.contains(r#"path="/old""#)));
+        assert!(line.contains(" deprecated=true"), "{line}");
+        let line = assert_some!(logs.lines().find(|line| line.contains(r#"path="/ok""#)));
+        assert!(!line.contains("deprecated"), "{line}");
+    }
+
+    #[tokio::test]
+    async fn start_lines_are_logged() {
+        let (logs, _guard) = capture_logs();
+
+        let request = || {
+            Request::get("/api/v1/summary")
+                .header("x-request-id", "abc123")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let config = Arc::new(LogRequestsConfig::for_testing());
+        let router = Router::new()
+            .route("/api/v1/summary", get(|| async { StatusCode::OK }))
+            .layer(from_fn_with_state(config, log_requests));
+        router.oneshot(request()).await.unwrap();
+        assert!(!logs.contents().contains("http.start"));
+
+        let config = Arc::new(LogRequestsConfig {
+            start_line: true,
+            ..LogRequestsConfig::for_testing()
+        });
+        let router = Router::new()
+            .route("/api/v1/summary", get(|| async { StatusCode::OK }))
+            .layer(from_fn_with_state(config, log_requests));
+        router.oneshot(request()).await.unwrap();
+
+        let logs = logs.contents();
+        let lines = logs.lines().skip(1).collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "{logs}");
+        assert!(lines[0].contains("http.start:"), "{}", lines[0]);
+        assert!(
+            lines[0].ends_with(r#"method=GET path="/api/v1/summary" request_id=abc123"#),
+            "{}",
+            lines[0]
+        );
+        assert!(lines[1].contains("http:"), "{}", lines[1]);
+        assert!(lines[1].contains("request_id=abc123"), "{}", lines[1]);
+        assert!(lines[1].contains("status=200"), "{}", lines[1]);
+    }
+
+    #[tokio::test]
+    async fn connection_requests_are_logged() {
+        let (logs, _guard) = capture_logs();
+
+        let config = Arc::new(LogRequestsConfig::for_testing());
+        let router = Router::new()
+            .route("/ok", get(|| async { StatusCode::OK }))
+            .layer(from_fn_with_state(config, log_requests));
+
+        let request = Request::get("/ok").body(axum::body::Body::empty()).unwrap();
+        router.clone().oneshot(request).await.unwrap();
+
+        let router = router.layer(Extension(ConnectionRequests(2)));
+        let request = Request::get("/ok").body(axum::body::Body::empty()).unwrap();
+        router.oneshot(request).await.unwrap();
+
+        let logs = logs.contents();
+        let lines = logs.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "{logs}");
+        assert!(!lines[0].contains("conn_reqs"), "{}", lines[0]);
+        assert!(lines[1].contains(" conn_reqs=2 "), "{}", lines[1]);
+    }
+
+    #[tokio::test]
+    async fn sentry_event_ids_are_logged() {
+        use axum::response::Response;
+        use sentry::types::Uuid;
+
+        let (logs, _guard) = capture_logs();
+
+        let event_id = Uuid::from_u128(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef);
+        let server_error = move || async move {
+            let mut response = Response::new(axum::body::boxed(axum::body::Empty::new()));
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response.extensions_mut().insert(SentryEventId(event_id));
+            response
+        };
+
+        let config = Arc::new(LogRequestsConfig::for_testing());
+        let router = Router::new()
+            .route("/ok", get(|| async { StatusCode::OK }))
+            .route("/error", get(server_error))
+            .layer(from_fn_with_state(config, log_requests));
+
+        for path in ["/ok", "/error"] {
+            let request = Request::get(path).body(axum::body::Body::empty()).unwrap();
+            router.clone().oneshot(request).await.unwrap();
+        }
+
+        let logs = logs.contents();
+        let line = assert_some!(logs.lines().find(|line| line.contains(r#"path="/error""#)));
+        let expected = format!(r#"sentry_id="{event_id}""#);
+        assert!(line.contains(&expected), "{line}");
+        let line = assert_some!(logs.lines().find(|line| line.contains(r#"path="/ok""#)));
+        assert!(!line.contains("sentry_id"), "{line}");
+    }
+
+    #[tokio::test]
+    async fn response_time_header_is_added() {
+        let router = |config| {
+            Router::new()
+                .route("/ok", get(|| async { StatusCode::OK }))
+                .layer(from_fn_with_state(Arc::new(config), log_requests))
+        };
+
+        let config = LogRequestsConfig {
+            response_time_header: true,
+            ..LogRequestsConfig::for_testing()
+        };
+        let request = Request::get("/ok").body(axum::body::Body::empty()).unwrap();
+        let response = router(config).oneshot(request).await.unwrap();
+        let value = assert_some!(response.headers().get("x-response-time"));
+        assert_ok!(assert_ok!(value.to_str()).parse::<u64>());
+
+        // The `Server-Timing` header is not affected
+        assert_none!(response.headers().get("server-timing"));
+
+        // The header is disabled by default
+        let config = LogRequestsConfig::for_testing();
+        let request = Request::get("/ok").body(axum::body::Body::empty()).unwrap();
+        let response = router(config).oneshot(request).await.unwrap();
+        assert_none!(response.headers().get("x-response-time"));
+    }
+
+    #[tokio::test]
+    async fn transfer_modes_are_logged() {
+        use axum::response::Response;
+
+        let (logs, _guard) = capture_logs();
+
+        let with_mode = |mode| {
+            move || async move {
+                let mut response = Response::new(axum::body::boxed(axum::body::Empty::new()));
+                response.extensions_mut().insert(mode);
+                response
+            }
+        };
+
+        let config = Arc::new(LogRequestsConfig::for_testing());
+        let router = Router::new()
+            .route("/file", get(with_mode(TransferMode::Chunked)))
+            .route("/owned", get(with_mode(TransferMode::ContentLength)))
+            .route("/ok", get(|| async { StatusCode::OK }))
+            .layer(from_fn_with_state(config, log_requests));
+
+        for path in ["/file", "/owned", "/ok"] {
+            let request = Request::get(path).body(axum::body::Body::empty()).unwrap();
+            router.clone().oneshot(request).await.unwrap();
+        }
+
+        let logs = logs.contents();
+        let line = assert_some!(logs.lines().find(|line| line.contains(r#"path="/file""#)));
+        assert!(line.contains(r#"transfer="chunked""#), "{line}");
+        let line = assert_some!(logs.lines().find(|line| line.contains(r#"path="/owned""#)));
+        assert!(line.contains(r#"transfer="content-length""#), "{line}");
+        let line = assert_some!(logs.lines().find(|line| line.contains(r#"path="/ok""#)));
+        assert!(!line.contains("transfer="), "{line}");
+    }
+
+    #[tokio::test]
+    async fn negotiated_representations_are_logged() {
+        use axum::response::Response;
+
+        let (logs, _guard) = capture_logs();
+
+        let with_format = |format| {
+            move || async move {
+                let mut response = Response::new(axum::body::boxed(axum::body::Empty::new()));
+                response.extensions_mut().insert(format);
+                response
+            }
+        };
+
+        let config = Arc::new(LogRequestsConfig::for_testing());
+        let router = Router::new()
+            .route("/json", get(with_format(ResponseFormat::Json)))
+            .route("/msgpack", get(with_format(ResponseFormat::MessagePack)))
+            .route("/ok", get(|| async { StatusCode::OK }))
+            .layer(from_fn_with_state(config, log_requests));
+
+        for path in ["/json", "/msgpack", "/ok"] {
+            let request = Request::get(path).body(axum::body::Body::empty()).unwrap();
+            router.clone().oneshot(request).await.unwrap();
+        }
+
+        let logs = logs.contents();
+        let line = assert_some!(logs.lines().find(|line| line.contains(r#"path="/json""#)));
+        assert!(line.contains(r#"repr="json""#), "{line}");
+        let line = assert_some!(logs
+            .lines()
+            .find(|line| line.contains(r#"path="/msgpack""#)));
+        assert!(line.contains(r#"repr="msgpack""#), "{line}");
+        let line = assert_some!(logs.lines().find(|line| line.contains(r#"path="/ok""#)));
+        assert!(!line.contains("repr="), "{line}");
+    }
+
+    #[tokio::test]
+    async fn sequence_numbers_are_logged() {
+        let (logs, _guard) = capture_logs();
+
+        let config = Arc::new(LogRequestsConfig {
+            verbose_sample_rate: 1.0,
+            ..LogRequestsConfig::for_testing()
+        });
+        let router = Router::new()
+            .route("/", get(|| async { StatusCode::OK }))
+            .layer(from_fn_with_state(config, log_requests));
+
+        for _ in 0..2 {
+            let request = Request::get("/").body(axum::body::Body::empty()).unwrap();
+            router.clone().oneshot(request).await.unwrap();
+        }
+
+        let logs = logs.contents();
+        let seqs = logs
+            .lines()
+            .map(|line| {
+                let (_, seq) = assert_some!(line.split_once(" seq="));
+                let seq = seq.split(' ').next().unwrap_or_default();
+                assert_ok!(seq.parse::<u64>())
+            })
+            .collect::<Vec<_>>();
+
+        // Each request has a regular and a verbose log line with the same sequence number
+        assert_eq!(seqs.len(), 4, "{logs}");
+        assert_eq!(seqs[0], seqs[1], "{logs}");
+        assert_eq!(seqs[2], seqs[3], "{logs}");
+        assert!(seqs[2] > seqs[0], "{logs}");
+    }
+
+    #[test]
+    fn log_statuses_are_parsed() {
+        assert_eq!(assert_ok!("all".parse()), LogStatuses::All);
+        assert_eq!(assert_ok!("errors".parse()), LogStatuses::ErrorsOnly);
+
+        let statuses: LogStatuses = assert_ok!("302,400-499".parse());
+        assert_eq!(statuses, LogStatuses::Custom(vec![302..=302, 400..=499]));
+        assert!(statuses.includes(302));
+        assert!(statuses.includes(404));
+        assert!(!statuses.includes(200));
+        assert!(!statuses.includes(500));
+
+        assert_err!("errors-only".parse::<LogStatuses>());
+        assert_err!("400-5xx".parse::<LogStatuses>());
+    }
+
+    #[test]
+    fn long_paths_are_truncated() {
+        let req = mock_request("/api/v1/crates");
+        let req: &dyn RequestExt = &req;
+
+        let uri = format!(
+            "/api/v1/crates?q={}",
+            "a".repeat(2 * MAX_LOGGED_PATH_LENGTH)
+        );
+        let request = request_metadata(Method::GET, &uri);
+        let line = metadata(request, StatusCode::URI_TOO_LONG, req).to_string();
+        let expected = format!(r#"path="{}...""#, &uri[..MAX_LOGGED_PATH_LENGTH]);
+        assert!(line.contains(&expected), "{line}");
+    }
+
+    #[test]
+    fn long_paths_are_truncated_at_char_boundaries() {
+        let prefix = "a".repeat(MAX_LOGGED_PATH_LENGTH - 1);
+        let path = format!("{prefix}é{}", "b".repeat(10));
+        assert!(!path.is_char_boundary(MAX_LOGGED_PATH_LENGTH));
+        assert_eq!(truncate_path(&path), format!("{prefix}..."));
+
+        let path = "a".repeat(MAX_LOGGED_PATH_LENGTH);
+        assert_eq!(truncate_path(&path), path);
+    }
+
+    #[test]
+    fn large_responses_are_marked() {
+        let req = mock_request("/api/v1/crates");
+        let req: &dyn RequestExt = &req;
+
+        let config = LogRequestsConfig {
+            large_response_threshold: 1000,
+            ..LogRequestsConfig::for_testing()
+        };
+
+        let request = request_metadata(Method::GET, "/api/v1/crates");
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.config = Arc::new(config.clone());
+        log.response_bytes = Some(1001);
+        let line = log.to_string();
+        assert!(line.contains("bytes=1001"), "{line}");
+        assert!(line.ends_with("LARGE RESPONSE"), "{line}");
+
+        let request = request_metadata(Method::GET, "/api/v1/crates");
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.config = Arc::new(config);
+        log.response_bytes = Some(1000);
+        let line = log.to_string();
+        assert!(line.contains("bytes=1000"), "{line}");
+        assert!(!line.contains("LARGE RESPONSE"), "{line}");
+    }
+
+    #[test]
+    fn compressed_sizes_are_logged() {
+        let req = mock_request("/assets/app.js");
+        let req: &dyn RequestExt = &req;
+
+        let request = request_metadata(Method::GET, "/assets/app.js");
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.response_bytes = Some(250);
+        log.response_bytes_raw = Some(1000);
+        let line = log.to_string();
+        assert!(line.contains("bytes=250 bytes_raw=1000"), "{line}");
+
+        let request = request_metadata(Method::GET, "/assets/app.js");
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.response_bytes = Some(1000);
+        let line = log.to_string();
+        assert!(line.contains("bytes=1000"), "{line}");
+        assert!(!line.contains("bytes_raw"), "{line}");
+    }
+
+    #[test]
+    fn selected_query_params_are_logged() {
+        let path = "/api/v1/crates?page=2&sort=downloads&per_page=10&q=serde";
+        let req = mock_request("/api/v1/crates");
+        let req: &dyn RequestExt = &req;
+
+        let request = request_metadata(Method::GET, path);
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.route = Some("/api/v1/crates".into());
+        log.config = Arc::new(LogRequestsConfig {
+            query_params: vec![
+                assert_ok!("page".parse()),
+                assert_ok!("sort@/api/v1/crates".parse()),
+                assert_ok!("q@/api/v1/keywords".parse()),
+            ],
+            ..LogRequestsConfig::for_testing()
+        });
+
+        let line = log.to_string();
+        assert!(line.contains(r#"q_page="2""#), "{line}");
+        assert!(line.contains(r#"q_sort="downloads""#), "{line}");
+        assert!(!line.contains("q_per_page"), "{line}");
+        assert!(!line.contains("q_q="), "{line}");
+    }
+
+    #[test]
+    fn credentials_in_query_params_are_redacted() {
+        let path = "/api/private/session/authorize?code=secret&state=secret";
+        let req = mock_request("/api/private/session/authorize");
+        let req: &dyn RequestExt = &req;
+
+        let request = request_metadata(Method::GET, path);
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.config = Arc::new(LogRequestsConfig {
+            query_params: vec![assert_ok!("code".parse())],
+            ..LogRequestsConfig::for_testing()
+        });
+
+        let line = log.to_string();
+        assert!(line.contains(r#"q_code="[redacted]""#), "{line}");
+    }
+
+    #[test]
+    fn logged_query_params_are_parsed() {
+        let param: LogQueryParam = assert_ok!("sort@/api/v1/crates".parse());
+        assert_eq!(param.name, "sort");
+        assert_eq!(param.route.as_deref(), Some("/api/v1/crates"));
+        assert!(param.applies_to(Some("/api/v1/crates")));
+        assert!(!param.applies_to(Some("/api/v1/keywords")));
+        assert!(!param.applies_to(None));
+
+        let param: LogQueryParam = assert_ok!("page".parse());
+        assert_none!(param.route);
+        assert!(param.applies_to(None));
+
+        assert_err!("".parse::<LogQueryParam>());
+        assert_err!("sort@api/v1/crates".parse::<LogQueryParam>());
+    }
+
+    #[test]
+    fn selected_path_params_are_logged() {
+        let req = mock_request("/api/v1/crates/foo/1.0.0");
+        let req: &dyn RequestExt = &req;
+
+        let mut params = route_recognizer::Params::new();
+        params.insert("crate_id".into(), "foo".into());
+        params.insert("version".into(), "1.0.0".into());
+
+        let request = request_metadata(Method::GET, "/api/v1/crates/foo/1.0.0");
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.path_params = Some(PathParams::from(&params));
+        log.config = Arc::new(LogRequestsConfig {
+            path_params: vec!["crate_id".into()],
+            ..LogRequestsConfig::for_testing()
+        });
+
+        let line = log.to_string();
+        assert!(line.contains(r#"param_crate_id="foo""#), "{line}");
+        assert!(!line.contains("param_version"), "{line}");
+    }
+
+    #[test]
+    fn download_redirects_are_trimmed() {
+        let req = mock_request("/api/v1/crates/foo/1.0.0/download");
+        let req: &dyn RequestExt = &req;
+
+        let path = "/api/v1/crates/foo/1.0.0/download";
+        let ip = Some([192, 0, 2, 1].into());
+        let request = || RequestMetadata::new(Method::GET, path, None, None, Some("abcd"), ip);
+
+        let line = metadata(request(), StatusCode::FOUND, req).to_string();
+        assert_eq!(
+            line,
+            r#"path="/api/v1/crates/foo/1.0.0/download" fwd="192.0.2.1" service=5ms user_agent="""#
+        );
+
+        let line = metadata(request(), StatusCode::OK, req).to_string();
+        assert!(line.starts_with("method=GET "), "{line}");
+        assert!(line.contains("request_id=abcd"), "{line}");
+        assert!(line.contains("protocol=https"), "{line}");
+        assert!(line.contains("status=200"), "{line}");
+    }
+
+    #[test]
+    fn crate_names_are_logged() {
+        let req = mock_request("/api/v1/crates/foo/downloads");
+        let req: &dyn RequestExt = &req;
+
+        let mut params = route_recognizer::Params::new();
+        params.insert("crate_id".into(), "foo".into());
+
+        let request = request_metadata(Method::GET, "/api/v1/crates/foo/downloads");
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.path_params = Some(PathParams::from(&params));
+        let line = log.to_string();
+        assert!(line.contains(r#"crate="foo""#), "{line}");
+
+        let mut params = route_recognizer::Params::new();
+        params.insert("user_id".into(), "1".into());
+
+        let request = request_metadata(Method::GET, "/api/v1/users/1");
+        let mut log = metadata(request, StatusCode::OK, req);
+        log.path_params = Some(PathParams::from(&params));
+        let line = log.to_string();
+        assert!(!line.contains("crate="), "{line}");
+    }
+
+    struct RecordCrateName;
+
+    impl conduit::Handler for RecordCrateName {
+        fn call(&self, req: &mut dyn RequestExt) -> conduit::HandlerResult {
+            let mut params = route_recognizer::Params::new();
+            if let Some(crate_name) = req.path().strip_prefix("/api/v1/crates/") {
+                params.insert("crate_id".into(), crate_name.into());
+            }
+            record_crate_name(&PathParams::from(&params));
+
+            info!("handling request");
+            Ok(Response::new(conduit::Body::empty()))
+        }
+    }
+
+    #[tokio::test]
+    async fn crate_names_are_recorded_on_the_request_span() {
+        use axum::extract::ConnectInfo;
+        use conduit_axum::ConduitFallback;
+        use std::net::SocketAddr;
+
+        let (logs, _guard) = capture_logs();
+
+        let config = Arc::new(LogRequestsConfig::for_testing());
+        let remote_addr: SocketAddr = ([127, 0, 0, 1], 80).into();
+        let router = Router::new()
+            .conduit_fallback(RecordCrateName)
+            .layer(from_fn_with_state(config, log_requests))
+            .layer(Extension(ConnectInfo(remote_addr)));
+
+        let request = Request::get("/api/v1/crates/foo")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let output = logs.contents();
+        assert!(output.contains(r#"request{crate="foo"}"#), "{output}");
+
+        let logs_before = output.len();
+        let request = Request::get("/api/v1/summary")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let output = logs.contents();
+        let output = &output[logs_before..];
+        assert!(output.contains("request: "), "{output}");
+        assert!(!output.contains("crate="), "{output}");
     }
 }
@@ -0,0 +1,101 @@
+//! Reject all requests with `503 Service Unavailable` during a planned maintenance
+//!
+//! While `App::maintenance_mode` is enabled, requests are answered with the configured
+//! maintenance message and a `Retry-After` header instead of being passed to the static file
+//! handlers or the endpoints. Clients that accept HTML receive a maintenance page, while all
+//! other clients receive the message as a JSON error. The `/healthz` liveness route and the
+//! configured maintenance asset are still served.
+
+use super::prelude::*;
+use super::readiness::LIVENESS_PATH;
+use crate::app::AppState;
+use crate::config::MaintenanceConfig;
+use axum::extract::State;
+use axum::middleware::Next;
+use axum::response::{Html, IntoResponse};
+use axum::Json;
+use http::HeaderMap;
+
+pub async fn check_maintenance_mode<B>(
+    State(state): State<AppState>,
+    req: http::Request<B>,
+    next: Next<B>,
+) -> axum::response::Response {
+    if !state.maintenance_mode.is_enabled() {
+        return next.run(req).await;
+    }
+
+    let config = &state.config.maintenance;
+    let path = req.uri().path();
+    if path == LIVENESS_PATH || config.asset_path.as_deref() == Some(path) {
+        return next.run(req).await;
+    }
+
+    req.add_custom_metadata("cause", "maintenance");
+
+    let status = StatusCode::SERVICE_UNAVAILABLE;
+    let headers = [(header::RETRY_AFTER, config.retry_after)];
+    if accepts_html(req.headers()) {
+        let body = Html(maintenance_page(config));
+        (status, headers, body).into_response()
+    } else {
+        let body = json!({ "errors": [{ "detail": config.message }] });
+        (status, headers, Json(body)).into_response()
+    }
+}
+
+fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .any(|value| value.to_str().unwrap_or_default().contains("html"))
+}
+
+fn maintenance_page(config: &MaintenanceConfig) -> String {
+    let message = escape_html(&config.message);
+    let image = match &config.asset_path {
+        Some(path) => format!(r#"<img src="{}" alt="crates.io">"#, escape_html(path)),
+        None => String::new(),
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>crates.io: Maintenance</title>
+</head>
+<body>
+{image}
+<h1>crates.io is down for maintenance</h1>
+<p>{message}</p>
+</body>
+</html>
+"#
+    )
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maintenance_page_is_escaped() {
+        let config = MaintenanceConfig {
+            message: "Back at <b>noon</b> & later".into(),
+            asset_path: Some("/assets/logo.svg".into()),
+            ..MaintenanceConfig::for_testing()
+        };
+
+        let page = maintenance_page(&config);
+        assert!(page.contains("<p>Back at &lt;b&gt;noon&lt;/b&gt; &amp; later</p>"));
+        assert!(page.contains(r#"<img src="/assets/logo.svg" alt="crates.io">"#));
+    }
+}
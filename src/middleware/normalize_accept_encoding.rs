@@ -0,0 +1,161 @@
+//! Sanitize the `Accept-Encoding` header before the compression negotiation runs
+//!
+//! Some old clients send malformed `Accept-Encoding` headers, which can result in unexpected
+//! responses. If `normalize_accept_encoding` is enabled, entries with invalid content codings or
+//! quality values are dropped, and valid quality values are clamped to the `0.0-1.0` range. For
+//! requests from clients with a `User-Agent` starting with one of the
+//! `strip_accept_encoding_user_agents`, the header is removed entirely, so that they receive
+//! uncompressed responses.
+
+use super::prelude::*;
+use crate::app::AppState;
+use axum::extract::State;
+use axum::middleware::Next;
+use http::{HeaderMap, HeaderValue};
+
+pub async fn normalize_accept_encoding<B>(
+    State(state): State<AppState>,
+    mut req: http::Request<B>,
+    next: Next<B>,
+) -> axum::response::Response {
+    let headers = req.headers_mut();
+
+    let strip_user_agents = &state.config.strip_accept_encoding_user_agents;
+    if is_stripped_user_agent(headers, strip_user_agents) {
+        headers.remove(header::ACCEPT_ENCODING);
+    } else if state.config.normalize_accept_encoding {
+        normalize(headers);
+    }
+
+    next.run(req).await
+}
+
+fn is_stripped_user_agent(headers: &HeaderMap, user_agents: &[String]) -> bool {
+    let Some(user_agent) = headers.get(header::USER_AGENT) else {
+        return false;
+    };
+
+    let user_agent = user_agent.to_str().unwrap_or_default();
+    user_agents
+        .iter()
+        .any(|prefix| user_agent.starts_with(prefix.as_str()))
+}
+
+/// Replace the `Accept-Encoding` headers with a single sanitized header
+///
+/// The header is removed if none of its entries are valid.
+fn normalize(headers: &mut HeaderMap) {
+    if !headers.contains_key(header::ACCEPT_ENCODING) {
+        return;
+    }
+
+    let entries = headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(normalize_entry)
+        .collect::<Vec<_>>();
+
+    match HeaderValue::from_str(&entries.join(", ")) {
+        Ok(value) if !entries.is_empty() => {
+            headers.insert(header::ACCEPT_ENCODING, value);
+        }
+        _ => {
+            headers.remove(header::ACCEPT_ENCODING);
+        }
+    }
+}
+
+/// Sanitize a single `coding[;q=value]` entry, or return `None` if it is invalid
+///
+/// Parameters other than the quality value are not allowed in `Accept-Encoding`, so they are
+/// dropped.
+fn normalize_entry(entry: &str) -> Option<String> {
+    let mut params = entry.split(';').map(str::trim);
+
+    let coding = params.next()?;
+    if coding != "*" && !is_token(coding) {
+        return None;
+    }
+
+    let coding = coding.to_ascii_lowercase();
+    let quality = params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim().eq_ignore_ascii_case("q").then(|| value.trim())
+    });
+
+    let Some(quality) = quality else {
+        return Some(coding);
+    };
+
+    let quality = quality
+        .parse::<f32>()
+        .ok()
+        .filter(|quality| quality.is_finite())?;
+    let quality = quality.clamp(0.0, 1.0);
+    Some(format!("{coding};q={quality}"))
+}
+
+/// Check if `s` is a `token` as defined in RFC 9110, section 5.6.2
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(value: &'static str) -> Option<String> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static(value));
+        normalize(&mut headers);
+
+        let value = headers.get(header::ACCEPT_ENCODING)?;
+        Some(value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn valid_headers_are_kept() {
+        assert_eq!(
+            assert_some!(normalized("gzip, deflate, br")),
+            "gzip, deflate, br"
+        );
+        assert_eq!(
+            assert_some!(normalized("gzip;q=1.0, *;q=0")),
+            "gzip;q=1, *;q=0"
+        );
+    }
+
+    #[test]
+    fn malformed_headers_are_sanitized() {
+        assert_eq!(
+            assert_some!(normalized("gzip;q=5, deflate;q=-1, br;q=0.5")),
+            "gzip;q=1, deflate;q=0, br;q=0.5"
+        );
+        assert_eq!(
+            assert_some!(normalized("x gzip, GZIP;level=9, deflate;q=high, ,")),
+            "gzip"
+        );
+        assert_none!(normalized("{gzip}, ;q=1"));
+    }
+
+    #[test]
+    fn user_agents_are_matched_by_prefix() {
+        let user_agents = vec!["BuggyClient/".to_string()];
+
+        let mut headers = HeaderMap::new();
+        assert!(!is_stripped_user_agent(&headers, &user_agents));
+
+        headers.insert(
+            header::USER_AGENT,
+            HeaderValue::from_static("BuggyClient/1.0"),
+        );
+        assert!(is_stripped_user_agent(&headers, &user_agents));
+
+        headers.insert(header::USER_AGENT, HeaderValue::from_static("cargo 1.66.0"));
+        assert!(!is_stripped_user_agent(&headers, &user_agents));
+    }
+}
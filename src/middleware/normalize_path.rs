@@ -1,24 +1,80 @@
 //! Normalize request path if necessary
+//!
+//! Paths with empty segments, or `.` or `..` segments are either rewritten to their normalized
+//! form, or the client is redirected to it, depending on the `NormalizePathMode`. In the
+//! `Redirect` mode, paths with a trailing slash are redirected to the path without it as well.
 
+use axum::extract::State;
 use axum::middleware::Next;
-use axum::response::Response;
-use http::{Request, Uri};
+use axum::response::{IntoResponse, Response};
+use http::{header, Request, StatusCode, Uri};
 use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct OriginalPath(pub String);
 
-pub async fn normalize_path<B>(mut req: Request<B>, next: Next<B>) -> Response {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormalizePathMode {
+    /// The request is handled as if it was sent for the normalized path, which is logged as the
+    /// `normalized_path` field
+    Rewrite,
+    /// The client is redirected to the normalized path with a `308 Permanent Redirect` response,
+    /// which preserves the method and the body of the request
+    Redirect,
+}
+
+impl FromStr for NormalizePathMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rewrite" => Ok(Self::Rewrite),
+            "redirect" => Ok(Self::Redirect),
+            _ => Err(format!("unknown path normalization mode: {s}")),
+        }
+    }
+}
+
+pub async fn normalize_path<B>(
+    State(mode): State<NormalizePathMode>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if mode == NormalizePathMode::Redirect {
+        if let Some(location) = redirect_location(req.uri()) {
+            let headers = [(header::LOCATION, location)];
+            return (StatusCode::PERMANENT_REDIRECT, headers).into_response();
+        }
+
+        return next.run(req).await;
+    }
+
     normalize_path_inner(&mut req);
     next.run(req).await
 }
 
 fn normalize_path_inner<B>(req: &mut Request<B>) {
-    let uri = req.uri();
-    let path = uri.path();
-    if path.contains("//") || path.contains("/.") {
-        let original_path = OriginalPath(path.to_string());
+    if let Some(new_uri) = normalized_uri(req.uri(), false) {
+        let original_path = OriginalPath(req.uri().path().to_string());
+        *req.uri_mut() = new_uri;
+        req.extensions_mut().insert(original_path);
+    }
+}
+
+/// The normalized path and query string of the `uri`, if the path is not normalized already
+fn redirect_location(uri: &Uri) -> Option<String> {
+    let new_uri = normalized_uri(uri, true)?;
+    Some(new_uri.path_and_query()?.to_string())
+}
 
+/// The normalized form of the `uri`, or `None` if it is already normalized
+///
+/// A trailing slash is only considered unnormalized if `strip_trailing_slash` is set.
+fn normalized_uri(uri: &Uri, strip_trailing_slash: bool) -> Option<Uri> {
+    let path = uri.path();
+    let has_trailing_slash = strip_trailing_slash && path.len() > 1 && path.ends_with('/');
+    if path.contains("//") || path.contains("/.") || has_trailing_slash {
         let path = Path::new(path)
             .components()
             .fold(
@@ -56,17 +112,81 @@ fn normalize_path_inner<B>(req: &mut Request<B>) {
         let mut parts = uri.clone().into_parts();
         parts.path_and_query = new_path_and_query;
 
-        if let Ok(new_uri) = Uri::from_parts(parts) {
-            *req.uri_mut() = new_uri;
-            req.extensions_mut().insert(original_path);
-        }
+        return Uri::from_parts(parts).ok();
     }
+
+    None
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{normalize_path_inner, OriginalPath};
-    use http::Request;
+    use super::*;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    fn router(mode: NormalizePathMode) -> Router {
+        let echo_path = |req: Request<axum::body::Body>| async move { req.uri().to_string() };
+        Router::new()
+            .route("/api/v1/crates", get(echo_path))
+            .layer(from_fn_with_state(mode, normalize_path))
+    }
+
+    fn request(uri: &str) -> Request<axum::body::Body> {
+        Request::get(uri).body(axum::body::Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn trailing_slashes_are_kept_when_rewriting() {
+        let router = router(NormalizePathMode::Rewrite);
+        let response = router
+            .clone()
+            .oneshot(request("/api/v1/crates/?q=serde"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = router
+            .oneshot(request("/api/v1/./crates?q=serde"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "/api/v1/crates?q=serde");
+    }
+
+    #[tokio::test]
+    async fn trailing_slashes_are_redirected() {
+        let router = router(NormalizePathMode::Redirect);
+        let response = router
+            .clone()
+            .oneshot(request("/api/v1/crates/?q=serde"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "/api/v1/crates?q=serde"
+        );
+
+        // Normalized paths are handled as usual
+        let response = router.oneshot(request("/api/v1/crates")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn modes_are_parsed() {
+        assert_eq!(
+            assert_ok!("rewrite".parse::<NormalizePathMode>()),
+            NormalizePathMode::Rewrite
+        );
+        assert_eq!(
+            assert_ok!("redirect".parse::<NormalizePathMode>()),
+            NormalizePathMode::Redirect
+        );
+        assert_err!("moved".parse::<NormalizePathMode>());
+    }
 
     #[test]
     fn path_normalization() {
@@ -86,6 +206,11 @@ mod tests {
             "/api/./v1"
         );
 
+        let mut req = Request::get("/api/v1/").body(()).unwrap();
+        normalize_path_inner(&mut req);
+        assert_eq!(req.uri().path(), "/api/v1/");
+        assert_none!(req.extensions().get::<OriginalPath>());
+
         let mut req = Request::get("//api/v1/../v2").body(()).unwrap();
         normalize_path_inner(&mut req);
         assert_eq!(req.uri().path(), "/api/v2");
@@ -0,0 +1,38 @@
+//! Reject requests with `503 Service Unavailable` until the application is ready
+//!
+//! During startup, the dependencies of the application (e.g. the database) may not be available
+//! yet. Until `App::readiness` is flipped to ready, all requests are rejected with a `Retry-After`
+//! header instead of being passed to the endpoints. The `/healthz` liveness route is always
+//! served, so that the process is not restarted while it is starting up.
+
+use super::prelude::*;
+use crate::app::AppState;
+use axum::extract::State;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+
+/// The path of the liveness route, which does not depend on the readiness of the application
+pub(super) const LIVENESS_PATH: &str = "/healthz";
+
+/// The number of seconds after which clients should retry requests that were rejected
+const RETRY_AFTER_SECONDS: u64 = 5;
+
+pub async fn check_readiness<B>(
+    State(state): State<AppState>,
+    req: http::Request<B>,
+    next: Next<B>,
+) -> axum::response::Response {
+    if req.uri().path() == LIVENESS_PATH {
+        return (StatusCode::OK, "OK").into_response();
+    }
+
+    if !state.readiness.is_ready() {
+        req.add_custom_metadata("cause", "not ready");
+
+        let headers = [(header::RETRY_AFTER, RETRY_AFTER_SECONDS)];
+        let body = "The service is starting up, please try again later";
+        return (StatusCode::SERVICE_UNAVAILABLE, headers, body).into_response();
+    }
+
+    next.run(req).await
+}
@@ -0,0 +1,169 @@
+//! Reject requests that arrived over an outdated TLS version
+//!
+//! The TLS connection is terminated by the proxy in front of the application, which reports the
+//! negotiated protocol in the `X-SSL-Protocol` header (e.g. `TLSv1.2`). Requests with a version
+//! below `min_tls_version` are rejected with a `426 Upgrade Required` response. Requests without
+//! the header (e.g. internal traffic that does not use TLS) are allowed or rejected depending on
+//! `allow_missing_tls_version`.
+
+use super::prelude::*;
+use crate::app::AppState;
+use axum::extract::State;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use http::HeaderMap;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+const TLS_PROTOCOL_HEADER: &str = "x-ssl-protocol";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    Ssl3,
+    Tls1_0,
+    Tls1_1,
+    Tls1_2,
+    Tls1_3,
+}
+
+impl FromStr for TlsVersion {
+    type Err = String;
+
+    /// Parses protocol names like `TLSv1.2`, or just the version number like `1.2`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let version = s.trim();
+        let version = version.strip_prefix("TLSv").unwrap_or(version);
+        match version {
+            "SSLv3" => Ok(Self::Ssl3),
+            "1" | "1.0" => Ok(Self::Tls1_0),
+            "1.1" => Ok(Self::Tls1_1),
+            "1.2" => Ok(Self::Tls1_2),
+            "1.3" => Ok(Self::Tls1_3),
+            _ => Err(format!("unknown TLS version: {s}")),
+        }
+    }
+}
+
+impl TlsVersion {
+    /// The version number, as used in the `Upgrade` header
+    fn number(&self) -> &'static str {
+        match self {
+            Self::Ssl3 => "3.0",
+            Self::Tls1_0 => "1.0",
+            Self::Tls1_1 => "1.1",
+            Self::Tls1_2 => "1.2",
+            Self::Tls1_3 => "1.3",
+        }
+    }
+}
+
+impl Display for TlsVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ssl3 => f.write_str("SSLv3"),
+            version => write!(f, "TLSv{}", version.number()),
+        }
+    }
+}
+
+pub async fn require_tls_version<B>(
+    State(state): State<AppState>,
+    req: http::Request<B>,
+    next: Next<B>,
+) -> axum::response::Response {
+    let Some(min_version) = state.config.min_tls_version else {
+        return next.run(req).await;
+    };
+
+    let allow_missing = state.config.allow_missing_tls_version;
+    if let Err(cause) = check_tls_version(req.headers(), min_version, allow_missing) {
+        req.add_custom_metadata("cause", cause);
+
+        let upgrade = format!("TLS/{}, HTTP/1.1", min_version.number());
+        let headers = [
+            (header::UPGRADE, upgrade),
+            (header::CONNECTION, "Upgrade".into()),
+        ];
+        let body = format!("{min_version} or newer is required");
+        return (StatusCode::UPGRADE_REQUIRED, headers, body).into_response();
+    }
+
+    next.run(req).await
+}
+
+/// Check the TLS version reported by the proxy, returning the reason if it is not acceptable
+fn check_tls_version(
+    headers: &HeaderMap,
+    min_version: TlsVersion,
+    allow_missing: bool,
+) -> Result<(), String> {
+    let Some(value) = headers.get(TLS_PROTOCOL_HEADER) else {
+        if allow_missing {
+            return Ok(());
+        }
+
+        return Err("missing tls version".into());
+    };
+
+    let value = value.to_str().unwrap_or_default();
+    match value.parse::<TlsVersion>() {
+        Ok(version) if version >= min_version => Ok(()),
+        Ok(version) => Err(format!("outdated tls version {version}")),
+        Err(_) => Err(format!("unknown tls version {value:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn headers(protocol: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(TLS_PROTOCOL_HEADER, HeaderValue::from_static(protocol));
+        headers
+    }
+
+    #[test]
+    fn tls_versions_are_parsed() {
+        assert_eq!(
+            assert_ok!("TLSv1".parse::<TlsVersion>()),
+            TlsVersion::Tls1_0
+        );
+        assert_eq!(
+            assert_ok!("TLSv1.1".parse::<TlsVersion>()),
+            TlsVersion::Tls1_1
+        );
+        assert_eq!(
+            assert_ok!("TLSv1.3".parse::<TlsVersion>()),
+            TlsVersion::Tls1_3
+        );
+        assert_eq!(assert_ok!("SSLv3".parse::<TlsVersion>()), TlsVersion::Ssl3);
+        assert_eq!(assert_ok!("1.2".parse::<TlsVersion>()), TlsVersion::Tls1_2);
+        assert_err!("TLSv2".parse::<TlsVersion>());
+        assert_err!("".parse::<TlsVersion>());
+    }
+
+    #[test]
+    fn outdated_versions_are_rejected() {
+        let min_version = TlsVersion::Tls1_2;
+        assert_err!(check_tls_version(&headers("SSLv3"), min_version, true));
+        assert_err!(check_tls_version(&headers("TLSv1"), min_version, true));
+        assert_err!(check_tls_version(&headers("TLSv1.1"), min_version, true));
+        assert_err!(check_tls_version(&headers("unknown"), min_version, true));
+    }
+
+    #[test]
+    fn current_versions_are_allowed() {
+        let min_version = TlsVersion::Tls1_2;
+        assert_ok!(check_tls_version(&headers("TLSv1.2"), min_version, false));
+        assert_ok!(check_tls_version(&headers("TLSv1.3"), min_version, false));
+    }
+
+    #[test]
+    fn missing_versions_are_configurable() {
+        let min_version = TlsVersion::Tls1_2;
+        assert_ok!(check_tls_version(&HeaderMap::new(), min_version, true));
+        assert_err!(check_tls_version(&HeaderMap::new(), min_version, false));
+    }
+}
@@ -0,0 +1,194 @@
+//! Rewrite or redirect legacy paths to their current form
+//!
+//! The `legacy_paths` config maps path patterns like `/crates/:id/downloads` to their
+//! replacement, e.g. `/api/v1/crates/:id/downloads`. Matching requests are either rewritten
+//! internally, so that they are handled by the endpoint of the new path, or redirected to the
+//! new path with a `301 Moved Permanently` response. In both cases the legacy pattern is logged as
+//! the `legacy_path` field, to track how often the legacy form is still used.
+
+use super::prelude::*;
+use crate::app::AppState;
+use axum::extract::State;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use http::uri::PathAndQuery;
+use http::Uri;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RewriteMode {
+    /// The request is handled as if it was sent for the new path
+    Rewrite,
+    /// The client is redirected to the new path with a `301 Moved Permanently` response
+    Redirect,
+}
+
+/// A mapping from a legacy path pattern to its replacement
+///
+/// Parsed from strings like `redirect:/crates/:id/downloads=/api/v1/crates/:id/downloads`, where
+/// the mode is either `rewrite` or `redirect`, and `:name` segments match any single segment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LegacyPath {
+    mode: RewriteMode,
+    pattern: String,
+    replacement: String,
+}
+
+impl FromStr for LegacyPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid legacy path mapping: {s}");
+
+        let (mode, mapping) = s.split_once(':').ok_or_else(invalid)?;
+        let mode = match mode {
+            "rewrite" => RewriteMode::Rewrite,
+            "redirect" => RewriteMode::Redirect,
+            _ => return Err(invalid()),
+        };
+
+        let (pattern, replacement) = mapping.split_once('=').ok_or_else(invalid)?;
+        if !pattern.starts_with('/') || !replacement.starts_with('/') {
+            return Err(invalid());
+        }
+
+        // All parameters of the replacement need to be captured by the pattern
+        let captured = pattern
+            .split('/')
+            .filter(|segment| segment.starts_with(':'))
+            .collect::<Vec<_>>();
+        let is_captured = |segment: &str| !segment.starts_with(':') || captured.contains(&segment);
+        if !replacement.split('/').all(is_captured) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            mode,
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+        })
+    }
+}
+
+impl Display for LegacyPath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mode = match self.mode {
+            RewriteMode::Rewrite => "rewrite",
+            RewriteMode::Redirect => "redirect",
+        };
+        write!(f, "{mode}:{}={}", self.pattern, self.replacement)
+    }
+}
+
+impl LegacyPath {
+    /// The replacement for the `path`, or `None` if the `path` does not match the pattern
+    fn new_path(&self, path: &str) -> Option<String> {
+        let mut params = HashMap::new();
+
+        let mut segments = path.split('/');
+        for pattern_segment in self.pattern.split('/') {
+            let segment = segments.next()?;
+            if pattern_segment.starts_with(':') {
+                if segment.is_empty() {
+                    return None;
+                }
+                params.insert(pattern_segment, segment);
+            } else if pattern_segment != segment {
+                return None;
+            }
+        }
+
+        if segments.next().is_some() {
+            return None;
+        }
+
+        let new_path = self
+            .replacement
+            .split('/')
+            .map(|segment| params.get(segment).copied().unwrap_or(segment))
+            .collect::<Vec<_>>();
+
+        Some(new_path.join("/"))
+    }
+}
+
+pub async fn rewrite_legacy_paths<B>(
+    State(state): State<AppState>,
+    mut req: http::Request<B>,
+    next: Next<B>,
+) -> axum::response::Response {
+    let path = req.uri().path();
+    let legacy_paths = &state.config.legacy_paths;
+    let found = legacy_paths
+        .iter()
+        .find_map(|legacy_path| Some((legacy_path, legacy_path.new_path(path)?)));
+
+    let Some((legacy_path, new_path)) = found else {
+        return next.run(req).await;
+    };
+
+    req.add_custom_metadata("legacy_path", &legacy_path.pattern);
+
+    let new_path_and_query = match req.uri().query() {
+        Some(query) => format!("{new_path}?{query}"),
+        None => new_path,
+    };
+
+    if legacy_path.mode == RewriteMode::Redirect {
+        let headers = [(header::LOCATION, new_path_and_query)];
+        return (StatusCode::MOVED_PERMANENTLY, headers).into_response();
+    }
+
+    let Ok(path_and_query) = new_path_and_query.parse::<PathAndQuery>() else {
+        return next.run(req).await;
+    };
+
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = Some(path_and_query);
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *req.uri_mut() = uri;
+    }
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_paths_are_parsed() {
+        let legacy_path: LegacyPath =
+            assert_ok!("redirect:/crates/:id/downloads=/api/v1/crates/:id/downloads".parse());
+        assert_eq!(legacy_path.mode, RewriteMode::Redirect);
+        assert_eq!(
+            legacy_path.to_string(),
+            "redirect:/crates/:id/downloads=/api/v1/crates/:id/downloads"
+        );
+
+        let legacy_path: LegacyPath = assert_ok!("rewrite:/summary=/api/v1/summary".parse());
+        assert_eq!(legacy_path.mode, RewriteMode::Rewrite);
+
+        assert_err!("/summary=/api/v1/summary".parse::<LegacyPath>());
+        assert_err!("move:/summary=/api/v1/summary".parse::<LegacyPath>());
+        assert_err!("rewrite:/summary".parse::<LegacyPath>());
+        assert_err!("rewrite:/crates/:id=/api/v1/crates/:name".parse::<LegacyPath>());
+    }
+
+    #[test]
+    fn matching_paths_are_replaced() {
+        let legacy_path: LegacyPath =
+            assert_ok!("rewrite:/crates/:id/downloads=/api/v1/crates/:id/downloads".parse());
+
+        assert_eq!(
+            assert_some!(legacy_path.new_path("/crates/serde/downloads")),
+            "/api/v1/crates/serde/downloads"
+        );
+        assert_none!(legacy_path.new_path("/crates//downloads"));
+        assert_none!(legacy_path.new_path("/crates/serde"));
+        assert_none!(legacy_path.new_path("/crates/serde/downloads/extra"));
+        assert_none!(legacy_path.new_path("/api/v1/crates/serde/downloads"));
+    }
+}
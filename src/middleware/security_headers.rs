@@ -0,0 +1,65 @@
+//! Add the configured security headers (e.g. `X-Content-Type-Options: nosniff`) to all responses
+//!
+//! Headers that were already set by a handler or an inner middleware layer are not overridden.
+//! See `SecurityHeadersConfig` for the defaults and how to disable individual headers.
+
+use crate::app::AppState;
+use axum::extract::State;
+use axum::middleware::Next;
+use axum::response::Response;
+use http::{HeaderMap, HeaderName, HeaderValue};
+
+pub async fn add_security_headers<B>(
+    State(state): State<AppState>,
+    req: http::Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut response = next.run(req).await;
+
+    let headers = &state.config.security_headers.headers;
+    insert_missing_headers(response.headers_mut(), headers);
+
+    response
+}
+
+fn insert_missing_headers(headers: &mut HeaderMap, defaults: &[(HeaderName, HeaderValue)]) {
+    for (name, value) in defaults {
+        headers.entry(name).or_insert_with(|| value.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SecurityHeadersConfig;
+    use http::header;
+
+    #[test]
+    fn missing_headers_are_added() {
+        let config = SecurityHeadersConfig::for_testing();
+
+        let mut headers = HeaderMap::new();
+        insert_missing_headers(&mut headers, &config.headers);
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "DENY");
+        assert_eq!(
+            headers[header::REFERRER_POLICY],
+            "strict-origin-when-cross-origin"
+        );
+        assert!(!headers.contains_key(header::STRICT_TRANSPORT_SECURITY));
+    }
+
+    #[test]
+    fn handler_headers_are_preserved() {
+        let config = SecurityHeadersConfig::for_testing();
+
+        let mut headers = HeaderMap::new();
+        let frame_options = HeaderValue::from_static("SAMEORIGIN");
+        headers.insert(header::X_FRAME_OPTIONS, frame_options);
+        insert_missing_headers(&mut headers, &config.headers);
+
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "SAMEORIGIN");
+        assert_eq!(headers.get_all(header::X_FRAME_OPTIONS).iter().count(), 1);
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+    }
+}
@@ -7,7 +7,7 @@ use http::Request;
 use std::collections::HashMap;
 use std::sync::{Arc, PoisonError, RwLock};
 
-static COOKIE_NAME: &str = "cargo_session";
+pub(crate) static COOKIE_NAME: &str = "cargo_session";
 static MAX_AGE_DAYS: i64 = 90;
 
 pub async fn attach_session<B>(
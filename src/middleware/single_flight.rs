@@ -0,0 +1,318 @@
+//! Coalesce concurrent identical requests into a single handler execution
+//!
+//! Expensive idempotent endpoints can be hit by many identical requests at the same time, e.g.
+//! when a cache in front of the application expires. With `coalesce_requests` enabled, the first
+//! request with a given signature runs the handler, while the following requests with the same
+//! signature wait for it to finish and receive a copy of its response. The waiting requests are
+//! logged with `coalesced=true`.
+//!
+//! Only responses with a `Static` or `Owned` body can be copied. If the handler returns an error,
+//! a `File` body or a `NegotiatedBody` (which is only encoded later by `conduit_axum`), the
+//! waiting requests run the handler themselves. Note that the extensions of the response are not
+//! copied either.
+
+use super::prelude::*;
+
+use conduit::HandlerResult;
+use conduit_axum::NegotiatedBody;
+use http::{HeaderMap, Method, Version};
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+
+/// Computes the signature of a request, or `None` if the request must not be coalesced
+pub type RequestSignature = fn(&dyn RequestExt) -> Option<String>;
+
+/// The default `RequestSignature`
+///
+/// Only `GET` requests without credentials are coalesced, since the responses to authenticated
+/// requests depend on the user. The signature consists of the path, the query string and the
+/// `Accept` header.
+pub fn request_signature(req: &dyn RequestExt) -> Option<String> {
+    if req.method() != Method::GET {
+        return None;
+    }
+
+    let headers = req.headers();
+    if headers.contains_key(header::AUTHORIZATION) || headers.contains_key(header::COOKIE) {
+        return None;
+    }
+
+    let path = req.path();
+    let query = req.query_string().unwrap_or_default();
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    Some(format!("{path}?{query} {accept}"))
+}
+
+pub struct SingleFlight {
+    handler: Option<Box<dyn Handler>>,
+    signature: RequestSignature,
+    in_flight: Mutex<HashMap<String, Arc<Flight>>>,
+}
+
+impl SingleFlight {
+    pub fn new(signature: RequestSignature) -> Self {
+        Self {
+            handler: None,
+            signature,
+            in_flight: Mutex::default(),
+        }
+    }
+
+    fn handler(&self) -> &dyn Handler {
+        self.handler.as_deref().expect("handler not set")
+    }
+
+    /// Run the handler as the leader of a new flight, sharing the response with its waiters
+    fn lead(
+        &self,
+        signature: String,
+        flight: Arc<Flight>,
+        req: &mut dyn RequestExt,
+    ) -> HandlerResult {
+        // Completes the flight even if the handler panics, so that the waiters don't hang
+        let mut guard = LeaderGuard {
+            in_flight: &self.in_flight,
+            signature,
+            flight,
+            response: None,
+        };
+
+        let response = self.handler().call(req)?;
+        if response.extensions().get::<NegotiatedBody>().is_some() {
+            return Ok(response);
+        }
+
+        let (parts, body) = response.into_parts();
+        let body = match body {
+            Body::Static(slice) => {
+                guard.response = Some(SharedResponse::new(&parts, SharedBody::Static(slice)));
+                Body::Static(slice)
+            }
+            Body::Owned(vec) => {
+                let shared_body = SharedBody::Owned(vec.clone());
+                guard.response = Some(SharedResponse::new(&parts, shared_body));
+                Body::Owned(vec)
+            }
+            body => body,
+        };
+
+        Ok(Response::from_parts(parts, body))
+    }
+}
+
+impl AroundMiddleware for SingleFlight {
+    fn with_handler(&mut self, handler: Box<dyn Handler>) {
+        self.handler = Some(handler);
+    }
+}
+
+impl Handler for SingleFlight {
+    fn call(&self, req: &mut dyn RequestExt) -> HandlerResult {
+        let Some(signature) = (self.signature)(req) else {
+            return self.handler().call(req);
+        };
+
+        let mut in_flight = lock(&self.in_flight);
+        if let Some(flight) = in_flight.get(&signature).cloned() {
+            drop(in_flight);
+
+            if let Some(response) = flight.wait() {
+                req.add_custom_metadata("coalesced", true);
+                return Ok(response.to_response());
+            }
+
+            return self.handler().call(req);
+        }
+
+        let flight = Arc::new(Flight::default());
+        in_flight.insert(signature.clone(), flight.clone());
+        drop(in_flight);
+
+        self.lead(signature, flight, req)
+    }
+}
+
+/// A handler execution that other requests with the same signature can wait for
+#[derive(Default)]
+struct Flight {
+    /// `None` while the handler is running, and then `Some(None)` if the response can't be shared
+    result: Mutex<Option<Option<SharedResponse>>>,
+    done: Condvar,
+}
+
+impl Flight {
+    fn wait(&self) -> Option<SharedResponse> {
+        let mut result = lock(&self.result);
+        loop {
+            if let Some(response) = &*result {
+                return response.clone();
+            }
+
+            result = self
+                .done
+                .wait(result)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+struct LeaderGuard<'a> {
+    in_flight: &'a Mutex<HashMap<String, Arc<Flight>>>,
+    signature: String,
+    flight: Arc<Flight>,
+    response: Option<SharedResponse>,
+}
+
+impl Drop for LeaderGuard<'_> {
+    fn drop(&mut self) {
+        // Later requests start a new flight, while the current waiters get this response
+        lock(self.in_flight).remove(&self.signature);
+
+        *lock(&self.flight.result) = Some(self.response.take());
+        self.flight.done.notify_all();
+    }
+}
+
+#[derive(Clone)]
+struct SharedResponse {
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+    body: SharedBody,
+}
+
+#[derive(Clone)]
+enum SharedBody {
+    Static(&'static [u8]),
+    Owned(Vec<u8>),
+}
+
+impl SharedResponse {
+    fn new(parts: &http::response::Parts, body: SharedBody) -> Self {
+        Self {
+            status: parts.status,
+            version: parts.version,
+            headers: parts.headers.clone(),
+            body,
+        }
+    }
+
+    fn to_response(&self) -> Response<Body> {
+        let body = match &self.body {
+            SharedBody::Static(slice) => Body::Static(*slice),
+            SharedBody::Owned(vec) => Body::Owned(vec.clone()),
+        };
+
+        let mut response = Response::new(body);
+        *response.status_mut() = self.status;
+        *response.version_mut() = self.version;
+        *response.headers_mut() = self.headers.clone();
+        response
+    }
+}
+
+/// Lock the `mutex`, even if another thread panicked while holding it
+///
+/// The protected data is only ever replaced as a whole, so it can't be left in an inconsistent
+/// state.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::log_request::CustomMetadata;
+    use conduit_test::MockRequest;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+
+    const REQUESTS: usize = 8;
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Expensive;
+
+    impl Handler for Expensive {
+        fn call(&self, req: &mut dyn RequestExt) -> HandlerResult {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+
+            // Give the other requests time to join the flight
+            thread::sleep(Duration::from_millis(200));
+
+            let body = format!("Hello from {}", req.path());
+            Response::builder()
+                .header(header::CONTENT_TYPE, "text/plain")
+                .body(Body::from_vec(body.into_bytes()))
+                .map_err(box_error)
+        }
+    }
+
+    fn request(method: Method, path: &str) -> MockRequest {
+        let mut req = MockRequest::new(method, path);
+        req.mut_extensions().insert(CustomMetadata::default());
+        req
+    }
+
+    #[test]
+    fn concurrent_identical_requests_are_coalesced() {
+        let mut single_flight = SingleFlight::new(request_signature);
+        single_flight.with_handler(Box::new(Expensive));
+        let single_flight = Arc::new(single_flight);
+        let barrier = Arc::new(Barrier::new(REQUESTS));
+
+        let threads = (0..REQUESTS)
+            .map(|_| {
+                let single_flight = single_flight.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    let mut req = request(Method::GET, "/api/v1/summary");
+                    barrier.wait();
+                    single_flight.call(&mut req).unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in threads {
+            let response = handle.join().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain");
+            assert!(matches!(
+                response.body(),
+                Body::Owned(body) if body == b"Hello from /api/v1/summary"
+            ));
+        }
+
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        assert!(lock(&single_flight.in_flight).is_empty());
+    }
+
+    #[test]
+    fn default_signature() {
+        let req = request(Method::GET, "/api/v1/summary");
+        assert_eq!(assert_some!(request_signature(&req)), "/api/v1/summary? ");
+
+        let mut req = request(Method::GET, "/api/v1/crates");
+        req.with_query("q=serde");
+        req.header(header::ACCEPT, "application/json");
+        assert_eq!(
+            assert_some!(request_signature(&req)),
+            "/api/v1/crates?q=serde application/json"
+        );
+
+        let mut req = request(Method::GET, "/api/v1/me");
+        req.header(header::COOKIE, "cargo_session=secret");
+        assert_none!(request_signature(&req));
+
+        assert_none!(request_signature(&request(
+            Method::PUT,
+            "/api/v1/crates/new"
+        )));
+    }
+}
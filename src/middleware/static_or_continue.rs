@@ -1,42 +1,738 @@
 //! This module implements middleware to serve static files from the
 //! specified directory.
 
+use crate::app::AppState;
+use crate::config::StaticFilesConfig;
+use crate::middleware::log_request::UncompressedSize;
+use axum::body::{Bytes, Empty, Full};
+use axum::extract::State;
 use axum::middleware::Next;
-use axum::response::Response;
-use http::{Method, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use conduit_axum::LayerTimings;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode};
+use moka::sync::Cache;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
 use tower::ServiceExt;
 use tower_http::services::ServeDir;
 
-pub async fn serve_local_uploads<B>(request: Request<B>, next: Next<B>) -> Response {
-    if request.method() == Method::GET || request.method() == Method::HEAD {
-        let mut static_req = Request::new(());
-        *static_req.method_mut() = request.method().clone();
-        *static_req.uri_mut() = request.uri().clone();
-        *static_req.headers_mut() = request.headers().clone();
+/// The compressed file contents and the uncompressed size
+type GzipCache = Cache<(String, String), (Bytes, u64)>;
 
-        if let Ok(response) = ServeDir::new("local_uploads").oneshot(static_req).await {
-            if response.status() != StatusCode::NOT_FOUND {
-                return response.map(axum::body::boxed);
-            }
+/// The directory that `serve_local_uploads()` serves files from
+pub type UploadsDir = Arc<Path>;
+
+/// The application state and the directory that `serve_dist()` serves files from
+pub type DistDir = (AppState, Arc<Path>);
+
+pub async fn serve_local_uploads<B>(
+    State(dir): State<UploadsDir>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if let Some(static_req) = static_request(&request) {
+        let serve = |req| serve_static(ServeDir::new(&*dir), req);
+        if let Some(response) = head_as_get(static_req, serve).await {
+            return response;
         }
     }
 
     next.run(request).await
 }
 
-pub async fn serve_dist<B>(request: Request<B>, next: Next<B>) -> Response {
-    if request.method() == Method::GET || request.method() == Method::HEAD {
-        let mut static_req = Request::new(());
-        *static_req.method_mut() = request.method().clone();
-        *static_req.uri_mut() = request.uri().clone();
-        *static_req.headers_mut() = request.headers().clone();
+pub async fn serve_dist<B>(
+    State((state, dir)): State<DistDir>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let record_timings = state.config.log_layer_timings;
+    let started = Instant::now();
 
-        if let Ok(response) = ServeDir::new("dist").oneshot(static_req).await {
-            if response.status() != StatusCode::NOT_FOUND {
-                return response.map(axum::body::boxed);
+    if let Some(static_req) = static_request(&request) {
+        let config = &state.config.static_files;
+        let cache = &state.static_gzip_cache;
+        let serve = |req| serve_dist_inner(&dir, config, cache, record_timings, req);
+        if let Some(mut response) = head_as_get(static_req, serve).await {
+            if record_timings {
+                // The `compression` layer is recorded separately
+                let extensions = response.extensions_mut();
+                let compression = extensions
+                    .get::<LayerTimings>()
+                    .and_then(|timings| timings.get("compression"))
+                    .unwrap_or_default();
+                let duration = started.elapsed().saturating_sub(compression);
+                LayerTimings::record(extensions, "static", duration);
             }
+            return response;
         }
     }
 
-    next.run(request).await
+    let static_check = started.elapsed();
+    let mut response = next.run(request).await;
+    if record_timings {
+        LayerTimings::record(response.extensions_mut(), "static", static_check);
+    }
+    response
+}
+
+async fn serve_dist_inner(
+    dir: &Path,
+    config: &StaticFilesConfig,
+    cache: &GzipCache,
+    record_timings: bool,
+    mut request: Request<()>,
+) -> Option<Response> {
+    let path = request.uri().path().to_string();
+    if let Some(prefix) = &config.strip_prefix {
+        strip_path_prefix(&mut request, prefix);
+    }
+
+    // `HEAD` responses have no body, so there is nothing to compress
+    let is_head = request.extensions().get::<ConvertedFromHead>().is_some();
+    let is_get = request.method() == Method::GET && !is_head;
+    let accepts_gzip = accepts_gzip(request.headers());
+
+    // The `Content-Range` of a partial response refers to the uncompressed file, so compression
+    // is skipped entirely for range requests, including the precompressed files
+    let is_range_request = request.headers().contains_key(header::RANGE);
+
+    let mut serve_dir = ServeDir::new(dir);
+    if config.gzip_level.is_some() && !is_range_request {
+        serve_dir = serve_dir.precompressed_gzip();
+    }
+
+    let mut response = serve_static(serve_dir, request).await?;
+
+    if let Some(mime_type) = mime_type_override(&path, &config.mime_types) {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, mime_type);
+    }
+
+    if let Some(level) = config.gzip_level {
+        // Whether a response is compressed depends on the `Accept-Encoding` header, so shared
+        // caches must not serve the identity variant to every client either
+        add_vary_accept_encoding(response.headers_mut());
+
+        let is_compressed = response.headers().contains_key(header::CONTENT_ENCODING);
+        let is_partial = response.headers().contains_key(header::CONTENT_RANGE);
+        let is_full_response = response.status() == StatusCode::OK && !is_partial;
+        let is_small_enough = content_length(response.headers())
+            .map_or(false, |length| length <= config.gzip_max_size);
+        if is_get && accepts_gzip && !is_compressed && is_full_response && is_small_enough {
+            let started = Instant::now();
+            response = gzip_response(response, path.clone(), level, cache).await;
+            if record_timings {
+                LayerTimings::record(response.extensions_mut(), "compression", started.elapsed());
+            }
+        }
+    }
+
+    if is_index_html(&path) {
+        add_preload_links(response.headers_mut(), &config.preload_resources);
+    }
+
+    Some(response)
+}
+
+/// A request extension marking the `GET` requests that `head_as_get()` converted from `HEAD`
+#[derive(Clone, Copy, Debug)]
+struct ConvertedFromHead;
+
+/// Serve a `HEAD` request like the corresponding `GET` request, but without the response body
+///
+/// This guarantees that both responses have identical headers, including the `Content-Length`.
+/// The only exception are responses that `serve_dist()` would compress on the fly, which are not
+/// compressed for `HEAD` requests, since the compressed body would be discarded anyway.
+async fn head_as_get<F, Fut>(mut request: Request<()>, serve: F) -> Option<Response>
+where
+    F: FnOnce(Request<()>) -> Fut,
+    Fut: Future<Output = Option<Response>>,
+{
+    let is_head = request.method() == Method::HEAD;
+    if is_head {
+        *request.method_mut() = Method::GET;
+        request.extensions_mut().insert(ConvertedFromHead);
+    }
+
+    let response = serve(request).await?;
+    if !is_head {
+        return Some(response);
+    }
+
+    let (parts, _) = response.into_parts();
+    Some(Response::from_parts(parts, axum::body::boxed(Empty::new())))
+}
+
+/// Copy the relevant parts of a `GET` or `HEAD` request, for use with `ServeDir`
+fn static_request<B>(request: &Request<B>) -> Option<Request<()>> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return None;
+    }
+
+    let mut static_req = Request::new(());
+    *static_req.method_mut() = request.method().clone();
+    *static_req.uri_mut() = request.uri().clone();
+    *static_req.headers_mut() = request.headers().clone();
+    Some(static_req)
+}
+
+/// Remove the `prefix` from the path of the `request`, if the path starts with it
+///
+/// Only whole path segments are matched, so a prefix of `/assets` does not apply to
+/// `/assets-old/app.js`.
+fn strip_path_prefix(request: &mut Request<()>, prefix: &str) {
+    let uri = request.uri();
+    let Some(rest) = uri.path().strip_prefix(prefix) else {
+        return;
+    };
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return;
+    }
+
+    let rest = if rest.is_empty() { "/" } else { rest };
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{rest}?{query}"),
+        None => rest.to_string(),
+    };
+
+    let mut parts = uri.clone().into_parts();
+    let Ok(path_and_query) = path_and_query.parse::<http::uri::PathAndQuery>() else {
+        return;
+    };
+    parts.path_and_query = Some(path_and_query);
+    if let Ok(uri) = http::Uri::from_parts(parts) {
+        *request.uri_mut() = uri;
+    }
+}
+
+/// Serve a file from `dir`, returning `None` if the request should be passed along to the
+/// remaining middleware layers.
+async fn serve_static(serve_dir: ServeDir, request: Request<()>) -> Option<Response> {
+    let response = serve_dir.oneshot(request).await.ok()?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return None;
+    }
+
+    Some(response.map(axum::body::boxed))
+}
+
+/// Compress the body of a static file response, reusing a previous result from the `cache` if
+/// the file has not been modified since
+async fn gzip_response(
+    response: Response,
+    path: String,
+    level: u32,
+    cache: &GzipCache,
+) -> Response {
+    let last_modified = response
+        .headers()
+        .get(header::LAST_MODIFIED)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+
+    // Without a modification time there is no way to tell if a cached result is stale
+    let key = match last_modified {
+        Some(last_modified) => (path, last_modified),
+        None => return response,
+    };
+
+    let (mut parts, body) = response.into_parts();
+
+    let (compressed, uncompressed_size) = match cache.get(&key) {
+        Some(entry) => entry,
+        None => {
+            let compressed = hyper::body::to_bytes(body)
+                .await
+                .map_err(|error| error.to_string())
+                .and_then(|body| {
+                    let compressed = gzip(&body, level).map_err(|error| error.to_string())?;
+                    Ok((compressed, body.len() as u64))
+                });
+
+            match compressed {
+                Ok(entry) => {
+                    cache.insert(key, entry.clone());
+                    entry
+                }
+                Err(error) => {
+                    warn!(%error, path = %key.0, "Failed to compress static file");
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            }
+        }
+    };
+
+    let headers = &mut parts.headers;
+    headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    headers.insert(header::CONTENT_LENGTH, compressed.len().into());
+    parts.extensions.insert(UncompressedSize(uncompressed_size));
+
+    Response::from_parts(parts, axum::body::boxed(Full::new(compressed)))
+}
+
+fn gzip(data: &[u8], level: u32) -> std::io::Result<Bytes> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(data)?;
+    encoder.finish().map(Bytes::from)
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Add `accept-encoding` to the `Vary` header, unless it is listed already
+fn add_vary_accept_encoding(headers: &mut HeaderMap) {
+    let is_listed = headers
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|name| name.trim().eq_ignore_ascii_case("accept-encoding"));
+
+    if !is_listed {
+        let value = HeaderValue::from_static("accept-encoding");
+        headers.append(header::VARY, value);
+    }
+}
+
+/// Check if the `Accept-Encoding` header allows a gzip encoded response
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| {
+            let mut params = encoding.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            let is_rejected = params
+                .filter_map(|param| param.strip_prefix("q="))
+                .any(|quality| quality.parse::<f32>().ok() == Some(0.0));
+
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !is_rejected
+        })
+}
+
+/// Look up the configured `Content-Type` for the file extension of the `path`
+fn mime_type_override(path: &str, mime_types: &HashMap<String, String>) -> Option<HeaderValue> {
+    let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+    let mime_type = mime_types.get(&extension)?;
+    HeaderValue::from_str(mime_type).ok()
+}
+
+fn is_index_html(path: &str) -> bool {
+    path == "/" || path == "/index.html"
+}
+
+/// Append a `Link: <...>; rel=preload` header for each of the configured resources
+fn add_preload_links(headers: &mut HeaderMap, resources: &[String]) {
+    for resource in resources {
+        let mut link = format!("<{resource}>; rel=preload");
+        if let Some(destination) = preload_destination(resource) {
+            link.push_str("; as=");
+            link.push_str(destination);
+
+            // Fonts are always fetched in CORS mode, so the preload needs to match
+            if destination == "font" {
+                link.push_str("; crossorigin");
+            }
+        }
+
+        match HeaderValue::from_str(&link) {
+            Ok(value) => {
+                headers.append(header::LINK, value);
+            }
+            Err(_) => warn!(%resource, "Skipping invalid preload resource"),
+        }
+    }
+}
+
+fn preload_destination(resource: &str) -> Option<&'static str> {
+    let extension = Path::new(resource).extension()?.to_str()?;
+    match extension {
+        "js" | "mjs" => Some("script"),
+        "css" => Some("style"),
+        "woff" | "woff2" | "ttf" | "otf" => Some("font"),
+        "png" | "jpg" | "jpeg" | "gif" | "svg" | "webp" => Some("image"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn dist_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "<html></html>").unwrap();
+        std::fs::create_dir(dir.path().join("assets")).unwrap();
+        std::fs::write(dir.path().join("assets/app.js"), "console.log(1);").unwrap();
+        std::fs::write(dir.path().join("assets/app.wasm"), b"\0asm").unwrap();
+        std::fs::write(dir.path().join("assets/app.js.map"), "{}").unwrap();
+        dir
+    }
+
+    fn config() -> StaticFilesConfig {
+        let mut config = StaticFilesConfig::for_testing();
+        config.preload_resources = vec!["/assets/app.js".into(), "/assets/app.css".into()];
+        config
+    }
+
+    fn links(response: &Response) -> Vec<&str> {
+        response
+            .headers()
+            .get_all(header::LINK)
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect()
+    }
+
+    fn cache() -> GzipCache {
+        Cache::new(100)
+    }
+
+    #[tokio::test]
+    async fn preload_links_on_index_html() {
+        let dir = dist_dir();
+        let config = config();
+        let cache = cache();
+
+        let request = Request::get("/").body(()).unwrap();
+        let response =
+            assert_some!(serve_dist_inner(dir.path(), &config, &cache, false, request).await);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            links(&response),
+            vec![
+                "</assets/app.js>; rel=preload; as=script",
+                "</assets/app.css>; rel=preload; as=style",
+            ]
+        );
+
+        let request = Request::get("/assets/app.js").body(()).unwrap();
+        let response =
+            assert_some!(serve_dist_inner(dir.path(), &config, &cache, false, request).await);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(links(&response).is_empty());
+    }
+
+    #[tokio::test]
+    async fn no_preload_links_by_default() {
+        let dir = dist_dir();
+        let config = StaticFilesConfig::for_testing();
+        let cache = cache();
+
+        let request = Request::get("/").body(()).unwrap();
+        let response =
+            assert_some!(serve_dist_inner(dir.path(), &config, &cache, false, request).await);
+        assert!(links(&response).is_empty());
+    }
+
+    #[tokio::test]
+    async fn gzip_on_the_fly_is_cached() {
+        let dir = dist_dir();
+        let mut config = StaticFilesConfig::for_testing();
+        config.gzip_level = Some(6);
+        let cache = cache();
+
+        let request = || {
+            Request::get("/assets/app.js")
+                .header(header::ACCEPT_ENCODING, "gzip, deflate")
+                .body(())
+                .unwrap()
+        };
+
+        // The first request compresses the file and stores the result in the cache
+        let response =
+            assert_some!(serve_dist_inner(dir.path(), &config, &cache, false, request()).await);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        let vary = response.headers().get_all(header::VARY);
+        assert_eq!(vary.iter().collect::<Vec<_>>(), ["accept-encoding"]);
+        let last_modified = response.headers()[header::LAST_MODIFIED].to_str().unwrap();
+        let key = ("/assets/app.js".to_string(), last_modified.to_string());
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "console.log(1);");
+        assert_eq!(assert_some!(cache.get(&key)), (body, 15));
+
+        // The second request is served from the cache without compressing the file again
+        cache.insert(key, (Bytes::from_static(b"cached"), 15));
+        let response =
+            assert_some!(serve_dist_inner(dir.path(), &config, &cache, false, request()).await);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "6");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "cached");
+    }
+
+    #[tokio::test]
+    async fn gzip_records_compression_time() {
+        let dir = dist_dir();
+        std::fs::write(
+            dir.path().join("assets/big.js"),
+            "console.log(1);\n".repeat(1000),
+        )
+        .unwrap();
+
+        let mut config = StaticFilesConfig::for_testing();
+        config.gzip_level = Some(6);
+        let cache = cache();
+
+        let request = Request::get("/assets/big.js")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(())
+            .unwrap();
+        let response = serve_dist_inner(dir.path(), &config, &cache, true, request).await;
+        let response = assert_some!(response);
+
+        let timings = assert_some!(response.extensions().get::<LayerTimings>());
+        assert_some!(timings.get("compression"));
+    }
+
+    #[tokio::test]
+    async fn gzip_records_uncompressed_size() {
+        let dir = dist_dir();
+        let content = "console.log(1);\n".repeat(1000);
+        std::fs::write(dir.path().join("assets/big.js"), &content).unwrap();
+
+        let mut config = StaticFilesConfig::for_testing();
+        config.gzip_level = Some(6);
+        let cache = cache();
+
+        let request = Request::get("/assets/big.js")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(())
+            .unwrap();
+        let response =
+            assert_some!(serve_dist_inner(dir.path(), &config, &cache, false, request).await);
+
+        let uncompressed_size = assert_some!(response.extensions().get::<UncompressedSize>());
+        assert_eq!(uncompressed_size.0, content.len() as u64);
+        assert_none!(response.extensions().get::<LayerTimings>());
+
+        let content_length = response.headers()[header::CONTENT_LENGTH].to_str().unwrap();
+        let content_length: u64 = content_length.parse().unwrap();
+        assert!(content_length < uncompressed_size.0);
+    }
+
+    #[tokio::test]
+    async fn no_gzip_without_accept_encoding() {
+        let dir = dist_dir();
+        let mut config = StaticFilesConfig::for_testing();
+        config.gzip_level = Some(6);
+        let cache = cache();
+
+        let request = Request::get("/assets/app.js").body(()).unwrap();
+        let response =
+            assert_some!(serve_dist_inner(dir.path(), &config, &cache, false, request).await);
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        // The identity variant varies by `Accept-Encoding` too, for shared caches
+        assert_eq!(response.headers()[header::VARY], "accept-encoding");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "console.log(1);");
+    }
+
+    #[tokio::test]
+    async fn large_files_are_not_compressed() {
+        let dir = dist_dir();
+        let mut config = StaticFilesConfig::for_testing();
+        config.gzip_level = Some(6);
+        config.gzip_max_size = 10;
+        let cache = cache();
+
+        let request = Request::get("/assets/app.js")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(())
+            .unwrap();
+        let response =
+            assert_some!(serve_dist_inner(dir.path(), &config, &cache, false, request).await);
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        assert_eq!(response.headers()[header::VARY], "accept-encoding");
+        assert_eq!(cache.iter().count(), 0);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "console.log(1);");
+    }
+
+    #[tokio::test]
+    async fn range_requests_are_not_compressed() {
+        let dir = dist_dir();
+        std::fs::write(dir.path().join("assets/app.js.gz"), b"precompressed").unwrap();
+
+        let mut config = StaticFilesConfig::for_testing();
+        config.gzip_level = Some(6);
+        let cache = cache();
+
+        let request = Request::get("/assets/app.js")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .header(header::RANGE, "bytes=0-6")
+            .body(())
+            .unwrap();
+        let response =
+            assert_some!(serve_dist_inner(dir.path(), &config, &cache, false, request).await);
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 0-6/15");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "console");
+
+        // Requests without a `Range` header are still compressed
+        std::fs::remove_file(dir.path().join("assets/app.js.gz")).unwrap();
+        let request = Request::get("/assets/app.js")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(())
+            .unwrap();
+        let response =
+            assert_some!(serve_dist_inner(dir.path(), &config, &cache, false, request).await);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+    }
+
+    #[tokio::test]
+    async fn mime_type_overrides() {
+        let dir = dist_dir();
+        let mut config = StaticFilesConfig::for_testing();
+        config
+            .mime_types
+            .insert("map".into(), "application/json".into());
+        let cache = cache();
+
+        let content_type = |response: Response| response.headers()[header::CONTENT_TYPE].clone();
+
+        let request = Request::get("/assets/app.wasm").body(()).unwrap();
+        let response =
+            assert_some!(serve_dist_inner(dir.path(), &config, &cache, false, request).await);
+        assert_eq!(content_type(response), "application/wasm");
+
+        let request = Request::get("/assets/app.js.map").body(()).unwrap();
+        let response =
+            assert_some!(serve_dist_inner(dir.path(), &config, &cache, false, request).await);
+        assert_eq!(content_type(response), "application/json");
+
+        // Files without a configured entry keep the type guessed by `ServeDir`
+        let request = Request::get("/assets/app.js").body(()).unwrap();
+        let response =
+            assert_some!(serve_dist_inner(dir.path(), &config, &cache, false, request).await);
+        let content_type = content_type(response);
+        assert!(content_type.to_str().unwrap().contains("javascript"));
+    }
+
+    #[tokio::test]
+    async fn head_responses_match_get_responses() {
+        let dir = dist_dir();
+        let mut config = StaticFilesConfig::for_testing();
+        config.gzip_level = Some(6);
+        let cache = cache();
+
+        let request = |method: Method, accept_encoding: &'static str| {
+            Request::builder()
+                .method(method)
+                .uri("/assets/app.js")
+                .header(header::ACCEPT_ENCODING, accept_encoding)
+                .body(())
+                .unwrap()
+        };
+
+        // `HEAD` requests are not compressed, so they match the identity `GET` response
+        for accept_encoding in ["gzip", "identity"] {
+            let serve = |req| serve_dist_inner(dir.path(), &config, &cache, false, req);
+            let get = request(Method::GET, "identity");
+            let get = assert_some!(head_as_get(get, serve).await);
+
+            let serve = |req| serve_dist_inner(dir.path(), &config, &cache, false, req);
+            let head = request(Method::HEAD, accept_encoding);
+            let head = assert_some!(head_as_get(head, serve).await);
+
+            assert_eq!(head.status(), get.status());
+            assert_eq!(head.headers(), get.headers());
+            assert!(head.headers().contains_key(header::CONTENT_LENGTH));
+
+            let get_body = hyper::body::to_bytes(get.into_body()).await.unwrap();
+            assert!(!get_body.is_empty());
+            let head_body = hyper::body::to_bytes(head.into_body()).await.unwrap();
+            assert!(head_body.is_empty());
+        }
+        assert_eq!(cache.iter().count(), 0);
+
+        // Also for files that are served without the dist-specific handling
+        let serve = |req| serve_static(ServeDir::new(dir.path()), req);
+        let get = assert_some!(head_as_get(request(Method::GET, "gzip"), serve).await);
+        let serve = |req| serve_static(ServeDir::new(dir.path()), req);
+        let head = assert_some!(head_as_get(request(Method::HEAD, "gzip"), serve).await);
+        assert_eq!(head.headers(), get.headers());
+        let head_body = hyper::body::to_bytes(head.into_body()).await.unwrap();
+        assert!(head_body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn prefix_is_stripped_before_lookup() {
+        let dir = dist_dir();
+        std::fs::write(dir.path().join("app.js"), "console.log(2);").unwrap();
+        let mut config = StaticFilesConfig::for_testing();
+        config.strip_prefix = Some("/assets".into());
+        let cache = cache();
+
+        let request = Request::get("/assets/app.js?v=1").body(()).unwrap();
+        let response =
+            assert_some!(serve_dist_inner(dir.path(), &config, &cache, false, request).await);
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "console.log(2);");
+
+        // Paths without the prefix are looked up unchanged
+        let request = Request::get("/").body(()).unwrap();
+        let response =
+            assert_some!(serve_dist_inner(dir.path(), &config, &cache, false, request).await);
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn non_matching_paths_fall_through() {
+        let dir = dist_dir();
+        let mut config = StaticFilesConfig::for_testing();
+        config.strip_prefix = Some("/assets".into());
+        let cache = cache();
+
+        // `dist/assets/app.js` exists, but is looked up as `dist/app.js`
+        let request = Request::get("/assets/app.js").body(()).unwrap();
+        assert_none!(serve_dist_inner(dir.path(), &config, &cache, false, request).await);
+
+        // The prefix only matches whole path segments
+        std::fs::write(dir.path().join("app.js"), "console.log(2);").unwrap();
+        let request = Request::get("/assets-old/app.js").body(()).unwrap();
+        assert_none!(serve_dist_inner(dir.path(), &config, &cache, false, request).await);
+    }
+
+    #[test]
+    fn accept_encoding_parsing() {
+        let accepts = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static(value));
+            accepts_gzip(&headers)
+        };
+
+        assert!(accepts("gzip"));
+        assert!(accepts("deflate, GZIP;q=0.5"));
+        assert!(accepts("*"));
+        assert!(!accepts("br"));
+        assert!(!accepts("gzip;q=0"));
+        assert!(!accepts(""));
+    }
 }
@@ -2,6 +2,7 @@ use crate::app::AppState;
 use axum::extract::{MatchedPath, State};
 use axum::middleware::Next;
 use axum::response::Response;
+use conduit_axum::BlockingWait;
 use conduit_router::RoutePattern;
 use http::Request;
 use prometheus::IntGauge;
@@ -35,6 +36,11 @@ pub async fn update_metrics<B>(
         .with_label_values(&[endpoint])
         .observe(start_instant.elapsed().as_millis() as f64 / 1000.0);
 
+    if let Some(blocking_wait) = response.extensions().get::<BlockingWait>() {
+        let wait_seconds = blocking_wait.0.as_secs_f64();
+        metrics.blocking_wait_times.observe(wait_seconds);
+    }
+
     let status = response.status().as_u16();
     metrics
         .responses_by_status_code_total
@@ -0,0 +1,161 @@
+//! Reject cross-site requests that are authenticated via the session cookie
+//!
+//! Browsers attach the session cookie to all requests for the site, including requests that were
+//! triggered by other sites. For state-changing requests (i.e. all methods except `GET`, `HEAD`,
+//! `OPTIONS` and `TRACE`) that carry a session cookie, the `Origin` header, or the origin of the
+//! `Referer` header if no `Origin` is sent, must be one of the `allowed_origins`. Otherwise the
+//! request is rejected with a `403 Forbidden` response.
+//!
+//! Requests that are authenticated via an API token in the `Authorization` header are not
+//! affected, since browsers don't attach these automatically. Requests without either header are
+//! passed along too, the same as in `controllers::util::verify_origin()`.
+
+use super::prelude::*;
+use crate::app::AppState;
+use crate::middleware::session::COOKIE_NAME;
+use axum::extract::State;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use http::{HeaderMap, Method};
+use url::Url;
+
+pub async fn verify_origin<B>(
+    State(state): State<AppState>,
+    req: http::Request<B>,
+    next: Next<B>,
+) -> axum::response::Response {
+    let allowed_origins = &state.config.allowed_origins;
+    if let Err(origin) = check_origin(req.method(), req.headers(), allowed_origins) {
+        req.add_custom_metadata("cause", format!("cross-site request from {origin:?}"));
+        let body = "only same-origin requests can be authenticated via cookies";
+        return (StatusCode::FORBIDDEN, body).into_response();
+    }
+
+    next.run(req).await
+}
+
+/// Returns the rejected origin if the request must not be processed
+fn check_origin(
+    method: &Method,
+    headers: &HeaderMap,
+    allowed_origins: &[String],
+) -> Result<(), String> {
+    let is_safe_method = matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    );
+    if is_safe_method || headers.contains_key(header::AUTHORIZATION) || !has_session(headers) {
+        return Ok(());
+    }
+
+    let origin = match request_origin(headers) {
+        Some(origin) => origin,
+        None => return Ok(()),
+    };
+
+    if allowed_origins.iter().any(|allowed| *allowed == origin) {
+        Ok(())
+    } else {
+        Err(origin)
+    }
+}
+
+fn has_session(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.split_once('='))
+        .any(|(name, _)| name.trim() == COOKIE_NAME)
+}
+
+/// The value of the `Origin` header, or the origin of the `Referer` header
+fn request_origin(headers: &HeaderMap) -> Option<String> {
+    if let Some(origin) = headers.get(header::ORIGIN) {
+        return Some(origin.to_str().unwrap_or_default().to_string());
+    }
+
+    let referer = headers.get(header::REFERER)?.to_str().unwrap_or_default();
+    match Url::parse(referer) {
+        Ok(url) => Some(url.origin().ascii_serialization()),
+        Err(_) => Some(referer.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn allowed_origins() -> Vec<String> {
+        vec!["https://crates.io".into()]
+    }
+
+    fn headers(pairs: &[(header::HeaderName, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    const SESSION: &str = "cargo_session=abc";
+
+    #[test]
+    fn allowed_origins_are_accepted() {
+        let allowed_origins = allowed_origins();
+
+        let origin = headers(&[
+            (header::COOKIE, SESSION),
+            (header::ORIGIN, "https://crates.io"),
+        ]);
+        assert_ok!(check_origin(&Method::PUT, &origin, &allowed_origins));
+
+        let referer = headers(&[
+            (header::COOKIE, SESSION),
+            (header::REFERER, "https://crates.io/crates/foo"),
+        ]);
+        assert_ok!(check_origin(&Method::DELETE, &referer, &allowed_origins));
+    }
+
+    #[test]
+    fn other_origins_are_rejected() {
+        let allowed_origins = allowed_origins();
+
+        let origin = headers(&[
+            (header::COOKIE, SESSION),
+            (header::ORIGIN, "https://evil.example"),
+        ]);
+        let rejected = assert_err!(check_origin(&Method::PUT, &origin, &allowed_origins));
+        assert_eq!(rejected, "https://evil.example");
+
+        let referer = headers(&[
+            (header::COOKIE, "foo=bar; cargo_session=abc"),
+            (header::REFERER, "https://crates.io.evil.example/crates/foo"),
+        ]);
+        let rejected = assert_err!(check_origin(&Method::POST, &referer, &allowed_origins));
+        assert_eq!(rejected, "https://crates.io.evil.example");
+    }
+
+    #[test]
+    fn safe_methods_and_tokens_bypass_the_check() {
+        let allowed_origins = allowed_origins();
+
+        let origin = headers(&[
+            (header::COOKIE, SESSION),
+            (header::ORIGIN, "https://evil.example"),
+        ]);
+        assert_ok!(check_origin(&Method::GET, &origin, &allowed_origins));
+        assert_ok!(check_origin(&Method::HEAD, &origin, &allowed_origins));
+
+        let token = headers(&[
+            (header::AUTHORIZATION, "token"),
+            (header::ORIGIN, "https://evil.example"),
+        ]);
+        assert_ok!(check_origin(&Method::PUT, &token, &allowed_origins));
+
+        let no_session = headers(&[(header::ORIGIN, "https://evil.example")]);
+        assert_ok!(check_origin(&Method::PUT, &no_session, &allowed_origins));
+    }
+}
@@ -1,12 +1,19 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
 use conduit::{Handler, HandlerResult, RequestExt};
 use conduit_router::{RequestParams, RouteBuilder, RoutePattern};
+use http::{HeaderValue, Method};
+use route_recognizer::Params;
 
+use crate::auth::UserTier;
+use crate::config;
 use crate::controllers::*;
 use crate::middleware::app::RequestApp;
-use crate::middleware::log_request::CustomMetadataRequestExt;
-use crate::util::errors::{std_error, AppError, RouteBlocked};
+use crate::middleware::log_request::{record_crate_name, CustomMetadataRequestExt};
+use crate::util::errors::{
+    bad_request, std_error, AppError, AppResult, PayloadTooLarge, RouteBlocked,
+};
 use crate::util::EndpointResult;
 use crate::{App, Env};
 
@@ -150,6 +157,8 @@ pub fn build_router(app: &App) -> RouteBuilder {
 
     // Metrics
     router.get("/api/private/metrics/:kind", C(metrics::prometheus));
+    router.get("/api/private/log_config", C(log_config::show));
+    router.get("/api/private/diagnostics", C(diagnostics::show));
 
     // Crate ownership invitations management in the frontend
     router.get(
@@ -176,10 +185,169 @@ pub fn build_router(app: &App) -> RouteBuilder {
     router
 }
 
+/// Maximum request body sizes for individual route patterns
+///
+/// This is available in the request extensions and enforced once the router has figured out
+/// which route pattern matches the request. The global `Content-Length` limit of `conduit_axum`
+/// still applies to all other routes.
+#[derive(Debug, Default)]
+pub struct BodySizeLimits(HashMap<&'static str, u64>);
+
+impl BodySizeLimits {
+    pub fn insert(&mut self, pattern: &'static str, limit: u64) {
+        self.0.insert(pattern, limit);
+    }
+
+    pub fn get(&self, pattern: &str) -> Option<u64> {
+        self.0.get(pattern).copied()
+    }
+}
+
+/// Routes that only accept small JSON request bodies
+///
+/// The publish endpoint only gets a generous cap here, because its limits depend on the user
+/// tier and can be configured per crate. They are checked once the user is authenticated.
+pub fn build_body_size_limits(config: &config::Server) -> BodySizeLimits {
+    const KB: u64 = 1024;
+
+    let publish_cap = config
+        .max_publish_body_size
+        .max(config.max_upload_size)
+        .max(config.premium_max_upload_size);
+
+    let mut limits = BodySizeLimits::default();
+    limits.insert("/api/v1/crates/new", publish_cap);
+    limits.insert("/api/v1/me/tokens", 64 * KB);
+    limits.insert("/api/v1/users/:user_id", 64 * KB);
+    limits.insert("/api/v1/crates/:crate_id/owners", 1024 * KB);
+    limits
+}
+
+/// A request body size limit that depends on the `UserTier`
+///
+/// The authentication happens in the endpoints, but the `BodySizeLimits` are enforced before the
+/// endpoint is called. Routes with a tiered limit therefore need a generous `BodySizeLimits`
+/// entry (at least the `premium` limit), and the endpoint calls `check()` once
+/// `AuthCheck::check_with_tier()` has resolved the tier of the user.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TierBodySizeLimit {
+    pub free: u64,
+    pub premium: u64,
+}
+
+impl TierBodySizeLimit {
+    /// The upload size limits of the publish endpoint
+    pub fn for_publish(config: &config::Server) -> Self {
+        Self {
+            free: config.max_upload_size,
+            premium: config.premium_max_upload_size,
+        }
+    }
+
+    pub fn get(&self, tier: UserTier) -> u64 {
+        match tier {
+            UserTier::Free => self.free,
+            UserTier::Premium => self.premium,
+        }
+    }
+
+    /// The limit of the `UserTier` in the request extensions
+    pub fn for_request(&self, req: &dyn RequestExt) -> u64 {
+        let tier = req.extensions().get::<UserTier>().copied();
+        self.get(tier.unwrap_or(UserTier::Free))
+    }
+
+    /// Reject the request with a `413 Payload Too Large` response if its body exceeds the limit
+    /// of the `UserTier` in the request extensions
+    pub fn check(&self, req: &dyn RequestExt) -> AppResult<()> {
+        let limit = self.for_request(req);
+        if req.content_length().unwrap_or_default() > limit {
+            req.add_custom_metadata("cause", "request body too large for user tier");
+            return Err(Box::new(PayloadTooLarge { limit }));
+        }
+
+        Ok(())
+    }
+}
+
+/// Routes that reject requests without a body, by method and route pattern
+///
+/// Like the `BodySizeLimits`, this is available in the request extensions and enforced once the
+/// router has figured out which route pattern matches the request. Requests with an empty body
+/// are rejected with a `400 Bad Request` response before they reach the endpoint, instead of
+/// failing while the endpoint parses the body.
+#[derive(Debug, Default)]
+pub struct BodyRequiredRoutes(HashSet<(Method, &'static str)>);
+
+impl BodyRequiredRoutes {
+    pub fn insert(&mut self, method: Method, pattern: &'static str) {
+        self.0.insert((method, pattern));
+    }
+
+    pub fn contains(&self, method: &Method, pattern: &str) -> bool {
+        self.0.iter().any(|(m, p)| m == method && *p == pattern)
+    }
+}
+
+pub fn build_body_required_routes() -> BodyRequiredRoutes {
+    let mut routes = BodyRequiredRoutes::default();
+    routes.insert(Method::PUT, "/api/v1/crates/new");
+    routes
+}
+
+/// Deprecation messages of routes, which are sent to clients in a `Warning` header
+///
+/// Entries look like `("/api/v1/crates/:crate_id/downloads", "Deprecated endpoint, use ...")`.
+pub fn build_deprecated_routes() -> HashMap<String, String> {
+    HashMap::new()
+}
+
+/// Default `Cache-Control` values of `GET` routes, for responses that don't set the header
+///
+/// Entries look like `("/api/v1/summary", HeaderValue::from_static("public, max-age=60"))`.
+pub fn build_cache_control_defaults() -> HashMap<String, HeaderValue> {
+    HashMap::new()
+}
+
+/// The path parameters captured by the router for the matched route
+///
+/// This is available in the request extensions of the endpoint handlers, and in the response
+/// extensions for the middleware layers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PathParams(BTreeMap<String, String>);
+
+impl PathParams {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    /// The crate identifier of crate-specific routes like `/api/v1/crates/:crate_id/...`
+    pub fn crate_name(&self) -> Option<&str> {
+        self.get("crate_id")
+    }
+}
+
+impl From<&Params> for PathParams {
+    fn from(params: &Params) -> Self {
+        let params = params.iter();
+        Self(
+            params
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+}
+
 struct C(pub fn(&mut dyn RequestExt) -> EndpointResult);
 
 impl Handler for C {
     fn call(&self, req: &mut dyn RequestExt) -> HandlerResult {
+        let path_params = req.extensions().get::<Params>().map(PathParams::from);
+        if let Some(path_params) = &path_params {
+            record_crate_name(path_params);
+            req.mut_extensions().insert(path_params.clone());
+        }
+
         if let Some(pattern) = req.extensions().get::<RoutePattern>() {
             let pattern = pattern.pattern();
 
@@ -193,21 +361,52 @@ impl Handler for C {
             if req.app().config.blocked_routes.contains(pattern) {
                 return Ok(RouteBlocked.response().unwrap());
             }
+
+            let limits = req.extensions().get::<Arc<BodySizeLimits>>();
+            if let Some(limit) = limits.and_then(|limits| limits.get(pattern)) {
+                if req.content_length().unwrap_or_default() > limit {
+                    req.add_custom_metadata("cause", "request body too large");
+                    return Ok(PayloadTooLarge { limit }.response().unwrap());
+                }
+            }
+
+            let body_required = req.extensions().get::<Arc<BodyRequiredRoutes>>();
+            if let Some(body_required) = body_required {
+                // Streamed bodies without a `Content-Length` are left to the endpoint
+                if body_required.contains(req.method(), pattern) && req.content_length() == Some(0)
+                {
+                    req.add_custom_metadata("cause", "missing request body");
+                    let error = bad_request("this request requires a non-empty body");
+                    return Ok(error.response().unwrap());
+                }
+            }
         }
 
         let C(f) = *self;
-        match f(req) {
+        let mut response = match f(req) {
             Ok(resp) => Ok(resp),
             Err(e) => {
                 if let Some(cause) = e.cause() {
                     req.add_custom_metadata("cause", cause.to_string())
                 };
+                if let Some(metadata) = e.metadata() {
+                    req.add_custom_metadata("error_code", metadata.code());
+                    if let Some(category) = metadata.category() {
+                        req.add_custom_metadata("error_category", category);
+                    }
+                }
                 match e.response() {
                     Some(response) => Ok(response),
                     None => Err(std_error(e)),
                 }
             }
+        };
+
+        if let (Ok(response), Some(path_params)) = (&mut response, path_params) {
+            response.extensions_mut().insert(path_params);
         }
+
+        response
     }
 }
 
@@ -225,8 +424,10 @@ impl<H: Handler> Handler for R<H> {
 mod tests {
     use super::*;
     use crate::middleware::log_request::CustomMetadata;
-    use crate::util::errors::{bad_request, cargo_err, forbidden, internal, not_found, AppError};
-    use crate::util::EndpointResult;
+    use crate::util::errors::{
+        bad_request, cargo_err, forbidden, internal, not_found, AppError, ErrorMetadata,
+    };
+    use crate::util::{AppResponse, EndpointResult};
 
     use conduit_test::MockRequest;
     use diesel::result::Error as DieselError;
@@ -236,6 +437,118 @@ mod tests {
         Err(Box::new(err))
     }
 
+    #[derive(Debug)]
+    struct QuotaExceeded;
+
+    impl std::fmt::Display for QuotaExceeded {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("quota exceeded")
+        }
+    }
+
+    impl AppError for QuotaExceeded {
+        fn response(&self) -> Option<AppResponse> {
+            None
+        }
+
+        fn metadata(&self) -> Option<&dyn ErrorMetadata> {
+            Some(self)
+        }
+    }
+
+    impl ErrorMetadata for QuotaExceeded {
+        fn code(&self) -> &str {
+            "quota_exceeded"
+        }
+
+        fn category(&self) -> Option<&str> {
+            Some("limits")
+        }
+    }
+
+    #[test]
+    fn path_params_are_available() {
+        let mut req = MockRequest::new(::conduit::Method::GET, "/api/v1/crates/foo");
+        req.mut_extensions().insert(CustomMetadata::default());
+
+        let mut params = Params::new();
+        params.insert("crate_id".into(), "foo".into());
+        req.mut_extensions().insert(params);
+
+        let handler = C(|req| {
+            let path_params = req.extensions().get::<PathParams>().unwrap();
+            assert_eq!(path_params.get("crate_id"), Some("foo"));
+            assert_eq!(path_params.get("version"), None);
+            Ok(crate::util::json_response(&()))
+        });
+        let response = handler.call(&mut req).unwrap();
+
+        let path_params = response.extensions().get::<PathParams>().unwrap();
+        assert_eq!(path_params.get("crate_id"), Some("foo"));
+    }
+
+    #[test]
+    fn structured_errors_are_logged_as_separate_fields() {
+        let mut req = MockRequest::new(::conduit::Method::GET, "/");
+        req.mut_extensions().insert(CustomMetadata::default());
+
+        let response = C(|_| Err(QuotaExceeded.chain(bad_request("please try again later"))))
+            .call(&mut req)
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let get_log_message = crate::middleware::log_request::get_log_message;
+        assert_eq!(get_log_message(&req, "error_code"), "quota_exceeded");
+        assert_eq!(get_log_message(&req, "error_category"), "limits");
+        assert_eq!(get_log_message(&req, "cause"), "quota exceeded");
+
+        // Errors without a response are still logged with the structured fields
+        let mut req = MockRequest::new(::conduit::Method::GET, "/");
+        req.mut_extensions().insert(CustomMetadata::default());
+        assert_err!(C(|_| err(QuotaExceeded)).call(&mut req));
+        assert_eq!(get_log_message(&req, "error_code"), "quota_exceeded");
+    }
+
+    #[test]
+    fn body_size_limits_depend_on_user_tier() {
+        const LIMIT: TierBodySizeLimit = TierBodySizeLimit {
+            free: 10,
+            premium: 100,
+        };
+
+        let handler = C(|req| {
+            LIMIT.check(req)?;
+            Ok(crate::util::json_response(&()))
+        });
+
+        let request = |tier: Option<UserTier>| {
+            let mut req = MockRequest::new(::conduit::Method::PUT, "/api/v1/crates/new");
+            req.mut_extensions().insert(CustomMetadata::default());
+            req.with_body(&[0; 50]);
+            if let Some(tier) = tier {
+                req.mut_extensions().insert(tier);
+            }
+            req
+        };
+
+        let mut req = request(Some(UserTier::Premium));
+        let response = handler.call(&mut req).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let mut req = request(Some(UserTier::Free));
+        let response = handler.call(&mut req).unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            crate::middleware::log_request::get_log_message(&req, "cause"),
+            "request body too large for user tier"
+        );
+
+        // Requests without a tier get the limits of the free tier
+        let mut req = request(None);
+        let response = handler.call(&mut req).unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
     #[test]
     fn http_error_responses() {
         let mut req = MockRequest::new(::conduit::Method::GET, "/");
@@ -4,17 +4,24 @@ use std::sync::Arc;
 
 /// Initializes the Sentry SDK from the environment variables.
 ///
-/// If `SENTRY_DSN_API` is not set then Sentry will not be initialized,
-/// otherwise it is required to be a valid DSN string. `SENTRY_ENV_API` must
-/// be set if a DSN is provided.
+/// If `SENTRY_DSN_API` is not set then Sentry will not be initialized. If it
+/// is not a valid DSN string, a warning is logged and `None` is returned, so
+/// that the app keeps running with only the regular log output.
+/// `SENTRY_ENV_API` must be set if a DSN is provided.
 ///
 /// `HEROKU_SLUG_COMMIT`, if present, will be used as the `release` property
 /// on all events.
-pub fn init() -> ClientInitGuard {
-    let dsn = dotenv::var("SENTRY_DSN_API")
-        .ok()
-        .into_dsn()
-        .expect("SENTRY_DSN_API is not a valid Sentry DSN value");
+///
+/// The logging framework should be initialized before, so that the warning
+/// is not lost.
+pub fn init() -> Option<ClientInitGuard> {
+    let dsn = match dotenv::var("SENTRY_DSN_API").ok().into_dsn() {
+        Ok(dsn) => dsn,
+        Err(error) => {
+            warn!("Sentry error reporting is disabled: invalid SENTRY_DSN_API value: {error}");
+            return None;
+        }
+    };
 
     let environment = dsn.as_ref().map(|_| {
         dotenv::var("SENTRY_ENV_API")
@@ -49,5 +56,27 @@ pub fn init() -> ClientInitGuard {
         ..Default::default()
     };
 
-    sentry::init(opts)
+    Some(sentry::init(opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::tracing::capture_logs;
+
+    #[test]
+    fn invalid_sentry_dsn_falls_back_to_log_output() {
+        let (logs, _guard) = capture_logs();
+
+        std::env::set_var("SENTRY_DSN_API", "not a dsn");
+        let sentry = init();
+        std::env::remove_var("SENTRY_DSN_API");
+        assert!(sentry.is_none());
+
+        error!("logging still works");
+
+        let output = logs.contents();
+        assert!(output.contains("invalid SENTRY_DSN_API value"), "{output}");
+        assert!(output.contains("logging still works"), "{output}");
+    }
 }
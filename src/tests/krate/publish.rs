@@ -123,6 +123,22 @@ fn new_krate() {
     assert_eq!(json.krate.max_version, "1.0.0");
 }
 
+#[test]
+fn new_krate_without_body() {
+    let (_, _, _, token) = TestApp::full().with_token();
+
+    let response = token.put::<()>("/api/v1/crates/new", b"");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        response.into_json(),
+        json!({ "errors": [{ "detail": "this request requires a non-empty body" }] })
+    );
+
+    let crate_to_publish = PublishBuilder::new("foo_body").version("1.0.0");
+    let json: GoodCrate = token.publish_crate(crate_to_publish).good();
+    assert_eq!(json.krate.name, "foo_body");
+}
+
 #[test]
 fn new_krate_with_token() {
     let (_, _, _, token) = TestApp::full().with_token();
@@ -408,6 +424,38 @@ fn new_krate_too_big_but_whitelisted() {
     token.publish_crate(crate_to_publish).good();
 }
 
+#[test]
+fn upload_size_limit_depends_on_user_tier() {
+    let (app, _, user) = TestApp::full()
+        .with_config(|config| {
+            config.premium_users = vec!["foo".into()];
+            config.premium_max_upload_size = 20_000;
+            config.max_unpack_size = 20_000;
+        })
+        .with_user();
+
+    // Pseudo-random bytes, so that the tarball exceeds the free limit of 3000 bytes
+    let mut state = 0x2545_f491_u32;
+    let data = (0..5000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect::<Vec<_>>();
+
+    let files = [("foo_tiered-1.0.0/data", &data as &[_])];
+    let crate_to_publish = PublishBuilder::new("foo_tiered").files(&files);
+    user.publish_crate(crate_to_publish).good();
+
+    let free_user = app.db_new_user("bar");
+    let files = [("bar_tiered-1.0.0/data", &data as &[_])];
+    let crate_to_publish = PublishBuilder::new("bar_tiered").files(&files);
+    let response = free_user.publish_crate(crate_to_publish);
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
 #[test]
 fn new_krate_wrong_files() {
     let (_, _, user) = TestApp::full().with_user();
@@ -425,7 +473,7 @@ fn new_krate_wrong_files() {
 
 #[test]
 fn new_krate_gzip_bomb() {
-    let (_, _, _, token) = TestApp::full().with_token();
+    let (app, _, _, token) = TestApp::full().with_token();
 
     let len = 512 * 1024;
     let mut body = io::repeat(0).take(len);
@@ -440,6 +488,9 @@ fn new_krate_gzip_bomb() {
         response.into_json(),
         json!({ "errors": [{ "detail": "uploaded tarball is malformed or too large when decompressed" }] })
     );
+
+    let metrics = &app.as_inner().instance_metrics;
+    assert_eq!(metrics.zip_bombs_suspected_total.get(), 1);
 }
 
 #[test]
@@ -0,0 +1,67 @@
+use crate::util::TestApp;
+use axum::extract::ConnectInfo;
+use axum::Extension;
+use cargo_registry::middleware::app_router::AppRouter;
+use conduit::{Body, RequestExt, ResponseResult};
+use http::{Request, StatusCode};
+use std::net::SocketAddr;
+use tower::ServiceExt;
+
+fn hello(_req: &mut dyn RequestExt) -> ResponseResult<http::Error> {
+    http::Response::builder().body(Body::from_static(b"Hello from conduit"))
+}
+
+#[tokio::test]
+async fn app_router_serves_static_files_uploads_and_conduit_routes() {
+    let (app, _anon) = TestApp::init().empty();
+
+    let dist = tempfile::tempdir().unwrap();
+    std::fs::write(dist.path().join("app.js"), "console.log('hello');").unwrap();
+
+    let uploads = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(uploads.path().join("crates/foo")).unwrap();
+    std::fs::write(
+        uploads.path().join("crates/foo/foo-1.0.0.crate"),
+        "crate file",
+    )
+    .unwrap();
+
+    let remote_addr = SocketAddr::from(([127, 0, 0, 1], 80));
+    let router = AppRouter::new(app.shared_app())
+        .with_conduit(hello)
+        .with_uploads(uploads.path())
+        .with_dist(dist.path())
+        .build()
+        .layer(Extension(ConnectInfo(remote_addr)));
+
+    let get = |path: &str| {
+        let request = Request::get(path).body(axum::body::Body::empty()).unwrap();
+        let router = router.clone();
+        async move {
+            let response = router.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            (status, String::from_utf8(body.to_vec()).unwrap())
+        }
+    };
+
+    let (status, body) = get("/app.js").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "console.log('hello');");
+
+    let (status, body) = get("/crates/foo/foo-1.0.0.crate").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "crate file");
+
+    let (status, body) = get("/api/v1/summary").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "Hello from conduit");
+
+    // Only `GET` and `HEAD` requests are served from the static directories
+    let request = Request::post("/app.js")
+        .body(axum::body::Body::empty())
+        .unwrap();
+    let response = router.oneshot(request).await.unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&*body, b"Hello from conduit");
+}
@@ -0,0 +1,42 @@
+use crate::util::{RequestHelper, TestApp};
+use cargo_registry::config::LogSinks;
+use cargo_registry::middleware::log_request::{LogSink, Metadata};
+use http::StatusCode;
+use std::sync::{Arc, Mutex};
+
+/// Request `/api/v1/site_metadata` and return its request log line
+fn log_line(log_layer_timings: bool) -> String {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let sink = {
+        let lines = lines.clone();
+        move |metadata: &Metadata| lines.lock().unwrap().push(metadata.to_string())
+    };
+    let sink: Arc<dyn LogSink> = Arc::new(sink);
+
+    let (_app, anon) = TestApp::init()
+        .with_config(|config| {
+            config.log_layer_timings = log_layer_timings;
+            config.log_requests.sinks = LogSinks::new(vec![sink]);
+        })
+        .empty();
+
+    let response = anon.get::<()>("/api/v1/site_metadata");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let lines = lines.lock().unwrap();
+    assert_eq!(lines.len(), 1);
+    lines[0].clone()
+}
+
+#[test]
+fn layer_timings_are_logged_when_enabled() {
+    let line = log_line(true);
+    assert!(line.contains(" t_layer_content_length_check="), "{line}");
+    assert!(line.contains(" t_layer_handler="), "{line}");
+}
+
+#[test]
+fn layer_timings_are_not_logged_by_default() {
+    let line = log_line(false);
+    assert!(!line.contains("t_layer_"), "{line}");
+}
@@ -1 +1,3 @@
+mod app_router;
 mod head;
+mod layer_timings;
@@ -0,0 +1,22 @@
+use crate::util::{assert_operator_endpoint_is_protected, operator_app, request_operator_endpoint};
+
+const PATH: &str = "/api/private/diagnostics";
+
+#[test]
+fn diagnostics_are_reported() {
+    let anon = operator_app(|config| config.expose_diagnostics = true);
+
+    let json = request_operator_endpoint(&anon, PATH).good();
+    assert!(json["requests_in_flight"].is_i64());
+    assert!(json["blocking_pool"]["in_flight"].is_u64());
+    assert!(json["blocking_pool"]["queued"].is_u64());
+    assert!(json["blocking_pool"]["waits"].is_u64());
+    assert!(json["blocking_pool"]["mean_wait_ms"].as_f64().unwrap() >= 0.0);
+    assert!(json["file_streams_open"].is_u64());
+    assert!(json["uptime_seconds"].as_u64().unwrap() < 60);
+}
+
+#[test]
+fn diagnostics_are_protected() {
+    assert_operator_endpoint_is_protected(PATH, |config| config.expose_diagnostics = true);
+}
@@ -0,0 +1,24 @@
+use crate::util::{assert_operator_endpoint_is_protected, operator_app, request_operator_endpoint};
+
+const PATH: &str = "/api/private/log_config";
+
+#[test]
+fn log_config_is_dumped() {
+    let anon = operator_app(|config| {
+        config.expose_log_config = true;
+        config.log_requests.large_response_threshold = 1234;
+        config.log_requests.verbose_sample_rate = 0.5;
+    });
+
+    let json = request_operator_endpoint(&anon, PATH).good();
+    assert_eq!(json["log_requests"]["large_response_threshold"], 1234);
+    assert_eq!(json["log_requests"]["verbose_sample_rate"], 0.5);
+    assert_eq!(json["log_requests"]["format"], "logfmt");
+    assert_eq!(json["log_requests"]["statuses"], "all");
+    assert!(json.get("rust_log").is_some());
+}
+
+#[test]
+fn log_config_is_protected() {
+    assert_operator_endpoint_is_protected(PATH, |config| config.expose_log_config = true);
+}
@@ -14,7 +14,9 @@
 pub mod categories;
 pub mod category_slugs;
 pub mod crates;
+pub mod diagnostics;
 pub mod keywords;
+pub mod log_config;
 pub mod me;
 pub mod metrics;
 pub mod session;
@@ -2,7 +2,10 @@ use crate::builders::*;
 use crate::util::*;
 
 use ::insta::assert_display_snapshot;
+use axum::extract::{ConnectInfo, Extension};
 use http::{header, Method, StatusCode};
+use std::net::SocketAddr;
+use tower_service::Service;
 
 #[test]
 fn user_agent_is_required() {
@@ -14,6 +17,151 @@ fn user_agent_is_required() {
     assert_eq!(resp.status(), StatusCode::FORBIDDEN);
 }
 
+#[test]
+fn transferred_bytes_are_counted() {
+    let (app, anon) = TestApp::init().empty();
+    let metrics = &app.as_inner().instance_metrics;
+
+    let mut req = anon.request_builder(Method::PUT, "/api/v1/site_metadata");
+    req.with_body(b"hello world");
+    let first = anon.run::<()>(req).into_text();
+
+    let second = anon.get::<()>("/api/v1/site_metadata").into_text();
+
+    assert_eq!(metrics.request_body_bytes_total.get(), 11);
+    assert_eq!(
+        metrics.response_body_bytes_total.get(),
+        (first.len() + second.len()) as u64
+    );
+}
+
+#[test]
+fn streamed_bytes_are_counted() {
+    let (app, _anon) = TestApp::init().empty();
+    let metrics = &app.as_inner().instance_metrics;
+
+    let remote_addr = SocketAddr::from(([127, 0, 0, 1], 80));
+    let mut router = app
+        .router()
+        .clone()
+        .layer(Extension(ConnectInfo(remote_addr)));
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    // A streamed request body, without a `Content-Length` or an exact size hint
+    let (mut sender, body) = hyper::Body::channel();
+    rt.block_on(sender.send_data("hello world".into())).unwrap();
+    drop(sender);
+
+    let req = http::Request::put("/api/v1/site_metadata")
+        .header(header::USER_AGENT, "conduit-test")
+        .body(body)
+        .unwrap();
+    let response = rt.block_on(router.call(req)).unwrap();
+    let body = rt.block_on(hyper::body::to_bytes(response.into_body()));
+    let body = body.unwrap();
+
+    assert_eq!(metrics.request_body_bytes_total.get(), 11);
+    assert_eq!(metrics.response_body_bytes_total.get(), body.len() as u64);
+}
+
+#[test]
+fn requests_are_rejected_until_ready() {
+    let (app, anon) = TestApp::init().empty();
+    app.as_inner().readiness.set_ready(false);
+
+    let resp = anon.get::<()>("/api/v1/site_metadata");
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(resp.headers()["retry-after"], "5");
+
+    let resp = anon.get::<()>("/healthz");
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    app.as_inner().readiness.set_ready(true);
+
+    let resp = anon.get::<()>("/api/v1/site_metadata");
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[test]
+fn maintenance_mode_rejects_requests() {
+    let (app, anon) = TestApp::init().empty();
+    app.as_inner().maintenance_mode.set_enabled(true);
+
+    let resp = anon.get::<()>("/api/v1/site_metadata");
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(resp.headers()["retry-after"], "300");
+    assert_eq!(
+        resp.into_json(),
+        json!({ "errors": [{ "detail": "crates.io is down for maintenance, please try again later" }] })
+    );
+
+    let mut req = anon.request_builder(Method::GET, "/crates/foo");
+    req.header(header::ACCEPT, "text/html");
+    let resp = anon.run::<()>(req);
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(resp
+        .into_text()
+        .contains("<h1>crates.io is down for maintenance</h1>"));
+
+    let resp = anon.get::<()>("/healthz");
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    app.as_inner().maintenance_mode.set_enabled(false);
+
+    let resp = anon.get::<()>("/api/v1/site_metadata");
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[test]
+fn legacy_paths_are_rewritten() {
+    let (_app, anon) = TestApp::init()
+        .with_config(|config| {
+            let legacy_path = "rewrite:/site_metadata=/api/v1/site_metadata";
+            config.legacy_paths = vec![legacy_path.parse().unwrap()];
+        })
+        .empty();
+
+    let resp = anon.get::<()>("/site_metadata");
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.into_json()["deployed_sha"].is_string());
+}
+
+#[test]
+fn legacy_paths_are_redirected() {
+    let (_app, anon) = TestApp::init()
+        .with_config(|config| {
+            let legacy_path = "redirect:/crates/:id/downloads=/api/v1/crates/:id/downloads";
+            config.legacy_paths = vec![legacy_path.parse().unwrap()];
+        })
+        .empty();
+
+    let resp = anon.get_query::<()>("/crates/foo/downloads", "before_date=2020-01-01");
+    assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        resp.headers()["location"],
+        "/api/v1/crates/foo/downloads?before_date=2020-01-01"
+    );
+}
+
+#[test]
+fn security_headers_are_added() {
+    let (_app, anon) = TestApp::init().empty();
+
+    let resp = anon.get::<()>("/api/v1/site_metadata");
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["x-content-type-options"], "nosniff");
+    assert_eq!(resp.headers()["x-frame-options"], "DENY");
+    assert_eq!(
+        resp.headers()["referrer-policy"],
+        "strict-origin-when-cross-origin"
+    );
+    assert!(!resp.headers().contains_key("strict-transport-security"));
+}
+
 #[test]
 fn user_agent_is_not_required_for_download() {
     let (app, anon, user) = TestApp::init().with_user();
@@ -76,3 +224,160 @@ fn block_traffic_via_arbitrary_header_and_value() {
     let resp = anon.run::<()>(req);
     assert_eq!(resp.status(), StatusCode::FOUND);
 }
+
+#[test]
+fn uri_length_is_limited() {
+    let (_app, anon) = TestApp::init()
+        .with_config(|config| config.max_uri_length = 100)
+        .empty();
+
+    let query = format!("q={}", "a".repeat(100));
+    let resp = anon.get_with_query::<()>("/api/v1/crates", &query);
+    assert_eq!(resp.status(), StatusCode::URI_TOO_LONG);
+
+    let resp = anon.get_with_query::<()>("/api/v1/crates", "q=foo");
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[test]
+fn host_must_be_allowed() {
+    let (_app, anon) = TestApp::init()
+        .with_config(|config| config.allowed_hosts = vec!["crates.io".into()])
+        .empty();
+
+    let mut req = anon.request_builder(Method::GET, "/api/v1/crates");
+    req.header(header::HOST, "crates.io");
+    let resp = anon.run::<()>(req);
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let mut req = anon.request_builder(Method::GET, "/api/v1/crates");
+    req.header(header::HOST, "evil.example");
+    let resp = anon.run::<()>(req);
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let resp = anon.get::<()>("/api/v1/crates");
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn client_request_rate_is_limited() {
+    let (_app, anon) = TestApp::init()
+        .with_config(|config| {
+            config.client_rate_limit.rate = Some(0.01);
+            config.client_rate_limit.burst = 2;
+        })
+        .empty();
+
+    for _ in 0..2 {
+        let resp = anon.get::<()>("/api/v1/site_metadata");
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    let resp = anon.get::<()>("/api/v1/site_metadata");
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after = resp.headers()[header::RETRY_AFTER].to_str().unwrap();
+    assert!((1..=100).contains(&retry_after.parse::<u64>().unwrap()));
+
+    // Other clients have their own limit
+    let mut req = anon.request_builder(Method::GET, "/api/v1/site_metadata");
+    req.header("x-real-ip", "10.0.0.1");
+    let resp = anon.run::<()>(req);
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[test]
+fn trusted_ips_are_not_rate_limited() {
+    let (_app, anon) = TestApp::init()
+        .with_config(|config| {
+            config.client_rate_limit.rate = Some(0.01);
+            config.client_rate_limit.burst = 1;
+            config.client_rate_limit.trusted_ips = vec!["127.0.0.0/8".parse().unwrap()];
+        })
+        .empty();
+
+    for _ in 0..3 {
+        let resp = anon.get::<()>("/api/v1/site_metadata");
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
+
+#[test]
+fn trusted_user_agents_are_not_rate_limited() {
+    let (_app, anon) = TestApp::init()
+        .with_config(|config| {
+            config.client_rate_limit.rate = Some(0.01);
+            config.client_rate_limit.burst = 1;
+            config.client_rate_limit.trusted_user_agents = vec!["crates-mirror/".into()];
+        })
+        .empty();
+
+    for _ in 0..3 {
+        let mut req = anon.request_builder(Method::GET, "/api/v1/site_metadata");
+        req.header(header::USER_AGENT, "crates-mirror/1.0");
+        let resp = anon.run::<()>(req);
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    // Other user agents are still limited
+    let resp = anon.get::<()>("/api/v1/site_metadata");
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = anon.get::<()>("/api/v1/site_metadata");
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[test]
+fn tls_version_is_enforced() {
+    let (_app, anon) = TestApp::init()
+        .with_config(|config| config.min_tls_version = Some("1.2".parse().unwrap()))
+        .empty();
+
+    let mut req = anon.request_builder(Method::GET, "/api/v1/crates");
+    req.header("x-ssl-protocol", "TLSv1.1");
+    let resp = anon.run::<()>(req);
+    assert_eq!(resp.status(), StatusCode::UPGRADE_REQUIRED);
+    assert_eq!(resp.headers()[header::UPGRADE], "TLS/1.2, HTTP/1.1");
+
+    let mut req = anon.request_builder(Method::GET, "/api/v1/crates");
+    req.header("x-ssl-protocol", "TLSv1.3");
+    let resp = anon.run::<()>(req);
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Requests without the header are allowed by default
+    let resp = anon.get::<()>("/api/v1/crates");
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[test]
+fn missing_tls_version_can_be_rejected() {
+    let (_app, anon) = TestApp::init()
+        .with_config(|config| {
+            config.min_tls_version = Some("1.2".parse().unwrap());
+            config.allow_missing_tls_version = false;
+        })
+        .empty();
+
+    let resp = anon.get::<()>("/api/v1/crates");
+    assert_eq!(resp.status(), StatusCode::UPGRADE_REQUIRED);
+}
+
+#[test]
+fn body_size_is_limited_per_route() {
+    let (app, _anon, user) = TestApp::init().with_user();
+
+    app.db(|conn| {
+        CrateBuilder::new("body_size", user.as_model().id).expect_build(conn);
+    });
+
+    // 100 KB exceeds the limit of the token endpoint, but not the one of the owners endpoint
+    let body = vec![b' '; 100 * 1024];
+
+    let resp = user.put::<()>("/api/v1/me/tokens", &body);
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let resp = user.put::<()>("/api/v1/crates/body_size/owners", &body);
+    assert_ne!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Requests below the limit are handled by the endpoint as usual
+    let resp = user.put::<()>("/api/v1/me/tokens", b"{}");
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
@@ -42,11 +42,15 @@ mod chaosproxy;
 mod fresh_schema;
 mod github;
 pub mod insta;
+mod operator_endpoint;
 mod response;
 mod test_app;
 
 pub(crate) use chaosproxy::ChaosProxy;
 pub(crate) use fresh_schema::FreshSchema;
+pub use operator_endpoint::{
+    assert_operator_endpoint_is_protected, operator_app, request_operator_endpoint,
+};
 pub use response::Response;
 pub use test_app::{TestApp, TestDatabase};
 
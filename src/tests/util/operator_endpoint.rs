@@ -0,0 +1,56 @@
+//! Helpers for the private endpoints that are meant for operators, like `/api/private/log_config`
+//!
+//! These endpoints require the `METRICS_AUTHORIZATION_TOKEN`, and are disabled unless they are
+//! explicitly enabled in the config.
+
+use super::{MockAnonymousUser, RequestHelper, Response, TestApp};
+use cargo_registry::config;
+use http::StatusCode;
+
+const TOKEN: &str = "secret";
+
+/// Create a `TestApp` with a `METRICS_AUTHORIZATION_TOKEN` and the config changes of `configure`
+pub fn operator_app(configure: impl FnOnce(&mut config::Server)) -> MockAnonymousUser {
+    let (_, anon) = TestApp::init()
+        .with_config(|config| {
+            config.metrics_authorization_token = Some(TOKEN.into());
+            configure(config);
+        })
+        .empty();
+    anon
+}
+
+/// Request the operator endpoint at `path` with the valid token
+pub fn request_operator_endpoint(
+    anon: &MockAnonymousUser,
+    path: &str,
+) -> Response<serde_json::Value> {
+    request_with_token(anon, path, Some(TOKEN))
+}
+
+/// Check that the operator endpoint at `path` requires the token, and that it is only available
+/// after `enable` changed the config
+#[track_caller]
+pub fn assert_operator_endpoint_is_protected(path: &str, enable: fn(&mut config::Server)) {
+    let anon = operator_app(enable);
+    for token in [Some("foobar"), None] {
+        let resp = request_with_token(&anon, path, token);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    let anon = operator_app(|_| {});
+    let resp = request_operator_endpoint(&anon, path);
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+fn request_with_token(
+    anon: &MockAnonymousUser,
+    path: &str,
+    token: Option<&str>,
+) -> Response<serde_json::Value> {
+    let mut req = anon.get_request(path);
+    if let Some(token) = token {
+        req.header("Authorization", &format!("Bearer {token}"));
+    }
+    anon.run(req)
+}
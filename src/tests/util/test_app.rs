@@ -1,7 +1,11 @@
 use super::{MockAnonymousUser, MockCookieUser, MockTokenUser};
 use crate::record;
 use crate::util::{chaosproxy::ChaosProxy, fresh_schema::FreshSchema};
-use cargo_registry::config::{self, BalanceCapacityConfig, DbPoolConfig};
+use cargo_registry::config::{
+    self, BalanceCapacityConfig, ClientRateLimitConfig, DbPoolConfig, LogRequestsConfig,
+    MaintenanceConfig, SecurityHeadersConfig, StaticFilesConfig,
+};
+use cargo_registry::middleware::normalize_path::NormalizePathMode;
 use cargo_registry::{background_jobs::Environment, App, Emails};
 use cargo_registry_index::testing::UpstreamIndex;
 use cargo_registry_index::{Credentials, Repository as WorkerRepository, RepositoryConfig};
@@ -155,6 +159,11 @@ impl TestApp {
         &self.0.app
     }
 
+    /// Obtain a shared handle to the inner `App` value
+    pub fn shared_app(&self) -> Arc<App> {
+        Arc::clone(&self.0.app)
+    }
+
     /// Obtain a reference to the axum Router
     pub fn router(&self) -> &axum::Router {
         &self.0.router
@@ -339,6 +348,9 @@ fn simple_config() -> config::Server {
         gh_base_url: "http://api.github.com".to_string(),
         max_upload_size: 3000,
         max_unpack_size: 2000,
+        premium_max_upload_size: 3000,
+        premium_users: vec![],
+        max_publish_body_size: 100_000,
         publish_rate_limit: Default::default(),
         new_version_rate_limit: Some(10),
         blocked_traffic: Default::default(),
@@ -351,6 +363,8 @@ fn simple_config() -> config::Server {
         downloads_persist_interval_ms: 1000,
         ownership_invitations_expiration_days: 30,
         metrics_authorization_token: None,
+        expose_log_config: false,
+        expose_diagnostics: false,
         use_test_database_pool: true,
         instance_metrics_log_every_seconds: None,
         force_unconditional_redirects: false,
@@ -359,6 +373,30 @@ fn simple_config() -> config::Server {
         version_id_cache_ttl: Duration::from_secs(5 * 60),
         cdn_user_agent: "Amazon CloudFront".to_string(),
         balance_capacity: BalanceCapacityConfig::for_testing(),
+        static_files: StaticFilesConfig::for_testing(),
+        max_uri_length: 8 * 1024,
+        behind_proxy: true,
+        prefer_forwarded_header: true,
+        log_requests: LogRequestsConfig::for_testing(),
+        content_length_monitor_limit: None,
+        max_file_streams: None,
+        file_redirect: None,
+        body_read_timeout: None,
+        decompress_request_bodies: false,
+        raw_body_path_prefixes: vec![],
+        max_response_header_size: None,
+        coalesce_requests: false,
+        log_layer_timings: false,
+        client_rate_limit: ClientRateLimitConfig::for_testing(),
+        maintenance: MaintenanceConfig::for_testing(),
+        allowed_hosts: vec![],
+        min_tls_version: None,
+        allow_missing_tls_version: true,
+        normalize_accept_encoding: false,
+        strip_accept_encoding_user_agents: vec![],
+        security_headers: SecurityHeadersConfig::for_testing(),
+        legacy_paths: vec![],
+        normalize_path_mode: NormalizePathMode::Rewrite,
     }
 }
 
@@ -382,6 +420,9 @@ fn build_app(config: config::Server, proxy: Option<String>) -> (Arc<App>, axum::
     // organizations without actually having to create GitHub accounts.
     app.github = Box::new(MockGitHubClient::new(&MOCK_GITHUB_DATA));
 
+    // The test database is set up before the app is built
+    app.readiness.set_ready(true);
+
     let app = Arc::new(app);
     let router = cargo_registry::build_handler(Arc::clone(&app));
     (app, router)
@@ -4,7 +4,7 @@ use conduit::Body;
 use http::{header, Response};
 use serde::Serialize;
 
-pub use self::io_util::{read_fill, read_le_u32, LimitErrorReader};
+pub use self::io_util::{read_fill, read_le_u32, LimitErrorReader, LimitReached};
 pub use self::request_helpers::*;
 
 pub mod errors;
@@ -28,7 +28,7 @@ mod json;
 pub use json::TOKEN_FORMAT_ERROR;
 pub(crate) use json::{
     InsecurelyGeneratedTokenRevoked, MetricsDisabled, NotFound, OwnershipInvitationExpired,
-    ReadOnlyMode, RouteBlocked, TooManyRequests,
+    PayloadTooLarge, ReadOnlyMode, RouteBlocked, TooManyRequests,
 };
 
 /// Returns an error with status 200 and the provided description as JSON
@@ -95,6 +95,14 @@ pub trait AppError: Send + fmt::Display + fmt::Debug + 'static {
         None
     }
 
+    /// Structured context of the error
+    ///
+    /// If present, it is logged by the `LogRequests` middleware as separate `error_code` and
+    /// `error_category` fields.
+    fn metadata(&self) -> Option<&dyn ErrorMetadata> {
+        None
+    }
+
     fn get_type_id(&self) -> TypeId {
         TypeId::of::<Self>()
     }
@@ -142,11 +150,26 @@ impl AppError for Box<dyn AppError> {
         (**self).cause()
     }
 
+    fn metadata(&self) -> Option<&dyn ErrorMetadata> {
+        (**self).metadata()
+    }
+
     fn get_type_id(&self) -> TypeId {
         (**self).get_type_id()
     }
 }
 
+/// Structured context of an error, see `AppError::metadata()`
+pub trait ErrorMetadata {
+    /// A stable identifier of the error, e.g. `crate_name_taken`
+    fn code(&self) -> &str;
+
+    /// The broader category of the error, e.g. `validation` or `database`
+    fn category(&self) -> Option<&str> {
+        None
+    }
+}
+
 pub type AppResult<T> = Result<T, Box<dyn AppError>>;
 
 // =============================================================================
@@ -166,6 +189,10 @@ impl<E: AppError> AppError for ChainedError<E> {
     fn cause(&self) -> Option<&dyn AppError> {
         Some(&*self.cause)
     }
+
+    fn metadata(&self) -> Option<&dyn ErrorMetadata> {
+        self.error.metadata().or_else(|| self.cause.metadata())
+    }
 }
 
 impl<E: AppError> fmt::Display for ChainedError<E> {
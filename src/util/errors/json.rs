@@ -269,6 +269,27 @@ impl fmt::Display for MetricsDisabled {
     }
 }
 
+#[derive(Debug)]
+pub(crate) struct PayloadTooLarge {
+    pub(crate) limit: u64,
+}
+
+impl AppError for PayloadTooLarge {
+    fn response(&self) -> Option<AppResponse> {
+        Some(json_error(&self.to_string(), StatusCode::PAYLOAD_TOO_LARGE))
+    }
+}
+
+impl fmt::Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The request body exceeds the limit of {} bytes",
+            self.limit
+        )
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct RouteBlocked;
 
@@ -18,15 +18,26 @@ impl<R: Read> LimitErrorReader<R> {
 impl<R: Read> Read for LimitErrorReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self.inner.read(buf) {
-            Ok(0) if self.inner.limit() == 0 => Err(io::Error::new(
-                io::ErrorKind::Other,
-                "maximum limit reached when reading",
-            )),
+            Ok(0) if self.inner.limit() == 0 => {
+                Err(io::Error::new(io::ErrorKind::Other, LimitReached))
+            }
             e => e,
         }
     }
 }
 
+/// The error of a `LimitErrorReader` that reached its limit
+#[derive(Debug, thiserror::Error)]
+#[error("maximum limit reached when reading")]
+pub struct LimitReached;
+
+impl LimitReached {
+    /// Whether the `error` was returned by a `LimitErrorReader` that reached its limit
+    pub fn is_cause_of(error: &io::Error) -> bool {
+        error.get_ref().map_or(false, |inner| inner.is::<Self>())
+    }
+}
+
 pub fn read_le_u32<R: Read + ?Sized>(r: &mut R) -> io::Result<u32> {
     let mut b = [0; 4];
     read_fill(r, &mut b)?;
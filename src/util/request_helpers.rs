@@ -1,4 +1,5 @@
 use conduit::RequestExt;
+use conduit_axum::NoStore;
 use http::header::AsHeaderName;
 
 /// Returns the value of the request header, or an empty slice if it is not
@@ -16,3 +17,9 @@ where
         .map(|value| value.to_str().unwrap_or_default())
         .unwrap_or_default()
 }
+
+/// Marks the response as uncacheable by adding a `Cache-Control: no-store` header, unless the
+/// handler has set a `Cache-Control` header itself.
+pub fn mark_no_store(req: &mut dyn RequestExt) {
+    req.mut_extensions().insert(NoStore);
+}
@@ -1,9 +1,22 @@
+use crate::env_optional;
+use once_cell::sync::OnceCell;
 use sentry::integrations::tracing::EventFilter;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::sync::{Arc, Mutex, PoisonError};
 use tracing::Level;
 use tracing::Metadata;
+use tracing::Subscriber;
 use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::{prelude::*, EnvFilter};
 
+/// The directives of the `RUST_LOG` filter, once `init()` has set up the logging framework
+static LOG_DIRECTIVES: OnceCell<String> = OnceCell::new();
+
+/// The guard of the `LOG_FILE` output, which is dropped by `shutdown_logging()`
+static LOG_FILE_GUARD: Mutex<Option<LogFileGuard>> = Mutex::new(None);
+
 /// Initializes the `tracing` logging framework.
 ///
 /// Regular CLI output is influenced by the
@@ -11,27 +24,173 @@ use tracing_subscriber::{prelude::*, EnvFilter};
 ///
 /// This function also sets up the Sentry error reporting integration for the
 /// `tracing` framework, which is hardcoded to include all `INFO` level events.
+/// Breadcrumbs for `INFO` events of the `http` target (i.e. the request log) are sampled
+/// according to the `SENTRY_HTTP_BREADCRUMB_SAMPLE_RATE` environment variable (default: 1.0).
+///
+/// If the `LOG_FILE` environment variable is set, the log output is additionally appended to
+/// that file. The file output is buffered in memory, so `shutdown_logging()` should be called
+/// before the process exits. The buffer is also flushed if the process panics.
 pub fn init() {
+    let log_file = match dotenv::var("LOG_FILE") {
+        Ok(path) => match LogFile::open(&path) {
+            Ok(log_file) => Some(log_file),
+            Err(error) => {
+                eprintln!("Failed to open the log file {path}: {error}");
+                None
+            }
+        },
+        Err(_) => None,
+    };
+
+    let subscriber = subscriber(std::io::stdout, log_file.clone());
+    if let Err(error) = subscriber.try_init() {
+        eprintln!("Failed to initialize the logging framework: {error}");
+        return;
+    }
+
+    if let Some(log_file) = log_file {
+        flush_on_panic(log_file.clone());
+        *lock(&LOG_FILE_GUARD) = Some(LogFileGuard(log_file));
+    }
+
+    let _ = LOG_DIRECTIVES.set(EnvFilter::from_default_env().to_string());
+}
+
+/// Flushes the buffered `LOG_FILE` output and closes the file
+///
+/// This should be called right before the process exits. Later log events are only written to
+/// the regular CLI output. It is safe to call this if no `LOG_FILE` is configured, or more than
+/// once.
+pub fn shutdown_logging() {
+    shutdown(&LOG_FILE_GUARD);
+}
+
+fn shutdown(guard: &Mutex<Option<LogFileGuard>>) {
+    let guard = lock(guard).take();
+    drop(guard);
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Flush the `log_file` after the previous panic hook has run
+///
+/// Sentry's panic hook is installed later by `sentry::init()`, and calls this one after it has
+/// captured the panic.
+fn flush_on_panic(log_file: LogFile) {
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        previous_hook(info);
+        let _ = log_file.clone().flush();
+    }));
+}
+
+/// A log output that is buffered in memory before it is appended to a file
+///
+/// Once the `LogFileGuard` is dropped, the file is closed and further output is discarded.
+#[derive(Clone)]
+struct LogFile(Arc<Mutex<Option<BufWriter<File>>>>);
+
+impl LogFile {
+    fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self(Arc::new(Mutex::new(Some(BufWriter::new(file))))))
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut *lock(&self.0) {
+            Some(writer) => writer.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut *lock(&self.0) {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Flushes and closes the `LogFile` when dropped
+struct LogFileGuard(LogFile);
+
+impl Drop for LogFileGuard {
+    fn drop(&mut self) {
+        if let Some(mut writer) = lock(&self.0 .0).take() {
+            if let Err(error) = writer.flush() {
+                eprintln!("Failed to flush the log file: {error}");
+            }
+        }
+    }
+}
+
+/// The effective directives of the `RUST_LOG` filter, or `None` if `init()` was not called
+pub fn log_directives() -> Option<&'static str> {
+    LOG_DIRECTIVES.get().map(String::as_str)
+}
+
+/// Builds the subscriber used by `init()`, writing the regular log output to `make_writer`, and
+/// to the `log_file` if there is one
+fn subscriber<W>(
+    make_writer: W,
+    log_file: Option<LogFile>,
+) -> impl Subscriber + Send + Sync + 'static
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let http_breadcrumb_sample_rate =
+        env_optional("SENTRY_HTTP_BREADCRUMB_SAMPLE_RATE").unwrap_or(1.0);
+
     let log_layer = tracing_subscriber::fmt::layer()
         .compact()
         .without_time()
+        .with_writer(make_writer)
         .with_filter(EnvFilter::from_default_env());
 
+    let log_file_layer = log_file.map(|log_file| {
+        tracing_subscriber::fmt::layer()
+            .compact()
+            .with_ansi(false)
+            .with_writer(move || log_file.clone())
+            .with_filter(EnvFilter::from_default_env())
+    });
+
     let sentry_layer = sentry::integrations::tracing::layer()
-        .event_filter(event_filter)
+        .event_filter(move |metadata| event_filter(metadata, http_breadcrumb_sample_rate))
         .with_filter(LevelFilter::INFO);
 
     tracing_subscriber::registry()
         .with(log_layer)
+        .with(log_file_layer)
         .with(sentry_layer)
-        .init();
 }
 
-pub fn event_filter(metadata: &Metadata<'_>) -> EventFilter {
-    match metadata.level() {
-        &Level::ERROR if metadata.target() == "http" => EventFilter::Breadcrumb,
-        &Level::ERROR if metadata.target() == "conduit_axum::fallback" => EventFilter::Ignore,
+pub fn event_filter(metadata: &Metadata<'_>, http_breadcrumb_sample_rate: f32) -> EventFilter {
+    filter(
+        metadata.level(),
+        metadata.target(),
+        http_breadcrumb_sample_rate,
+    )
+}
+
+fn filter(level: &Level, target: &str, http_breadcrumb_sample_rate: f32) -> EventFilter {
+    match level {
+        &Level::ERROR if target == "http" => EventFilter::Breadcrumb,
+        &Level::ERROR if target == "conduit_axum::fallback" => EventFilter::Ignore,
         &Level::ERROR => EventFilter::Exception,
+        // The verbose request log is only meant for the log output
+        _ if target == "http.verbose" => EventFilter::Ignore,
+        &Level::INFO if target == "http" => {
+            if rand::random::<f32>() < http_breadcrumb_sample_rate {
+                EventFilter::Breadcrumb
+            } else {
+                EventFilter::Ignore
+            }
+        }
         &Level::WARN | &Level::INFO => EventFilter::Breadcrumb,
         &Level::DEBUG | &Level::TRACE => EventFilter::Ignore,
     }
@@ -46,3 +205,99 @@ pub fn init_for_test() {
         .with_test_writer()
         .try_init();
 }
+
+/// Collects the output of a `tracing_subscriber::fmt` subscriber in tests
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl LogBuffer {
+    pub(crate) fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl<'a> MakeWriter<'a> for LogBuffer {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Captures the logs of the current thread in a `LogBuffer`, until the guard is dropped
+#[cfg(test)]
+pub(crate) fn capture_logs() -> (LogBuffer, tracing::subscriber::DefaultGuard) {
+    let logs = LogBuffer::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(logs.clone())
+        .with_ansi(false)
+        .finish();
+    (logs, tracing::subscriber::set_default(subscriber))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_file_is_flushed_on_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crates-io.log");
+        let path = path.to_str().unwrap();
+
+        let log_file = LogFile::open(path).unwrap();
+        let guard = Mutex::new(Some(LogFileGuard(log_file.clone())));
+
+        let subscriber = subscriber(std::io::sink, Some(log_file));
+        tracing::subscriber::with_default(subscriber, || error!("written before shutdown"));
+
+        // The line is still buffered in memory
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "");
+
+        shutdown(&guard);
+        let contents = std::fs::read_to_string(path).unwrap();
+        assert!(contents.contains("written before shutdown"), "{contents}");
+
+        // Shutting down again, or without a log file, is a no-op
+        shutdown(&guard);
+        shutdown(&Mutex::new(None));
+    }
+
+    #[test]
+    fn http_breadcrumbs_are_sampled() {
+        for _ in 0..100 {
+            let result = filter(&Level::INFO, "http", 0.0);
+            assert!(matches!(result, EventFilter::Ignore));
+
+            let result = filter(&Level::INFO, "http", 1.0);
+            assert!(matches!(result, EventFilter::Breadcrumb));
+        }
+
+        let result = filter(&Level::WARN, "http", 0.0);
+        assert!(matches!(result, EventFilter::Breadcrumb));
+
+        let result = filter(&Level::ERROR, "http", 0.0);
+        assert!(matches!(result, EventFilter::Breadcrumb));
+
+        let result = filter(&Level::INFO, "http.verbose", 1.0);
+        assert!(matches!(result, EventFilter::Ignore));
+
+        let result = filter(&Level::INFO, "cargo_registry::worker", 0.0);
+        assert!(matches!(result, EventFilter::Breadcrumb));
+    }
+}